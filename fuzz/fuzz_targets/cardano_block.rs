@@ -0,0 +1,11 @@
+// Blocks and headers as received from block-fetch and chain-sync
+#![no_main]
+
+use cardano_rust_node::consensus::Header;
+use cardano_rust_node::storage::parse_cardano_block;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_cardano_block(data);
+    let _ = Header::from_cbor(data);
+});
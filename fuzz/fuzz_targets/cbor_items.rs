@@ -0,0 +1,12 @@
+// Raw item splitting, run on every message a peer sends
+#![no_main]
+
+use cardano_rust_node::codec::{item_len, split_array};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(len) = item_len(data) {
+        assert!(len <= data.len());
+    }
+    let _ = split_array(data);
+});
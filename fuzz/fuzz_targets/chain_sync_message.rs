@@ -0,0 +1,13 @@
+#![no_main]
+
+use cardano_rust_node::chain_sync::Message;
+use ciborium::{from_reader, Value};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(value) = from_reader::<Value, _>(data) {
+        if let Ok(Message::RollForward(header, _)) = Message::from_value(&value) {
+            let _ = header.summary();
+        }
+    }
+});
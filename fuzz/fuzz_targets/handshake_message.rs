@@ -0,0 +1,11 @@
+#![no_main]
+
+use cardano_rust_node::handshake::Message;
+use ciborium::{from_reader, Value};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(value) = from_reader::<Value, _>(data) {
+        let _ = Message::from_value(value);
+    }
+});
@@ -0,0 +1,26 @@
+// The peer's reply to the legacy handshake of `negotiate`, which reads raw
+// segments from the bearer
+#![no_main]
+
+use cardano_rust_node::bearer::duplex;
+use cardano_rust_node::handshake::{negotiate, NodeConfig};
+use libfuzzer_sys::fuzz_target;
+use tokio::io::AsyncWriteExt;
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let versions = vec![13, 14];
+        let (client, mut server) = duplex();
+        let node_config = NodeConfig::with_bearer("fuzz", 2, "Fuzz", client);
+        let reply = async move {
+            let _ = server.write_all(data).await;
+            let _ = server.shutdown().await;
+            // Keeps the pipe open until the proposal is written
+            server
+        };
+        let _ = tokio::join!(negotiate(node_config, &versions), reply);
+    });
+});
@@ -0,0 +1,168 @@
+// Bearers: the byte streams a multiplexed connection runs over. TCP to
+// remote peers, Unix domain sockets to processes on the same host and
+// in-memory pipes to run both ends in one process, e.g. in tests.
+
+use std::net::SocketAddr;
+use tokio::io::{self, AsyncRead, AsyncWrite, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::{lookup_host, tcp, TcpListener, TcpSocket, TcpStream};
+#[cfg(unix)]
+use tokio::net::{unix, UnixStream};
+use tracing::info;
+
+// Buffer of each direction of an in-memory pipe, one segment fits
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A bidirectional byte stream that splits into halves which are read and
+/// written independently.
+pub trait Bearer: Send + 'static {
+    type Read: AsyncRead + Send + Unpin + 'static;
+    type Write: AsyncWrite + Send + Unpin + 'static;
+
+    fn split(self) -> (Self::Read, Self::Write);
+}
+
+impl Bearer for TcpStream {
+    type Read = tcp::OwnedReadHalf;
+    type Write = tcp::OwnedWriteHalf;
+
+    fn split(self) -> (Self::Read, Self::Write) {
+        self.into_split()
+    }
+}
+
+#[cfg(unix)]
+impl Bearer for UnixStream {
+    type Read = unix::OwnedReadHalf;
+    type Write = unix::OwnedWriteHalf;
+
+    fn split(self) -> (Self::Read, Self::Write) {
+        self.into_split()
+    }
+}
+
+impl Bearer for DuplexStream {
+    type Read = ReadHalf<DuplexStream>;
+    type Write = WriteHalf<DuplexStream>;
+
+    fn split(self) -> (Self::Read, Self::Write) {
+        io::split(self)
+    }
+}
+
+/// Connects to `host`, given as `name:port`.
+pub async fn connect_tcp(host: &str) -> Result<TcpStream, String> {
+    info!("Connecting host: {}", host);
+    let stream = TcpStream::connect(host)
+        .await
+        .map_err(|error| format!("Could not connect to {}: {}", host, error))?;
+    // Segments are written whole, waiting to coalesce them only adds latency
+    stream
+        .set_nodelay(true)
+        .map_err(|error| format!("Could not configure connection to {}: {}", host, error))?;
+    Ok(stream)
+}
+
+/// Connects to `host` from `local`, the address we accept connections on, so
+/// the peer sees an address it can connect back to. Both ends of the
+/// connection are then the same as when the peer connects to us, which lets
+/// either side use it in both directions.
+pub async fn connect_tcp_from(host: &str, local: SocketAddr) -> Result<TcpStream, String> {
+    info!("Connecting host: {} from {}", host, local);
+    let remote = lookup_host(host)
+        .await
+        .map_err(|error| format!("Could not resolve {}: {}", host, error))?
+        .find(|remote| remote.is_ipv4() == local.is_ipv4())
+        .ok_or(format!("No address of {} to reach from {}", host, local))?;
+    let socket = reusable_socket(local)
+        .and_then(|socket| socket.bind(local).map(|()| socket))
+        .map_err(|error| format!("Could not bind {}: {}", local, error))?;
+    let stream = socket
+        .connect(remote)
+        .await
+        .map_err(|error| format!("Could not connect to {}: {}", host, error))?;
+    stream
+        .set_nodelay(true)
+        .map_err(|error| format!("Could not configure connection to {}: {}", host, error))?;
+    Ok(stream)
+}
+
+/// Listens on `address`, which connections opened with `connect_tcp_from`
+/// can share.
+pub fn listen_tcp(address: SocketAddr) -> Result<TcpListener, String> {
+    reusable_socket(address)
+        .and_then(|socket| {
+            socket.bind(address)?;
+            socket.listen(1024)
+        })
+        .map_err(|error| format!("Could not listen on {}: {}", address, error))
+}
+
+fn reusable_socket(address: SocketAddr) -> io::Result<TcpSocket> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    Ok(socket)
+}
+
+/// Connects to the Unix domain socket at `path`.
+#[cfg(unix)]
+pub async fn connect_unix(path: &str) -> Result<UnixStream, String> {
+    info!("Connecting socket: {}", path);
+    UnixStream::connect(path)
+        .await
+        .map_err(|error| format!("Could not connect to {}: {}", path, error))
+}
+
+/// Both ends of an in-memory pipe.
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    io::duplex(DUPLEX_BUFFER_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::{self, MINI_PROTOCOL_ID_HANDSHAKE};
+    use crate::mux::{Mode, Mux};
+
+    async fn handshake<B: Bearer, C: Bearer>(client: B, server: C) {
+        let protocols = [MINI_PROTOCOL_ID_HANDSHAKE];
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &protocols);
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &protocols);
+        let mut server = responder.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+        let server =
+            tokio::spawn(
+                async move { handshake::accept(&mut server, &vec![13, 14], 2, false).await },
+            );
+        let mut client = initiator.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+        let response = handshake::propose(&mut client, &vec![13], 2, false, false)
+            .await
+            .unwrap();
+        assert_eq!(response.accepted_version(), Some(13));
+        assert_eq!(server.await.unwrap().map(|(version, _)| version), Ok(13));
+    }
+
+    #[tokio::test]
+    async fn handshake_over_bearers() {
+        let (client, server) = duplex();
+        handshake(client, server).await;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (client, server) = tokio::join!(connect_tcp(&address), listener.accept());
+        handshake(client.unwrap(), server.unwrap().0).await;
+
+        #[cfg(unix)]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("node.socket");
+            let listener = tokio::net::UnixListener::bind(&path).unwrap();
+            let path = path.to_str().unwrap();
+            let (client, server) = tokio::join!(connect_unix(path), listener.accept());
+            handshake(client.unwrap(), server.unwrap().0).await;
+        }
+    }
+}
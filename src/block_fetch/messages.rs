@@ -0,0 +1,99 @@
+use crate::chain_sync::{point_from_value, point_to_value};
+use crate::storage::Point;
+use ciborium::Value;
+
+// 3.8 Block-Fetch mini-protocol: blocks are sent as cardano-node stores
+// them, tagged with their era and wrapped in CBOR in CBOR (tag 24).
+
+pub const MINI_PROTOCOL_ID_BLOCK_FETCH: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // MsgRequestRange, both points inclusive
+    RequestRange(Point, Point),
+    // MsgClientDone
+    ClientDone,
+    // MsgStartBatch
+    StartBatch,
+    // MsgNoBlocks
+    NoBlocks,
+    // MsgBlock
+    Block(Vec<u8>),
+    // MsgBatchDone
+    BatchDone,
+}
+
+impl Message {
+    pub fn to_value(&self) -> Value {
+        match self {
+            Message::RequestRange(from, to) => Value::Array(vec![
+                Value::from(0),
+                point_to_value(from),
+                point_to_value(to),
+            ]),
+            Message::ClientDone => Value::Array(vec![Value::from(1)]),
+            Message::StartBatch => Value::Array(vec![Value::from(2)]),
+            Message::NoBlocks => Value::Array(vec![Value::from(3)]),
+            Message::Block(block) => Value::Array(vec![
+                Value::from(4),
+                Value::Tag(24, Box::new(Value::Bytes(block.clone()))),
+            ]),
+            Message::BatchDone => Value::Array(vec![Value::from(5)]),
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Result<Message, String> {
+        let array = value
+            .as_array()
+            .ok_or("Could not convert Message into array")?;
+        let index = array
+            .first()
+            .and_then(|index| index.as_integer())
+            .ok_or("No index found at message index 0")?;
+        let field = |position: usize| {
+            array
+                .get(position)
+                .ok_or(format!("No value found at message index {}", position))
+        };
+        match i128::from(index) {
+            0 => Ok(Message::RequestRange(
+                point_from_value(field(1)?)?,
+                point_from_value(field(2)?)?,
+            )),
+            1 => Ok(Message::ClientDone),
+            2 => Ok(Message::StartBatch),
+            3 => Ok(Message::NoBlocks),
+            4 => match field(1)? {
+                Value::Tag(24, block) => Ok(Message::Block(
+                    block
+                        .as_bytes()
+                        .ok_or("Block: Expect bytes in tag 24")?
+                        .clone(),
+                )),
+                _ => Err("Block: Expect tag 24".to_owned()),
+            },
+            5 => Ok(Message::BatchDone),
+            index => Err(format!("Message: Do not expect any other index {}!", index)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::RequestRange(Point::Origin, Point::Specific(9, [1; 32])),
+            Message::ClientDone,
+            Message::StartBatch,
+            Message::NoBlocks,
+            // An empty Conway block, tagged 7
+            Message::Block(vec![0x82, 0x07, 0x80]),
+            Message::BatchDone,
+        ] {
+            assert_eq!(Message::from_value(&message.to_value()).unwrap(), message);
+        }
+    }
+}
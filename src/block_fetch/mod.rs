@@ -0,0 +1,9 @@
+mod messages;
+#[cfg(feature = "storage")]
+mod server;
+mod workflows;
+
+pub use self::messages::{Message, MINI_PROTOCOL_ID_BLOCK_FETCH};
+#[cfg(feature = "storage")]
+pub use self::server::serve;
+pub use self::workflows::{done, fetch_range};
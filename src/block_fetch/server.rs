@@ -0,0 +1,43 @@
+use super::{workflows::recv, Message};
+use crate::mux::Channel;
+use crate::storage::ChainDb;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+/// Serves ranges of the selected chain of `chain_db` until the client is
+/// done or the connection closes.
+pub async fn serve(channel: &mut Channel, chain_db: Arc<Mutex<ChainDb>>) -> Result<(), String> {
+    loop {
+        let (from, to) = match recv(channel).await? {
+            Message::RequestRange(from, to) => (from, to),
+            Message::ClientDone => {
+                info!("Block-fetch client done");
+                return Ok(());
+            }
+            message => return Err(format!("Block-fetch server: Unexpected {:?}", message)),
+        };
+        let mut iterator = match chain_db.lock().await.stream(&from, &to) {
+            Ok(iterator) => iterator,
+            Err(error) => {
+                debug!("No blocks from {:?} to {:?}: {}", from, to, error);
+                channel.send_value(&Message::NoBlocks.to_value())?;
+                continue;
+            }
+        };
+        channel.send_value(&Message::StartBatch.to_value())?;
+        // The lock is taken per block so that other connections are served
+        // in between, and blocks are queued as fast as the client reads them
+        loop {
+            channel.writable().await;
+            let next = iterator
+                .next(&*chain_db.lock().await)
+                .map_err(|e| e.to_string())?;
+            match next {
+                Some((_, block)) => channel.send_value(&Message::Block(block).to_value())?,
+                None => break,
+            }
+        }
+        channel.send_value(&Message::BatchDone.to_value())?;
+    }
+}
@@ -0,0 +1,37 @@
+use super::Message;
+use crate::mux::Channel;
+use crate::storage::Point;
+use tracing::debug;
+
+pub(super) async fn recv(channel: &mut Channel) -> Result<Message, String> {
+    let message = Message::from_value(&channel.recv_value().await?)?;
+    debug!("Block-fetch received {:?}", message);
+    Ok(message)
+}
+
+/// Fetches the blocks from `from` to `to`, both inclusive. An empty list
+/// means the server does not have the range.
+pub async fn fetch_range(
+    channel: &mut Channel,
+    from: &Point,
+    to: &Point,
+) -> Result<Vec<Vec<u8>>, String> {
+    channel.send_value(&Message::RequestRange(*from, *to).to_value())?;
+    match recv(channel).await? {
+        Message::StartBatch => {}
+        Message::NoBlocks => return Ok(vec![]),
+        message => return Err(format!("Expected batch, got {:?}", message)),
+    }
+    let mut blocks = vec![];
+    loop {
+        match recv(channel).await? {
+            Message::Block(block) => blocks.push(block),
+            Message::BatchDone => return Ok(blocks),
+            message => return Err(format!("Expected block, got {:?}", message)),
+        }
+    }
+}
+
+pub fn done(channel: &Channel) -> Result<(), String> {
+    channel.send_value(&Message::ClientDone.to_value())
+}
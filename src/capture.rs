@@ -0,0 +1,411 @@
+// Recorded sessions. A `Capture` bearer writes every segment passing over
+// the bearer it wraps to a file, a `Replay` bearer plays such a file back to
+// the client that recorded it, so real traffic can be kept as test fixtures.
+//
+// The file is the sequence of segments in the order they were seen, each
+// preceded by one byte for its direction, 0 sent and 1 received, and by the
+// time it was captured, in microseconds since the UNIX epoch as 8 bytes big
+// endian. Segments keep their header, with the sender's timestamp, mode bit,
+// mini-protocol id and length.
+
+use crate::bearer::{duplex, Bearer};
+use crate::mux::{read_segment, SegmentHeader, SEGMENT_HEADER_SIZE};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    fs::File,
+    io::{
+        self, AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream, ReadHalf, WriteHalf,
+    },
+    sync::Mutex,
+};
+use tracing::{debug, warn};
+
+const CAPTURED_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    // Written by the recording side
+    Sent,
+    // Read by the recording side
+    Received,
+}
+
+/// One segment of a recorded session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub direction: Direction,
+    // Microseconds since the UNIX epoch
+    pub captured: u64,
+    pub header: SegmentHeader,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![match self.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }];
+        bytes.extend_from_slice(&self.captured.to_be_bytes());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+/// Decodes the records of a session file.
+pub fn parse_records(mut bytes: &[u8]) -> Result<Vec<Record>, String> {
+    let mut records = vec![];
+    while let Some((&direction, rest)) = bytes.split_first() {
+        let direction = match direction {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            byte => {
+                return Err(format!(
+                    "Invalid direction {} of record {}",
+                    byte,
+                    records.len()
+                ))
+            }
+        };
+        let captured: &[u8; CAPTURED_SIZE] = rest
+            .get(..CAPTURED_SIZE)
+            .and_then(|captured| captured.try_into().ok())
+            .ok_or(format!(
+                "Truncated capture time of record {}",
+                records.len()
+            ))?;
+        let rest = &rest[CAPTURED_SIZE..];
+        let header: &[u8; SEGMENT_HEADER_SIZE] = rest
+            .get(..SEGMENT_HEADER_SIZE)
+            .and_then(|header| header.try_into().ok())
+            .ok_or(format!("Truncated header of record {}", records.len()))?;
+        let header = SegmentHeader::from_bytes(header);
+        let rest = &rest[SEGMENT_HEADER_SIZE..];
+        let payload = rest
+            .get(..header.length as usize)
+            .ok_or(format!("Truncated payload of record {}", records.len()))?;
+        records.push(Record {
+            direction,
+            captured: u64::from_be_bytes(*captured),
+            header,
+            payload: payload.to_vec(),
+        });
+        bytes = &rest[header.length as usize..];
+    }
+    Ok(records)
+}
+
+/// Reads the records of the session file at `path`.
+pub fn read_records(path: &Path) -> Result<Vec<Record>, String> {
+    let bytes =
+        std::fs::read(path).map_err(|error| format!("Could not read {:?}: {}", path, error))?;
+    parse_records(&bytes)
+}
+
+/// Wraps a bearer and records the segments sent and received over it.
+pub struct Capture<B> {
+    bearer: B,
+    file: File,
+}
+
+impl<B: Bearer> Capture<B> {
+    /// Records to `path`, which is created or truncated.
+    pub fn create(bearer: B, path: &Path) -> Result<Capture<B>, String> {
+        let file = std::fs::File::create(path)
+            .map_err(|error| format!("Could not create {:?}: {}", path, error))?;
+        Ok(Capture {
+            bearer,
+            file: File::from_std(file),
+        })
+    }
+}
+
+impl<B: Bearer> Bearer for Capture<B> {
+    type Read = ReadHalf<DuplexStream>;
+    type Write = WriteHalf<DuplexStream>;
+
+    /// Starts copying segments between the wrapped bearer and the returned
+    /// halves, which needs a runtime.
+    fn split(self) -> (Self::Read, Self::Write) {
+        let (ours, theirs) = duplex();
+        let (read, write) = self.bearer.split();
+        let (from_us, to_us) = io::split(theirs);
+        let file = Arc::new(Mutex::new(BufWriter::new(self.file)));
+        tokio::spawn(copy(from_us, write, Direction::Sent, file.clone()));
+        tokio::spawn(copy(read, to_us, Direction::Received, file));
+        io::split(ours)
+    }
+}
+
+/// Copies segments from `read` to `write` until `read` ends, then shuts
+/// `write` down. Segments are recorded before they are passed on, so what
+/// either side has seen is in the file.
+async fn copy<R, W>(
+    mut read: R,
+    mut write: W,
+    direction: Direction,
+    file: Arc<Mutex<BufWriter<File>>>,
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let (header, payload) = match read_segment(&mut read).await {
+            Ok(Some(segment)) => segment,
+            Ok(None) => break,
+            Err(error) => {
+                debug!("Capture: {}", error);
+                break;
+            }
+        };
+        let captured = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let record = Record {
+            direction,
+            captured,
+            header,
+            payload,
+        };
+        let bytes = record.to_bytes();
+        {
+            let mut file = file.lock().await;
+            let written = match file.write_all(&bytes).await {
+                Ok(()) => file.flush().await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = written {
+                warn!("Could not record segment: {}", error);
+            }
+        }
+        if let Err(error) = write.write_all(&bytes[1 + CAPTURED_SIZE..]).await {
+            debug!("Capture: could not copy segment: {}", error);
+            return;
+        }
+        let _ = write.flush().await;
+    }
+    let _ = write.shutdown().await;
+}
+
+/// Plays a recorded session back: received segments are sent in order, and
+/// before each the segments the recording side sent up to that point are
+/// awaited from the client. Playback stops once the recording ends, or when
+/// the client sends a segment of another mini-protocol than recorded.
+pub struct Replay {
+    records: Vec<Record>,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Replay {
+        Replay { records }
+    }
+
+    pub fn open(path: &Path) -> Result<Replay, String> {
+        Ok(Replay::new(read_records(path)?))
+    }
+}
+
+impl Bearer for Replay {
+    type Read = ReadHalf<DuplexStream>;
+    type Write = WriteHalf<DuplexStream>;
+
+    /// Starts the playback, which needs a runtime.
+    fn split(self) -> (Self::Read, Self::Write) {
+        let (ours, theirs) = duplex();
+        tokio::spawn(play(self.records, theirs));
+        io::split(ours)
+    }
+}
+
+async fn play(records: Vec<Record>, stream: DuplexStream) {
+    let (mut read, mut write) = io::split(stream);
+    for (i, record) in records.into_iter().enumerate() {
+        match record.direction {
+            Direction::Received => {
+                if let Err(error) = write
+                    .write_all(&record.to_bytes()[1 + CAPTURED_SIZE..])
+                    .await
+                {
+                    debug!("Replay: could not send record {}: {}", i, error);
+                    return;
+                }
+            }
+            Direction::Sent => match read_segment(&mut read).await {
+                Ok(Some((header, _))) if header.protocol == record.header.protocol => {}
+                Ok(Some((header, _))) => {
+                    warn!(
+                        "Replay: record {} was sent on protocol {}, got {}",
+                        i, record.header.protocol, header.protocol
+                    );
+                    break;
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    debug!("Replay: {}", error);
+                    break;
+                }
+            },
+        }
+    }
+    let _ = write.shutdown().await;
+    // The client may still send, e.g. on closing
+    while let Ok(Some(_)) = read_segment(&mut read).await {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bearer::connect_tcp;
+    use crate::block_fetch::{self, MINI_PROTOCOL_ID_BLOCK_FETCH};
+    use crate::chain_sync::{self, MINI_PROTOCOL_ID_CHAIN_SYNC};
+    use crate::connection::PeerConnection;
+    use crate::handshake::{self, MINI_PROTOCOL_ID_HANDSHAKE};
+    use crate::mock::{MockNode, Reply, Script};
+    use crate::storage::Point;
+    use ciborium::{from_reader, Value};
+
+    // `sync --capture` against `serve` with a database of three Conway
+    // blocks, on preprod
+    const LOCAL_SYNC: &[u8] = include_bytes!("../fixtures/sessions/local-sync.session");
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let node = MockNode::start(Script::new(&[13], 2, Reply::Accept).with_segment_size(5))
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("handshake.session");
+
+        let stream = connect_tcp(&node.host()).await.unwrap();
+        let capture = Capture::create(stream, &path).unwrap();
+        let connection = PeerConnection::initiate("mock", capture, 2, &vec![13, 14], &[])
+            .await
+            .unwrap();
+        connection.close().await;
+        drop(node);
+
+        let records = read_records(&path).unwrap();
+        let directions: Vec<Direction> = records.iter().map(|r| r.direction).collect();
+        assert_eq!(directions[0], Direction::Sent);
+        assert!(directions[1..].iter().all(|&d| d == Direction::Received));
+        assert!(records.len() > 2);
+        assert!(records
+            .iter()
+            .all(|r| r.header.protocol == MINI_PROTOCOL_ID_HANDSHAKE
+                && r.header.responder == (r.direction == Direction::Received)
+                && r.header.length as usize == r.payload.len()));
+        // Segments read one after the other were captured in that order
+        let received = records
+            .iter()
+            .filter(|r| r.direction == Direction::Received);
+        let times: Vec<u64> = received.map(|r| r.captured).collect();
+        assert!(times[0] > 0 && times.windows(2).all(|t| t[0] <= t[1]));
+        let bytes: Vec<u8> = records.iter().flat_map(Record::to_bytes).collect();
+        assert_eq!(parse_records(&bytes).unwrap(), records);
+        assert!(parse_records(&bytes[..bytes.len() - 1]).is_err());
+
+        let replay = Replay::open(&path).unwrap();
+        let connection = PeerConnection::initiate("replay", replay, 2, &vec![13, 14], &[])
+            .await
+            .unwrap();
+        assert_eq!(connection.version(), 13);
+        connection.close().await;
+
+        // Nothing is played back to a client that deviates from the recording
+        let mut records = records;
+        records[0].header.protocol = 2;
+        let error = PeerConnection::initiate("replay", Replay::new(records), 2, &vec![13], &[])
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, "Connection closed");
+    }
+
+    #[tokio::test]
+    async fn recorded_session() {
+        let records = parse_records(LOCAL_SYNC).unwrap();
+        let mut messages = vec![];
+        for record in &records {
+            let value: Value = from_reader(&record.payload[..]).unwrap();
+            let message = match record.header.protocol {
+                MINI_PROTOCOL_ID_HANDSHAKE => {
+                    format!("{:?}", handshake::Message::from_value(value).unwrap())
+                }
+                MINI_PROTOCOL_ID_CHAIN_SYNC => {
+                    format!("{:?}", chain_sync::Message::from_value(&value).unwrap())
+                }
+                MINI_PROTOCOL_ID_BLOCK_FETCH => {
+                    format!("{:?}", block_fetch::Message::from_value(&value).unwrap())
+                }
+                protocol => panic!("Unexpected protocol {}", protocol),
+            };
+            let name = message.split('(').next().unwrap().to_owned();
+            messages.push((record.direction, record.header.responder, name));
+        }
+        let sent = |name: &str| (Direction::Sent, false, name.to_owned());
+        let received = |name: &str| (Direction::Received, true, name.to_owned());
+        assert_eq!(
+            messages,
+            [
+                sent("ProposeVersions"),
+                received("AcceptVersion"),
+                sent("FindIntersect"),
+                received("IntersectFound"),
+                sent("RequestNext"),
+                received("RollForward"),
+                sent("RequestNext"),
+                received("RollForward"),
+                sent("RequestNext"),
+                received("RollForward"),
+                sent("RequestRange"),
+                received("StartBatch"),
+                received("Block"),
+                received("Block"),
+                received("Block"),
+                received("BatchDone"),
+                // Sent at the tip of the server, while the client has agency
+                sent("Done"),
+                sent("ClientDone"),
+                // The connection was duplex, the server ends the initiator
+                // side it did not use
+                (Direction::Received, false, "Done".to_owned()),
+                (Direction::Received, false, "ClientDone".to_owned()),
+            ]
+        );
+
+        // The client runs against the recording as it did against the server
+        let protocols = [MINI_PROTOCOL_ID_CHAIN_SYNC, MINI_PROTOCOL_ID_BLOCK_FETCH];
+        let replay = Replay::new(records);
+        let connection = PeerConnection::initiate("replay", replay, 1, &vec![13, 14], &protocols)
+            .await
+            .unwrap();
+        assert_eq!(connection.version(), 14);
+        let handle = connection.protocol(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        let mut channel = handle.lock().await;
+        let (point, tip) = chain_sync::find_intersect(&mut channel, &[Point::Origin])
+            .await
+            .unwrap();
+        assert_eq!(point, Some(Point::Origin));
+        assert_eq!(tip.block_number, 3);
+        let mut points = vec![];
+        for _ in 0..3 {
+            match chain_sync::request_next(&mut channel).await.unwrap() {
+                chain_sync::Message::RollForward(header, _) => {
+                    points.push(header.summary().unwrap().info.point())
+                }
+                message => panic!("Unexpected {:?}", message),
+            }
+        }
+        assert_eq!(points[2], tip.point);
+        drop(channel);
+        let handle = connection.protocol(MINI_PROTOCOL_ID_BLOCK_FETCH).unwrap();
+        let blocks = block_fetch::fetch_range(&mut *handle.lock().await, &points[0], &points[2])
+            .await
+            .unwrap();
+        assert_eq!(blocks.len(), 3);
+        connection.close().await;
+    }
+}
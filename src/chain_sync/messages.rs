@@ -0,0 +1,340 @@
+use crate::consensus::Era;
+use crate::crypto::Hash32;
+use crate::storage::{parse_cardano_header, split_era, BlockInfo, BlockSummary, Point, Tip};
+use ciborium::Value;
+
+// 3.7 Chain-Sync mini-protocol, node-to-node instance: the server sends
+// block headers.
+
+pub const MINI_PROTOCOL_ID_CHAIN_SYNC: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // MsgRequestNext
+    RequestNext,
+    // MsgAwaitReply
+    AwaitReply,
+    // MsgRollForward
+    RollForward(WrappedHeader, Tip),
+    // MsgRollBackward
+    RollBackward(Point, Tip),
+    // MsgFindIntersect
+    FindIntersect(Vec<Point>),
+    // MsgIntersectFound
+    IntersectFound(Point, Tip),
+    // MsgIntersectNotFound
+    IntersectNotFound(Tip),
+    // MsgDone
+    Done,
+}
+
+impl Message {
+    pub fn to_value(&self) -> Value {
+        let index = |index: u8, mut values: Vec<Value>| {
+            values.insert(0, Value::from(index));
+            Value::Array(values)
+        };
+        match self {
+            Message::RequestNext => index(0, vec![]),
+            Message::AwaitReply => index(1, vec![]),
+            Message::RollForward(header, tip) => {
+                index(2, vec![header.to_value(), tip_to_value(tip)])
+            }
+            Message::RollBackward(point, tip) => {
+                index(3, vec![point_to_value(point), tip_to_value(tip)])
+            }
+            Message::FindIntersect(points) => index(
+                4,
+                vec![Value::Array(points.iter().map(point_to_value).collect())],
+            ),
+            Message::IntersectFound(point, tip) => {
+                index(5, vec![point_to_value(point), tip_to_value(tip)])
+            }
+            Message::IntersectNotFound(tip) => index(6, vec![tip_to_value(tip)]),
+            Message::Done => index(7, vec![]),
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Result<Message, String> {
+        let array = value
+            .as_array()
+            .ok_or("Could not convert Message into array")?;
+        let index = array
+            .first()
+            .and_then(|index| index.as_integer())
+            .ok_or("No index found at message index 0")?;
+        let field = |position: usize| {
+            array
+                .get(position)
+                .ok_or(format!("No value found at message index {}", position))
+        };
+        match i128::from(index) {
+            0 => Ok(Message::RequestNext),
+            1 => Ok(Message::AwaitReply),
+            2 => Ok(Message::RollForward(
+                WrappedHeader::from_value(field(1)?)?,
+                tip_from_value(field(2)?)?,
+            )),
+            3 => Ok(Message::RollBackward(
+                point_from_value(field(1)?)?,
+                tip_from_value(field(2)?)?,
+            )),
+            4 => Ok(Message::FindIntersect(
+                field(1)?
+                    .as_array()
+                    .ok_or("Could not convert points into array")?
+                    .iter()
+                    .map(point_from_value)
+                    .collect::<Result<_, _>>()?,
+            )),
+            5 => Ok(Message::IntersectFound(
+                point_from_value(field(1)?)?,
+                tip_from_value(field(2)?)?,
+            )),
+            6 => Ok(Message::IntersectNotFound(tip_from_value(field(1)?)?)),
+            7 => Ok(Message::Done),
+            index => Err(format!("Message: Do not expect any other index {}!", index)),
+        }
+    }
+}
+
+/// A header tagged with its era, `[era_index, header]`. Headers of Shelley
+/// based eras are CBOR in CBOR (tag 24). Byron headers come as
+/// `[[tag, block_size], #6.24(header)]`, tag 0 marking epoch boundary blocks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrappedHeader {
+    pub era: Era,
+    pub is_ebb: bool,
+    // Size of the Byron block, 0 for other eras
+    pub block_size: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl WrappedHeader {
+    /// Header of a block as cardano-node stores it. Blocks are tagged
+    /// differently from headers, see `Era::from_block_tag`.
+    pub fn from_block(block: &[u8], info: &BlockInfo) -> Result<WrappedHeader, String> {
+        let (era, is_ebb, inner) = split_era(block)?;
+        let bytes = block
+            .get(info.header_offset as usize..)
+            .and_then(|header| header.get(..info.header_size as usize))
+            .ok_or("Header out of block bounds")?;
+        let block_size = match era {
+            Era::Byron => u32::try_from(inner.len()).map_err(|_| "Byron block too large")?,
+            _ => 0,
+        };
+        Ok(WrappedHeader {
+            era,
+            is_ebb,
+            block_size,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    pub fn summary(&self) -> Result<BlockSummary, String> {
+        parse_cardano_header(self.era, self.is_ebb, &self.bytes)
+    }
+
+    pub fn to_value(&self) -> Value {
+        let bytes = Value::Tag(24, Box::new(Value::Bytes(self.bytes.clone())));
+        let header = match self.era {
+            Era::Byron => Value::Array(vec![
+                Value::Array(vec![
+                    Value::from(if self.is_ebb { 0 } else { 1 }),
+                    Value::from(self.block_size),
+                ]),
+                bytes,
+            ]),
+            _ => bytes,
+        };
+        Value::Array(vec![Value::from(self.era.index()), header])
+    }
+
+    pub fn from_value(value: &Value) -> Result<WrappedHeader, String> {
+        let (era, header) = match value.as_array().map(|array| &array[..]) {
+            Some([era, header]) => (era, header),
+            _ => return Err("Header: Expect [era, header]".to_owned()),
+        };
+        let era = era
+            .as_integer()
+            .and_then(|era| u64::try_from(era).ok())
+            .ok_or("Could not convert era to integer")?;
+        let era = Era::from_index(era)?;
+        let (is_ebb, block_size, header) = match era {
+            Era::Byron => {
+                let fields = header.as_array().map(|array| &array[..]);
+                let (tag, block_size, header) = match fields {
+                    Some([info, header]) => match info.as_array().map(|array| &array[..]) {
+                        Some([tag, block_size]) => (tag, block_size, header),
+                        _ => return Err("Byron header: Expect [tag, size]".to_owned()),
+                    },
+                    _ => return Err("Byron header: Expect [[tag, size], header]".to_owned()),
+                };
+                (integer(tag)? == 0, integer(block_size)? as u32, header)
+            }
+            _ => (false, 0, header),
+        };
+        let bytes = match header {
+            Value::Tag(24, bytes) => bytes.as_bytes().ok_or("Header: Expect bytes in tag 24")?,
+            _ => return Err("Header: Expect tag 24".to_owned()),
+        };
+        Ok(WrappedHeader {
+            era,
+            is_ebb,
+            block_size,
+            bytes: bytes.clone(),
+        })
+    }
+}
+
+fn integer(value: &Value) -> Result<u64, String> {
+    value
+        .as_integer()
+        .and_then(|value| u64::try_from(value).ok())
+        .ok_or(format!("Could not convert {:?} to integer", value))
+}
+
+/// `[]` for origin, `[slot, hash]` otherwise.
+pub fn point_to_value(point: &Point) -> Value {
+    match point {
+        Point::Origin => Value::Array(vec![]),
+        Point::Specific(slot, hash) => {
+            Value::Array(vec![Value::from(*slot), Value::Bytes(hash.to_vec())])
+        }
+    }
+}
+
+pub fn point_from_value(value: &Value) -> Result<Point, String> {
+    match value.as_array().map(|array| &array[..]) {
+        Some([]) => Ok(Point::Origin),
+        Some([slot, hash]) => {
+            let hash: Hash32 = hash
+                .as_bytes()
+                .and_then(|hash| hash.as_slice().try_into().ok())
+                .ok_or("Point: Expect 32 bytes hash")?;
+            Ok(Point::Specific(integer(slot)?, hash))
+        }
+        _ => Err(format!("Point: Do not expect {:?}", value)),
+    }
+}
+
+/// `[point, block_number]`
+pub fn tip_to_value(tip: &Tip) -> Value {
+    Value::Array(vec![
+        point_to_value(&tip.point),
+        Value::from(tip.block_number),
+    ])
+}
+
+pub fn tip_from_value(value: &Value) -> Result<Tip, String> {
+    match value.as_array().map(|array| &array[..]) {
+        Some([point, block_number]) => Ok(Tip {
+            point: point_from_value(point)?,
+            block_number: integer(block_number)?,
+        }),
+        _ => Err(format!("Tip: Do not expect {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let tip = Tip {
+            point: Point::Specific(42, [3; 32]),
+            block_number: 7,
+        };
+        let header = WrappedHeader {
+            era: Era::Babbage,
+            is_ebb: false,
+            block_size: 0,
+            bytes: vec![0x80],
+        };
+        let byron_header = WrappedHeader {
+            era: Era::Byron,
+            is_ebb: true,
+            block_size: 1234,
+            bytes: vec![0x80],
+        };
+        for message in [
+            Message::RequestNext,
+            Message::AwaitReply,
+            Message::RollForward(header.clone(), tip),
+            Message::RollForward(byron_header, tip),
+            Message::RollBackward(Point::Origin, tip),
+            Message::FindIntersect(vec![tip.point, Point::Origin]),
+            Message::IntersectFound(tip.point, tip),
+            Message::IntersectNotFound(tip),
+            Message::Done,
+        ] {
+            assert_eq!(Message::from_value(&message.to_value()).unwrap(), message);
+        }
+
+        let value = Message::RollForward(header, tip).to_value();
+        assert_eq!(
+            value,
+            Value::Array(vec![
+                Value::from(2),
+                Value::Array(vec![
+                    Value::from(5),
+                    Value::Tag(24, Box::new(Value::Bytes(vec![0x80])))
+                ]),
+                Value::Array(vec![
+                    Value::Array(vec![Value::from(42), Value::Bytes(vec![3; 32])]),
+                    Value::from(7)
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn headers_of_stored_blocks() {
+        use crate::storage::parse_cardano_block;
+        use ciborium::into_writer;
+
+        let encode = |value: &Value| {
+            let mut bytes = vec![];
+            into_writer(value, &mut bytes).unwrap();
+            bytes
+        };
+        // Conway blocks are tagged 7, their headers carry era index 6
+        let header = Value::Array(vec![
+            Value::Array(vec![Value::from(1), Value::from(10), Value::Null]),
+            Value::Bytes(vec![0; 4]),
+        ]);
+        let block = encode(&Value::Array(vec![
+            Value::from(7),
+            Value::Array(vec![
+                header.clone(),
+                Value::Array(vec![]),
+                Value::Array(vec![]),
+                Value::Map(vec![]),
+                Value::Array(vec![]),
+            ]),
+        ]));
+        let info = parse_cardano_block(&block).unwrap().info;
+        let wrapped = WrappedHeader::from_block(&block, &info).unwrap();
+        assert_eq!(wrapped.era, Era::Conway);
+        assert_eq!(wrapped.bytes, encode(&header));
+        assert_eq!(wrapped.to_value().as_array().unwrap()[0], Value::from(6));
+
+        // Byron epoch boundary blocks are tagged 0, like their headers
+        let ebb_header = Value::Array(vec![
+            Value::from(2),
+            Value::Bytes(vec![1; 32]),
+            Value::Bytes(vec![]),
+            Value::Array(vec![Value::from(0), Value::Array(vec![Value::from(0)])]),
+            Value::Array(vec![]),
+        ]);
+        let ebb = Value::Array(vec![ebb_header, Value::Array(vec![]), Value::Array(vec![])]);
+        let block = encode(&Value::Array(vec![Value::from(0), ebb.clone()]));
+        let info = parse_cardano_block(&block).unwrap().info;
+        let wrapped = WrappedHeader::from_block(&block, &info).unwrap();
+        assert_eq!((wrapped.era, wrapped.is_ebb), (Era::Byron, true));
+        assert_eq!(wrapped.block_size as usize, encode(&ebb).len());
+        assert_eq!(wrapped.to_value().as_array().unwrap()[0], Value::from(0));
+        assert_eq!(wrapped.summary().unwrap().info.hash, info.hash);
+    }
+}
@@ -0,0 +1,11 @@
+mod messages;
+#[cfg(feature = "storage")]
+mod server;
+mod workflows;
+
+pub use self::messages::{
+    point_from_value, point_to_value, Message, WrappedHeader, MINI_PROTOCOL_ID_CHAIN_SYNC,
+};
+#[cfg(feature = "storage")]
+pub use self::server::serve;
+pub use self::workflows::{find_intersect, recv, request_next};
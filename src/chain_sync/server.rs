@@ -0,0 +1,203 @@
+use super::{recv, Message, WrappedHeader};
+use crate::mux::Channel;
+use crate::storage::{ChainDb, ChainEvent, Follower, FollowerUpdate, Point};
+use std::sync::Arc;
+use tokio::{
+    select,
+    sync::{broadcast::error::RecvError, Mutex},
+};
+use tracing::info;
+
+/// Serves the selected chain of `chain_db` to a client until it is done or
+/// the connection closes. Clients start at origin unless they find an
+/// intersection.
+pub async fn serve(channel: &mut Channel, chain_db: Arc<Mutex<ChainDb>>) -> Result<(), String> {
+    let (mut follower, mut events) = {
+        let chain_db = chain_db.lock().await;
+        (
+            chain_db
+                .follower(Point::Origin)
+                .map_err(|e| e.to_string())?,
+            chain_db.subscribe(),
+        )
+    };
+    loop {
+        match recv(channel).await? {
+            Message::FindIntersect(points) => {
+                let chain_db = chain_db.lock().await;
+                let tip = chain_db.tip();
+                let message = match chain_db
+                    .find_intersect(&points)
+                    .map_err(|e| e.to_string())?
+                {
+                    Some(point) => {
+                        follower = chain_db.follower(point).map_err(|e| e.to_string())?;
+                        Message::IntersectFound(point, tip)
+                    }
+                    None => Message::IntersectNotFound(tip),
+                };
+                channel.send_value(&message.to_value())?;
+            }
+            Message::RequestNext => {
+                if let Some(message) = next_update(&chain_db, &mut follower).await? {
+                    channel.send_value(&message.to_value())?;
+                    continue;
+                }
+                channel.send_value(&Message::AwaitReply.to_value())?;
+                loop {
+                    // The client has no agency until we reply, it can only
+                    // go away meanwhile
+                    let event = select! {
+                        event = events.recv() => event,
+                        message = recv(channel) => {
+                            return Err(format!(
+                                "Chain-sync server: Unexpected {:?} while awaiting",
+                                message?
+                            ))
+                        }
+                    };
+                    match event {
+                        Ok(ChainEvent::TipChanged { .. }) | Err(RecvError::Lagged(_)) => {}
+                        Ok(ChainEvent::ImmutableTipChanged(_)) => continue,
+                        Err(RecvError::Closed) => return Err("ChainDB closed".to_owned()),
+                    }
+                    if let Some(message) = next_update(&chain_db, &mut follower).await? {
+                        channel.send_value(&message.to_value())?;
+                        break;
+                    }
+                }
+            }
+            Message::Done => {
+                info!("Chain-sync client done");
+                return Ok(());
+            }
+            message => return Err(format!("Chain-sync server: Unexpected {:?}", message)),
+        }
+    }
+}
+
+async fn next_update(
+    chain_db: &Mutex<ChainDb>,
+    follower: &mut Follower,
+) -> Result<Option<Message>, String> {
+    let chain_db = chain_db.lock().await;
+    let tip = chain_db.tip();
+    match follower.next(&chain_db).map_err(|e| e.to_string())? {
+        Some(FollowerUpdate::RollForward(info, block)) => Ok(Some(Message::RollForward(
+            WrappedHeader::from_block(&block, &info)?,
+            tip,
+        ))),
+        Some(FollowerUpdate::RollBackward(point)) => Ok(Some(Message::RollBackward(point, tip))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bearer::duplex;
+    use crate::block_fetch::{self, MINI_PROTOCOL_ID_BLOCK_FETCH};
+    use crate::chain_sync::{find_intersect, request_next, MINI_PROTOCOL_ID_CHAIN_SYNC};
+    use crate::mux::{Mode, Mux};
+    use crate::storage::parse_cardano_block;
+    use crate::storage::test_blocks::{chain, point};
+
+    #[tokio::test]
+    async fn serve_chain_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let blocks = chain(None, 6, 0);
+        let mut chain_db = ChainDb::open(dir.path(), 3, parse_cardano_block).unwrap();
+        for block in &blocks[..5] {
+            chain_db.add_block(block).unwrap();
+        }
+        let chain_db = Arc::new(Mutex::new(chain_db));
+
+        let (client, server) = duplex();
+        let protocols = [MINI_PROTOCOL_ID_CHAIN_SYNC, MINI_PROTOCOL_ID_BLOCK_FETCH];
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &protocols);
+        let mut channel = responder.channel(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        let server_chain_sync = tokio::spawn({
+            let chain_db = chain_db.clone();
+            async move { serve(&mut channel, chain_db).await }
+        });
+        let mut channel = responder.channel(MINI_PROTOCOL_ID_BLOCK_FETCH).unwrap();
+        let server_block_fetch = tokio::spawn({
+            let chain_db = chain_db.clone();
+            async move { block_fetch::serve(&mut channel, chain_db).await }
+        });
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &protocols);
+        let mut chain_sync = initiator.channel(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        let mut fetch = initiator.channel(MINI_PROTOCOL_ID_BLOCK_FETCH).unwrap();
+
+        let unknown = Point::Specific(99, [9; 32]);
+        let (intersection, tip) = find_intersect(&mut chain_sync, &[unknown, point(&blocks[1])])
+            .await
+            .unwrap();
+        assert_eq!(intersection, Some(point(&blocks[1])));
+        assert_eq!(tip.point, point(&blocks[4]));
+        for block in &blocks[2..5] {
+            match request_next(&mut chain_sync).await.unwrap() {
+                Message::RollForward(header, _) => {
+                    assert_eq!(header.summary().unwrap().info.point(), point(block))
+                }
+                message => panic!("Unexpected {:?}", message),
+            }
+        }
+        assert_eq!(
+            request_next(&mut chain_sync).await.unwrap(),
+            Message::AwaitReply
+        );
+        chain_db.lock().await.add_block(&blocks[5]).unwrap();
+        match recv(&mut chain_sync).await.unwrap() {
+            Message::RollForward(header, tip) => {
+                assert_eq!(header.summary().unwrap().info.point(), point(&blocks[5]));
+                assert_eq!(tip.block_number, 6);
+            }
+            message => panic!("Unexpected {:?}", message),
+        }
+
+        // The range spans the ImmutableDB and the volatile store
+        let fetched = block_fetch::fetch_range(&mut fetch, &point(&blocks[0]), &point(&blocks[5]))
+            .await
+            .unwrap();
+        assert_eq!(fetched, blocks);
+        let none = block_fetch::fetch_range(&mut fetch, &unknown, &unknown)
+            .await
+            .unwrap();
+        assert!(none.is_empty());
+
+        chain_sync.send_value(&Message::Done.to_value()).unwrap();
+        block_fetch::done(&fetch).unwrap();
+        server_chain_sync.await.unwrap().unwrap();
+        server_block_fetch.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_gone_while_awaiting() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain_db = ChainDb::open(dir.path(), 3, parse_cardano_block).unwrap();
+        let chain_db = Arc::new(Mutex::new(chain_db));
+
+        let (client, server) = duplex();
+        let protocols = [MINI_PROTOCOL_ID_CHAIN_SYNC];
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &protocols);
+        let mut channel = responder.channel(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        let server = tokio::spawn(async move { serve(&mut channel, chain_db).await });
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &protocols);
+        let mut chain_sync = initiator.channel(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        assert_eq!(
+            request_next(&mut chain_sync).await.unwrap(),
+            Message::AwaitReply
+        );
+
+        // Nothing is ever added to the ChainDB, the server ends on the closed
+        // connection alone
+        drop(chain_sync);
+        drop(initiator);
+        let served = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(served.is_err());
+    }
+}
@@ -0,0 +1,36 @@
+use super::Message;
+use crate::mux::Channel;
+use crate::storage::{Point, Tip};
+use tracing::debug;
+
+pub async fn recv(channel: &mut Channel) -> Result<Message, String> {
+    let message = Message::from_value(&channel.recv_value().await?)?;
+    debug!("Chain-sync received {:?}", message);
+    Ok(message)
+}
+
+/// Finds the first of `points` on the server's chain, `None` when the
+/// server has none of them.
+pub async fn find_intersect(
+    channel: &mut Channel,
+    points: &[Point],
+) -> Result<(Option<Point>, Tip), String> {
+    channel.send_value(&Message::FindIntersect(points.to_vec()).to_value())?;
+    match recv(channel).await? {
+        Message::IntersectFound(point, tip) => Ok((Some(point), tip)),
+        Message::IntersectNotFound(tip) => Ok((None, tip)),
+        message => Err(format!("Expected intersection, got {:?}", message)),
+    }
+}
+
+/// Requests the next update. On `AwaitReply` the server is at its tip and
+/// the update follows with `recv` once the server has one.
+pub async fn request_next(channel: &mut Channel) -> Result<Message, String> {
+    channel.send_value(&Message::RequestNext.to_value())?;
+    match recv(channel).await? {
+        message @ (Message::RollForward(..) | Message::RollBackward(..) | Message::AwaitReply) => {
+            Ok(message)
+        }
+        message => Err(format!("Expected update, got {:?}", message)),
+    }
+}
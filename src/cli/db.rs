@@ -0,0 +1,170 @@
+use super::{hex, point_json, point_text, print_json, OutputFormat};
+use cardano_rust_node::ledger::{stake_snapshot_from_json, LedgerPeers};
+use cardano_rust_node::storage::{NodeDb, Point, DEFAULT_DB_PATH};
+use clap::Subcommand;
+use std::path::PathBuf;
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// Summarize a database directory of cardano-node
+    Inspect {
+        #[arg(default_value = DEFAULT_DB_PATH)]
+        dir: PathBuf,
+    },
+    /// List the relays announced by the stake pools registered on the chain
+    Relays {
+        #[arg(default_value = DEFAULT_DB_PATH)]
+        dir: PathBuf,
+        /// Leave out the pools retired by the start of this epoch
+        #[arg(long)]
+        epoch: Option<u64>,
+        /// Output of `cardano-cli query stake-snapshot --all-stake-pools`,
+        /// to show the relative stake of the pools
+        #[arg(long)]
+        stake: Option<PathBuf>,
+    },
+}
+
+pub fn run(command: &DbCommand, output: OutputFormat) -> Result<(), String> {
+    match command {
+        DbCommand::Inspect { dir } => inspect(dir, output),
+        DbCommand::Relays { dir, epoch, stake } => relays(dir, *epoch, stake.as_ref(), output),
+    }
+}
+
+fn inspect(dir: &PathBuf, output: OutputFormat) -> Result<(), String> {
+    let node_db = NodeDb::open(dir).map_err(|e| e.to_string())?;
+    let immutable_tip = node_db.immutable_tip().map_err(|e| e.to_string())?;
+    let volatile_chain = node_db.volatile_chain().map_err(|e| e.to_string())?;
+    let snapshots = node_db.ledger_snapshots().map_err(|e| e.to_string())?;
+
+    match output {
+        OutputFormat::Text => {
+            println!("Database: {:?}", node_db.dir());
+            println!("Protocol magic: {:?}", node_db.protocol_magic());
+            println!("Chunk size: {}", node_db.chunk_size());
+            match &immutable_tip {
+                Some(tip) => println!("Immutable tip: {}", point_text(&tip.point())),
+                None => println!("Immutable tip: origin"),
+            }
+            println!("Volatile blocks: {}", node_db.volatile_len());
+            match volatile_chain.last() {
+                Some(tip) => println!(
+                    "Volatile chain: {} blocks, tip {} block number {}",
+                    volatile_chain.len(),
+                    point_text(&tip.info.point()),
+                    tip.block_number
+                ),
+                None => println!("Volatile chain: empty"),
+            }
+            for snapshot in &snapshots {
+                println!(
+                    "Ledger snapshot: slot {} {:?} {} bytes",
+                    snapshot.slot, snapshot.path, snapshot.size
+                );
+            }
+        }
+        _ => {
+            let json = serde_json::json!({
+                "dir": node_db.dir(),
+                "protocol_magic": node_db.protocol_magic(),
+                "chunk_size": node_db.chunk_size(),
+                "immutable_tip": immutable_tip.map(|tip| point_json(&tip.point())),
+                "volatile_blocks": node_db.volatile_len(),
+                "volatile_chain": volatile_chain.last().map(|tip| serde_json::json!({
+                    "length": volatile_chain.len(),
+                    "tip": point_json(&tip.info.point()),
+                    "block_number": tip.block_number,
+                })),
+                "ledger_snapshots": snapshots.iter().map(|snapshot| serde_json::json!({
+                    "slot": snapshot.slot,
+                    "suffix": snapshot.suffix,
+                    "path": snapshot.path,
+                    "size": snapshot.size,
+                    "backend": snapshot.meta.as_ref().and_then(|meta| meta.backend.clone()),
+                })).collect::<Vec<_>>(),
+            });
+            print_json(&json, output)?;
+        }
+    }
+    Ok(())
+}
+
+// Applies the blocks of the immutable and then the volatile chain
+fn relays(
+    dir: &PathBuf,
+    epoch: Option<u64>,
+    stake: Option<&PathBuf>,
+    output: OutputFormat,
+) -> Result<(), String> {
+    let node_db = NodeDb::open(dir).map_err(|e| e.to_string())?;
+    let mut ledger = LedgerPeers::new();
+    for block in node_db
+        .immutable_blocks(&Point::Origin)
+        .map_err(|e| e.to_string())?
+    {
+        let (info, block) = block.map_err(|e| e.to_string())?;
+        ledger
+            .apply_block(&block)
+            .map_err(|e| format!("Block {}: {}", point_text(&info.point()), e))?;
+    }
+    for summary in node_db.volatile_chain().map_err(|e| e.to_string())? {
+        let point = summary.info.point();
+        let block = node_db
+            .volatile_block(&summary.info.hash)
+            .map_err(|e| e.to_string())?
+            .ok_or(format!("Block {} is missing", point_text(&point)))?;
+        ledger
+            .apply_block(&block)
+            .map_err(|e| format!("Block {}: {}", point_text(&point), e))?;
+    }
+    if let Some(epoch) = epoch {
+        ledger.retire(epoch);
+    }
+    if let Some(path) = stake {
+        let json = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {:?}: {}", path, error))?;
+        ledger.set_stake(stake_snapshot_from_json(&json)?);
+    }
+
+    let pools: Vec<_> = ledger.pools().collect();
+    match output {
+        OutputFormat::Text => {
+            for pool in &pools {
+                let retiring = ledger
+                    .retiring(&pool.operator)
+                    .map_or(String::new(), |epoch| {
+                        format!(" retiring in epoch {}", epoch)
+                    });
+                let stake = ledger.relative_stake(&pool.operator);
+                for relay in &pool.relays {
+                    println!("{} {:.6} {}{}", hex(&pool.operator), stake, relay, retiring);
+                }
+            }
+            let relays: usize = pools.iter().map(|pool| pool.relays.len()).sum();
+            println!("{} pools, {} relays", pools.len(), relays);
+        }
+        _ => {
+            let relays = pools.iter().flat_map(|pool| {
+                pool.relays.iter().map(|relay| {
+                    serde_json::json!({
+                        "pool": hex(&pool.operator),
+                        "relay": relay.to_string(),
+                        "host": relay.host(),
+                        "relative_stake": ledger.relative_stake(&pool.operator),
+                        "retiring": ledger.retiring(&pool.operator),
+                    })
+                })
+            });
+            match output {
+                OutputFormat::Ndjson => {
+                    for relay in relays {
+                        print_json(&relay, output)?;
+                    }
+                }
+                _ => print_json(&serde_json::Value::Array(relays.collect()), output)?,
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,294 @@
+// Command line interface. Network commands run against the hosts of the
+// configuration file, where the environment and the flags given here
+// override its settings, see `config::get_app_config`.
+
+mod db;
+mod monitor;
+mod ping;
+mod serve;
+mod sync;
+
+pub use self::db::DbCommand;
+pub use self::monitor::MonitorArgs;
+pub use self::serve::ServeArgs;
+pub use self::sync::{FetchBlockArgs, SyncArgs};
+
+use cardano_rust_node::bearer;
+use cardano_rust_node::block_fetch::MINI_PROTOCOL_ID_BLOCK_FETCH;
+use cardano_rust_node::capture::Capture;
+use cardano_rust_node::chain_sync::MINI_PROTOCOL_ID_CHAIN_SYNC;
+use cardano_rust_node::config::{
+    enable_tracing, get_app_config, AppConfig, ConfigOverrides, HostConfig, LoggingConfig, Network,
+};
+use cardano_rust_node::connection::PeerConnection;
+use cardano_rust_node::crypto::Hash32;
+use cardano_rust_node::metrics::{self, metrics};
+use cardano_rust_node::storage::{Point, Tip};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::Path;
+use tokio::net::TcpListener;
+use tracing::info;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file
+    #[arg(
+        long,
+        short,
+        global = true,
+        env = "CARDANO_RUST_NODE_CONFIG",
+        default_value = "App.yaml"
+    )]
+    pub config: String,
+    /// Named network: mainnet, preprod, preview or sanchonet
+    #[arg(long, short, global = true)]
+    pub network: Option<Network>,
+    /// Host to connect to instead of the configured ones, may be repeated
+    #[arg(long = "host", short = 'H', global = true, value_name = "HOST:PORT")]
+    pub hosts: Vec<String>,
+    /// Handshake versions to propose, comma separated
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub versions: Option<Vec<i64>>,
+    /// Format of the results printed to stdout
+    #[arg(long, short, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    /// Serve Prometheus metrics on http://ADDRESS/metrics. One-shot commands
+    /// keep serving after they are done, until interrupted
+    #[arg(
+        long,
+        global = true,
+        env = "CARDANO_RUST_NODE_METRICS",
+        value_name = "ADDRESS"
+    )]
+    pub metrics: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Handshake with every host and report the timings, the default
+    Ping,
+    /// Handshake with every host repeatedly and print statistics
+    Monitor(MonitorArgs),
+    /// Ask every host which versions it supports
+    QueryVersions,
+    /// Download the chain of the first host into a local database
+    Sync(SyncArgs),
+    /// Serve a local database to peers
+    Serve(ServeArgs),
+    /// Download one block from the first host
+    FetchBlock(FetchBlockArgs),
+    /// Inspect databases on disk
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    // One JSON object per line, streamed as results come in
+    Ndjson,
+    // Only for ping results: ping and monitor
+    Csv,
+}
+
+pub async fn run(cli: Cli) -> Result<(), String> {
+    let command = cli.command.unwrap_or(Command::Ping);
+    if cli.output == OutputFormat::Csv && !matches!(command, Command::Ping | Command::Monitor(_)) {
+        return Err("CSV output is only available for ping and monitor".to_owned());
+    }
+    // Database commands do not need the configuration
+    if let Command::Db { command } = &command {
+        let _guard = enable_tracing(&LoggingConfig::default())?;
+        return db::run(command, cli.output);
+    }
+
+    let overrides = ConfigOverrides {
+        network: cli.network,
+        hosts: match cli.hosts.is_empty() {
+            true => None,
+            false => Some(
+                cli.hosts
+                    .iter()
+                    .map(|host| HostConfig {
+                        host: host.clone(),
+                        network_magic: None,
+                        network_id: String::new(),
+                        network: None,
+                    })
+                    .collect(),
+            ),
+        },
+        supported_versions: cli.versions,
+    };
+    let app_config: AppConfig = get_app_config(&cli.config, &overrides)
+        .map_err(|error| format!("Could not load configuration {}: {}", cli.config, error))?;
+    // Flushes the log file when dropped
+    let _guard = enable_tracing(&app_config.logging)?;
+
+    let metrics_server = match &cli.metrics {
+        Some(address) => {
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|error| format!("Could not listen on {}: {}", address, error))?;
+            info!("Serving metrics on http://{}/metrics", address);
+            Some(tokio::spawn(metrics::serve(listener)))
+        }
+        None => None,
+    };
+    let long_running = matches!(command, Command::Monitor(_) | Command::Serve(_));
+
+    let result = match command {
+        Command::Ping => ping::ping(&app_config, cli.output).await,
+        Command::Monitor(args) => monitor::monitor(&app_config, &args, cli.output).await,
+        Command::QueryVersions => ping::query_versions(&app_config, cli.output).await,
+        Command::Sync(args) => sync::sync(&app_config, &args, cli.output).await,
+        Command::Serve(args) => serve::serve(&app_config, &args).await,
+        Command::FetchBlock(args) => sync::fetch_block(&app_config, &args, cli.output).await,
+        Command::Db { .. } => unreachable!(),
+    };
+    if let Some(server) = metrics_server {
+        if !long_running {
+            info!("Done, serving metrics until interrupted");
+            let _ = tokio::signal::ctrl_c().await;
+        }
+        server.abort();
+    }
+    result
+}
+
+fn first_host(app_config: &AppConfig) -> Result<HostConfig, String> {
+    app_config
+        .peer_hosts()?
+        .into_iter()
+        .next()
+        .ok_or("No host configured".to_owned())
+}
+
+/// Connects to a host and completes the handshake. The connection carries
+/// chain-sync and block-fetch, its segments are recorded to `capture`.
+async fn connect(
+    host: &HostConfig,
+    supported_versions: &Vec<i64>,
+    capture: Option<&Path>,
+) -> Result<PeerConnection, String> {
+    // Resolved by peer_hosts()
+    let network_magic = host.network_magic.unwrap_or_default();
+    let protocols = [MINI_PROTOCOL_ID_CHAIN_SYNC, MINI_PROTOCOL_ID_BLOCK_FETCH];
+    match capture {
+        Some(path) => {
+            let stream = bearer::connect_tcp(&host.host).await?;
+            info!("Recording the session to {:?}", path);
+            let capture = Capture::create(stream, path)?;
+            PeerConnection::initiate(
+                &host.host,
+                capture,
+                network_magic,
+                supported_versions,
+                &protocols,
+            )
+            .await
+        }
+        None => {
+            PeerConnection::connect(&host.host, network_magic, supported_versions, &protocols).await
+        }
+    }
+}
+
+/// Exports the tip of the local chain, nothing while it is empty.
+fn export_tip(tip: &Tip) {
+    if let Some(slot) = tip.point.slot() {
+        metrics().set_tip(slot, tip.block_number);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hash(hex: &str) -> Result<Hash32, String> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(format!("Expected 32 bytes hex hash, got {:?}", hex));
+    }
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| format!("Invalid hex in hash {:?}", hex))?;
+    }
+    Ok(hash)
+}
+
+fn point_json(point: &Point) -> serde_json::Value {
+    match point {
+        Point::Origin => serde_json::Value::String("origin".to_owned()),
+        Point::Specific(slot, hash) => serde_json::json!({"slot": slot, "hash": hex(hash)}),
+    }
+}
+
+/// Prints a result pretty for JSON, on one line for NDJSON.
+fn print_json(json: &serde_json::Value, output: OutputFormat) -> Result<(), String> {
+    let json = match output {
+        OutputFormat::Ndjson => serde_json::to_string(json),
+        _ => serde_json::to_string_pretty(json),
+    };
+    println!("{}", json.map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+fn point_text(point: &Point) -> String {
+    match point {
+        Point::Origin => "origin".to_owned(),
+        Point::Specific(slot, hash) => format!("{}.{}", slot, hex(hash)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_flags() {
+        let cli = Cli::try_parse_from([
+            "cardano_rust_node",
+            "--network",
+            "preview",
+            "-H",
+            "a:1",
+            "--host",
+            "b:2",
+            "--versions",
+            "13,14",
+            "--output",
+            "json",
+            "db",
+            "inspect",
+            "/tmp/db",
+        ])
+        .unwrap();
+        assert_eq!(cli.network, Some(Network::Preview));
+        assert_eq!(cli.hosts, vec!["a:1", "b:2"]);
+        assert_eq!(cli.versions, Some(vec![13, 14]));
+        assert_eq!(cli.output, OutputFormat::Json);
+        assert!(matches!(cli.command, Some(Command::Db { .. })));
+
+        let cli = Cli::try_parse_from(["cardano_rust_node", "fetch-block", "5", &"ab".repeat(32)])
+            .unwrap();
+        match cli.command {
+            Some(Command::FetchBlock(args)) => assert_eq!(args.slot, 5),
+            command => panic!("Unexpected {:?}", command),
+        }
+        assert!(Cli::try_parse_from(["cardano_rust_node", "--network", "devnet"]).is_err());
+    }
+
+    #[test]
+    fn parse_hashes() {
+        assert_eq!(parse_hash(&"0f".repeat(32)).unwrap(), [15; 32]);
+        assert!(parse_hash("0f").is_err());
+        assert!(parse_hash(&"zz".repeat(32)).is_err());
+    }
+}
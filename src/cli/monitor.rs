@@ -0,0 +1,302 @@
+use super::ping::{ping_all, PingResult, RecordWriter};
+use super::OutputFormat;
+use cardano_rust_node::config::AppConfig;
+use clap::Args;
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::time::{self, Duration, MissedTickBehavior};
+use tracing::info;
+
+// Durations of this many most recent rounds make up the statistics, the
+// success rate covers the whole run
+const MAX_SAMPLES: usize = 1000;
+
+#[derive(Debug, Args)]
+pub struct MonitorArgs {
+    /// Seconds between rounds of handshakes
+    #[arg(long, default_value_t = 10)]
+    pub interval: u64,
+    /// Print the summary every this many rounds
+    #[arg(long, default_value_t = 6)]
+    pub summary_every: u64,
+    /// Stop after this many rounds, runs until interrupted otherwise
+    #[arg(long)]
+    pub count: Option<u64>,
+}
+
+/// Minimum, average, 95th percentile and maximum in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DurationStats {
+    pub min: u64,
+    pub avg: f64,
+    pub p95: u64,
+    pub max: u64,
+}
+
+impl DurationStats {
+    pub fn from_samples(samples: &VecDeque<u64>) -> Option<DurationStats> {
+        let mut sorted: Vec<u64> = samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let (min, max) = (*sorted.first()?, *sorted.last()?);
+        // Nearest rank
+        let rank = (sorted.len() * 95).div_ceil(100);
+        Some(DurationStats {
+            min,
+            avg: sorted.iter().sum::<u64>() as f64 / sorted.len() as f64,
+            p95: sorted[rank.saturating_sub(1)],
+            max,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostStats {
+    pub host: String,
+    pub network_id: String,
+    pub attempts: u64,
+    pub successes: u64,
+    #[serde(skip)]
+    connect_ms: VecDeque<u64>,
+    #[serde(skip)]
+    negotiate_ms: VecDeque<u64>,
+    pub last_refuse_reason: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl HostStats {
+    pub fn new(host: &str, network_id: &str) -> HostStats {
+        HostStats {
+            host: host.to_owned(),
+            network_id: network_id.to_owned(),
+            attempts: 0,
+            successes: 0,
+            connect_ms: VecDeque::new(),
+            negotiate_ms: VecDeque::new(),
+            last_refuse_reason: None,
+            last_error: None,
+        }
+    }
+
+    pub fn record(&mut self, result: &PingResult) {
+        self.attempts += 1;
+        if result.is_success() {
+            self.successes += 1;
+        }
+        for (samples, sample) in [
+            (&mut self.connect_ms, result.connect_ms),
+            (&mut self.negotiate_ms, result.negotiate_ms),
+        ] {
+            if let Some(sample) = sample {
+                if samples.len() == MAX_SAMPLES {
+                    samples.pop_front();
+                }
+                samples.push_back(sample);
+            }
+        }
+        if result.refuse_reason.is_some() {
+            self.last_refuse_reason = result.refuse_reason.clone();
+        }
+        if result.error.is_some() {
+            self.last_error = result.error.clone();
+        }
+    }
+
+    /// Share of successful handshakes in percent.
+    pub fn success_rate(&self) -> f64 {
+        match self.attempts {
+            0 => 0.0,
+            attempts => self.successes as f64 * 100.0 / attempts as f64,
+        }
+    }
+
+    pub fn connect(&self) -> Option<DurationStats> {
+        DurationStats::from_samples(&self.connect_ms)
+    }
+
+    pub fn negotiate(&self) -> Option<DurationStats> {
+        DurationStats::from_samples(&self.negotiate_ms)
+    }
+}
+
+/// Handshakes with every host each interval and prints per host statistics
+/// every `summary_every` rounds, after the last round and when interrupted.
+/// NDJSON and CSV stream every handshake's record instead.
+pub async fn monitor(
+    app_config: &AppConfig,
+    args: &MonitorArgs,
+    output: OutputFormat,
+) -> Result<(), String> {
+    if args.interval == 0 || args.summary_every == 0 {
+        return Err("Interval and summary period must not be 0".to_owned());
+    }
+    let hosts = app_config.peer_hosts()?;
+    let mut stats: Vec<HostStats> = hosts
+        .iter()
+        .map(|host| HostStats::new(&host.host, &host.network_id))
+        .collect();
+    let mut interval = time::interval(Duration::from_secs(args.interval));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let streaming = matches!(output, OutputFormat::Ndjson | OutputFormat::Csv);
+    let mut writer = RecordWriter::new(output);
+
+    let mut round = 0;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!("Interrupted after {} rounds", round);
+                return match streaming {
+                    true => Ok(()),
+                    false => print_summary(&stats, output),
+                };
+            }
+        }
+        let results = ping_all(&hosts, &app_config.supported_versions, |result| {
+            if streaming {
+                writer.write(result)
+            }
+        })
+        .await;
+        for (stats, result) in stats.iter_mut().zip(&results) {
+            stats.record(result);
+        }
+        round += 1;
+
+        let last = args.count.is_some_and(|count| round >= count);
+        if !streaming && (last || round % args.summary_every == 0) {
+            print_summary(&stats, output)?;
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn print_summary(stats: &[HostStats], output: OutputFormat) -> Result<(), String> {
+    match output {
+        OutputFormat::Text => print!("{}", summary_table(stats)),
+        _ => {
+            let summary: Vec<serde_json::Value> = stats
+                .iter()
+                .map(|host| {
+                    let mut json = serde_json::to_value(host).map_err(|e| e.to_string())?;
+                    json["success_rate"] = serde_json::json!(host.success_rate());
+                    json["connect_ms"] = serde_json::json!(host.connect());
+                    json["negotiate_ms"] = serde_json::json!(host.negotiate());
+                    Ok(json)
+                })
+                .collect::<Result<_, String>>()?;
+            println!(
+                "{}",
+                serde_json::to_string(&summary).map_err(|e| e.to_string())?
+            );
+        }
+    }
+    Ok(())
+}
+
+fn summary_table(stats: &[HostStats]) -> String {
+    let format_stats = |stats: Option<DurationStats>| match stats {
+        Some(stats) => format!("{}/{:.1}/{}/{}", stats.min, stats.avg, stats.p95, stats.max),
+        None => "-".to_owned(),
+    };
+    let width = stats
+        .iter()
+        .map(|host| host.host.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let mut table = format!(
+        "{:<width$}  {:>5}  {:>7}  {:<26}  {:<26}  {}\n",
+        "HOST", "SENT", "SUCCESS", "CONNECT MS min/avg/p95/max", "NEGOTIATE MS", "LAST REFUSAL"
+    );
+    for host in stats {
+        table += &format!(
+            "{:<width$}  {:>5}  {:>6.1}%  {:<26}  {:<26}  {}\n",
+            host.host,
+            host.attempts,
+            host.success_rate(),
+            format_stats(host.connect()),
+            format_stats(host.negotiate()),
+            host.last_refuse_reason.as_deref().unwrap_or("-")
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ping::ErrorClass;
+    use super::*;
+
+    fn result(connect_ms: Option<u64>, refuse_reason: Option<&str>) -> PingResult {
+        PingResult {
+            timestamp_ms: 0,
+            host: "a:1".to_owned(),
+            network_id: "preview".to_owned(),
+            connect_ms,
+            negotiate_ms: connect_ms.map(|ms| ms * 2),
+            total_ms: connect_ms.map(|ms| ms * 3),
+            version: match (connect_ms, refuse_reason) {
+                (Some(_), None) => Some(13),
+                _ => None,
+            },
+            refuse_reason: refuse_reason.map(str::to_owned),
+            error_class: match (connect_ms, refuse_reason) {
+                (None, _) => Some(ErrorClass::Connect),
+                (_, Some(_)) => Some(ErrorClass::Refused),
+                _ => None,
+            },
+            error: connect_ms
+                .is_none()
+                .then(|| "Connection refused".to_owned()),
+        }
+    }
+
+    #[test]
+    fn host_statistics() {
+        let mut stats = HostStats::new("a:1", "preview");
+        assert_eq!(stats.connect(), None);
+        for ms in 1..=20 {
+            stats.record(&result(Some(ms), None));
+        }
+        stats.record(&result(Some(100), Some("VersionMismatch [13, 14]")));
+        stats.record(&result(None, None));
+
+        assert_eq!(stats.attempts, 22);
+        assert_eq!(stats.successes, 20);
+        assert!((stats.success_rate() - 90.909).abs() < 0.001);
+        assert_eq!(
+            stats.connect(),
+            Some(DurationStats {
+                min: 1,
+                avg: 310.0 / 21.0,
+                p95: 20,
+                max: 100,
+            })
+        );
+        assert_eq!(stats.negotiate().unwrap().max, 200);
+        assert_eq!(
+            stats.last_refuse_reason.as_deref(),
+            Some("VersionMismatch [13, 14]")
+        );
+        assert_eq!(stats.last_error.as_deref(), Some("Connection refused"));
+
+        let table = summary_table(&[stats]);
+        assert!(table
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("a:1      22    90.9%  1/14.8/20/100"));
+    }
+
+    #[test]
+    fn samples_are_bounded() {
+        let mut stats = HostStats::new("a:1", "preview");
+        for ms in 0..MAX_SAMPLES as u64 + 10 {
+            stats.record(&result(Some(ms), None));
+        }
+        assert_eq!(stats.connect().unwrap().min, 10);
+        assert_eq!(stats.attempts, MAX_SAMPLES as u64 + 10);
+    }
+}
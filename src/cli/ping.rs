@@ -0,0 +1,472 @@
+use super::{print_json, OutputFormat};
+use cardano_rust_node::bearer;
+use cardano_rust_node::config::{AppConfig, HostConfig};
+use cardano_rust_node::handshake::{
+    self, Message, NodeConfig, NodeToNodeVersionData, MINI_PROTOCOL_ID_HANDSHAKE,
+};
+use cardano_rust_node::metrics::metrics;
+use cardano_rust_node::mux::{Mode, Mux};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{task::JoinSet, time::Instant};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    // The TCP connection could not be established
+    Connect,
+    // The host refused the proposed versions or their version data
+    Refused,
+    // No response, or one that could not be decoded or was not expected
+    Protocol,
+}
+
+impl ErrorClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Connect => "connect",
+            ErrorClass::Refused => "refused",
+            ErrorClass::Protocol => "protocol",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PingResult {
+    // Unix time in milliseconds the connection was started at
+    pub timestamp_ms: u64,
+    pub host: String,
+    pub network_id: String,
+    // Version accepted by the host
+    pub version: Option<i64>,
+    pub refuse_reason: Option<String>,
+    pub error_class: Option<ErrorClass>,
+    pub error: Option<String>,
+    pub connect_ms: Option<u64>,
+    pub negotiate_ms: Option<u64>,
+    pub total_ms: Option<u64>,
+}
+
+impl PingResult {
+    /// Result of a ping of `host_config` starting now, before anything is
+    /// known.
+    fn new(host_config: &HostConfig) -> PingResult {
+        PingResult {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis() as u64),
+            host: host_config.host.clone(),
+            network_id: host_config.network_id.clone(),
+            version: None,
+            refuse_reason: None,
+            error_class: None,
+            error: None,
+            connect_ms: None,
+            negotiate_ms: None,
+            total_ms: None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.version.is_some()
+    }
+}
+
+const CSV_HEADER: &str = "timestamp_ms,host,network_id,version,refuse_reason,error_class,error,connect_ms,negotiate_ms,total_ms";
+
+/// Prints ping results to stdout in the chosen format. NDJSON and CSV
+/// records are written as soon as they are known, JSON as one array once
+/// all are.
+pub struct RecordWriter {
+    output: OutputFormat,
+    header_written: bool,
+    records: Vec<PingResult>,
+}
+
+impl RecordWriter {
+    pub fn new(output: OutputFormat) -> RecordWriter {
+        RecordWriter {
+            output,
+            header_written: false,
+            records: vec![],
+        }
+    }
+
+    pub fn write(&mut self, result: &PingResult) {
+        match self.output {
+            OutputFormat::Text => println!("{}", text_record(result)),
+            OutputFormat::Json => self.records.push(result.clone()),
+            OutputFormat::Ndjson => match serde_json::to_string(result) {
+                Ok(line) => println!("{}", line),
+                Err(error) => error!("Could not encode {:?}: {}", result, error),
+            },
+            OutputFormat::Csv => {
+                if !self.header_written {
+                    println!("{}", CSV_HEADER);
+                    self.header_written = true;
+                }
+                println!("{}", csv_record(result));
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        if self.output == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&self.records).map_err(|e| e.to_string())?
+            );
+        }
+        Ok(())
+    }
+}
+
+fn text_record(result: &PingResult) -> String {
+    let durations = format!(
+        "connect {} ms, negotiate {} ms, total {} ms",
+        optional(&result.connect_ms),
+        optional(&result.negotiate_ms),
+        optional(&result.total_ms)
+    );
+    match (&result.version, &result.refuse_reason, &result.error) {
+        (Some(version), _, _) => format!("{} version {}: {}", result.host, version, durations),
+        (_, Some(reason), _) => format!("{} refused {}: {}", result.host, reason, durations),
+        (_, _, error) => format!(
+            "{} failed: {}",
+            result.host,
+            error.as_deref().unwrap_or_default()
+        ),
+    }
+}
+
+fn csv_record(result: &PingResult) -> String {
+    [
+        result.timestamp_ms.to_string(),
+        csv_field(&result.host),
+        csv_field(&result.network_id),
+        optional(&result.version),
+        csv_field(result.refuse_reason.as_deref().unwrap_or_default()),
+        result
+            .error_class
+            .map_or("", |class| class.as_str())
+            .to_owned(),
+        csv_field(result.error.as_deref().unwrap_or_default()),
+        optional(&result.connect_ms),
+        optional(&result.negotiate_ms),
+        optional(&result.total_ms),
+    ]
+    .join(",")
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or(String::new(), |value| value.to_string())
+}
+
+// RFC 4180: fields with separators, quotes or line breaks are quoted
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_owned(),
+    }
+}
+
+/// Handshakes with every host concurrently. Fails when a host could not be
+/// reached or refused all versions.
+pub async fn ping(app_config: &AppConfig, output: OutputFormat) -> Result<(), String> {
+    let hosts = app_config.peer_hosts()?;
+    let mut writer = RecordWriter::new(output);
+    let results = ping_all(&hosts, &app_config.supported_versions, |result| {
+        writer.write(result)
+    })
+    .await;
+    writer.finish()?;
+
+    let failed = results.iter().filter(|result| !result.is_success()).count();
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} hosts failed", failed, results.len())),
+    }
+}
+
+/// Handshakes with every host concurrently. `on_result` sees each result as
+/// it completes, the returned results are in the order of `hosts`.
+pub async fn ping_all(
+    hosts: &[HostConfig],
+    supported_versions: &[i64],
+    mut on_result: impl FnMut(&PingResult),
+) -> Vec<PingResult> {
+    let mut set = JoinSet::new();
+    let mut indices = HashMap::new();
+    for (index, host_config) in hosts.iter().cloned().enumerate() {
+        let supported_versions = supported_versions.to_vec();
+        let task = set.spawn(async move { ping_host(&host_config, &supported_versions).await });
+        indices.insert(task.id(), index);
+    }
+
+    // One result per host, also for the tasks that failed
+    let mut results = vec![];
+    while let Some(joined) = set.join_next_with_id().await {
+        let (id, result) = match joined {
+            Ok((id, result)) => (id, result),
+            Err(error) => {
+                let mut result = PingResult::new(&hosts[indices[&error.id()]]);
+                result.error = Some(format!("Ping failed: {}", error));
+                (error.id(), result)
+            }
+        };
+        on_result(&result);
+        results.push((indices[&id], result));
+    }
+    results.sort_by_key(|(index, _)| *index);
+    let results: Vec<PingResult> = results.into_iter().map(|(_, result)| result).collect();
+
+    for result in &results {
+        match (&result.refuse_reason, &result.error) {
+            (None, None) => info!(
+                "Ping {} success! : connect_duration: {}, negotiate_duration: {}, total_duration: {}",
+                result.host,
+                result.connect_ms.unwrap_or_default(),
+                result.negotiate_ms.unwrap_or_default(),
+                result.total_ms.unwrap_or_default()
+            ),
+            (Some(reason), _) => error!("Ping {} refused! : {}", result.host, reason),
+            (_, Some(error)) => error!("Ping {} failed! : {}", result.host, error),
+        }
+    }
+    results
+}
+
+async fn ping_host(host_config: &HostConfig, supported_versions: &Vec<i64>) -> PingResult {
+    let mut result = PingResult::new(host_config);
+    let host = &host_config.host;
+    metrics().handshake_attempt(host);
+    let connect_start = Instant::now();
+    let node_config = match NodeConfig::init(
+        &host_config.host,
+        // Resolved by peer_hosts()
+        host_config.network_magic.unwrap_or_default(),
+        &host_config.network_id,
+    )
+    .await
+    {
+        Ok(config) => config,
+        Err(error) => {
+            metrics().handshake_failed(host, ErrorClass::Connect.as_str());
+            result.error_class = Some(ErrorClass::Connect);
+            result.error = Some(error);
+            return result;
+        }
+    };
+    metrics().observe_connect(host, connect_start.elapsed());
+    result.connect_ms = Some(connect_start.elapsed().as_millis() as u64);
+    match handshake::negotiate(node_config, supported_versions).await {
+        Ok((response, negotiate_duration, total_duration)) => {
+            metrics().observe_negotiate(host, negotiate_duration);
+            result.negotiate_ms = Some(negotiate_duration.as_millis() as u64);
+            result.total_ms = Some(total_duration.as_millis() as u64);
+            match response {
+                Message::Refuse(reason) => {
+                    metrics().handshake_refused(host, reason.name());
+                    result.error_class = Some(ErrorClass::Refused);
+                    result.refuse_reason = Some(reason.to_string());
+                }
+                response => match response.accepted_version() {
+                    Some(version) => {
+                        metrics().handshake_accepted(host, version as i64);
+                        result.version = Some(version as i64)
+                    }
+                    None => {
+                        metrics().handshake_failed(host, ErrorClass::Protocol.as_str());
+                        result.error_class = Some(ErrorClass::Protocol);
+                        result.error = Some(format!("Unexpected {:?}", response));
+                    }
+                },
+            }
+        }
+        Err(error) => {
+            metrics().handshake_failed(host, ErrorClass::Protocol.as_str());
+            result.error_class = Some(ErrorClass::Protocol);
+            result.error = Some(error);
+        }
+    }
+    result
+}
+
+/// Asks every host for its version table. Hosts only answer queries
+/// proposed with version 11 or later.
+pub async fn query_versions(app_config: &AppConfig, output: OutputFormat) -> Result<(), String> {
+    if app_config
+        .supported_versions
+        .iter()
+        .all(|version| *version < 11)
+    {
+        return Err("Querying versions needs version 11 or later".to_owned());
+    }
+    let hosts = app_config.peer_hosts()?;
+    let mut set = JoinSet::new();
+    for host_config in hosts {
+        let supported_versions = app_config.supported_versions.clone();
+        set.spawn(async move {
+            let reply = query_host(&host_config, &supported_versions).await;
+            (host_config, reply)
+        });
+    }
+
+    let mut results = vec![];
+    let mut failed = 0;
+    while let Some(res) = set.join_next().await {
+        let (host_config, reply) = res.map_err(|e| e.to_string())?;
+        let versions = match reply {
+            Ok(Message::QueryReply(version_table)) => version_table,
+            Ok(message) => {
+                failed += 1;
+                results.push(serde_json::json!({
+                    "host": host_config.host,
+                    "error": format!("{:?}", message),
+                }));
+                continue;
+            }
+            Err(error) => {
+                failed += 1;
+                results.push(serde_json::json!({"host": host_config.host, "error": error}));
+                continue;
+            }
+        };
+        let versions: Vec<serde_json::Value> = versions
+            .iter()
+            .map(|(version, data)| {
+                let mut entry = serde_json::json!({"version": *version as i64});
+                for field in data {
+                    let (key, value) = match field {
+                        NodeToNodeVersionData::NetworkMagic(magic) => {
+                            ("network_magic", serde_json::json!(magic))
+                        }
+                        NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(mode) => {
+                            ("initiator_only", serde_json::json!(mode))
+                        }
+                        NodeToNodeVersionData::PeerSharing(peer_sharing) => {
+                            ("peer_sharing", serde_json::json!(peer_sharing))
+                        }
+                        NodeToNodeVersionData::Query(query) => ("query", serde_json::json!(query)),
+                    };
+                    entry[key] = value;
+                }
+                entry
+            })
+            .collect();
+        results.push(serde_json::json!({"host": host_config.host, "versions": versions}));
+    }
+
+    match output {
+        OutputFormat::Text => {
+            for result in &results {
+                match result.get("versions").and_then(|v| v.as_array()) {
+                    Some(versions) => {
+                        println!("{}", result["host"].as_str().unwrap_or_default());
+                        for version in versions {
+                            println!("  {}", version);
+                        }
+                    }
+                    None => println!(
+                        "{} failed: {}",
+                        result["host"].as_str().unwrap_or_default(),
+                        result["error"].as_str().unwrap_or_default()
+                    ),
+                }
+            }
+        }
+        OutputFormat::Ndjson => {
+            for result in &results {
+                print_json(result, output)?;
+            }
+        }
+        _ => print_json(&serde_json::Value::Array(results.clone()), output)?,
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} hosts failed", failed, results.len())),
+    }
+}
+
+async fn query_host(
+    host_config: &HostConfig,
+    supported_versions: &Vec<i64>,
+) -> Result<Message, String> {
+    let stream = bearer::connect_tcp(&host_config.host).await?;
+    let mut mux = Mux::with_bearer(stream, Mode::Initiator, &[MINI_PROTOCOL_ID_HANDSHAKE]);
+    let mut channel = mux
+        .channel(MINI_PROTOCOL_ID_HANDSHAKE)
+        .ok_or("No handshake channel")?;
+    handshake::propose(
+        &mut channel,
+        supported_versions,
+        // Resolved by peer_hosts()
+        host_config.network_magic.unwrap_or_default(),
+        false,
+        true,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let mut result = PingResult {
+            timestamp_ms: 1_700_000_000_000,
+            host: "a:1".to_owned(),
+            network_id: "preview".to_owned(),
+            version: None,
+            refuse_reason: Some("Refused 13: magic \"2\", expected 1".to_owned()),
+            error_class: Some(ErrorClass::Refused),
+            error: None,
+            connect_ms: Some(3),
+            negotiate_ms: Some(5),
+            total_ms: Some(8),
+        };
+        assert_eq!(
+            csv_record(&result),
+            "1700000000000,a:1,preview,,\"Refused 13: magic \"\"2\"\", expected 1\",refused,,3,5,8"
+        );
+        assert_eq!(CSV_HEADER.split(',').count(), 10);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap()["error_class"],
+            serde_json::json!("refused")
+        );
+
+        result.version = Some(13);
+        result.refuse_reason = None;
+        result.error_class = None;
+        assert_eq!(
+            text_record(&result),
+            "a:1 version 13: connect 3 ms, negotiate 5 ms, total 8 ms"
+        );
+        assert_eq!(csv_record(&result), "1700000000000,a:1,preview,13,,,,3,5,8");
+    }
+
+    #[tokio::test]
+    async fn one_result_per_host() {
+        let hosts: Vec<HostConfig> = ["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"]
+            .into_iter()
+            .map(|host| HostConfig {
+                host: host.to_owned(),
+                network_magic: Some(2),
+                network_id: String::new(),
+                network: None,
+            })
+            .collect();
+        let mut streamed = 0;
+        let results = ping_all(&hosts, &[13], |_| streamed += 1).await;
+        assert_eq!(streamed, 3);
+        let hosts: Vec<&str> = hosts.iter().map(|host| host.host.as_str()).collect();
+        let pinged: Vec<&str> = results.iter().map(|result| result.host.as_str()).collect();
+        assert_eq!(pinged, hosts);
+        assert!(results.iter().all(|result| result.error.is_some()));
+    }
+}
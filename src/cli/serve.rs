@@ -0,0 +1,149 @@
+use super::export_tip;
+use cardano_rust_node::bearer;
+use cardano_rust_node::block_fetch::{self, MINI_PROTOCOL_ID_BLOCK_FETCH};
+use cardano_rust_node::chain_sync::{self, MINI_PROTOCOL_ID_CHAIN_SYNC};
+use cardano_rust_node::config::AppConfig;
+use cardano_rust_node::connection::PeerConnection;
+use cardano_rust_node::connection_manager::{ConnectionManager, ManagerConfig};
+use cardano_rust_node::keep_alive::{self, MINI_PROTOCOL_ID_KEEP_ALIVE};
+use cardano_rust_node::peer_sharing::{self, MINI_PROTOCOL_ID_PEER_SHARING};
+use cardano_rust_node::storage::{parse_cardano_block, ChainDb};
+use clap::Args;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::{
+    join,
+    net::{lookup_host, TcpStream},
+    sync::Mutex,
+};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Database directory, as written by `sync`
+    #[arg(long, default_value = "db")]
+    pub db: PathBuf,
+    /// Address to accept connections on
+    #[arg(long, default_value = "0.0.0.0:3001")]
+    pub listen: String,
+    /// Security parameter k, 2160 on the public networks
+    #[arg(long, default_value_t = 2160)]
+    pub security_param: u64,
+    /// Connections accepted at most at a time
+    #[arg(long, default_value_t = 512)]
+    pub max_inbound: usize,
+}
+
+/// Serves chain-sync and block-fetch from the ChainDB in `args.db`, and
+/// keep-alive, to every peer that completes the handshake on the
+/// application's network. Peer sharing shares the root hosts of the
+/// configuration.
+pub async fn serve(app_config: &AppConfig, args: &ServeArgs) -> Result<(), String> {
+    let network_magic = app_config.network_magic()?;
+    let chain_db = ChainDb::open(&args.db, args.security_param, parse_cardano_block)
+        .map_err(|e| e.to_string())?;
+    export_tip(&chain_db.tip());
+    let chain_db = Arc::new(Mutex::new(chain_db));
+    let address = args
+        .listen
+        .parse()
+        .map_err(|error| format!("Invalid address {}: {}", args.listen, error))?;
+    let listener = bearer::listen_tcp(address)?;
+    let local_address = listener
+        .local_addr()
+        .map_err(|error| format!("Could not listen on {}: {}", args.listen, error))?;
+    let shared = Arc::new(resolve(app_config.root_hosts()?.into_iter().map(|h| h.host)).await);
+    info!("Serving {:?} on {}", args.db, args.listen);
+    let manager = ConnectionManager::new(
+        ManagerConfig::new(
+            network_magic,
+            &app_config.supported_versions,
+            &[
+                MINI_PROTOCOL_ID_CHAIN_SYNC,
+                MINI_PROTOCOL_ID_BLOCK_FETCH,
+                MINI_PROTOCOL_ID_KEEP_ALIVE,
+                MINI_PROTOCOL_ID_PEER_SHARING,
+            ],
+        )
+        .with_local_address(local_address)
+        .with_max_inbound(args.max_inbound),
+    );
+
+    loop {
+        let (stream, peer) = listener
+            .accept()
+            .await
+            .map_err(|error| format!("Could not accept connection: {}", error))?;
+        info!("Connection from {}", peer);
+        let chain_db = chain_db.clone();
+        let manager = manager.clone();
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            match serve_peer(&manager, stream, peer, chain_db, &shared).await {
+                Ok(()) => info!("Connection from {} done", peer),
+                Err(error) => error!("Connection from {} failed: {}", peer, error),
+            }
+        });
+    }
+}
+
+async fn serve_peer(
+    manager: &ConnectionManager,
+    stream: TcpStream,
+    peer: SocketAddr,
+    chain_db: Arc<Mutex<ChainDb>>,
+    shared: &[SocketAddr],
+) -> Result<(), String> {
+    let connection = manager.accept_inbound(peer, stream).await?;
+    let served = serve_connection(&connection, chain_db, shared).await;
+    manager.release_inbound(connection).await;
+    served
+}
+
+// Addresses of `hosts`, those which do not resolve are not shared
+async fn resolve(hosts: impl Iterator<Item = String>) -> Vec<SocketAddr> {
+    let mut addresses = vec![];
+    for host in hosts {
+        match lookup_host(&host).await {
+            Ok(resolved) => addresses.extend(resolved),
+            Err(error) => warn!("Could not resolve {} to share it: {}", host, error),
+        }
+    }
+    addresses
+}
+
+async fn serve_connection(
+    connection: &PeerConnection,
+    chain_db: Arc<Mutex<ChainDb>>,
+    shared: &[SocketAddr],
+) -> Result<(), String> {
+    let mut chain_sync = connection
+        .protocol(MINI_PROTOCOL_ID_CHAIN_SYNC)
+        .ok_or("No chain-sync channel")?
+        .lock()
+        .await;
+    let mut block_fetch = connection
+        .protocol(MINI_PROTOCOL_ID_BLOCK_FETCH)
+        .ok_or("No block-fetch channel")?
+        .lock()
+        .await;
+    let mut keep_alive = connection
+        .protocol(MINI_PROTOCOL_ID_KEEP_ALIVE)
+        .ok_or("No keep-alive channel")?
+        .lock()
+        .await;
+    let mut peer_sharing = connection
+        .protocol(MINI_PROTOCOL_ID_PEER_SHARING)
+        .ok_or("No peer sharing channel")?
+        .lock()
+        .await;
+    let (chain_sync, block_fetch, keep_alive, peer_sharing) = join!(
+        chain_sync::serve(&mut chain_sync, chain_db.clone()),
+        block_fetch::serve(&mut block_fetch, chain_db),
+        keep_alive::serve(&mut keep_alive),
+        peer_sharing::serve(&mut peer_sharing, shared)
+    );
+    chain_sync
+        .and(block_fetch)
+        .and(keep_alive)
+        .and(peer_sharing)
+}
@@ -0,0 +1,426 @@
+use super::{
+    connect, export_tip, first_host, hex, parse_hash, point_json, point_text, print_json,
+    OutputFormat,
+};
+use cardano_rust_node::block_fetch::{self, MINI_PROTOCOL_ID_BLOCK_FETCH};
+use cardano_rust_node::chain_sync::{self, Message, MINI_PROTOCOL_ID_CHAIN_SYNC};
+use cardano_rust_node::config::AppConfig;
+use cardano_rust_node::connection::{PeerConnection, ProtocolHandle};
+use cardano_rust_node::connection_manager::{ConnectionManager, ManagerConfig};
+use cardano_rust_node::governor::{self, Governor, Report, Request, Targets};
+use cardano_rust_node::keep_alive::{self, MINI_PROTOCOL_ID_KEEP_ALIVE};
+use cardano_rust_node::mux::Channel;
+use cardano_rust_node::peer_sharing::{self, MINI_PROTOCOL_ID_PEER_SHARING};
+use cardano_rust_node::storage::{parse_cardano_block, split_era, ChainDb, Point};
+use clap::Args;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::{sync::mpsc, task::JoinHandle, time::timeout};
+use tracing::{info, warn};
+
+// Headers collected before their blocks are fetched in one range
+const FETCH_BATCH: usize = 100;
+// Peers followed one at a time, the established ones are ready to take over
+const TARGETS: Targets = Targets {
+    known: 20,
+    established: 3,
+    active: 1,
+};
+const GOVERNOR_INTERVAL: Duration = Duration::from_secs(5);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const SHARE_TIMEOUT: Duration = Duration::from_secs(30);
+// Given up when no peer could be activated for this long
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    /// Database directory, created when missing
+    #[arg(long, default_value = "db")]
+    pub db: PathBuf,
+    /// Security parameter k, 2160 on the public networks
+    #[arg(long, default_value_t = 2160)]
+    pub security_param: u64,
+    /// Keep following the chain once the tip of the host is reached
+    #[arg(long)]
+    pub follow: bool,
+    /// Stop after this many blocks
+    #[arg(long)]
+    pub limit: Option<u64>,
+    /// Record the segments of the session to this file
+    #[arg(long, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct FetchBlockArgs {
+    /// Slot of the block
+    pub slot: u64,
+    /// Header hash of the block, hex encoded
+    pub hash: String,
+    /// Write the block to this file instead of printing it
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+    /// Record the segments of the session to this file
+    #[arg(long, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+}
+
+/// Follows the chain of the peers of the configuration with chain-sync and
+/// adds the blocks fetched from them to the ChainDB in `args.db`. The peer
+/// governor picks the peer to follow and a failing one is replaced by
+/// another established peer, which resumes from the tip of the ChainDB.
+/// A recorded session follows the first host only.
+pub async fn sync(
+    app_config: &AppConfig,
+    args: &SyncArgs,
+    output: OutputFormat,
+) -> Result<(), String> {
+    let mut chain_db = ChainDb::open(&args.db, args.security_param, parse_cardano_block)
+        .map_err(|e| e.to_string())?;
+    export_tip(&chain_db.tip());
+    let mut fetched = 0;
+    match &args.capture {
+        Some(path) => {
+            let host = first_host(app_config)?;
+            let connection = connect(&host, &app_config.supported_versions, Some(path)).await?;
+            let followed = follow(&connection, &mut chain_db, args, &mut fetched, None).await;
+            connection.close().await;
+            followed?;
+        }
+        None => follow_governed(app_config, &mut chain_db, args, &mut fetched).await?,
+    }
+
+    let tip = chain_db.tip();
+    match output {
+        OutputFormat::Text => println!(
+            "Fetched {} blocks, tip {} block number {}",
+            fetched,
+            point_text(&tip.point),
+            tip.block_number
+        ),
+        _ => print_json(
+            &serde_json::json!({
+                "blocks": fetched,
+                "tip": point_json(&tip.point),
+                "block_number": tip.block_number,
+            }),
+            output,
+        )?,
+    }
+    Ok(())
+}
+
+// Runs the governor over the peers of the configuration and follows the
+// peer it activates, until one of them is followed to the end. Sync changes
+// peers on failures only, the active peer is not churned.
+async fn follow_governed(
+    app_config: &AppConfig,
+    chain_db: &mut ChainDb,
+    args: &SyncArgs,
+    fetched: &mut u64,
+) -> Result<(), String> {
+    let mut governor =
+        Governor::new(TARGETS).with_churn_interval(Duration::from_secs(u32::MAX.into()));
+    governor.add_config(app_config)?;
+    if governor.counts().0 == 0 {
+        return Err("No host configured".to_owned());
+    }
+    let protocols = [
+        MINI_PROTOCOL_ID_CHAIN_SYNC,
+        MINI_PROTOCOL_ID_BLOCK_FETCH,
+        MINI_PROTOCOL_ID_KEEP_ALIVE,
+        MINI_PROTOCOL_ID_PEER_SHARING,
+    ];
+    let manager = ConnectionManager::new(ManagerConfig::new(
+        app_config.network_magic()?,
+        &app_config.supported_versions,
+        &protocols,
+    ));
+    let (requests, mut requested) = mpsc::unbounded_channel();
+    let (reports, reported) = mpsc::unbounded_channel();
+    let driver = tokio::spawn(governor::run(
+        governor,
+        manager,
+        GOVERNOR_INTERVAL,
+        requests,
+        reported,
+    ));
+
+    let mut keep_alives: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let result = loop {
+        let request = match timeout(ACTIVATION_TIMEOUT, requested.recv()).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break Err("Peer governor stopped".to_owned()),
+            Err(_) => {
+                break Err(format!(
+                    "No peer could be followed for {} seconds",
+                    ACTIVATION_TIMEOUT.as_secs()
+                ))
+            }
+        };
+        let connection = match request {
+            Request::Activate(connection) => connection,
+            Request::Deactivate(host) => {
+                if let Some(keep_alive) = keep_alives.remove(&host) {
+                    keep_alive.abort();
+                }
+                continue;
+            }
+            Request::SharePeers(connection, amount) => {
+                tokio::spawn(share_peers(connection, amount, reports.clone()));
+                continue;
+            }
+        };
+        let host = connection.peer().to_owned();
+        let _ = reports.send(Report::Activated(host.clone(), Ok(())));
+        if let Some(handle) = connection.protocol(MINI_PROTOCOL_ID_KEEP_ALIVE) {
+            let keep_alive = tokio::spawn(measure_rtt(host.clone(), handle, reports.clone()));
+            keep_alives.insert(host.clone(), keep_alive);
+        }
+        let followed = follow(&connection, chain_db, args, fetched, Some(&reports)).await;
+        drop(connection);
+        if let Some(keep_alive) = keep_alives.remove(&host) {
+            keep_alive.abort();
+        }
+        match followed {
+            Ok(()) => break Ok(()),
+            Err(error) => {
+                warn!("Following {} failed: {}", host, error);
+                let _ = reports.send(Report::Failed(host));
+            }
+        }
+    };
+    for (_, keep_alive) in keep_alives {
+        keep_alive.abort();
+    }
+    // The driver releases its connections once reports are closed
+    drop(reports);
+    let _ = driver.await;
+    result
+}
+
+// Reports the keep-alive round trip of an active peer until it fails
+async fn measure_rtt(host: String, handle: ProtocolHandle, reports: mpsc::UnboundedSender<Report>) {
+    let mut ticks = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    let mut cookie: u16 = 0;
+    loop {
+        ticks.tick().await;
+        match keep_alive::keep_alive(&mut *handle.lock().await, cookie).await {
+            Ok(rtt) => {
+                if reports.send(Report::Rtt(host.clone(), rtt)).is_err() {
+                    return;
+                }
+            }
+            Err(error) => {
+                warn!("Keep-alive with {} failed: {}", host, error);
+                return;
+            }
+        }
+        cookie = cookie.wrapping_add(1);
+    }
+}
+
+// Asks an established peer for up to `amount` peers. Peers which did not
+// enable peer sharing in the handshake share none.
+async fn share_peers(
+    connection: Arc<PeerConnection>,
+    amount: u8,
+    reports: mpsc::UnboundedSender<Report>,
+) {
+    let host = connection.peer().to_owned();
+    let handle = connection
+        .protocol(MINI_PROTOCOL_ID_PEER_SHARING)
+        .filter(|_| connection.peer_sharing());
+    let peers = match handle {
+        Some(handle) => {
+            let shared = timeout(SHARE_TIMEOUT, async {
+                peer_sharing::share_request(&mut *handle.lock().await, amount).await
+            });
+            match shared.await {
+                Ok(Ok(peers)) => peers.iter().map(SocketAddr::to_string).collect(),
+                Ok(Err(error)) => {
+                    warn!("Peer sharing with {} failed: {}", host, error);
+                    vec![]
+                }
+                Err(_) => {
+                    warn!("{} shared no peers in time", host);
+                    vec![]
+                }
+            }
+        }
+        None => vec![],
+    };
+    let _ = reports.send(Report::SharedPeers(host, peers));
+}
+
+/// Follows the chain of `connection` from the tip of `chain_db`, adding its
+/// blocks, until the tip of the peer is reached or, with `--follow`, for
+/// ever, or until `--limit` blocks were fetched in all. `fetched` counts
+/// the blocks added, also when following fails. Following stops with the
+/// client's agency, so that the connection can be closed gracefully. The
+/// governor is told how long block-fetch took and which blocks became the
+/// tip of the ChainDB through `reports`.
+async fn follow(
+    connection: &PeerConnection,
+    chain_db: &mut ChainDb,
+    args: &SyncArgs,
+    fetched: &mut u64,
+    reports: Option<&mpsc::UnboundedSender<Report>>,
+) -> Result<(), String> {
+    let host = connection.peer();
+    let mut chain_sync = connection
+        .protocol(MINI_PROTOCOL_ID_CHAIN_SYNC)
+        .ok_or("No chain-sync channel")?
+        .lock()
+        .await;
+    let mut block_fetch = connection
+        .protocol(MINI_PROTOCOL_ID_BLOCK_FETCH)
+        .ok_or("No block-fetch channel")?
+        .lock()
+        .await;
+
+    let mut points = vec![
+        chain_db.tip().point,
+        chain_db.immutable_tip(),
+        Point::Origin,
+    ];
+    points.dedup();
+    let (intersection, remote_tip) = chain_sync::find_intersect(&mut chain_sync, &points).await?;
+    info!(
+        "Intersection with {} at {:?}, its tip is {:?}",
+        host, intersection, remote_tip
+    );
+    if !args.follow && intersection == Some(remote_tip.point) {
+        return Ok(());
+    }
+
+    let mut pending: Vec<Point> = vec![];
+    loop {
+        let message = match chain_sync::request_next(&mut chain_sync).await? {
+            // The server has agency until it sends the update, even when
+            // not following its tip moved since its last update
+            Message::AwaitReply => {
+                *fetched += fetch(&mut block_fetch, chain_db, &mut pending, host, reports).await?;
+                info!("At the tip of {}, waiting for blocks", host);
+                chain_sync::recv(&mut chain_sync).await?
+            }
+            message => message,
+        };
+        let (point, tip) = match message {
+            Message::RollForward(header, tip) => {
+                let point = header.summary()?.info.point();
+                pending.push(point);
+                (point, tip)
+            }
+            // Blocks not fetched yet may be gone, fetched ones stay in the
+            // volatile store for chain selection
+            Message::RollBackward(point, tip) => {
+                pending.retain(|p| p.slot() <= point.slot());
+                (point, tip)
+            }
+            message => return Err(format!("Expected update, got {:?}", message)),
+        };
+        let at_tip = !args.follow && point == tip.point;
+        let limit_reached = args
+            .limit
+            .is_some_and(|limit| *fetched + pending.len() as u64 >= limit);
+        if pending.len() >= FETCH_BATCH || limit_reached || at_tip {
+            *fetched += fetch(&mut block_fetch, chain_db, &mut pending, host, reports).await?;
+        }
+        if limit_reached || at_tip {
+            return Ok(());
+        }
+    }
+}
+
+async fn fetch(
+    channel: &mut Channel,
+    chain_db: &mut ChainDb,
+    pending: &mut Vec<Point>,
+    host: &str,
+    reports: Option<&mpsc::UnboundedSender<Report>>,
+) -> Result<u64, String> {
+    let (from, to) = match (pending.first(), pending.last()) {
+        (Some(from), Some(to)) => (*from, *to),
+        _ => return Ok(0),
+    };
+    let start = Instant::now();
+    let blocks = block_fetch::fetch_range(channel, &from, &to).await?;
+    let report = |report| {
+        if let Some(reports) = reports {
+            let _ = reports.send(report);
+        }
+    };
+    if blocks.is_empty() {
+        warn!("No blocks from {:?} to {:?}", from, to);
+    } else {
+        report(Report::FetchLatency(host.to_owned(), start.elapsed()));
+    }
+    for block in &blocks {
+        let tip = chain_db.tip().point;
+        chain_db.add_block(block).map_err(|e| e.to_string())?;
+        if chain_db.tip().point != tip {
+            report(Report::TipContribution(host.to_owned()));
+        }
+    }
+    pending.clear();
+    export_tip(&chain_db.tip());
+    info!("Fetched {} blocks, tip {:?}", blocks.len(), chain_db.tip());
+    Ok(blocks.len() as u64)
+}
+
+pub async fn fetch_block(
+    app_config: &AppConfig,
+    args: &FetchBlockArgs,
+    output: OutputFormat,
+) -> Result<(), String> {
+    let host = first_host(app_config)?;
+    let point = Point::Specific(args.slot, parse_hash(&args.hash)?);
+    let connection = connect(
+        &host,
+        &app_config.supported_versions,
+        args.capture.as_deref(),
+    )
+    .await?;
+    let mut channel = connection
+        .protocol(MINI_PROTOCOL_ID_BLOCK_FETCH)
+        .ok_or("No block-fetch channel")?
+        .lock()
+        .await;
+    let block = block_fetch::fetch_range(&mut channel, &point, &point).await?;
+    drop(channel);
+    connection.close().await;
+    let block = block.into_iter().next().ok_or(format!(
+        "{} does not have block {}",
+        host.host,
+        point_text(&point)
+    ))?;
+
+    let era = split_era(&block)?.0;
+    if let Some(out) = &args.out {
+        std::fs::write(out, &block)
+            .map_err(|error| format!("Could not write {:?}: {}", out, error))?;
+    }
+    match (output, &args.out) {
+        (OutputFormat::Text, Some(out)) => {
+            println!("Wrote {} block of {} bytes to {:?}", era, block.len(), out)
+        }
+        (OutputFormat::Text, None) => println!("{}", hex(&block)),
+        (_, out) => {
+            let mut json = serde_json::json!({
+                "point": point_json(&point),
+                "era": era.to_string(),
+                "size": block.len(),
+            });
+            match out {
+                Some(out) => json["file"] = serde_json::json!(out),
+                None => json["cbor"] = serde_json::json!(hex(&block)),
+            }
+            print_json(&json, output)?;
+        }
+    }
+    Ok(())
+}
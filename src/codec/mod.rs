@@ -0,0 +1,3 @@
+mod raw;
+
+pub use self::raw::{item_len, split_array, ItemError};
@@ -0,0 +1,257 @@
+// Raw CBOR item splitting.
+//
+// Hashes on Cardano (transaction ids, block body hashes, KES signed header
+// bodies) are computed over the bytes exactly as they were received. Decoding
+// into `ciborium::Value` and encoding again does not preserve those bytes, so
+// here we only walk item boundaries and hand out the original slices.
+
+use std::fmt;
+
+const BREAK: u8 = 0xff;
+// Deepest nesting of arrays, maps and tags walked, as ciborium's decoder
+const MAX_DEPTH: usize = 256;
+
+/// Why `item_len` found no whole item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemError {
+    // The item was cut short, more bytes may complete it
+    Incomplete(&'static str),
+    // The bytes are no CBOR item we walk, or nest too deep
+    Invalid(String),
+}
+
+impl fmt::Display for ItemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemError::Incomplete(message) => write!(f, "{}", message),
+            ItemError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ItemError> for String {
+    fn from(error: ItemError) -> String {
+        error.to_string()
+    }
+}
+
+/// Argument of a CBOR header. `None` stands for indefinite length.
+fn header(bytes: &[u8]) -> Result<(u8, Option<u64>, usize), ItemError> {
+    let initial = *bytes
+        .first()
+        .ok_or(ItemError::Incomplete("Unexpected end of CBOR input"))?;
+    let major = initial >> 5;
+    let info = initial & 0x1f;
+    let (argument, header_len) = match info {
+        0..=23 => (Some(info as u64), 1),
+        24..=27 => {
+            let size = 1 << (info - 24);
+            let argument = bytes
+                .get(1..1 + size)
+                .ok_or(ItemError::Incomplete("Unexpected end of CBOR header"))?
+                .iter()
+                .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            (Some(argument), 1 + size)
+        }
+        31 if (2..=5).contains(&major) => (None, 1),
+        _ => {
+            return Err(ItemError::Invalid(format!(
+                "Invalid CBOR additional info {} for major type {}",
+                info, major
+            )))
+        }
+    };
+    Ok((major, argument, header_len))
+}
+
+/// Returns the length in bytes of the CBOR data item at the start of `bytes`.
+pub fn item_len(bytes: &[u8]) -> Result<usize, ItemError> {
+    nested_item_len(bytes, 0)
+}
+
+fn nested_item_len(bytes: &[u8], depth: usize) -> Result<usize, ItemError> {
+    if depth > MAX_DEPTH {
+        return Err(ItemError::Invalid(format!(
+            "CBOR nested deeper than {} levels",
+            MAX_DEPTH
+        )));
+    }
+    let (major, argument, header_len) = header(bytes)?;
+    let rest = &bytes[header_len..];
+    let len = match (major, argument) {
+        // Unsigned, negative integers and simple values / floats
+        (0 | 1 | 7, Some(_)) => header_len,
+        // Byte and text strings
+        (2 | 3, Some(len)) => header_len.saturating_add(len as usize),
+        (2 | 3, None) => header_len + items_until_break(rest, depth)?,
+        // Arrays
+        (4, Some(count)) => header_len + items_len(rest, count, depth)?,
+        // Maps hold a key and a value per entry
+        (5, Some(count)) => header_len + items_len(rest, count.saturating_mul(2), depth)?,
+        (4 | 5, None) => header_len + items_until_break(rest, depth)?,
+        // Tags wrap exactly one item
+        (6, Some(_)) => header_len + nested_item_len(rest, depth + 1)?,
+        _ => {
+            return Err(ItemError::Invalid(format!(
+                "Unsupported CBOR major type {}",
+                major
+            )))
+        }
+    };
+    if len > bytes.len() {
+        return Err(ItemError::Incomplete("Unexpected end of CBOR item"));
+    }
+    Ok(len)
+}
+
+fn items_len(bytes: &[u8], count: u64, depth: usize) -> Result<usize, ItemError> {
+    let mut offset = 0;
+    for _ in 0..count {
+        let rest = bytes
+            .get(offset..)
+            .ok_or(ItemError::Incomplete("Unexpected end of CBOR input"))?;
+        offset += nested_item_len(rest, depth + 1)?;
+    }
+    Ok(offset)
+}
+
+fn items_until_break(bytes: &[u8], depth: usize) -> Result<usize, ItemError> {
+    let mut offset = 0;
+    loop {
+        match bytes.get(offset) {
+            Some(&BREAK) => return Ok(offset + 1),
+            Some(_) => offset += nested_item_len(&bytes[offset..], depth + 1)?,
+            None => {
+                return Err(ItemError::Incomplete(
+                    "Missing break for indefinite length item",
+                ))
+            }
+        }
+    }
+}
+
+/// Splits a CBOR array into the raw bytes of each of its elements.
+pub fn split_array(bytes: &[u8]) -> Result<Vec<&[u8]>, String> {
+    let (major, argument, header_len) = header(bytes)?;
+    if major != 4 {
+        return Err(format!("Expected CBOR array, found major type {}", major));
+    }
+    let mut elements = vec![];
+    let mut offset = header_len;
+    loop {
+        match argument {
+            Some(count) if elements.len() as u64 == count => break,
+            None if bytes.get(offset) == Some(&BREAK) => break,
+            _ => {}
+        }
+        let rest = bytes.get(offset..).ok_or("Unexpected end of CBOR array")?;
+        let len = item_len(rest)?;
+        elements.push(&rest[..len]);
+        offset += len;
+    }
+    Ok(elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::{into_writer, Value};
+
+    #[test]
+    fn item_len_of_nested_values() {
+        let value = Value::Array(vec![
+            Value::from(1_000_000),
+            Value::Bytes(vec![0; 40]),
+            Value::Map(vec![(Value::from(0), Value::Text("abc".to_owned()))]),
+            Value::Tag(258, Box::new(Value::Array(vec![Value::Bool(true)]))),
+        ]);
+        let mut bytes = vec![];
+        into_writer(&value, &mut bytes).unwrap();
+        let len = bytes.len();
+        bytes.extend_from_slice(&[0x01, 0x02]);
+
+        assert_eq!(item_len(&bytes).unwrap(), len);
+        assert_eq!(split_array(&bytes).unwrap().len(), 4);
+        for end in 0..len {
+            assert!(matches!(
+                item_len(&bytes[..end]),
+                Err(ItemError::Incomplete(_))
+            ));
+        }
+        assert!(matches!(item_len(&[0x1c]), Err(ItemError::Invalid(_))));
+    }
+
+    #[test]
+    fn split_indefinite_array() {
+        // [_ 1, h'0102', [_ ]]
+        let bytes = [0x9f, 0x01, 0x42, 0x01, 0x02, 0x9f, 0xff, 0xff];
+        let elements = split_array(&bytes).unwrap();
+        assert_eq!(
+            elements,
+            vec![&[0x01][..], &[0x42, 0x01, 0x02], &[0x9f, 0xff]]
+        );
+        assert_eq!(item_len(&bytes).unwrap(), bytes.len());
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert!(item_len(&[0x82, 0x01]).is_err());
+        assert!(item_len(&[0x58, 0x20, 0x00]).is_err());
+        assert!(split_array(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // [[[...1...]]] one level deeper than walked
+        let mut bytes = vec![0x81; MAX_DEPTH + 1];
+        bytes.push(0x01);
+        assert!(item_len(&bytes).is_err());
+        assert_eq!(item_len(&bytes[1..]), Ok(MAX_DEPTH + 1));
+        // A complete message nested too deep is not waited on
+        let mut bytes = vec![0x81; 1_000_000];
+        bytes.push(0x01);
+        assert!(matches!(item_len(&bytes), Err(ItemError::Invalid(_))));
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                any::<i64>().prop_map(Value::from),
+                any::<bool>().prop_map(Value::Bool),
+                prop::collection::vec(any::<u8>(), 0..64).prop_map(Value::Bytes),
+                any::<String>().prop_map(Value::Text),
+            ];
+            leaf.prop_recursive(4, 64, 8, |inner| {
+                prop_oneof![
+                    prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+                    prop::collection::vec((inner.clone(), inner.clone()), 0..4)
+                        .prop_map(Value::Map),
+                    (any::<u64>(), inner).prop_map(|(tag, v)| Value::Tag(tag, Box::new(v))),
+                ]
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn item_len_of_encoded_values(value in value(), trailing in any::<u8>()) {
+                let mut bytes = vec![];
+                into_writer(&value, &mut bytes).unwrap();
+                let len = bytes.len();
+                bytes.push(trailing);
+                prop_assert_eq!(item_len(&bytes), Ok(len));
+                prop_assert!(item_len(&bytes[..len - 1]).is_err());
+            }
+
+            #[test]
+            fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+                if let Ok(len) = item_len(&bytes) {
+                    prop_assert!(len <= bytes.len());
+                }
+                let _ = split_array(&bytes);
+            }
+        }
+    }
+}
@@ -0,0 +1,404 @@
+// Genesis files of the four eras that have one.
+//
+// Only the fields this node needs are read. The hashes cardano-node's
+// config.json pins them with are Blake2b-256 of the file bytes, except for
+// Byron where the hash is over the canonical JSON rendering of the file.
+
+use crate::consensus::UnitInterval;
+use crate::crypto::{blake2b_256, Hash32};
+use num_integer::Integer;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{fs, path::Path};
+
+/// An exact rational number. Genesis files write them either as decimal
+/// numbers or as `{"numerator": n, "denominator": d}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RationalRepr")]
+pub struct Rational {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RationalRepr {
+    Decimal(serde_json::Number),
+    Fraction { numerator: u64, denominator: u64 },
+}
+
+impl TryFrom<RationalRepr> for Rational {
+    type Error = String;
+
+    fn try_from(repr: RationalRepr) -> Result<Self, Self::Error> {
+        match repr {
+            RationalRepr::Decimal(number) => Rational::from_decimal(&number.to_string()),
+            RationalRepr::Fraction {
+                numerator,
+                denominator,
+            } => Rational::new(numerator, denominator),
+        }
+    }
+}
+
+impl Rational {
+    pub fn new(numerator: u64, denominator: u64) -> Result<Rational, String> {
+        if denominator == 0 {
+            return Err(format!("{}/0 has a zero denominator", numerator));
+        }
+        let gcd = numerator.gcd(&denominator);
+        Ok(Rational {
+            numerator: numerator / gcd,
+            denominator: denominator / gcd,
+        })
+    }
+
+    /// Parses a non-negative decimal like `0.05`, `15` or `7.21e-5`.
+    pub fn from_decimal(decimal: &str) -> Result<Rational, String> {
+        let invalid = || format!("Invalid decimal {:?}", decimal);
+        let (mantissa, exponent) = match decimal.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => {
+                (mantissa, exponent.parse::<i32>().map_err(|_| invalid())?)
+            }
+            None => (decimal, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = format!("{}{}", integer, fraction);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let scale = exponent - fraction.len() as i32;
+        let ten = |power: i32| 10u64.checked_pow(power as u32).ok_or_else(invalid);
+        let mut numerator: u64 = digits.parse().map_err(|_| invalid())?;
+        let mut denominator = 1;
+        match scale {
+            0.. => numerator = numerator.checked_mul(ten(scale)?).ok_or_else(invalid)?,
+            _ => denominator = ten(-scale)?,
+        }
+        Rational::new(numerator, denominator)
+    }
+
+    pub fn to_unit_interval(self) -> Result<UnitInterval, String> {
+        UnitInterval::new(self.numerator, self.denominator)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ByronGenesis {
+    pub protocol_consts: ByronProtocolConsts,
+    // Unix time in seconds
+    pub start_time: u64,
+    pub block_version_data: ByronBlockVersionData,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ByronProtocolConsts {
+    pub k: u64,
+    pub protocol_magic: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ByronBlockVersionData {
+    // Milliseconds, written as a string
+    pub slot_duration: String,
+}
+
+impl ByronGenesis {
+    pub fn slot_duration_ms(&self) -> Result<u64, String> {
+        self.block_version_data.slot_duration.parse().map_err(|_| {
+            format!(
+                "Invalid slot duration {:?}",
+                self.block_version_data.slot_duration
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShelleyGenesis {
+    pub network_magic: u32,
+    pub network_id: String,
+    pub security_param: u64,
+    pub active_slots_coeff: Rational,
+    // Seconds
+    pub slot_length: Rational,
+    pub epoch_length: u64,
+    // ISO 8601 UTC, e.g. "2017-09-23T21:44:51Z"
+    pub system_start: String,
+    #[serde(rename = "slotsPerKESPeriod")]
+    pub slots_per_kes_period: u64,
+    #[serde(rename = "maxKESEvolutions")]
+    pub max_kes_evolutions: u64,
+    pub max_lovelace_supply: u64,
+    pub protocol_params: ShelleyProtocolParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShelleyProtocolParams {
+    pub protocol_version: ProtocolVersion,
+    pub min_fee_a: u64,
+    pub min_fee_b: u64,
+    pub max_block_body_size: u64,
+    pub max_tx_size: u64,
+    pub max_block_header_size: u64,
+    pub key_deposit: u64,
+    pub pool_deposit: u64,
+    pub e_max: u64,
+    pub n_opt: u64,
+    pub a0: Rational,
+    pub rho: Rational,
+    pub tau: Rational,
+    #[serde(default)]
+    pub min_pool_cost: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ProtocolVersion {
+    pub major: u64,
+    pub minor: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlonzoGenesis {
+    #[serde(rename = "lovelacePerUTxOWord", alias = "coinsPerUTxOWord")]
+    pub coins_per_utxo_word: u64,
+    pub execution_prices: ExecutionPrices,
+    pub max_tx_ex_units: ExUnits,
+    pub max_block_ex_units: ExUnits,
+    pub max_value_size: u64,
+    pub collateral_percentage: u64,
+    pub max_collateral_inputs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ExecutionPrices {
+    #[serde(rename = "prSteps", alias = "priceSteps")]
+    pub steps: Rational,
+    #[serde(rename = "prMem", alias = "priceMemory")]
+    pub memory: Rational,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ExUnits {
+    #[serde(rename = "exUnitsMem", alias = "memory")]
+    pub memory: u64,
+    #[serde(rename = "exUnitsSteps", alias = "steps")]
+    pub steps: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConwayGenesis {
+    pub committee_min_size: u64,
+    pub committee_max_term_length: u64,
+    pub gov_action_lifetime: u64,
+    pub gov_action_deposit: u64,
+    #[serde(rename = "dRepDeposit")]
+    pub drep_deposit: u64,
+    #[serde(rename = "dRepActivity")]
+    pub drep_activity: u64,
+    pub min_fee_ref_script_cost_per_byte: Rational,
+}
+
+/// Reads a genesis file and checks it against the hash pinned in the node
+/// configuration, if any. Returns the file's hash along with its content.
+pub fn load_genesis<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    expected_hash: Option<&Hash32>,
+) -> Result<(T, Hash32), String> {
+    let path = path.as_ref();
+    let bytes =
+        fs::read(path).map_err(|error| format!("Could not read genesis {:?}: {}", path, error))?;
+    let genesis = serde_json::from_slice(&bytes)
+        .map_err(|error| format!("Could not parse genesis {:?}: {}", path, error))?;
+    let hash = blake2b_256(&bytes);
+    check_hash(path, hash, expected_hash)?;
+    Ok((genesis, hash))
+}
+
+/// Same as `load_genesis` for the Byron genesis, whose hash is over its
+/// canonical JSON.
+pub fn load_byron_genesis(
+    path: impl AsRef<Path>,
+    expected_hash: Option<&Hash32>,
+) -> Result<(ByronGenesis, Hash32), String> {
+    let path = path.as_ref();
+    let bytes =
+        fs::read(path).map_err(|error| format!("Could not read genesis {:?}: {}", path, error))?;
+    let value: serde_json::Value = serde_json::from_slice(&bytes)
+        .map_err(|error| format!("Could not parse genesis {:?}: {}", path, error))?;
+    let mut canonical = String::new();
+    canonical_json(&value, &mut canonical)?;
+    let hash = blake2b_256(canonical.as_bytes());
+    check_hash(path, hash, expected_hash)?;
+    let genesis = serde_json::from_value(value)
+        .map_err(|error| format!("Could not parse genesis {:?}: {}", path, error))?;
+    Ok((genesis, hash))
+}
+
+fn check_hash(path: &Path, hash: Hash32, expected: Option<&Hash32>) -> Result<(), String> {
+    match expected {
+        Some(expected) if *expected != hash => Err(format!(
+            "Genesis {:?} has hash {}, expected {}",
+            path,
+            hex(&hash),
+            hex(expected)
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Canonical JSON as rendered by Haskell's canonical-json: no whitespace, keys
+// sorted, integers only, and only `"` and `\` escaped in strings.
+fn canonical_json(value: &serde_json::Value, out: &mut String) -> Result<(), String> {
+    use serde_json::Value;
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(bool) => out.push_str(if *bool { "true" } else { "false" }),
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(integer), _) => out.push_str(&integer.to_string()),
+            (_, Some(integer)) => out.push_str(&integer.to_string()),
+            _ => return Err(format!("Canonical JSON has no fractions: {}", number)),
+        },
+        Value::String(string) => canonical_string(string, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                canonical_json(item, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            // serde_json keeps objects sorted by key
+            out.push('{');
+            for (i, (key, item)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                canonical_string(key, out);
+                out.push(':');
+                canonical_json(item, out)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+fn canonical_string(string: &str, out: &mut String) {
+    out.push('"');
+    for char in string.chars() {
+        if char == '"' || char == '\\' {
+            out.push('\\');
+        }
+        out.push(char);
+    }
+    out.push('"');
+}
+
+/// Seconds since the Unix epoch of a UTC timestamp like
+/// `2017-09-23T21:44:51Z`. Fractional seconds are dropped.
+pub fn parse_utc_timestamp(timestamp: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid UTC timestamp {:?}", timestamp);
+    let (date, time) = timestamp
+        .strip_suffix('Z')
+        .and_then(|rest| rest.split_once('T'))
+        .ok_or_else(invalid)?;
+    let numbers = |part: &str, separator: char| -> Result<Vec<u64>, String> {
+        part.split(separator)
+            .map(|number| number.parse::<u64>().map_err(|_| invalid()))
+            .collect()
+    };
+    let date = numbers(date, '-')?;
+    let time = numbers(time.split('.').next().unwrap_or(time), ':')?;
+    let (year, month, day) = match date[..] {
+        [year, month @ 1..=12, day @ 1..=31] if year >= 1970 => (year, month, day),
+        _ => return Err(invalid()),
+    };
+    let (hour, minute, second) = match time[..] {
+        [hour @ 0..=23, minute @ 0..=59, second @ 0..=60] => (hour, minute, second),
+        _ => return Err(invalid()),
+    };
+
+    // Days from civil date, with years starting in March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Ok(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rationals_from_genesis_notations() {
+        let rational = |json: &str| serde_json::from_str::<Rational>(json).unwrap();
+        assert_eq!(rational("0.05"), Rational::new(1, 20).unwrap());
+        assert_eq!(rational("7.21e-5"), Rational::new(721, 10_000_000).unwrap());
+        assert_eq!(rational("15"), Rational::new(15, 1).unwrap());
+        assert_eq!(
+            rational(r#"{"numerator": 577, "denominator": 10000}"#),
+            Rational::new(577, 10_000).unwrap()
+        );
+        assert!(Rational::from_decimal("-0.5").is_err());
+    }
+
+    #[test]
+    fn utc_timestamps() {
+        // Mainnet, preprod and preview system starts
+        assert_eq!(
+            parse_utc_timestamp("2017-09-23T21:44:51Z").unwrap(),
+            1_506_203_091
+        );
+        assert_eq!(
+            parse_utc_timestamp("2022-06-01T00:00:00Z").unwrap(),
+            1_654_041_600
+        );
+        assert_eq!(
+            parse_utc_timestamp("2022-10-25T00:00:00.000Z").unwrap(),
+            1_666_656_000
+        );
+        assert!(parse_utc_timestamp("2022-13-01T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn byron_hash_over_canonical_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("byron-genesis.json");
+        fs::write(
+            &path,
+            r#"{
+                "startTime": 1506203091,
+                "protocolConsts": { "protocolMagic": 764824073, "k": 2160 },
+                "blockVersionData": { "slotDuration": "20000" },
+                "ftsSeed": "a\"b"
+            }"#,
+        )
+        .unwrap();
+        let canonical = r#"{"blockVersionData":{"slotDuration":"20000"},"ftsSeed":"a\"b","protocolConsts":{"k":2160,"protocolMagic":764824073},"startTime":1506203091}"#;
+        let expected = blake2b_256(canonical.as_bytes());
+
+        let (genesis, hash) = load_byron_genesis(&path, Some(&expected)).unwrap();
+        assert_eq!(hash, expected);
+        assert_eq!(genesis.protocol_consts.k, 2160);
+        assert_eq!(genesis.slot_duration_ms().unwrap(), 20_000);
+        assert!(load_byron_genesis(&path, Some(&[0; 32])).is_err());
+    }
+}
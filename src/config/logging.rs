@@ -0,0 +1,233 @@
+// Logging settings, the `logging` key of App.yaml. Every field is optional:
+//
+//   logging:
+//     format: json            # text (default) or json
+//     level: info             # level of everything not listed in `levels`
+//     levels:                 # per module of this crate
+//       handshake: debug
+//       mux: trace
+//     span_events: false      # also log when spans are entered and closed
+//     file:                   # written in addition to stderr
+//       directory: logs
+//       prefix: cardano_rust_node.log
+//       rotation: daily       # minutely, hourly, daily or never
+//       max_files: 7
+//
+// RUST_LOG, when set, replaces `level` and `levels`.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::Subscriber;
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{self, format::FmtSpan, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    EnvFilter, Layer,
+};
+
+// Target prefix of the modules named in `levels`
+const CRATE_TARGET: &str = "cardano_rust_node";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // One JSON object per event
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LogFileConfig {
+    pub directory: PathBuf,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub rotation: Rotation,
+    // Older files are deleted on rotation, all are kept without it
+    pub max_files: Option<usize>,
+}
+
+fn default_prefix() -> String {
+    format!("{}.log", CRATE_TARGET)
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub level: String,
+    pub levels: BTreeMap<String, String>,
+    pub span_events: bool,
+    pub file: Option<LogFileConfig>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Text,
+            level: "info".to_owned(),
+            levels: BTreeMap::new(),
+            span_events: false,
+            file: None,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// `EnvFilter` directives of the configured levels, `rust_log` instead
+    /// when given.
+    fn directives(&self, rust_log: Option<&str>) -> Result<String, String> {
+        if let Some(rust_log) = rust_log.filter(|rust_log| !rust_log.trim().is_empty()) {
+            return Ok(rust_log.to_owned());
+        }
+        // A bare word would be taken as a target by EnvFilter
+        let level = |level: &str| {
+            LevelFilter::from_str(level).map_err(|_| format!("Invalid log level {:?}", level))
+        };
+        let mut directives = vec![level(&self.level)?.to_string()];
+        for (module, module_level) in &self.levels {
+            directives.push(format!(
+                "{}::{}={}",
+                CRATE_TARGET,
+                module,
+                level(module_level)?
+            ));
+        }
+        Ok(directives.join(","))
+    }
+}
+
+/// Installs the global subscriber. Logs go to stderr, results are printed to
+/// stdout. The returned guard flushes the log file when dropped and must be
+/// kept until the end of the program. Fails when a subscriber is already
+/// installed.
+pub fn enable_tracing(logging: &LoggingConfig) -> Result<Option<WorkerGuard>, String> {
+    let directives = logging.directives(std::env::var("RUST_LOG").ok().as_deref())?;
+    let filter = EnvFilter::try_new(&directives)
+        .map_err(|error| format!("Invalid log levels {:?}: {}", directives, error))?;
+    let span_events = match logging.span_events {
+        true => FmtSpan::FULL,
+        false => FmtSpan::NONE,
+    };
+
+    let mut layers = vec![layer(
+        logging.format,
+        span_events.clone(),
+        true,
+        std::io::stderr,
+    )];
+    let mut guard = None;
+    if let Some(file) = &logging.file {
+        let rotation = match file.rotation {
+            Rotation::Minutely => rolling::Rotation::MINUTELY,
+            Rotation::Hourly => rolling::Rotation::HOURLY,
+            Rotation::Daily => rolling::Rotation::DAILY,
+            Rotation::Never => rolling::Rotation::NEVER,
+        };
+        let mut builder = rolling::RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(&file.prefix);
+        if let Some(max_files) = file.max_files {
+            builder = builder.max_log_files(max_files);
+        }
+        let appender = builder.build(&file.directory).map_err(|error| {
+            format!("Could not open log file in {:?}: {}", file.directory, error)
+        })?;
+        let (writer, file_guard) = tracing_appender::non_blocking(appender);
+        layers.push(layer(logging.format, span_events, false, writer));
+        guard = Some(file_guard);
+    }
+
+    let subscriber = tracing_subscriber::registry().with(filter).with(layers);
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|_| "Tracing is already enabled".to_owned())?;
+    Ok(guard)
+}
+
+fn layer<S, W>(
+    format: LogFormat,
+    span_events: FmtSpan,
+    ansi: bool,
+    writer: W,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer()
+        .with_span_events(span_events)
+        .with_ansi(ansi)
+        .with_thread_ids(true)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => layer
+            .compact()
+            .with_file(false)
+            .with_line_number(false)
+            .with_target(false)
+            .boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use figment::{
+        providers::{Format, Yaml},
+        Figment,
+    };
+
+    #[test]
+    fn logging_settings() {
+        let logging: LoggingConfig = Figment::new()
+            .merge(Yaml::string(
+                r#"
+                format: json
+                level: warn
+                levels:
+                  handshake: debug
+                  mux: trace
+                file:
+                  directory: logs
+                  rotation: hourly
+                "#,
+            ))
+            .extract()
+            .unwrap();
+        assert_eq!(logging.format, LogFormat::Json);
+        assert!(!logging.span_events);
+        let file = logging.file.as_ref().unwrap();
+        assert_eq!(file.prefix, "cardano_rust_node.log");
+        assert_eq!(file.rotation, Rotation::Hourly);
+        assert_eq!(
+            logging.directives(None).unwrap(),
+            "warn,cardano_rust_node::handshake=debug,cardano_rust_node::mux=trace"
+        );
+        assert_eq!(logging.directives(Some("debug")).unwrap(), "debug");
+        assert_eq!(
+            LoggingConfig::default().directives(Some(" ")).unwrap(),
+            "info"
+        );
+        let loud = LoggingConfig {
+            level: "loud".to_owned(),
+            ..LoggingConfig::default()
+        };
+        assert!(loud.directives(None).is_err());
+    }
+}
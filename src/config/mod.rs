@@ -0,0 +1,310 @@
+mod genesis;
+mod logging;
+mod network;
+mod node;
+mod topology;
+
+pub use self::genesis::{
+    load_byron_genesis, load_genesis, parse_utc_timestamp, AlonzoGenesis, ByronGenesis,
+    ConwayGenesis, ExUnits, ExecutionPrices, ProtocolVersion, Rational, ShelleyGenesis,
+};
+pub use self::logging::{enable_tracing, LogFileConfig, LogFormat, LoggingConfig, Rotation};
+pub use self::network::{Network, NetworkPreset};
+pub use self::node::{CardanoNodeConfig, NetworkParameters, ProtocolParameters};
+pub use self::topology::{PeerSource, Topology, TopologyPeer};
+
+use crate::ledger::{LedgerPeerSnapshot, LedgerPool};
+use figment::{
+    providers::{Env, Format, Serialized, Yaml},
+    Figment,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub hosts: Vec<HostConfig>,
+    pub supported_versions: Vec<i64>,
+    // Preset supplying the magic of hosts that have none and, without
+    // `hosts` and `topology`, the relays to connect to
+    pub network: Option<Network>,
+    // cardano-node topology.json whose peers are added to `hosts`
+    pub topology: Option<TopologyConfig>,
+    // cardano-node config.json, the network magic of hosts without one is
+    // taken from its genesis files
+    pub node_config: Option<String>,
+    // Ledger peer snapshot of cardano-cli, whose stake pool relays are
+    // added after the other hosts
+    pub ledger_peer_snapshot: Option<String>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct HostConfig {
+    pub host: String,
+    // Overrides the magic of `network` and of the application's network
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_magic: Option<u32>,
+    #[serde(default)]
+    pub network_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+}
+
+// Environment variables with this prefix set the keys of App.yaml, e.g.
+// CARDANO_RUST_NODE_NETWORK=preview or
+// CARDANO_RUST_NODE_SUPPORTED_VERSIONS=[13,14]
+pub const ENV_PREFIX: &str = "CARDANO_RUST_NODE_";
+
+/// Settings given on the command line. They take precedence over the
+/// environment, which takes precedence over the configuration file.
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+    // Replaces the hosts of the configuration, its topology peers and its
+    // ledger peers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<Vec<HostConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported_versions: Option<Vec<i64>>,
+}
+
+pub fn get_app_config(
+    path: &str,
+    overrides: &ConfigOverrides,
+) -> Result<AppConfig, Box<figment::Error>> {
+    let mut app_config: AppConfig = Figment::new()
+        .merge(Yaml::file(path))
+        .merge(Env::prefixed(ENV_PREFIX))
+        .merge(Serialized::defaults(overrides))
+        .extract()
+        .map_err(Box::new)?;
+    if overrides.hosts.is_some() {
+        app_config.topology = None;
+        app_config.ledger_peer_snapshot = None;
+    }
+    Ok(app_config)
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct TopologyConfig {
+    pub path: String,
+    pub network_magic: Option<u32>,
+    #[serde(default)]
+    pub network_id: String,
+}
+
+impl AppConfig {
+    /// Magic of the application's network: the one of its preset, else the
+    /// one of the node config's genesis.
+    pub fn network_magic(&self) -> Result<u32, String> {
+        match (self.network, &self.node_config) {
+            (Some(network), _) => Ok(network.preset().network_magic),
+            (None, Some(node_config)) => Ok(NetworkParameters::load(node_config)?.network_magic),
+            (None, None) => Err("Either network or node_config is needed".to_owned()),
+        }
+    }
+
+    /// Root hosts followed by the relays of the ledger peer snapshot that
+    /// can be connected to, in the order of the snapshot. A host
+    /// listed twice is only kept once. Every returned host has a network
+    /// magic: its own, else the one of its preset, of the application's
+    /// preset or of the node config's genesis, in that order.
+    pub fn peer_hosts(&self) -> Result<Vec<HostConfig>, String> {
+        let mut hosts = self.configured_hosts()?;
+        let relays = self
+            .ledger_pools()?
+            .into_iter()
+            .flat_map(|pool| pool.relays);
+        for relay in relays {
+            if let Some(host) = relay.host() {
+                if hosts.iter().all(|known| known.host != host) {
+                    hosts.push(HostConfig {
+                        host,
+                        network_magic: None,
+                        network_id: String::new(),
+                        network: None,
+                    });
+                }
+            }
+        }
+        self.resolve(hosts)
+    }
+
+    /// Hosts of App.yaml followed by the peers of the topology file, if
+    /// configured, or else the relays of the network preset, with their
+    /// network magic resolved like for `peer_hosts`.
+    pub fn root_hosts(&self) -> Result<Vec<HostConfig>, String> {
+        self.resolve(self.configured_hosts()?)
+    }
+
+    /// Stake pools of the ledger peer snapshot, if configured.
+    pub fn ledger_pools(&self) -> Result<Vec<LedgerPool>, String> {
+        match &self.ledger_peer_snapshot {
+            Some(path) => Ok(LedgerPeerSnapshot::from_file(path)?.pools),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn configured_hosts(&self) -> Result<Vec<HostConfig>, String> {
+        let mut hosts = self.hosts.clone();
+        if let (true, None, Some(network)) = (hosts.is_empty(), &self.topology, self.network) {
+            hosts = network
+                .preset()
+                .relays
+                .iter()
+                .map(|relay| HostConfig {
+                    host: relay.to_string(),
+                    network_magic: None,
+                    network_id: String::new(),
+                    network: Some(network),
+                })
+                .collect();
+        }
+        if let Some(config) = &self.topology {
+            let topology = Topology::from_file(&config.path)?;
+            for host in topology.host_configs(config.network_magic, &config.network_id) {
+                if hosts.iter().all(|known| known.host != host.host) {
+                    hosts.push(host);
+                }
+            }
+        }
+        Ok(hosts)
+    }
+
+    fn resolve(&self, mut hosts: Vec<HostConfig>) -> Result<Vec<HostConfig>, String> {
+        for host in hosts.iter_mut() {
+            let network = host.network.or(self.network);
+            if host.network_magic.is_none() {
+                host.network_magic = network.map(|network| network.preset().network_magic);
+            }
+            if let (true, Some(network)) = (host.network_id.is_empty(), network) {
+                host.network_id = network.to_string();
+            }
+        }
+
+        if let (Some(node_config), Some(network)) = (&self.node_config, self.network) {
+            CardanoNodeConfig::from_file(node_config)?.check_preset(network)?;
+        }
+        if hosts.iter().any(|host| host.network_magic.is_none()) {
+            let node_config = self.node_config.as_ref().ok_or(
+                "Hosts without network_magic need node_config to derive it from".to_owned(),
+            )?;
+            let network = NetworkParameters::load(node_config)?;
+            for host in hosts.iter_mut() {
+                host.network_magic.get_or_insert(network.network_magic);
+            }
+        }
+        Ok(hosts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_config(yaml: &str) -> AppConfig {
+        Figment::new().merge(Yaml::string(yaml)).extract().unwrap()
+    }
+
+    #[test]
+    fn network_preset_supplies_relays_and_magic() {
+        let hosts = app_config("supported_versions: [10]\nnetwork: preprod")
+            .peer_hosts()
+            .unwrap();
+        assert_eq!(hosts[0].host, "preprod-node.world.dev.cardano.org:30000");
+        assert_eq!(hosts[0].network_magic, Some(1));
+        assert_eq!(hosts[0].network_id, "preprod");
+    }
+
+    #[test]
+    fn host_settings_override_the_preset() {
+        let hosts = app_config(
+            r#"
+            supported_versions: [10]
+            network: mainnet
+            hosts:
+              - host: "relays-new.cardano-mainnet.iohk.io:3001"
+              - host: "preview-node.world.dev.cardano.org:30002"
+                network: preview
+              - host: "0.0.0.0:3001"
+                network_id: "Local_Dev_Net"
+                network_magic: 42
+            "#,
+        )
+        .peer_hosts()
+        .unwrap();
+        let magics: Vec<Option<u32>> = hosts.iter().map(|host| host.network_magic).collect();
+        assert_eq!(magics, vec![Some(764824073), Some(2), Some(42)]);
+        assert_eq!(hosts[2].network_id, "Local_Dev_Net");
+
+        let error = app_config("supported_versions: [10]\nhosts: [{host: \"a:1\"}]")
+            .peer_hosts()
+            .unwrap_err();
+        assert!(error.contains("node_config"));
+    }
+
+    #[test]
+    fn ledger_peers_follow_the_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peer-snapshot.json");
+        std::fs::write(
+            &path,
+            r#"{"bigLedgerPools": [
+                {"relativeStake": 0.1, "relays": [{"domain": "a", "port": 1}]},
+                {"relativeStake": 0.2, "relays": [{"address": "10.0.0.1", "port": 3001},
+                                                  {"srv": "_cardano._tcp.b"}]}],
+                "slotNo": 1, "version": 2}"#,
+        )
+        .unwrap();
+        let app_config = app_config(&format!(
+            "supported_versions: [10]\nnetwork: preview\nhosts: [{{host: \"a:1\"}}]\n\
+             ledger_peer_snapshot: {:?}",
+            path
+        ));
+        let hosts: Vec<String> = app_config
+            .peer_hosts()
+            .unwrap()
+            .into_iter()
+            .map(|host| host.host)
+            .collect();
+        assert_eq!(hosts, vec!["a:1", "10.0.0.1:3001"]);
+        assert_eq!(app_config.root_hosts().unwrap().len(), 1);
+        assert_eq!(app_config.ledger_pools().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("App.yaml");
+        std::fs::write(
+            &path,
+            "supported_versions: [10]\nnetwork: mainnet\nhosts: [{host: \"a:1\"}]",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let app_config = get_app_config(path, &ConfigOverrides::default()).unwrap();
+        assert_eq!(app_config.network, Some(Network::Mainnet));
+
+        let overrides = ConfigOverrides {
+            network: Some(Network::Preview),
+            hosts: Some(vec![HostConfig {
+                host: "b:2".to_owned(),
+                network_magic: None,
+                network_id: String::new(),
+                network: None,
+            }]),
+            supported_versions: Some(vec![13, 14]),
+        };
+        let app_config = get_app_config(path, &overrides).unwrap();
+        assert_eq!(app_config.network, Some(Network::Preview));
+        assert_eq!(app_config.supported_versions, vec![13, 14]);
+        let hosts = app_config.peer_hosts().unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].host, "b:2");
+        assert_eq!(hosts[0].network_magic, Some(2));
+    }
+}
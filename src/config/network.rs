@@ -0,0 +1,170 @@
+// Built-in settings of the public networks, so that App.yaml can name a
+// network instead of spelling out its magic and relays.
+
+use crate::consensus::Era;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Preprod,
+    Preview,
+    Sanchonet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkPreset {
+    pub network_magic: u32,
+    pub relays: &'static [&'static str],
+    // Hex encoded, as pinned in the network's config.json. SanchoNet is
+    // respun from time to time, so its hashes are not fixed here.
+    pub byron_genesis_hash: Option<&'static str>,
+    pub shelley_genesis_hash: Option<&'static str>,
+    pub alonzo_genesis_hash: Option<&'static str>,
+    pub conway_genesis_hash: Option<&'static str>,
+    // Unix time in seconds
+    pub system_start: u64,
+    // Epochs at which eras started, empty for SanchoNet
+    pub hard_fork_epochs: &'static [(Era, u64)],
+    // Slots per chunk of cardano-node's ImmutableDB: a Byron epoch, 10 k of
+    // the Byron genesis. Unknown for SanchoNet.
+    pub chunk_size: Option<u64>,
+}
+
+const ALONZO_GENESIS_HASH: &str =
+    "7e94a15f55d1e82d10f09203fa1d40f8eede58fd8066542cf6566008068ed874";
+
+impl Network {
+    pub const ALL: [Network; 4] = [
+        Network::Mainnet,
+        Network::Preprod,
+        Network::Preview,
+        Network::Sanchonet,
+    ];
+
+    pub fn preset(&self) -> NetworkPreset {
+        match self {
+            Network::Mainnet => NetworkPreset {
+                network_magic: 764824073,
+                relays: &[
+                    "relays-new.cardano-mainnet.iohk.io:3001",
+                    "backbone.cardano-mainnet.iohk.io:3001",
+                    "backbone.mainnet.emurgornd.com:3001",
+                ],
+                byron_genesis_hash: Some(
+                    "5f20df933584822601f9e3f8c024eb5eb252fe8cefb24d1317dc3d432e940ebb",
+                ),
+                shelley_genesis_hash: Some(
+                    "1a3be38bcbb7911969283716ad7aa550250226b76a61fc51cc9a9a35d9276d81",
+                ),
+                alonzo_genesis_hash: Some(ALONZO_GENESIS_HASH),
+                conway_genesis_hash: Some(
+                    "15a199f895e461ec0ffc6dd4e4028af28a492ab4e806d39cb674c88f7643ef62",
+                ),
+                system_start: 1_506_203_091,
+                hard_fork_epochs: &[
+                    (Era::Shelley, 208),
+                    (Era::Allegra, 236),
+                    (Era::Mary, 251),
+                    (Era::Alonzo, 290),
+                    (Era::Babbage, 365),
+                    (Era::Conway, 507),
+                ],
+                chunk_size: Some(21600),
+            },
+            Network::Preprod => NetworkPreset {
+                network_magic: 1,
+                relays: &["preprod-node.world.dev.cardano.org:30000"],
+                byron_genesis_hash: Some(
+                    "d4b8de7a11d929a323373cbab6c1a9bdc931beffff11db111cf9d57356ee1937",
+                ),
+                shelley_genesis_hash: Some(
+                    "162d29c4e1cf6b8a84f2d692e67a3ac6bc7851bc3e6e4afe64d15778bed8bd86",
+                ),
+                alonzo_genesis_hash: Some(ALONZO_GENESIS_HASH),
+                conway_genesis_hash: Some(
+                    "0eb6adaec3fcb1fe286c1b4ae0da2a117eafc3add51e17577d36dd39eddfc3db",
+                ),
+                system_start: 1_654_041_600,
+                hard_fork_epochs: &[
+                    (Era::Shelley, 4),
+                    (Era::Allegra, 5),
+                    (Era::Mary, 6),
+                    (Era::Alonzo, 7),
+                    (Era::Babbage, 12),
+                    (Era::Conway, 163),
+                ],
+                chunk_size: Some(21600),
+            },
+            Network::Preview => NetworkPreset {
+                network_magic: 2,
+                relays: &["preview-node.world.dev.cardano.org:30002"],
+                byron_genesis_hash: Some(
+                    "83de1d7302569ad56cf9139a41e2e11346d4cb4a31c00142557b6ab3fa550761",
+                ),
+                shelley_genesis_hash: Some(
+                    "363498d1024f84bb39d3fa9593ce391483cb40d479b87233f868d6e57c3a400d",
+                ),
+                alonzo_genesis_hash: Some(ALONZO_GENESIS_HASH),
+                conway_genesis_hash: Some(
+                    "9cc5084f02e27210eacba47af0872e3dba8946ad9460b6072d793e1d2f3987ef",
+                ),
+                system_start: 1_666_656_000,
+                hard_fork_epochs: &[
+                    (Era::Shelley, 0),
+                    (Era::Allegra, 0),
+                    (Era::Mary, 0),
+                    (Era::Alonzo, 0),
+                    (Era::Babbage, 3),
+                    (Era::Conway, 646),
+                ],
+                chunk_size: Some(4320),
+            },
+            Network::Sanchonet => NetworkPreset {
+                network_magic: 4,
+                relays: &["sanchonet-node.world.dev.cardano.org:30004"],
+                byron_genesis_hash: None,
+                shelley_genesis_hash: None,
+                alonzo_genesis_hash: None,
+                conway_genesis_hash: None,
+                system_start: 1_686_789_000,
+                hard_fork_epochs: &[],
+                chunk_size: None,
+            },
+        }
+    }
+
+    pub fn from_magic(network_magic: u32) -> Option<Network> {
+        Network::ALL
+            .into_iter()
+            .find(|network| network.preset().network_magic == network_magic)
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Preprod => "preprod",
+            Network::Preview => "preview",
+            Network::Sanchonet => "sanchonet",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Network::ALL
+            .into_iter()
+            .find(|network| network.to_string() == name.to_lowercase())
+            .ok_or(format!(
+                "Unknown network {:?}, expected one of mainnet, preprod, preview, sanchonet",
+                name
+            ))
+    }
+}
@@ -0,0 +1,479 @@
+// cardano-node's config.json and the network parameters derived from the
+// genesis files it points to.
+
+use super::genesis::{
+    load_byron_genesis, load_genesis, parse_utc_timestamp, AlonzoGenesis, ByronGenesis,
+    ConwayGenesis, ExUnits, ExecutionPrices, ProtocolVersion, Rational, ShelleyGenesis,
+};
+use super::Network;
+use crate::consensus::{Era, EraHistory, EraParams, PraosParams, UnitInterval};
+use crate::crypto::Hash32;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The parts of config.json that locate and pin the genesis files. Paths are
+/// relative to the directory of config.json.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CardanoNodeConfig {
+    pub byron_genesis_file: PathBuf,
+    pub byron_genesis_hash: Option<String>,
+    pub shelley_genesis_file: PathBuf,
+    pub shelley_genesis_hash: Option<String>,
+    pub alonzo_genesis_file: PathBuf,
+    pub alonzo_genesis_hash: Option<String>,
+    // Missing in configurations from before Conway
+    pub conway_genesis_file: Option<PathBuf>,
+    pub conway_genesis_hash: Option<String>,
+    #[serde(default)]
+    pub requires_network_magic: Option<String>,
+    // Era transitions of test networks, mainnet learns them on chain
+    pub test_shelley_hard_fork_at_epoch: Option<u64>,
+    pub test_allegra_hard_fork_at_epoch: Option<u64>,
+    pub test_mary_hard_fork_at_epoch: Option<u64>,
+    pub test_alonzo_hard_fork_at_epoch: Option<u64>,
+    pub test_babbage_hard_fork_at_epoch: Option<u64>,
+    pub test_conway_hard_fork_at_epoch: Option<u64>,
+}
+
+impl CardanoNodeConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<CardanoNodeConfig, String> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read node config {:?}: {}", path, error))?;
+        serde_json::from_str(&json)
+            .map_err(|error| format!("Could not parse node config {:?}: {}", path, error))
+    }
+
+    /// Checks the genesis hashes pinned here against those of a preset.
+    pub fn check_preset(&self, network: Network) -> Result<(), String> {
+        let preset = network.preset();
+        for (name, pinned, expected) in [
+            ("Byron", &self.byron_genesis_hash, preset.byron_genesis_hash),
+            (
+                "Shelley",
+                &self.shelley_genesis_hash,
+                preset.shelley_genesis_hash,
+            ),
+            (
+                "Alonzo",
+                &self.alonzo_genesis_hash,
+                preset.alonzo_genesis_hash,
+            ),
+            (
+                "Conway",
+                &self.conway_genesis_hash,
+                preset.conway_genesis_hash,
+            ),
+        ] {
+            if let (Some(pinned), Some(expected)) = (pinned, expected) {
+                if !pinned.eq_ignore_ascii_case(expected) {
+                    return Err(format!(
+                        "{} genesis hash {} of the node config is not the one of {}",
+                        name, pinned, network
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Epochs at which eras start, for the eras the configuration sets with
+    /// the `Test*HardForkAtEpoch` keys of test networks.
+    pub fn hard_fork_epochs(&self) -> Vec<(Era, u64)> {
+        [
+            (Era::Shelley, self.test_shelley_hard_fork_at_epoch),
+            (Era::Allegra, self.test_allegra_hard_fork_at_epoch),
+            (Era::Mary, self.test_mary_hard_fork_at_epoch),
+            (Era::Alonzo, self.test_alonzo_hard_fork_at_epoch),
+            (Era::Babbage, self.test_babbage_hard_fork_at_epoch),
+            (Era::Conway, self.test_conway_hard_fork_at_epoch),
+        ]
+        .into_iter()
+        .filter_map(|(era, epoch)| epoch.map(|epoch| (era, epoch)))
+        .collect()
+    }
+}
+
+/// Initial protocol parameters, as set by the Shelley, Alonzo and Conway
+/// genesis files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolParameters {
+    pub protocol_version: ProtocolVersion,
+    pub min_fee_a: u64,
+    pub min_fee_b: u64,
+    pub max_block_body_size: u64,
+    pub max_tx_size: u64,
+    pub max_block_header_size: u64,
+    pub key_deposit: u64,
+    pub pool_deposit: u64,
+    pub e_max: u64,
+    pub n_opt: u64,
+    pub a0: Rational,
+    pub rho: Rational,
+    pub tau: Rational,
+    pub min_pool_cost: u64,
+    pub coins_per_utxo_word: u64,
+    pub execution_prices: ExecutionPrices,
+    pub max_tx_ex_units: ExUnits,
+    pub max_block_ex_units: ExUnits,
+    pub max_value_size: u64,
+    pub collateral_percentage: u64,
+    pub max_collateral_inputs: u64,
+    // `None` without a Conway genesis
+    pub conway: Option<ConwayGenesis>,
+}
+
+/// Constants of a network, derived from its genesis files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkParameters {
+    pub network_magic: u32,
+    // k, the maximum rollback in blocks
+    pub security_param: u64,
+    pub byron_slot_length_ms: u64,
+    pub byron_epoch_length: u64,
+    // Slot length and epoch length from Shelley on
+    pub slot_length_ms: u64,
+    pub epoch_length: u64,
+    pub active_slot_coeff: UnitInterval,
+    // Unix time in seconds of slot 0
+    pub system_start: u64,
+    pub slots_per_kes_period: u64,
+    pub max_kes_evolutions: u64,
+    pub protocol_params: ProtocolParameters,
+    // Epochs at which eras start: those of the node config, and for the
+    // other eras those of the public network with the same magic
+    pub hard_fork_epochs: Vec<(Era, u64)>,
+}
+
+impl NetworkParameters {
+    /// Loads config.json and its genesis files, verifying their hashes.
+    pub fn load(config_path: impl AsRef<Path>) -> Result<NetworkParameters, String> {
+        let config_path = config_path.as_ref();
+        let config = CardanoNodeConfig::from_file(config_path)?;
+        let dir = config_path.parent().unwrap_or(Path::new("."));
+        let expected = |hash: &Option<String>| hash.as_deref().map(parse_hash).transpose();
+
+        let (byron, _) = load_byron_genesis(
+            dir.join(&config.byron_genesis_file),
+            expected(&config.byron_genesis_hash)?.as_ref(),
+        )?;
+        let (shelley, _) = load_genesis(
+            dir.join(&config.shelley_genesis_file),
+            expected(&config.shelley_genesis_hash)?.as_ref(),
+        )?;
+        let (alonzo, _) = load_genesis(
+            dir.join(&config.alonzo_genesis_file),
+            expected(&config.alonzo_genesis_hash)?.as_ref(),
+        )?;
+        let conway = match &config.conway_genesis_file {
+            Some(file) => Some(
+                load_genesis(
+                    dir.join(file),
+                    expected(&config.conway_genesis_hash)?.as_ref(),
+                )?
+                .0,
+            ),
+            None => None,
+        };
+        let mut network = NetworkParameters::from_genesis(&byron, &shelley, &alonzo, conway)?;
+        // The keys override the preset era by era, test networks set only
+        // the eras they start in
+        for (era, epoch) in config.hard_fork_epochs() {
+            network.hard_fork_epochs.retain(|&(known, _)| known != era);
+            network.hard_fork_epochs.push((era, epoch));
+        }
+        network.hard_fork_epochs.sort();
+        Ok(network)
+    }
+
+    pub fn from_genesis(
+        byron: &ByronGenesis,
+        shelley: &ShelleyGenesis,
+        alonzo: &AlonzoGenesis,
+        conway: Option<ConwayGenesis>,
+    ) -> Result<NetworkParameters, String> {
+        if byron.protocol_consts.protocol_magic != shelley.network_magic {
+            return Err(format!(
+                "Byron protocol magic {} differs from Shelley network magic {}",
+                byron.protocol_consts.protocol_magic, shelley.network_magic
+            ));
+        }
+        let system_start = parse_utc_timestamp(&shelley.system_start)?;
+        if byron.start_time != system_start {
+            return Err(format!(
+                "Byron start time {} differs from Shelley system start {}",
+                byron.start_time, shelley.system_start
+            ));
+        }
+        let slot_length = shelley.slot_length;
+        let params = &shelley.protocol_params;
+        Ok(NetworkParameters {
+            network_magic: shelley.network_magic,
+            security_param: shelley.security_param,
+            byron_slot_length_ms: byron.slot_duration_ms()?,
+            byron_epoch_length: 10 * byron.protocol_consts.k,
+            slot_length_ms: slot_length.numerator * 1000 / slot_length.denominator,
+            epoch_length: shelley.epoch_length,
+            active_slot_coeff: shelley.active_slots_coeff.to_unit_interval()?,
+            system_start,
+            slots_per_kes_period: shelley.slots_per_kes_period,
+            max_kes_evolutions: shelley.max_kes_evolutions,
+            protocol_params: ProtocolParameters {
+                protocol_version: params.protocol_version,
+                min_fee_a: params.min_fee_a,
+                min_fee_b: params.min_fee_b,
+                max_block_body_size: params.max_block_body_size,
+                max_tx_size: params.max_tx_size,
+                max_block_header_size: params.max_block_header_size,
+                key_deposit: params.key_deposit,
+                pool_deposit: params.pool_deposit,
+                e_max: params.e_max,
+                n_opt: params.n_opt,
+                a0: params.a0,
+                rho: params.rho,
+                tau: params.tau,
+                min_pool_cost: params.min_pool_cost,
+                coins_per_utxo_word: alonzo.coins_per_utxo_word,
+                execution_prices: alonzo.execution_prices,
+                max_tx_ex_units: alonzo.max_tx_ex_units,
+                max_block_ex_units: alonzo.max_block_ex_units,
+                max_value_size: alonzo.max_value_size,
+                collateral_percentage: alonzo.collateral_percentage,
+                max_collateral_inputs: alonzo.max_collateral_inputs,
+                conway,
+            },
+            hard_fork_epochs: Network::from_magic(shelley.network_magic)
+                .map_or(vec![], |network| network.preset().hard_fork_epochs.to_vec()),
+        })
+    }
+
+    pub fn praos_params(&self) -> PraosParams {
+        PraosParams {
+            active_slot_coeff: self.active_slot_coeff,
+            slots_per_kes_period: self.slots_per_kes_period,
+            max_kes_evolutions: self.max_kes_evolutions,
+        }
+    }
+
+    /// Slot and time conversions over the known era transitions. Fails when
+    /// none are known rather than taking the whole chain for Byron.
+    pub fn era_history(&self) -> Result<EraHistory, String> {
+        if self.hard_fork_epochs.is_empty() {
+            return Err(format!(
+                "Era transitions of network magic {} are unknown, \
+                 set the Test*HardForkAtEpoch keys of the node config",
+                self.network_magic
+            ));
+        }
+        EraHistory::new(
+            self.system_start,
+            EraParams {
+                slot_length_ms: self.byron_slot_length_ms,
+                epoch_length: self.byron_epoch_length,
+            },
+            EraParams {
+                slot_length_ms: self.slot_length_ms,
+                epoch_length: self.epoch_length,
+            },
+            &self.hard_fork_epochs,
+        )
+    }
+}
+
+fn parse_hash(hex: &str) -> Result<Hash32, String> {
+    let invalid = || format!("Invalid genesis hash {:?}", hex);
+    if hex.len() != 64 {
+        return Err(invalid());
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2).ok_or_else(invalid)?, 16)
+            .map_err(|_| invalid())?;
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::blake2b_256;
+
+    const SHELLEY: &str = r#"{
+        "activeSlotsCoeff": 0.05,
+        "epochLength": 432000,
+        "maxKESEvolutions": 62,
+        "maxLovelaceSupply": 45000000000000000,
+        "networkId": "Testnet",
+        "networkMagic": 1,
+        "protocolParams": {
+            "protocolVersion": { "minor": 0, "major": 2 },
+            "decentralisationParam": 1,
+            "eMax": 18,
+            "extraEntropy": { "tag": "NeutralNonce" },
+            "maxTxSize": 16384,
+            "maxBlockBodySize": 65536,
+            "maxBlockHeaderSize": 1100,
+            "minFeeA": 44,
+            "minFeeB": 155381,
+            "minUTxOValue": 1000000,
+            "poolDeposit": 500000000,
+            "minPoolCost": 340000000,
+            "keyDeposit": 2000000,
+            "nOpt": 150,
+            "rho": 0.003,
+            "tau": 0.2,
+            "a0": 0.3
+        },
+        "securityParam": 2160,
+        "slotLength": 1,
+        "slotsPerKESPeriod": 129600,
+        "systemStart": "2022-06-01T00:00:00Z",
+        "updateQuorum": 5
+    }"#;
+
+    const ALONZO: &str = r#"{
+        "lovelacePerUTxOWord": 34482,
+        "executionPrices": {
+            "prSteps": { "numerator": 721, "denominator": 10000000 },
+            "prMem": { "numerator": 577, "denominator": 10000 }
+        },
+        "maxTxExUnits": { "exUnitsMem": 10000000, "exUnitsSteps": 10000000000 },
+        "maxBlockExUnits": { "exUnitsMem": 50000000, "exUnitsSteps": 40000000000 },
+        "maxValueSize": 5000,
+        "collateralPercentage": 150,
+        "maxCollateralInputs": 3
+    }"#;
+
+    const CONWAY: &str = r#"{
+        "committeeMinSize": 7,
+        "committeeMaxTermLength": 146,
+        "govActionLifetime": 6,
+        "govActionDeposit": 100000000000,
+        "dRepDeposit": 500000000,
+        "dRepActivity": 20,
+        "minFeeRefScriptCostPerByte": 15
+    }"#;
+
+    const BYRON: &str = r#"{
+        "protocolConsts": { "k": 2160, "protocolMagic": 1 },
+        "startTime": 1654041600,
+        "blockVersionData": { "slotDuration": "20000" }
+    }"#;
+
+    #[test]
+    fn load_config_and_genesis_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for (file, content) in [
+            ("byron-genesis.json", BYRON),
+            ("shelley-genesis.json", SHELLEY),
+            ("alonzo-genesis.json", ALONZO),
+            ("conway-genesis.json", CONWAY),
+        ] {
+            fs::write(dir.path().join(file), content).unwrap();
+        }
+        let hex = |bytes: &[u8]| -> String {
+            blake2b_256(bytes)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        };
+        let config = |shelley_hash: &str| {
+            format!(
+                r#"{{
+                    "Protocol": "Cardano",
+                    "ByronGenesisFile": "byron-genesis.json",
+                    "ShelleyGenesisFile": "shelley-genesis.json",
+                    "ShelleyGenesisHash": "{}",
+                    "AlonzoGenesisFile": "alonzo-genesis.json",
+                    "AlonzoGenesisHash": "{}",
+                    "ConwayGenesisFile": "conway-genesis.json",
+                    "RequiresNetworkMagic": "RequiresMagic",
+                    "TestShelleyHardForkAtEpoch": 4
+                }}"#,
+                shelley_hash,
+                hex(ALONZO.as_bytes())
+            )
+        };
+        let config_path = dir.path().join("config.json");
+        fs::write(&config_path, config(&hex(SHELLEY.as_bytes()))).unwrap();
+
+        let network = NetworkParameters::load(&config_path).unwrap();
+        assert_eq!(network.network_magic, 1);
+        assert_eq!(network.security_param, 2160);
+        assert_eq!(network.byron_slot_length_ms, 20_000);
+        assert_eq!(network.byron_epoch_length, 21_600);
+        assert_eq!(network.slot_length_ms, 1000);
+        assert_eq!(network.epoch_length, 432_000);
+        assert_eq!(network.active_slot_coeff, UnitInterval::new(1, 20).unwrap());
+        assert_eq!(network.system_start, 1_654_041_600);
+        assert_eq!(network.protocol_params.a0, Rational::new(3, 10).unwrap());
+        assert_eq!(
+            network.protocol_params.max_tx_ex_units.steps,
+            10_000_000_000
+        );
+        assert_eq!(
+            network
+                .protocol_params
+                .conway
+                .as_ref()
+                .unwrap()
+                .drep_deposit,
+            500_000_000
+        );
+
+        let history = network.era_history().unwrap();
+        assert_eq!(history.era(86_400), Era::Shelley);
+        assert_eq!(history.slot_to_epoch(86_400 + 432_000), (5, 0));
+
+        // Without the test keys, the transitions of preprod
+        let preprod = config(&hex(SHELLEY.as_bytes())).replace(
+            r#""TestShelleyHardForkAtEpoch": 4"#,
+            r#""LastKnownBlockVersion-Major": 3"#,
+        );
+        fs::write(&config_path, preprod).unwrap();
+        let mut network = NetworkParameters::load(&config_path).unwrap();
+        let history = network.era_history().unwrap();
+        assert_eq!(history.era(86_400 + 8 * 432_000), Era::Babbage);
+        assert_eq!(history.era(86_400 + 8 * 432_000 - 1), Era::Alonzo);
+        assert_eq!(history.era(86_400 + 159 * 432_000), Era::Conway);
+        network.hard_fork_epochs = vec![];
+        assert!(network.era_history().unwrap_err().contains("magic 1"));
+
+        // Preview's config only sets the eras it starts in, the later ones
+        // come from its preset
+        let preview = |content: &str| {
+            content
+                .replace(r#""networkMagic": 1"#, r#""networkMagic": 2"#)
+                .replace(r#""protocolMagic": 1"#, r#""protocolMagic": 2"#)
+        };
+        fs::write(dir.path().join("byron-genesis.json"), preview(BYRON)).unwrap();
+        fs::write(dir.path().join("shelley-genesis.json"), preview(SHELLEY)).unwrap();
+        let keys = config(&hex(preview(SHELLEY).as_bytes())).replace(
+            r#""TestShelleyHardForkAtEpoch": 4"#,
+            r#""TestShelleyHardForkAtEpoch": 0,
+               "TestAllegraHardForkAtEpoch": 0,
+               "TestMaryHardForkAtEpoch": 0,
+               "TestAlonzoHardForkAtEpoch": 0"#,
+        );
+        fs::write(&config_path, keys).unwrap();
+        let network = NetworkParameters::load(&config_path).unwrap();
+        assert_eq!(network.network_magic, 2);
+        assert_eq!(
+            network.hard_fork_epochs,
+            Network::Preview.preset().hard_fork_epochs
+        );
+        assert_eq!(
+            network.era_history().unwrap().era(3 * 432_000),
+            Era::Babbage
+        );
+
+        fs::write(&config_path, config(&hex(b"other"))).unwrap();
+        assert!(NetworkParameters::load(&config_path)
+            .unwrap_err()
+            .contains("shelley-genesis.json"));
+    }
+}
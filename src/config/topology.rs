@@ -0,0 +1,276 @@
+// cardano-node's topology.json, in either of its formats.
+//
+// Legacy (non-P2P):
+//   { "Producers": [ { "addr": "relay.example", "port": 3001, "valency": 2 } ] }
+//
+// P2P:
+//   { "localRoots": [ { "accessPoints": [ { "address": "..", "port": 3001 } ],
+//                       "advertise": false, "trustable": true, "valency": 1 } ],
+//     "publicRoots": [ { "accessPoints": [..], "advertise": false } ],
+//     "bootstrapPeers": [ { "address": "..", "port": 3001 } ],
+//     "useLedgerAfterSlot": 128908821 }
+
+use super::HostConfig;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSource {
+    // Entry of the legacy `Producers` list
+    Producer,
+    LocalRoot,
+    PublicRoot,
+    BootstrapPeer,
+}
+
+/// A peer of the topology with the attributes of the group it was listed in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyPeer {
+    pub address: String,
+    pub port: u16,
+    pub source: PeerSource,
+    // Number of peers of the group to keep connected
+    pub valency: u32,
+    pub trustable: bool,
+    pub advertise: bool,
+}
+
+impl TopologyPeer {
+    pub fn host(&self) -> String {
+        match self.address.contains(':') {
+            // IPv6 literal
+            true => format!("[{}]:{}", self.address, self.port),
+            false => format!("{}:{}", self.address, self.port),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topology {
+    pub peers: Vec<TopologyPeer>,
+    // Slot after which peers are also taken from stake pool registrations,
+    // `None` when ledger peers are disabled
+    pub use_ledger_after_slot: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LegacyTopology {
+    producers: Vec<Producer>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct P2pTopology {
+    #[serde(default)]
+    local_roots: Vec<RootGroup>,
+    #[serde(default)]
+    public_roots: Vec<RootGroup>,
+    #[serde(default)]
+    bootstrap_peers: Option<Vec<AccessPoint>>,
+    use_ledger_after_slot: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Producer {
+    addr: String,
+    port: u16,
+    #[serde(default = "default_valency")]
+    valency: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RootGroup {
+    access_points: Vec<AccessPoint>,
+    #[serde(default)]
+    advertise: bool,
+    #[serde(default)]
+    trustable: bool,
+    // Newer nodes call it `hotValency`
+    #[serde(alias = "hotValency")]
+    valency: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct AccessPoint {
+    address: String,
+    port: u16,
+}
+
+fn default_valency() -> u32 {
+    1
+}
+
+impl Topology {
+    pub fn from_json(json: &str) -> Result<Topology, String> {
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|error| format!("Could not parse topology: {}", error))?;
+        // Only the legacy format has a `Producers` list
+        if value.get("Producers").is_some() {
+            let legacy: LegacyTopology = serde_json::from_value(value)
+                .map_err(|error| format!("Could not parse legacy topology: {}", error))?;
+            return Ok(Topology {
+                peers: legacy
+                    .producers
+                    .into_iter()
+                    .map(|producer| TopologyPeer {
+                        address: producer.addr,
+                        port: producer.port,
+                        source: PeerSource::Producer,
+                        valency: producer.valency,
+                        trustable: false,
+                        advertise: false,
+                    })
+                    .collect(),
+                use_ledger_after_slot: None,
+            });
+        }
+
+        let P2pTopology {
+            local_roots,
+            public_roots,
+            bootstrap_peers,
+            use_ledger_after_slot,
+        } = serde_json::from_value(value)
+            .map_err(|error| format!("Could not parse P2P topology: {}", error))?;
+        let mut peers = vec![];
+        for (groups, source) in [
+            (local_roots, PeerSource::LocalRoot),
+            (public_roots, PeerSource::PublicRoot),
+        ] {
+            for group in groups {
+                // Without a valency all access points are targeted
+                let valency = group.valency.unwrap_or(group.access_points.len() as u32);
+                peers.extend(group.access_points.into_iter().map(|point| TopologyPeer {
+                    address: point.address,
+                    port: point.port,
+                    source,
+                    valency,
+                    trustable: group.trustable,
+                    advertise: group.advertise,
+                }));
+            }
+        }
+        peers.extend(
+            bootstrap_peers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|point| TopologyPeer {
+                    address: point.address,
+                    port: point.port,
+                    source: PeerSource::BootstrapPeer,
+                    valency: 1,
+                    trustable: true,
+                    advertise: false,
+                }),
+        );
+        Ok(Topology {
+            peers,
+            // A negative slot disables ledger peers
+            use_ledger_after_slot: use_ledger_after_slot.and_then(|slot| u64::try_from(slot).ok()),
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Topology, String> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read topology {:?}: {}", path, error))?;
+        Topology::from_json(&json)
+    }
+
+    /// The peers as hosts to connect to, without duplicates.
+    pub fn host_configs(&self, network_magic: Option<u32>, network_id: &str) -> Vec<HostConfig> {
+        let mut hosts: Vec<HostConfig> = vec![];
+        for peer in &self.peers {
+            let host = peer.host();
+            if hosts.iter().all(|known| known.host != host) {
+                hosts.push(HostConfig {
+                    host,
+                    network_magic,
+                    network_id: network_id.to_owned(),
+                    network: None,
+                });
+            }
+        }
+        hosts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_legacy_topology() {
+        let topology = Topology::from_json(
+            r#"{"Producers": [
+                {"addr": "relays-new.cardano-mainnet.iohk.io", "port": 3001, "valency": 2},
+                {"addr": "2001:db8::1", "port": 3001}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(topology.peers.len(), 2);
+        assert_eq!(topology.peers[0].valency, 2);
+        assert_eq!(topology.peers[1].host(), "[2001:db8::1]:3001");
+        assert_eq!(topology.use_ledger_after_slot, None);
+    }
+
+    #[test]
+    fn parse_p2p_topology() {
+        let topology = Topology::from_json(
+            r#"{
+                "bootstrapPeers": [
+                    {"address": "backbone.cardano.iog.io", "port": 3001},
+                    {"address": "backbone.mainnet.emurgornd.com", "port": 3001}
+                ],
+                "localRoots": [{
+                    "accessPoints": [
+                        {"address": "10.0.0.1", "port": 3001},
+                        {"address": "10.0.0.2", "port": 3001}
+                    ],
+                    "advertise": false,
+                    "trustable": true,
+                    "valency": 1
+                }],
+                "publicRoots": [{
+                    "accessPoints": [{"address": "backbone.cardano.iog.io", "port": 3001}],
+                    "advertise": true
+                }],
+                "useLedgerAfterSlot": 128908821
+            }"#,
+        )
+        .unwrap();
+        let sources: Vec<PeerSource> = topology.peers.iter().map(|peer| peer.source).collect();
+        assert_eq!(
+            sources,
+            vec![
+                PeerSource::LocalRoot,
+                PeerSource::LocalRoot,
+                PeerSource::PublicRoot,
+                PeerSource::BootstrapPeer,
+                PeerSource::BootstrapPeer
+            ]
+        );
+        assert!(topology.peers[0].trustable);
+        assert!(topology.peers[2].advertise);
+        assert_eq!(topology.use_ledger_after_slot, Some(128908821));
+
+        let hosts = topology.host_configs(Some(764824073), "Main_Net");
+        assert_eq!(hosts.len(), 4);
+        assert_eq!(hosts[2].host, "backbone.cardano.iog.io:3001");
+        assert_eq!(hosts[2].network_magic, Some(764824073));
+    }
+
+    #[test]
+    fn disabled_ledger_peers_and_null_bootstrap() {
+        let topology = Topology::from_json(
+            r#"{"localRoots": [], "publicRoots": [], "bootstrapPeers": null,
+                "useLedgerAfterSlot": -1}"#,
+        )
+        .unwrap();
+        assert!(topology.peers.is_empty());
+        assert_eq!(topology.use_ledger_after_slot, None);
+        assert!(Topology::from_json(r#"{"Producers": [{"port": 1}]}"#).is_err());
+    }
+}
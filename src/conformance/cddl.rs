@@ -0,0 +1,660 @@
+// The subset of CDDL (RFC 8610) the mini-protocol specifications use: type
+// rules with choices, integer ranges, arrays and maps of groups with
+// occurrence indicators, tags and the `.cbor` and `.size` controls. Group
+// choices, generics and sockets are not supported.
+//
+// A spec generates random instances of its first rule and tells whether a
+// value conforms to it.
+
+use ciborium::{from_reader, into_writer, Value};
+use std::collections::HashMap;
+
+// Entries of arrays and maps generated beyond their minimum occurrence
+const MAX_EXTRA_ENTRIES: usize = 4;
+// Nesting from which no optional entries are generated
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Choice(Vec<Type>),
+    Uint,
+    Nint,
+    Int,
+    Bytes,
+    Text,
+    Bool,
+    Null,
+    Any,
+    Integer(i128),
+    Literal(String),
+    BoolLiteral(bool),
+    // Inclusive bounds
+    Range(i128, i128),
+    Name(String),
+    Array(Vec<Entry>),
+    Map(Vec<Entry>),
+    Tag(u64, Box<Type>),
+    // Bytes holding the CBOR encoding of the type
+    Cbor(Box<Type>),
+    // Byte strings of this length, unsigned integers of this many bytes
+    Size(Box<Type>, u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    min: usize,
+    max: usize,
+    // Map keys, a `label:` stands for the text key "label"
+    key: Option<Type>,
+    value: Type,
+}
+
+pub struct Spec {
+    root: String,
+    rules: HashMap<String, Type>,
+}
+
+impl Spec {
+    /// Parses the rules of `source`, the first one is the root.
+    pub fn parse(source: &str) -> Result<Spec, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let mut root = None;
+        let mut rules = HashMap::new();
+        while parser.peek().is_some() {
+            let name = match parser.next() {
+                Some(Token::Ident(name)) => name,
+                token => return Err(format!("Expected rule name, got {:?}", token)),
+            };
+            parser.expect(Token::Assign)?;
+            let rule = parser.choice()?;
+            root.get_or_insert(name.clone());
+            if rules.insert(name.clone(), rule).is_some() {
+                return Err(format!("Rule {} defined twice", name));
+            }
+        }
+        let spec = Spec {
+            root: root.ok_or("Empty specification")?,
+            rules,
+        };
+        for rule in spec.rules.values() {
+            spec.check_names(rule)?;
+        }
+        Ok(spec)
+    }
+
+    /// A random instance of the root rule.
+    pub fn generate(&self, rng: &mut Rng) -> Value {
+        self.generate_type(&Type::Name(self.root.clone()), rng, 0)
+    }
+
+    /// Whether `value` conforms to the root rule.
+    pub fn matches(&self, value: &Value) -> bool {
+        self.matches_rule(&self.root, value)
+    }
+
+    pub fn matches_rule(&self, rule: &str, value: &Value) -> bool {
+        self.matches_type(&Type::Name(rule.to_owned()), value)
+    }
+
+    fn resolve(&self, name: &str) -> &Type {
+        // Checked by parse
+        &self.rules[name]
+    }
+
+    fn check_names(&self, rule: &Type) -> Result<(), String> {
+        match rule {
+            Type::Name(name) if !self.rules.contains_key(name) => {
+                Err(format!("Undefined rule {}", name))
+            }
+            Type::Choice(types) => types.iter().try_for_each(|t| self.check_names(t)),
+            Type::Array(entries) | Type::Map(entries) => entries.iter().try_for_each(|entry| {
+                if let Some(key) = &entry.key {
+                    self.check_names(key)?;
+                }
+                self.check_names(&entry.value)
+            }),
+            Type::Tag(_, inner) | Type::Cbor(inner) | Type::Size(inner, _) => {
+                self.check_names(inner)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn generate_type(&self, rule: &Type, rng: &mut Rng, depth: usize) -> Value {
+        match rule {
+            Type::Choice(types) => {
+                let choice = &types[rng.below(types.len() as u64) as usize];
+                self.generate_type(choice, rng, depth)
+            }
+            Type::Uint => Value::from(rng.uint()),
+            Type::Nint => Value::from(-1 - (rng.uint() >> 1) as i64),
+            Type::Int => match rng.below(2) {
+                0 => Value::from(rng.uint()),
+                _ => Value::from(-1 - (rng.uint() >> 1) as i64),
+            },
+            Type::Bytes => {
+                let len = rng.below(40) as usize;
+                Value::Bytes(rng.bytes(len))
+            }
+            Type::Text => Value::Text(rng.text()),
+            Type::Bool => Value::Bool(rng.below(2) == 0),
+            Type::Null => Value::Null,
+            Type::Any => match rng.below(3) {
+                0 => Value::from(rng.uint()),
+                1 => {
+                    let len = rng.below(8) as usize;
+                    Value::Bytes(rng.bytes(len))
+                }
+                _ => Value::Text(rng.text()),
+            },
+            Type::Integer(integer) => integer_value(*integer),
+            Type::Literal(text) => Value::Text(text.clone()),
+            Type::BoolLiteral(bool) => Value::Bool(*bool),
+            Type::Range(min, max) => integer_value(rng.range(*min, *max)),
+            Type::Name(name) => self.generate_type(self.resolve(name), rng, depth),
+            Type::Array(entries) => {
+                let mut items = vec![];
+                for entry in entries {
+                    for _ in 0..rng.occurrences(entry, depth) {
+                        items.push(self.generate_type(&entry.value, rng, depth + 1));
+                    }
+                }
+                Value::Array(items)
+            }
+            Type::Map(entries) => {
+                let mut pairs: Vec<(Value, Value)> = vec![];
+                for entry in entries {
+                    let key = entry.key.as_ref().unwrap_or(&Type::Any);
+                    for _ in 0..rng.occurrences(entry, depth) {
+                        let key = self.generate_type(key, rng, depth + 1);
+                        // Duplicate keys are not valid CBOR maps
+                        if pairs.iter().all(|(k, _)| *k != key) {
+                            pairs.push((key, self.generate_type(&entry.value, rng, depth + 1)));
+                        }
+                    }
+                }
+                Value::Map(pairs)
+            }
+            Type::Tag(tag, inner) => {
+                Value::Tag(*tag, Box::new(self.generate_type(inner, rng, depth + 1)))
+            }
+            Type::Cbor(inner) => {
+                let mut bytes = vec![];
+                into_writer(&self.generate_type(inner, rng, depth + 1), &mut bytes).unwrap();
+                Value::Bytes(bytes)
+            }
+            Type::Size(inner, size) => match self.base(inner) {
+                Type::Bytes => Value::Bytes(rng.bytes(*size as usize)),
+                _ => Value::from(match size {
+                    8.. => rng.uint(),
+                    _ => rng.uint() % (1 << (8 * size)),
+                }),
+            },
+        }
+    }
+
+    fn matches_type(&self, rule: &Type, value: &Value) -> bool {
+        let integer = value.as_integer().map(i128::from);
+        match rule {
+            Type::Choice(types) => types.iter().any(|t| self.matches_type(t, value)),
+            Type::Uint => integer.is_some_and(|i| i >= 0),
+            Type::Nint => integer.is_some_and(|i| i < 0),
+            Type::Int => integer.is_some(),
+            Type::Bytes => value.is_bytes(),
+            Type::Text => value.is_text(),
+            Type::Bool => value.is_bool(),
+            Type::Null => value.is_null(),
+            Type::Any => true,
+            Type::Integer(expected) => integer == Some(*expected),
+            Type::Literal(expected) => value.as_text() == Some(expected),
+            Type::BoolLiteral(expected) => value.as_bool() == Some(*expected),
+            Type::Range(min, max) => integer.is_some_and(|i| *min <= i && i <= *max),
+            Type::Name(name) => self.matches_type(self.resolve(name), value),
+            Type::Array(entries) => value
+                .as_array()
+                .is_some_and(|items| self.matches_sequence(entries, items)),
+            Type::Map(entries) => value
+                .as_map()
+                .is_some_and(|pairs| self.matches_map(entries, pairs)),
+            Type::Tag(tag, inner) => match value {
+                Value::Tag(actual, value) => actual == tag && self.matches_type(inner, value),
+                _ => false,
+            },
+            Type::Cbor(inner) => value
+                .as_bytes()
+                .and_then(|bytes| from_reader::<Value, _>(&bytes[..]).ok())
+                .is_some_and(|value| self.matches_type(inner, &value)),
+            Type::Size(inner, size) => {
+                self.matches_type(inner, value)
+                    && match value {
+                        Value::Bytes(bytes) => bytes.len() as u64 == *size,
+                        _ => integer.is_some_and(|i| *size >= 16 || i < 1 << (8 * size)),
+                    }
+            }
+        }
+    }
+
+    // Entries consume items in order, as many as they can while the rest
+    // still matches
+    fn matches_sequence(&self, entries: &[Entry], items: &[Value]) -> bool {
+        let (entry, rest) = match entries.split_first() {
+            Some(first) => first,
+            None => return items.is_empty(),
+        };
+        let matching = items
+            .iter()
+            .take(entry.max)
+            .take_while(|item| self.matches_type(&entry.value, item))
+            .count();
+        (entry.min..=matching)
+            .rev()
+            .any(|count| self.matches_sequence(rest, &items[count..]))
+    }
+
+    // Every pair matches an entry, and each entry occurs as often as allowed
+    fn matches_map(&self, entries: &[Entry], pairs: &[(Value, Value)]) -> bool {
+        let mut counts = vec![0; entries.len()];
+        for (key, value) in pairs {
+            let entry = entries.iter().position(|entry| {
+                entry.key.as_ref().is_none_or(|k| self.matches_type(k, key))
+                    && self.matches_type(&entry.value, value)
+            });
+            match entry {
+                Some(entry) => counts[entry] += 1,
+                None => return false,
+            }
+        }
+        entries
+            .iter()
+            .zip(counts)
+            .all(|(entry, count)| entry.min <= count && count <= entry.max)
+    }
+
+    // The prelude type under names and sizes
+    fn base<'a>(&'a self, rule: &'a Type) -> &'a Type {
+        match rule {
+            Type::Name(name) => self.base(self.resolve(name)),
+            Type::Size(inner, _) => self.base(inner),
+            rule => rule,
+        }
+    }
+}
+
+fn integer_value(integer: i128) -> Value {
+    Value::Integer(integer.try_into().expect("CBOR integer"))
+}
+
+/// Xorshift generator, seeded so failures reproduce.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+
+    // Unsigned integers of every CBOR header size
+    fn uint(&mut self) -> u64 {
+        match self.below(5) {
+            0 => self.below(24),
+            1 => self.below(1 << 8),
+            2 => self.below(1 << 16),
+            3 => self.below(1 << 32),
+            _ => self.next(),
+        }
+    }
+
+    // Bounds are picked more often than the values between them
+    fn range(&mut self, min: i128, max: i128) -> i128 {
+        match self.below(4) {
+            0 => min,
+            1 => max,
+            _ => min + (self.next() as u128 % (max - min + 1) as u128) as i128,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    fn text(&mut self) -> String {
+        const CHARS: [char; 8] = ['a', 'z', '0', ' ', '-', 'é', 'λ', '✓'];
+        (0..self.below(20))
+            .map(|_| CHARS[self.below(CHARS.len() as u64) as usize])
+            .collect()
+    }
+
+    fn occurrences(&mut self, entry: &Entry, depth: usize) -> usize {
+        let max = match depth {
+            MAX_DEPTH.. => entry.min,
+            _ => entry.max.min(entry.min + MAX_EXTRA_ENTRIES),
+        };
+        entry.min + self.below((max - entry.min + 1) as u64) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Integer(i128),
+    Text(String),
+    Assign,
+    Arrow,
+    Slash,
+    Colon,
+    Comma,
+    Star,
+    Question,
+    Plus,
+    Range,
+    ExclusiveRange,
+    Control(String),
+    Tag(u64),
+    Open(char),
+    Close(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    let ident = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    while let Some(&c) = chars.get(i) {
+        let next = chars.get(i + 1).copied();
+        match c {
+            ';' => {
+                while chars.get(i).is_some_and(|&c| c != '\n') {
+                    i += 1;
+                }
+                continue;
+            }
+            c if c.is_whitespace() => {}
+            '=' if next == Some('>') => {
+                tokens.push(Token::Arrow);
+                i += 1;
+            }
+            '=' => tokens.push(Token::Assign),
+            '/' if next == Some('/') => return Err("Group choices are not supported".to_owned()),
+            '/' => tokens.push(Token::Slash),
+            ':' => tokens.push(Token::Colon),
+            ',' => tokens.push(Token::Comma),
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Question),
+            '+' => tokens.push(Token::Plus),
+            '[' | '{' | '(' => tokens.push(Token::Open(c)),
+            ']' | '}' | ')' => tokens.push(Token::Close(c)),
+            '.' if chars[i..].starts_with(&['.', '.', '.']) => {
+                tokens.push(Token::ExclusiveRange);
+                i += 2;
+            }
+            '.' if next == Some('.') => {
+                tokens.push(Token::Range);
+                i += 1;
+            }
+            '.' => {
+                let start = i + 1;
+                i = start;
+                while chars.get(i).copied().is_some_and(ident) {
+                    i += 1;
+                }
+                tokens.push(Token::Control(chars[start..i].iter().collect()));
+                continue;
+            }
+            '#' => {
+                let start = i;
+                i += 1;
+                while chars
+                    .get(i)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    i += 1;
+                }
+                let tag: String = chars[start..i].iter().collect();
+                let tag = tag
+                    .strip_prefix("#6.")
+                    .and_then(|tag| tag.parse().ok())
+                    .ok_or(format!("Unsupported major type {}", tag))?;
+                tokens.push(Token::Tag(tag));
+                continue;
+            }
+            '"' => {
+                let start = i + 1;
+                i = start;
+                while chars.get(i).is_some_and(|&c| c != '"') {
+                    i += 1;
+                }
+                tokens.push(Token::Text(chars[start..i].iter().collect()));
+            }
+            c if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let start = i;
+                i += 1;
+                while chars.get(i).is_some_and(|c| c.is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let integer = match literal.strip_prefix("0x") {
+                    Some(hex) => i128::from_str_radix(hex, 16),
+                    None => literal.parse(),
+                };
+                tokens.push(Token::Integer(
+                    integer.map_err(|_| format!("Invalid integer {}", literal))?,
+                ));
+                continue;
+            }
+            c if ident(c) || c == '$' || c == '@' => {
+                let start = i;
+                while chars.get(i).copied().is_some_and(ident) {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+                continue;
+            }
+            c => return Err(format!("Unexpected character {:?}", c)),
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_second(&self) -> Option<&Token> {
+        self.tokens.get(self.position + 1)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(format!("Expected {:?}, got {:?}", expected, token)),
+        }
+    }
+
+    // The next rule starts with `name =`
+    fn at_rule(&self) -> bool {
+        matches!(
+            (self.peek(), self.peek_second()),
+            (Some(Token::Ident(_)), Some(Token::Assign))
+        )
+    }
+
+    fn choice(&mut self) -> Result<Type, String> {
+        let mut types = vec![self.type1()?];
+        while self.peek() == Some(&Token::Slash) {
+            self.next();
+            types.push(self.type1()?);
+        }
+        Ok(match types.len() {
+            1 => types.remove(0),
+            _ => Type::Choice(types),
+        })
+    }
+
+    fn type1(&mut self) -> Result<Type, String> {
+        let first = self.type2()?;
+        match self.peek().cloned() {
+            Some(range @ (Token::Range | Token::ExclusiveRange)) => {
+                self.next();
+                let (min, max) = match (first, self.type2()?) {
+                    (Type::Integer(min), Type::Integer(max)) => (min, max),
+                    bounds => return Err(format!("Unsupported range {:?}", bounds)),
+                };
+                Ok(match range {
+                    Token::Range => Type::Range(min, max),
+                    _ => Type::Range(min, max - 1),
+                })
+            }
+            Some(Token::Control(control)) => {
+                self.next();
+                match (control.as_str(), self.type2()?) {
+                    ("cbor", inner) => Ok(Type::Cbor(Box::new(inner))),
+                    ("size", Type::Integer(size)) => Ok(Type::Size(Box::new(first), size as u64)),
+                    (control, _) => Err(format!("Unsupported control .{}", control)),
+                }
+            }
+            _ => Ok(first),
+        }
+    }
+
+    fn type2(&mut self) -> Result<Type, String> {
+        match self.next() {
+            Some(Token::Integer(integer)) => Ok(Type::Integer(integer)),
+            Some(Token::Text(text)) => Ok(Type::Literal(text)),
+            Some(Token::Ident(name)) => Ok(prelude(&name).unwrap_or(Type::Name(name))),
+            Some(Token::Open('[')) => Ok(Type::Array(self.group(']')?)),
+            Some(Token::Open('{')) => Ok(Type::Map(self.group('}')?)),
+            Some(Token::Open('(')) => {
+                let inner = self.choice()?;
+                self.expect(Token::Close(')'))?;
+                Ok(inner)
+            }
+            Some(Token::Tag(tag)) => {
+                self.expect(Token::Open('('))?;
+                let inner = self.choice()?;
+                self.expect(Token::Close(')'))?;
+                Ok(Type::Tag(tag, Box::new(inner)))
+            }
+            token => Err(format!("Unexpected {:?}", token)),
+        }
+    }
+
+    fn group(&mut self, close: char) -> Result<Vec<Entry>, String> {
+        let mut entries = vec![];
+        loop {
+            match self.peek() {
+                Some(Token::Close(c)) if *c == close => {
+                    self.next();
+                    return Ok(entries);
+                }
+                Some(Token::Comma) => {
+                    self.next();
+                }
+                Some(_) if !self.at_rule() => entries.push(self.entry()?),
+                _ => return Err(format!("Missing {:?}", close)),
+            }
+        }
+    }
+
+    fn entry(&mut self) -> Result<Entry, String> {
+        let (min, max) = self.occurrence();
+        let key = match (self.peek().cloned(), self.peek_second()) {
+            (Some(Token::Ident(label)), Some(Token::Colon)) => {
+                self.position += 2;
+                Some(Type::Literal(label))
+            }
+            _ => None,
+        };
+        let value = self.choice()?;
+        if key.is_none() && self.peek() == Some(&Token::Arrow) {
+            self.next();
+            let key = Some(value);
+            return Ok(Entry {
+                min,
+                max,
+                key,
+                value: self.choice()?,
+            });
+        }
+        Ok(Entry {
+            min,
+            max,
+            key,
+            value,
+        })
+    }
+
+    // `?`, `*`, `+`, `n*`, `*m` or `n*m`, once by default
+    fn occurrence(&mut self) -> (usize, usize) {
+        match (self.peek().cloned(), self.peek_second()) {
+            (Some(Token::Question), _) => {
+                self.next();
+                (0, 1)
+            }
+            (Some(Token::Plus), _) => {
+                self.next();
+                (1, usize::MAX)
+            }
+            (Some(Token::Star), _) => {
+                self.next();
+                (0, self.bound().unwrap_or(usize::MAX))
+            }
+            (Some(Token::Integer(min)), Some(Token::Star)) => {
+                self.position += 2;
+                (min as usize, self.bound().unwrap_or(usize::MAX))
+            }
+            _ => (1, 1),
+        }
+    }
+
+    fn bound(&mut self) -> Option<usize> {
+        match self.peek() {
+            Some(&Token::Integer(max)) if self.peek_second() != Some(&Token::Range) => {
+                self.next();
+                Some(max as usize)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn prelude(name: &str) -> Option<Type> {
+    Some(match name {
+        "uint" => Type::Uint,
+        "nint" => Type::Nint,
+        "int" => Type::Int,
+        "bstr" | "bytes" => Type::Bytes,
+        "tstr" | "text" => Type::Text,
+        "bool" => Type::Bool,
+        "true" => Type::BoolLiteral(true),
+        "false" => Type::BoolLiteral(false),
+        "null" | "nil" => Type::Null,
+        "any" => Type::Any,
+        _ => return None,
+    })
+}
@@ -0,0 +1,217 @@
+// Conformance of the message codecs with the CDDL specifications of the
+// mini-protocols in `specs`: our decoders accept random instances generated
+// from the specs, and what our encoders emit conforms to them.
+
+mod cddl;
+
+use self::cddl::{Rng, Spec};
+use crate::handshake::{self, NodeToClientVersionData, NodeToNodeVersionData, ProposeVersion};
+use crate::{block_fetch, chain_sync};
+use ciborium::{from_reader, into_writer, Value};
+
+const HANDSHAKE_NODE_TO_NODE: &str = include_str!("specs/handshake-node-to-node.cddl");
+const HANDSHAKE_NODE_TO_CLIENT: &str = include_str!("specs/handshake-node-to-client.cddl");
+const CHAIN_SYNC: &str = include_str!("specs/chain-sync.cddl");
+const BLOCK_FETCH: &str = include_str!("specs/block-fetch.cddl");
+const NETWORK: &str = include_str!("specs/network.cddl");
+
+// Instances generated per specification
+const INSTANCES: u64 = 500;
+
+fn spec(sources: &[&str]) -> Spec {
+    Spec::parse(&sources.concat()).unwrap()
+}
+
+/// Encodes and decodes `INSTANCES` instances of `spec` with `codec`, which
+/// returns the encoding of what it decoded.
+fn check(spec: &Spec, codec: impl Fn(Value) -> Result<Value, String>) {
+    for seed in 0..INSTANCES {
+        let instance = spec.generate(&mut Rng::new(seed));
+        assert!(spec.matches(&instance), "Invalid instance {:?}", instance);
+        let mut bytes = vec![];
+        into_writer(&instance, &mut bytes).unwrap();
+        let value: Value = from_reader(&bytes[..]).unwrap();
+        let encoded = codec(value)
+            .unwrap_or_else(|error| panic!("Rejected {:?} (seed {}): {}", instance, seed, error));
+        assert!(
+            spec.matches(&encoded),
+            "Encoded {:?} from {:?} (seed {}) does not conform",
+            encoded,
+            instance,
+            seed
+        );
+        assert_eq!(encoded, instance, "seed {}", seed);
+    }
+}
+
+#[test]
+fn handshake_node_to_node() {
+    let spec = spec(&[HANDSHAKE_NODE_TO_NODE]);
+    check(&spec, |value| {
+        handshake::Message::from_value(value)?.to_value()
+    });
+
+    // Proposals, acceptances and refusals built by the handshake
+    let versions: Vec<i64> = (7..=14).collect();
+    for query in [false, true] {
+        let proposal = handshake::Message::ProposeVersions(vec![
+            ProposeVersion::Index(0),
+            ProposeVersion::create_version_table(&versions, 764824073, query),
+        ]);
+        assert!(spec.matches(&proposal.to_value().unwrap()));
+    }
+    let accept = handshake::Message::AcceptVersion(vec![
+        handshake::AcceptVersion::Index(1),
+        handshake::AcceptVersion::VersionNumber(13),
+        handshake::AcceptVersion::NodeToNodeVersionData(vec![
+            NodeToNodeVersionData::NetworkMagic(2),
+            NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(false),
+            NodeToNodeVersionData::PeerSharing(0),
+            NodeToNodeVersionData::Query(false),
+        ]),
+    ]);
+    assert!(spec.matches(&accept.to_value().unwrap()));
+    for reason in [
+        handshake::RefuseReason::VersionMismatch(vec![13, 14]),
+        handshake::RefuseReason::HandshakeDecodeError(13, "bad".to_owned()),
+        handshake::RefuseReason::Refused(13, "no".to_owned()),
+    ] {
+        let refuse = handshake::Message::Refuse(reason);
+        assert!(spec.matches(&refuse.to_value().unwrap()));
+    }
+
+    // Not every shape passes
+    for value in [
+        // Version 6 is not specified
+        Value::Array(vec![
+            Value::from(0),
+            Value::Map(vec![(
+                Value::from(6),
+                Value::Array(vec![Value::from(1), Value::Bool(false)]),
+            )]),
+        ]),
+        // Version 13 carries four fields
+        Value::Array(vec![
+            Value::from(3),
+            Value::Map(vec![(
+                Value::from(13),
+                Value::Array(vec![Value::from(1), Value::Bool(false)]),
+            )]),
+        ]),
+        Value::Array(vec![
+            Value::from(2),
+            Value::Array(vec![Value::from(3), Value::from(13)]),
+        ]),
+        Value::Array(vec![Value::from(1), Value::from(13)]),
+    ] {
+        assert!(!spec.matches(&value), "{:?} conforms", value);
+    }
+}
+
+#[test]
+fn handshake_node_to_client() {
+    let spec = spec(&[HANDSHAKE_NODE_TO_CLIENT]);
+    check(&spec, |value| {
+        handshake::Message::from_value(value)?.to_value()
+    });
+
+    // Version numbers with bit 15 set carry node-to-client version data
+    let accept = Value::Array(vec![
+        Value::from(1),
+        Value::from(32787),
+        Value::Array(vec![Value::from(2), Value::Bool(true)]),
+    ]);
+    assert!(spec.matches(&accept));
+    assert_eq!(
+        handshake::Message::from_value(accept).unwrap(),
+        handshake::Message::AcceptVersion(vec![
+            handshake::AcceptVersion::Index(1),
+            handshake::AcceptVersion::VersionNumber(32787),
+            handshake::AcceptVersion::NodeToClientVersionData(vec![
+                NodeToClientVersionData::NetworkMagic(2),
+                NodeToClientVersionData::Query(true),
+            ]),
+        ])
+    );
+}
+
+#[test]
+fn chain_sync() {
+    let spec = spec(&[CHAIN_SYNC, NETWORK]);
+    check(&spec, |value| {
+        Ok(chain_sync::Message::from_value(&value)?.to_value())
+    });
+    assert!(!spec.matches(&Value::Array(vec![Value::from(8)])));
+    // Headers are wrapped in tag 24
+    assert!(!spec.matches(&Value::Array(vec![
+        Value::from(2),
+        Value::Array(vec![Value::from(5), Value::Bytes(vec![0x80])]),
+        Value::Array(vec![Value::Array(vec![]), Value::from(0)]),
+    ])));
+}
+
+#[test]
+fn block_fetch() {
+    let spec = spec(&[BLOCK_FETCH, NETWORK]);
+    check(&spec, |value| {
+        Ok(block_fetch::Message::from_value(&value)?.to_value())
+    });
+    // Hashes are 32 bytes
+    assert!(!spec.matches(&Value::Array(vec![
+        Value::from(0),
+        Value::Array(vec![]),
+        Value::Array(vec![Value::from(1), Value::Bytes(vec![0; 31])]),
+    ])));
+}
+
+#[test]
+fn cddl_subset() {
+    let spec = Spec::parse(
+        "
+        root = [ ?flag: bool, 2*3 small, * tstr ]
+        small = 0...4 / -1
+        record = { name: tstr, ? 0 => bytes .size 2, * uint => #6.30(bytes .cbor root) }
+        ",
+    )
+    .unwrap();
+    let array = |values: Vec<Value>| Value::Array(values);
+    assert!(spec.matches(&array(vec![Value::from(0), Value::from(3)])));
+    assert!(spec.matches(&array(vec![
+        Value::Bool(true),
+        Value::from(-1),
+        Value::from(1),
+        Value::from(2),
+        Value::Text("a".to_owned()),
+    ])));
+    // 4 is excluded, one item is too few and four too many
+    assert!(!spec.matches(&array(vec![Value::from(0), Value::from(4)])));
+    assert!(!spec.matches(&array(vec![Value::from(0)])));
+    assert!(!spec.matches(&array(vec![Value::from(0); 4])));
+
+    let mut root = vec![];
+    into_writer(&array(vec![Value::from(0), Value::from(0)]), &mut root).unwrap();
+    let record = Value::Map(vec![
+        (Value::Text("name".to_owned()), Value::Text("x".to_owned())),
+        (Value::from(0), Value::Bytes(vec![1, 2])),
+        (
+            Value::from(9),
+            Value::Tag(30, Box::new(Value::Bytes(root.clone()))),
+        ),
+    ]);
+    assert!(spec.matches_rule("record", &record));
+    let mut missing_name = record.clone();
+    missing_name.as_map_mut().unwrap().remove(0);
+    assert!(!spec.matches_rule("record", &missing_name));
+    root[1] = 0x05;
+    let invalid_root = Value::Map(vec![
+        (Value::Text("name".to_owned()), Value::Text("x".to_owned())),
+        (Value::from(9), Value::Tag(30, Box::new(Value::Bytes(root)))),
+    ]);
+    assert!(!spec.matches_rule("record", &invalid_root));
+
+    for seed in 0..100 {
+        assert!(spec.matches(&spec.generate(&mut Rng::new(seed))));
+    }
+    assert!(Spec::parse("a = b").is_err());
+    assert!(Spec::parse("a = [b // c]\nb = 1\nc = 2").is_err());
+}
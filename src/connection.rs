@@ -0,0 +1,423 @@
+// Connections to peers: a multiplexed bearer on which the handshake
+// succeeded. A connection owns everything it needs, so it can be kept in
+// long-lived maps and moved between tasks, and hands out cloneable handles
+// to the channels of its mini-protocols. When both sides negotiated
+// `InitiatorAndResponderDiffusionMode` the connection is duplex, and has
+// handles for the initiator and the responder side of every mini-protocol.
+
+use crate::bearer::{self, Bearer};
+use crate::block_fetch::{self, MINI_PROTOCOL_ID_BLOCK_FETCH};
+use crate::chain_sync::{self, MINI_PROTOCOL_ID_CHAIN_SYNC};
+use crate::handshake::{self, Message, NodeToNodeVersionData, MINI_PROTOCOL_ID_HANDSHAKE};
+use crate::metrics::metrics;
+use crate::mux::{Channel, Mode, Mux};
+use crate::peer_sharing::{self, MINI_PROTOCOL_ID_PEER_SHARING};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::{
+    sync::{Mutex, OwnedMutexGuard},
+    time::Instant,
+};
+use tracing::{debug, info};
+
+/// Shared access to the channel of one mini-protocol. Clones refer to the
+/// same channel, whoever holds the lock runs the protocol, e.g. for one
+/// request and its reply.
+#[derive(Clone)]
+pub struct ProtocolHandle {
+    protocol: u16,
+    channel: Arc<Mutex<Channel>>,
+}
+
+impl ProtocolHandle {
+    pub fn protocol(&self) -> u16 {
+        self.protocol
+    }
+
+    pub async fn lock(&self) -> OwnedMutexGuard<Channel> {
+        self.channel.clone().lock_owned().await
+    }
+
+    /// The channel unless another task is running the protocol on it.
+    pub fn try_lock(&self) -> Option<OwnedMutexGuard<Channel>> {
+        self.channel.clone().try_lock_owned().ok()
+    }
+}
+
+pub struct PeerConnection {
+    peer: String,
+    mode: Mode,
+    version: i128,
+    duplex: bool,
+    peer_sharing: bool,
+    handles: HashMap<(u16, Mode), ProtocolHandle>,
+    mux: Mux,
+}
+
+impl PeerConnection {
+    /// Connects to `host` over TCP and proposes `versions`.
+    pub async fn connect(
+        host: &str,
+        network_magic: u32,
+        versions: &Vec<i64>,
+        protocols: &[u16],
+    ) -> Result<PeerConnection, String> {
+        PeerConnection::dial(host, host, network_magic, versions, protocols).await
+    }
+
+    /// Connects to `host` at `address`, one of the addresses it resolves to.
+    pub async fn connect_to(
+        host: &str,
+        address: SocketAddr,
+        network_magic: u32,
+        versions: &Vec<i64>,
+        protocols: &[u16],
+    ) -> Result<PeerConnection, String> {
+        let address = address.to_string();
+        PeerConnection::dial(host, &address, network_magic, versions, protocols).await
+    }
+
+    async fn dial(
+        host: &str,
+        address: &str,
+        network_magic: u32,
+        versions: &Vec<i64>,
+        protocols: &[u16],
+    ) -> Result<PeerConnection, String> {
+        metrics().handshake_attempt(host);
+        let connect_start = Instant::now();
+        let stream = bearer::connect_tcp(address)
+            .await
+            .inspect_err(|_| metrics().handshake_failed(host, "connect"))?;
+        metrics().observe_connect(host, connect_start.elapsed());
+        PeerConnection::handshake(host, stream, network_magic, versions, protocols).await
+    }
+
+    /// Proposes `versions` to the peer at the other end of `bearer`.
+    pub async fn initiate<B: Bearer>(
+        peer: &str,
+        bearer: B,
+        network_magic: u32,
+        versions: &Vec<i64>,
+        protocols: &[u16],
+    ) -> Result<PeerConnection, String> {
+        metrics().handshake_attempt(peer);
+        PeerConnection::handshake(peer, bearer, network_magic, versions, protocols).await
+    }
+
+    async fn handshake<B: Bearer>(
+        peer: &str,
+        bearer: B,
+        network_magic: u32,
+        versions: &Vec<i64>,
+        protocols: &[u16],
+    ) -> Result<PeerConnection, String> {
+        let (mux, mut channel) = start(bearer, Mode::Initiator, protocols)?;
+        let negotiate_start = Instant::now();
+        let peer_sharing = protocols.contains(&MINI_PROTOCOL_ID_PEER_SHARING);
+        let response =
+            handshake::propose(&mut channel, versions, network_magic, peer_sharing, false)
+                .await
+                .inspect_err(|_| metrics().handshake_failed(peer, "protocol"))?;
+        metrics().observe_negotiate(peer, negotiate_start.elapsed());
+        let duplex = response
+            .accepted_version_data()
+            .is_some_and(NodeToNodeVersionData::is_duplex);
+        let peer_sharing = response
+            .accepted_version_data()
+            .is_some_and(NodeToNodeVersionData::is_peer_sharing);
+        let version = match (response.accepted_version(), response) {
+            (Some(version), _) => version,
+            (None, Message::Refuse(reason)) => {
+                metrics().handshake_refused(peer, reason.name());
+                return Err(format!("{} refused the handshake: {}", peer, reason));
+            }
+            (None, message) => {
+                metrics().handshake_failed(peer, "protocol");
+                return Err(format!("Handshake with {} failed: {:?}", peer, message));
+            }
+        };
+        info!("Connected to {} with version {}", peer, version);
+        metrics().handshake_accepted(peer, version as i64);
+        Ok(PeerConnection::new(
+            peer,
+            Mode::Initiator,
+            version,
+            duplex,
+            peer_sharing,
+            mux,
+            protocols,
+        ))
+    }
+
+    /// Answers the proposal of the peer at the other end of `bearer` with
+    /// the highest common version.
+    pub async fn respond<B: Bearer>(
+        peer: &str,
+        bearer: B,
+        network_magic: u32,
+        versions: &Vec<i64>,
+        protocols: &[u16],
+    ) -> Result<PeerConnection, String> {
+        let (mux, mut channel) = start(bearer, Mode::Responder, protocols)?;
+        let peer_sharing = protocols.contains(&MINI_PROTOCOL_ID_PEER_SHARING);
+        let (version, data) =
+            handshake::accept(&mut channel, versions, network_magic, peer_sharing).await?;
+        info!("Accepted {} with version {}", peer, version);
+        Ok(PeerConnection::new(
+            peer,
+            Mode::Responder,
+            version,
+            NodeToNodeVersionData::is_duplex(&data),
+            NodeToNodeVersionData::is_peer_sharing(&data),
+            mux,
+            protocols,
+        ))
+    }
+
+    fn new(
+        peer: &str,
+        mode: Mode,
+        version: i128,
+        duplex: bool,
+        peer_sharing: bool,
+        mut mux: Mux,
+        protocols: &[u16],
+    ) -> PeerConnection {
+        let mut handles = HashMap::new();
+        for &protocol in protocols {
+            for side in [Mode::Initiator, Mode::Responder] {
+                // Channels of the other side are dropped unless duplex,
+                // which drops what the peer sends on them
+                let channel = match mux.channel_in(side, protocol) {
+                    Some(channel) if side == mode || duplex => channel,
+                    _ => continue,
+                };
+                let channel = Arc::new(Mutex::new(channel));
+                handles.insert((protocol, side), ProtocolHandle { protocol, channel });
+            }
+        }
+        PeerConnection {
+            peer: peer.to_owned(),
+            mode,
+            version,
+            duplex,
+            peer_sharing,
+            handles,
+            mux,
+        }
+    }
+
+    /// Address of the peer.
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// Our side of the handshake: initiators opened the connection.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Whether both sides run both directions of the mini-protocols.
+    pub fn is_duplex(&self) -> bool {
+        self.duplex
+    }
+
+    /// Whether both sides enabled the peer sharing protocol.
+    pub fn peer_sharing(&self) -> bool {
+        self.peer_sharing
+    }
+
+    /// The negotiated handshake version.
+    pub fn version(&self) -> i128 {
+        self.version
+    }
+
+    /// Handle to the channel of `protocol`, `None` when the connection was
+    /// not opened with it.
+    pub fn protocol(&self, protocol: u16) -> Option<ProtocolHandle> {
+        self.protocol_in(self.mode, protocol)
+    }
+
+    /// Handle to the `mode` side of `protocol`. Duplex connections have
+    /// both, e.g. to serve the peer on a connection we opened.
+    pub fn protocol_in(&self, mode: Mode, protocol: u16) -> Option<ProtocolHandle> {
+        self.handles.get(&(protocol, mode)).cloned()
+    }
+
+    /// Ends the connection gracefully. Initiators tell the servers of
+    /// chain-sync, block-fetch and peer sharing they are done when the
+    /// client has agency, i.e. no task holds the channel for an exchange in
+    /// progress, such as chain-sync waiting for the update after
+    /// `AwaitReply`. Busy channels are not waited for. Then the messages
+    /// sent so far are written and the bearer is shut down.
+    pub async fn close(self) {
+        let initiator = |protocol| self.protocol_in(Mode::Initiator, protocol);
+        if let Some(channel) = initiator(MINI_PROTOCOL_ID_CHAIN_SYNC).and_then(|h| h.try_lock()) {
+            let _ = channel.send_value(&chain_sync::Message::Done.to_value());
+        }
+        if let Some(channel) = initiator(MINI_PROTOCOL_ID_BLOCK_FETCH).and_then(|h| h.try_lock()) {
+            let _ = block_fetch::done(&channel);
+        }
+        if let Some(channel) = initiator(MINI_PROTOCOL_ID_PEER_SHARING).and_then(|h| h.try_lock()) {
+            let _ = peer_sharing::done(&channel);
+        }
+        debug!("Closing connection to {}", self.peer);
+        self.mux.close().await;
+    }
+}
+
+/// Starts the mux with the handshake and `protocols`, returns it with the
+/// handshake channel. Both directions are multiplexed until the handshake
+/// tells whether the connection is duplex, the handshake itself runs in
+/// `mode` only.
+fn start<B: Bearer>(bearer: B, mode: Mode, protocols: &[u16]) -> Result<(Mux, Channel), String> {
+    let mut all = vec![MINI_PROTOCOL_ID_HANDSHAKE];
+    all.extend(
+        protocols
+            .iter()
+            .filter(|&&p| p != MINI_PROTOCOL_ID_HANDSHAKE),
+    );
+    let mut mux = Mux::duplex(bearer, mode, &all);
+    let channel = mux
+        .channel(MINI_PROTOCOL_ID_HANDSHAKE)
+        .ok_or("No handshake channel")?;
+    let other = match mode {
+        Mode::Initiator => Mode::Responder,
+        Mode::Responder => Mode::Initiator,
+    };
+    drop(mux.channel_in(other, MINI_PROTOCOL_ID_HANDSHAKE));
+    Ok((mux, channel))
+}
+
+#[cfg(all(test, feature = "storage"))]
+mod tests {
+    use super::*;
+    use crate::storage::{parse_cardano_block, ChainDb, Point};
+
+    #[tokio::test]
+    async fn connect_share_and_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain_db = ChainDb::open(dir.path(), 3, parse_cardano_block).unwrap();
+        let chain_db = Arc::new(Mutex::new(chain_db));
+        let protocols = [
+            MINI_PROTOCOL_ID_CHAIN_SYNC,
+            MINI_PROTOCOL_ID_BLOCK_FETCH,
+            MINI_PROTOCOL_ID_PEER_SHARING,
+        ];
+        let peers: Vec<SocketAddr> = vec!["192.0.2.1:3001".parse().unwrap()];
+
+        let (client, server) = bearer::duplex();
+        let shared = peers.clone();
+        let server = tokio::spawn(async move {
+            let connection =
+                PeerConnection::respond("client", server, 2, &vec![13, 14], &protocols)
+                    .await
+                    .unwrap();
+            assert_eq!(connection.mode(), Mode::Responder);
+            let mut chain_sync = connection
+                .protocol(MINI_PROTOCOL_ID_CHAIN_SYNC)
+                .unwrap()
+                .lock()
+                .await;
+            let mut block_fetch = connection
+                .protocol(MINI_PROTOCOL_ID_BLOCK_FETCH)
+                .unwrap()
+                .lock()
+                .await;
+            let mut peer_sharing = connection
+                .protocol(MINI_PROTOCOL_ID_PEER_SHARING)
+                .unwrap()
+                .lock()
+                .await;
+            let (chain_sync, block_fetch, peer_sharing) = tokio::join!(
+                chain_sync::serve(&mut chain_sync, chain_db.clone()),
+                block_fetch::serve(&mut block_fetch, chain_db),
+                peer_sharing::serve(&mut peer_sharing, &shared)
+            );
+            chain_sync.and(block_fetch).and(peer_sharing)
+        });
+
+        let connection = PeerConnection::initiate("server", client, 2, &vec![13], &protocols)
+            .await
+            .unwrap();
+        assert_eq!(connection.peer(), "server");
+        assert_eq!(connection.version(), 13);
+        assert!(connection.protocol(MINI_PROTOCOL_ID_HANDSHAKE).is_none());
+
+        // Clones used from another task share the channel
+        let handle = connection.protocol(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        let (_, tip) = tokio::spawn(async move {
+            chain_sync::find_intersect(&mut *handle.lock().await, &[Point::Origin]).await
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(tip.point, Point::Origin);
+        assert!(connection.peer_sharing());
+        let handle = connection.protocol(MINI_PROTOCOL_ID_PEER_SHARING).unwrap();
+        let shared = peer_sharing::share_request(&mut *handle.lock().await, 5).await;
+        assert_eq!(shared, Ok(peers));
+        // The servers see all protocols done, then the end of the bearer
+        connection.close().await;
+        assert_eq!(server.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn close_while_awaiting_reply() {
+        let protocols = [MINI_PROTOCOL_ID_CHAIN_SYNC];
+        let (client, server) = bearer::duplex();
+        let server = tokio::spawn(async move {
+            let connection = PeerConnection::respond("client", server, 2, &vec![13], &protocols)
+                .await
+                .unwrap();
+            let mut channel = connection
+                .protocol(MINI_PROTOCOL_ID_CHAIN_SYNC)
+                .unwrap()
+                .lock()
+                .await;
+            // Answers the request, never the update after it
+            channel.recv_value().await.unwrap();
+            channel
+                .send_value(&chain_sync::Message::AwaitReply.to_value())
+                .unwrap();
+            channel.recv_value().await
+        });
+
+        let connection = PeerConnection::initiate("server", client, 2, &vec![13], &protocols)
+            .await
+            .unwrap();
+        let handle = connection.protocol(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+        let (awaiting, awaited) = tokio::sync::oneshot::channel();
+        let client = tokio::spawn(async move {
+            let mut channel = handle.lock().await;
+            let reply = chain_sync::request_next(&mut channel).await.unwrap();
+            assert_eq!(reply, chain_sync::Message::AwaitReply);
+            awaiting.send(()).unwrap();
+            // Holds the channel while waiting for the update
+            chain_sync::recv(&mut channel).await
+        });
+        awaited.await.unwrap();
+        // Returns without the channel, and without sending `Done`
+        tokio::time::timeout(std::time::Duration::from_secs(5), connection.close())
+            .await
+            .unwrap();
+        assert!(server.await.unwrap().is_err());
+        assert!(client.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn refused() {
+        let (client, server) = bearer::duplex();
+        let server = tokio::spawn(async move {
+            PeerConnection::respond("client", server, 2, &vec![13], &[]).await
+        });
+        let error = PeerConnection::initiate("server", client, 1, &vec![13], &[])
+            .await
+            .err()
+            .unwrap();
+        assert!(error.starts_with("server refused the handshake: Refused 13"));
+        assert!(server.await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,669 @@
+// Connection manager: at most one connection per peer address, shared by
+// our side as initiator (outbound) and the peer as initiator (inbound) when
+// the handshake made it duplex. Outbound connections are opened from the
+// address we accept connections on, so the peer can reuse them the same way.
+// Peers are tracked by socket address: host names are resolved, and
+// addresses that can be written several ways are made canonical, so a peer
+// is the same whichever side connected and however it was named.
+//
+// Closing a TCP connection leaves its socket pair in TIME_WAIT for a while,
+// and with both ends bound to the listening addresses the same pair would be
+// used again: a closed connection keeps its peer in `TimeWait` for
+// `time_wait` before the peer is dialled again. Every change of state is
+// broadcast as a `ConnectionEvent`.
+
+use crate::bearer::{self, Bearer};
+use crate::connection::PeerConnection;
+use std::collections::HashMap;
+use std::net::{SocketAddr, SocketAddrV6};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::{net::lookup_host, sync::broadcast, time::Instant};
+use tracing::{debug, info};
+
+const EVENTS_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ManagerConfig {
+    pub network_magic: u32,
+    pub versions: Vec<i64>,
+    // Mini-protocols of every connection, besides the handshake
+    pub protocols: Vec<u16>,
+    // Address we accept connections on, outbound connections are opened
+    // from it when set
+    pub local_address: Option<SocketAddr>,
+    // Inbound connections kept at most, including those still negotiating
+    pub max_inbound: usize,
+    // Inbound connections not negotiated by then are dropped, so that they
+    // do not hold on to the inbound limit
+    pub handshake_timeout: Duration,
+    pub time_wait: Duration,
+}
+
+impl ManagerConfig {
+    pub fn new(network_magic: u32, versions: &[i64], protocols: &[u16]) -> ManagerConfig {
+        ManagerConfig {
+            network_magic,
+            versions: versions.to_vec(),
+            protocols: protocols.to_vec(),
+            local_address: None,
+            max_inbound: 512,
+            handshake_timeout: Duration::from_secs(10),
+            time_wait: Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_local_address(mut self, local_address: SocketAddr) -> ManagerConfig {
+        self.local_address = Some(local_address);
+        self
+    }
+
+    pub fn with_max_inbound(mut self, max_inbound: usize) -> ManagerConfig {
+        self.max_inbound = max_inbound;
+        self
+    }
+
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> ManagerConfig {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    pub fn with_time_wait(mut self, time_wait: Duration) -> ManagerConfig {
+        self.time_wait = time_wait;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Dialling and negotiating an outbound connection
+    ReservedOutbound,
+    // Negotiating an accepted connection
+    UnnegotiatedInbound,
+    // Unidirectional connection used by our side
+    Outbound,
+    // Unidirectional connection used by the peer
+    Inbound,
+    // Duplex connection and the sides using it
+    Duplex { outbound: bool, inbound: bool },
+    // Closed, the peer is not dialled until the time-wait is over
+    TimeWait,
+}
+
+impl ConnectionState {
+    fn is_inbound(self) -> bool {
+        matches!(
+            self,
+            ConnectionState::UnnegotiatedInbound
+                | ConnectionState::Inbound
+                | ConnectionState::Duplex { inbound: true, .. }
+        )
+    }
+}
+
+/// A peer moved from one state to another, `None` before it is known and
+/// after it is forgotten.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionEvent {
+    pub peer: SocketAddr,
+    pub from: Option<ConnectionState>,
+    pub to: Option<ConnectionState>,
+}
+
+struct Entry {
+    state: ConnectionState,
+    connection: Option<Arc<PeerConnection>>,
+    // End of the time-wait
+    until: Option<Instant>,
+}
+
+struct Shared {
+    config: ManagerConfig,
+    entries: Mutex<HashMap<SocketAddr, Entry>>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl Shared {
+    fn entries(&self) -> MutexGuard<'_, HashMap<SocketAddr, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Moves `peer` to `to`, keeping its connection, or forgets it.
+    fn transition(
+        &self,
+        entries: &mut HashMap<SocketAddr, Entry>,
+        peer: SocketAddr,
+        to: Option<ConnectionState>,
+    ) {
+        let from = match to {
+            Some(state) => match entries.get_mut(&peer) {
+                Some(entry) => Some(std::mem::replace(&mut entry.state, state)),
+                None => {
+                    let entry = Entry {
+                        state,
+                        connection: None,
+                        until: None,
+                    };
+                    entries.insert(peer, entry);
+                    None
+                }
+            },
+            None => entries.remove(&peer).map(|entry| entry.state),
+        };
+        debug!("Connection to {}: {:?} -> {:?}", peer, from, to);
+        let _ = self.events.send(ConnectionEvent { peer, from, to });
+    }
+}
+
+/// Tracks the connections to peers by address. Clones share the
+/// connections. Connections are handed out to each side until it releases
+/// them, which callers do when they are done or once the mini-protocols on
+/// them fail.
+#[derive(Clone)]
+pub struct ConnectionManager {
+    shared: Arc<Shared>,
+}
+
+impl ConnectionManager {
+    pub fn new(config: ManagerConfig) -> ConnectionManager {
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        ConnectionManager {
+            shared: Arc::new(Shared {
+                config,
+                entries: Mutex::new(HashMap::new()),
+                events,
+            }),
+        }
+    }
+
+    /// Events of the transitions from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.shared.events.subscribe()
+    }
+
+    pub fn state(&self, peer: SocketAddr) -> Option<ConnectionState> {
+        let entries = self.shared.entries();
+        entries.get(&canonical(peer)).map(|entry| entry.state)
+    }
+
+    /// Connections the peers use or are negotiating.
+    pub fn inbound_count(&self) -> usize {
+        let entries = self.shared.entries();
+        entries.values().filter(|e| e.state.is_inbound()).count()
+    }
+
+    /// Connection for our side to use to `host`, given as `name:port`: the
+    /// duplex connection the peer opened if there is one, otherwise a new
+    /// one to the address `host` resolves to.
+    pub async fn acquire_outbound(&self, host: &str) -> Result<Arc<PeerConnection>, String> {
+        let config = &self.shared.config;
+        let peer = resolve(host, config.local_address).await?;
+        {
+            let mut entries = self.shared.entries();
+            match entries.get(&peer).map(|entry| entry.state) {
+                None => self.shared.transition(
+                    &mut entries,
+                    peer,
+                    Some(ConnectionState::ReservedOutbound),
+                ),
+                Some(ConnectionState::Duplex {
+                    outbound: false,
+                    inbound,
+                }) => {
+                    info!("Reusing duplex connection from {}", peer);
+                    let to = ConnectionState::Duplex {
+                        outbound: true,
+                        inbound,
+                    };
+                    self.shared.transition(&mut entries, peer, Some(to));
+                    return connection(&entries, peer);
+                }
+                Some(state) => return Err(unavailable(peer, state)),
+            }
+        }
+
+        let connection = match config.local_address {
+            Some(local) => match bearer::connect_tcp_from(&peer.to_string(), local).await {
+                Ok(stream) => {
+                    let versions = &config.versions;
+                    let magic = config.network_magic;
+                    PeerConnection::initiate(host, stream, magic, versions, &config.protocols).await
+                }
+                Err(error) => Err(error),
+            },
+            None => {
+                PeerConnection::connect_to(
+                    host,
+                    peer,
+                    config.network_magic,
+                    &config.versions,
+                    &config.protocols,
+                )
+                .await
+            }
+        };
+        let mut entries = self.shared.entries();
+        let connection = match connection {
+            Ok(connection) => Arc::new(connection),
+            Err(error) => {
+                self.shared.transition(&mut entries, peer, None);
+                return Err(error);
+            }
+        };
+        let to = match connection.is_duplex() {
+            true => ConnectionState::Duplex {
+                outbound: true,
+                inbound: false,
+            },
+            false => ConnectionState::Outbound,
+        };
+        self.shared.transition(&mut entries, peer, Some(to));
+        if let Some(entry) = entries.get_mut(&peer) {
+            entry.connection = Some(connection.clone());
+        }
+        Ok(connection)
+    }
+
+    /// Negotiates a connection the peer at `peer` opened, unless that makes
+    /// more inbound connections than allowed or the peer is connected
+    /// already. Peers get `handshake_timeout` to negotiate. Dropping
+    /// `bearer` on errors closes it.
+    pub async fn accept_inbound<B: Bearer>(
+        &self,
+        peer: SocketAddr,
+        bearer: B,
+    ) -> Result<Arc<PeerConnection>, String> {
+        let config = &self.shared.config;
+        let peer = canonical(peer);
+        {
+            let mut entries = self.shared.entries();
+            let inbound = entries.values().filter(|e| e.state.is_inbound()).count();
+            if inbound >= config.max_inbound {
+                return Err(format!(
+                    "Refusing {}: {} inbound connections",
+                    peer, config.max_inbound
+                ));
+            }
+            match entries.get(&peer).map(|entry| entry.state) {
+                None | Some(ConnectionState::TimeWait) => self.shared.transition(
+                    &mut entries,
+                    peer,
+                    Some(ConnectionState::UnnegotiatedInbound),
+                ),
+                Some(state) => return Err(unavailable(peer, state)),
+            }
+        }
+
+        let host = peer.to_string();
+        let respond = PeerConnection::respond(
+            &host,
+            bearer,
+            config.network_magic,
+            &config.versions,
+            &config.protocols,
+        );
+        let connection = tokio::time::timeout(config.handshake_timeout, respond)
+            .await
+            .unwrap_or_else(|_| Err(format!("Handshake with {} timed out", peer)));
+        let mut entries = self.shared.entries();
+        let connection = match connection {
+            Ok(connection) => Arc::new(connection),
+            Err(error) => {
+                self.shared.transition(&mut entries, peer, None);
+                return Err(error);
+            }
+        };
+        let to = match connection.is_duplex() {
+            true => ConnectionState::Duplex {
+                outbound: false,
+                inbound: true,
+            },
+            false => ConnectionState::Inbound,
+        };
+        self.shared.transition(&mut entries, peer, Some(to));
+        if let Some(entry) = entries.get_mut(&peer) {
+            entry.connection = Some(connection.clone());
+            entry.until = None;
+        }
+        Ok(connection)
+    }
+
+    /// The duplex connection we opened to `peer`, for serving the peer once
+    /// it uses the connection as its outbound one.
+    pub fn acquire_inbound(&self, peer: SocketAddr) -> Result<Arc<PeerConnection>, String> {
+        let peer = canonical(peer);
+        let mut entries = self.shared.entries();
+        match entries.get(&peer).map(|entry| entry.state) {
+            Some(ConnectionState::Duplex {
+                outbound,
+                inbound: false,
+            }) => {
+                let to = ConnectionState::Duplex {
+                    outbound,
+                    inbound: true,
+                };
+                self.shared.transition(&mut entries, peer, Some(to));
+                connection(&entries, peer)
+            }
+            Some(state) => Err(unavailable(peer, state)),
+            None => Err(format!("Not connected to {}", peer)),
+        }
+    }
+
+    /// Our side is done with `connection`, which is closed unless the peer
+    /// still uses it.
+    pub async fn release_outbound(&self, connection: Arc<PeerConnection>) {
+        self.release(connection, |state| match state {
+            ConnectionState::Outbound => Some(None),
+            ConnectionState::Duplex {
+                outbound: true,
+                inbound,
+            } => Some(inbound.then_some(ConnectionState::Duplex {
+                outbound: false,
+                inbound,
+            })),
+            _ => None,
+        })
+        .await
+    }
+
+    /// The peer is done with `connection`, which is closed unless our side
+    /// still uses it.
+    pub async fn release_inbound(&self, connection: Arc<PeerConnection>) {
+        self.release(connection, |state| match state {
+            ConnectionState::Inbound => Some(None),
+            ConnectionState::Duplex {
+                outbound,
+                inbound: true,
+            } => Some(outbound.then_some(ConnectionState::Duplex {
+                outbound,
+                inbound: false,
+            })),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Moves the connection to the state `next` gives for its current one:
+    /// `Some(None)` when it is unused and closes, `None` when it was not
+    /// held on this side.
+    async fn release(
+        &self,
+        connection: Arc<PeerConnection>,
+        next: impl Fn(ConnectionState) -> Option<Option<ConnectionState>>,
+    ) {
+        let until = Instant::now() + self.shared.config.time_wait;
+        let peer = {
+            let mut entries = self.shared.entries();
+            let held = entries.iter().find(|(_, entry)| {
+                let ours = entry.connection.as_ref();
+                ours.is_some_and(|ours| Arc::ptr_eq(ours, &connection))
+            });
+            let peer = match held.and_then(|(&peer, entry)| Some((peer, next(entry.state)?))) {
+                Some((peer, Some(state))) => {
+                    self.shared.transition(&mut entries, peer, Some(state));
+                    return;
+                }
+                Some((peer, None)) => peer,
+                None => {
+                    debug!("Connection to {} released twice", connection.peer());
+                    return;
+                }
+            };
+            self.shared
+                .transition(&mut entries, peer, Some(ConnectionState::TimeWait));
+            if let Some(entry) = entries.get_mut(&peer) {
+                entry.connection = None;
+                entry.until = Some(until);
+            }
+            peer
+        };
+
+        match Arc::try_unwrap(connection) {
+            Ok(connection) => connection.close().await,
+            // Closes once the last user drops it
+            Err(_) => debug!("Connection to {} still in use, not closing it", peer),
+        }
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(until).await;
+            let mut entries = shared.entries();
+            let expired = entries.get(&peer).is_some_and(|entry| {
+                entry.state == ConnectionState::TimeWait && entry.until == Some(until)
+            });
+            if expired {
+                shared.transition(&mut entries, peer, None);
+            }
+        });
+    }
+}
+
+fn connection(
+    entries: &HashMap<SocketAddr, Entry>,
+    peer: SocketAddr,
+) -> Result<Arc<PeerConnection>, String> {
+    entries
+        .get(&peer)
+        .and_then(|entry| entry.connection.clone())
+        .ok_or(format!("No connection to {}", peer))
+}
+
+fn unavailable(peer: SocketAddr, state: ConnectionState) -> String {
+    match state {
+        ConnectionState::ReservedOutbound | ConnectionState::UnnegotiatedInbound => {
+            format!("Connection to {} is being negotiated", peer)
+        }
+        ConnectionState::TimeWait => format!("{} is in time-wait", peer),
+        ConnectionState::Inbound => format!("{} connected to us without duplex", peer),
+        state => format!("Already connected to {}: {:?}", peer, state),
+    }
+}
+
+/// The address `host` resolves to, one of the family of `local` when we
+/// connect from there.
+async fn resolve(host: &str, local: Option<SocketAddr>) -> Result<SocketAddr, String> {
+    lookup_host(host)
+        .await
+        .map_err(|error| format!("Could not resolve {}: {}", host, error))?
+        .map(canonical)
+        .find(|peer| local.is_none_or(|local| local.is_ipv4() == peer.is_ipv4()))
+        .ok_or(format!("No address of {} to reach", host))
+}
+
+/// `address` written one way: IPv4-mapped IPv6 addresses as IPv4, and IPv6
+/// addresses without flow information.
+pub fn canonical(address: SocketAddr) -> SocketAddr {
+    match address {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => SocketAddr::V6(SocketAddrV6::new(*v6.ip(), v6.port(), 0, v6.scope_id())),
+        },
+        v4 => v4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_sync::MINI_PROTOCOL_ID_CHAIN_SYNC;
+    use crate::mock::{MockNode, Reply, Script};
+    use crate::mux::Mode;
+    use ciborium::Value;
+    use tokio::net::TcpListener;
+
+    fn config() -> ManagerConfig {
+        ManagerConfig::new(2, &[13, 14], &[MINI_PROTOCOL_ID_CHAIN_SYNC])
+    }
+
+    /// Accepts every connection on `listener` with `manager`.
+    fn listen(listener: TcpListener, manager: ConnectionManager) {
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                let _ = manager.accept_inbound(peer, stream).await;
+            }
+        });
+    }
+
+    async fn next(events: &mut broadcast::Receiver<ConnectionEvent>) -> Option<ConnectionState> {
+        events.recv().await.unwrap().to
+    }
+
+    #[tokio::test]
+    async fn duplex_connection_is_reused() {
+        let listen_a = bearer::listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listen_b = bearer::listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+        let (address_a, address_b) = (
+            listen_a.local_addr().unwrap(),
+            listen_b.local_addr().unwrap(),
+        );
+        let a = ConnectionManager::new(config().with_local_address(address_a));
+        let b = ConnectionManager::new(config().with_local_address(address_b));
+        listen(listen_b, b.clone());
+        let mut events = b.subscribe();
+
+        let outbound = a.acquire_outbound(&address_b.to_string()).await.unwrap();
+        assert!(outbound.is_duplex());
+        let duplex = |outbound, inbound| Some(ConnectionState::Duplex { outbound, inbound });
+        assert_eq!(a.state(address_b), duplex(true, false));
+        // B sees the connection coming from A's listening address and uses it
+        // to reach A
+        let peer_a = address_a.to_string();
+        assert_eq!(
+            next(&mut events).await,
+            Some(ConnectionState::UnnegotiatedInbound)
+        );
+        assert_eq!(next(&mut events).await, duplex(false, true));
+        assert_eq!(b.inbound_count(), 1);
+        let reused = b.acquire_outbound(&peer_a).await.unwrap();
+        assert_eq!(next(&mut events).await, duplex(true, true));
+        assert_eq!(reused.mode(), Mode::Responder);
+
+        let served = a.acquire_inbound(address_b).unwrap();
+        let client = reused.protocol_in(Mode::Initiator, MINI_PROTOCOL_ID_CHAIN_SYNC);
+        let server = served.protocol_in(Mode::Responder, MINI_PROTOCOL_ID_CHAIN_SYNC);
+        let (client, server) = (client.unwrap(), server.unwrap());
+        client.lock().await.send_value(&Value::from(1)).unwrap();
+        assert_eq!(
+            server.lock().await.recv_value().await.unwrap(),
+            Value::from(1)
+        );
+        drop((client, server, served));
+
+        // Closed once neither side uses it
+        b.release_outbound(reused).await;
+        assert_eq!(next(&mut events).await, duplex(false, true));
+        let inbound = b.acquire_outbound(&peer_a).await.unwrap();
+        assert_eq!(next(&mut events).await, duplex(true, true));
+        b.release_outbound(inbound.clone()).await;
+        b.release_outbound(inbound.clone()).await;
+        assert_eq!(next(&mut events).await, duplex(false, true));
+        b.release_inbound(inbound).await;
+        assert_eq!(next(&mut events).await, Some(ConnectionState::TimeWait));
+        assert_eq!(b.inbound_count(), 0);
+        assert_eq!(
+            b.acquire_outbound(&peer_a).await.err().unwrap(),
+            format!("{} is in time-wait", peer_a)
+        );
+    }
+
+    #[tokio::test]
+    async fn inbound_limit() {
+        let manager = ConnectionManager::new(config().with_max_inbound(1));
+        let mut results = vec![];
+        let (one, two) = ("10.0.0.1:3001", "10.0.0.2:3001");
+        for peer in [one, two] {
+            let peer = peer.parse().unwrap();
+            let (client, server) = bearer::duplex();
+            let client = tokio::spawn(async move {
+                PeerConnection::initiate("server", client, 2, &vec![13], &[]).await
+            });
+            let inbound = manager.accept_inbound(peer, server).await;
+            results.push((inbound, client.await.unwrap()));
+        }
+        assert!(results[0].0.is_ok() && results[0].1.is_ok());
+        assert_eq!(
+            results[1].0.as_ref().err().unwrap(),
+            "Refusing 10.0.0.2:3001: 1 inbound connections"
+        );
+        assert!(results[1].1.is_err());
+        assert_eq!(manager.state(two.parse().unwrap()), None);
+
+        // Connected already, also when the address is written another way
+        let (_, server) = bearer::duplex();
+        let mapped = "[::ffff:10.0.0.1]:3001".parse().unwrap();
+        assert!(manager.accept_inbound(mapped, server).await.is_err());
+        let reused = manager.acquire_outbound(one).await.unwrap();
+        assert_eq!(reused.peer(), one);
+        assert_eq!(
+            manager.state(mapped),
+            Some(ConnectionState::Duplex {
+                outbound: true,
+                inbound: true
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        let timeout = Duration::from_millis(50);
+        let manager =
+            ConnectionManager::new(config().with_max_inbound(1).with_handshake_timeout(timeout));
+        let peer = "10.0.0.1:3001".parse().unwrap();
+        // The peer never proposes, the slot it took is given back
+        let (_silent, server) = bearer::duplex();
+        assert_eq!(
+            manager.accept_inbound(peer, server).await.err().unwrap(),
+            "Handshake with 10.0.0.1:3001 timed out"
+        );
+        assert_eq!(manager.state(peer), None);
+
+        let (client, server) = bearer::duplex();
+        let client = tokio::spawn(async move {
+            PeerConnection::initiate("server", client, 2, &vec![13], &[]).await
+        });
+        assert!(manager.accept_inbound(peer, server).await.is_ok());
+        assert!(client.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn time_wait() {
+        let node = MockNode::start(Script::new(&[13], 2, Reply::Accept))
+            .await
+            .unwrap();
+        let time_wait = Duration::from_millis(50);
+        let manager = ConnectionManager::new(config().with_time_wait(time_wait));
+        let mut events = manager.subscribe();
+
+        let connection = manager.acquire_outbound(&node.host()).await.unwrap();
+        assert!(manager.acquire_outbound(&node.host()).await.is_err());
+        manager.release_outbound(connection).await;
+        let states = [
+            Some(ConnectionState::ReservedOutbound),
+            Some(ConnectionState::Duplex {
+                outbound: true,
+                inbound: false,
+            }),
+            Some(ConnectionState::TimeWait),
+            None,
+        ];
+        for state in states {
+            assert_eq!(next(&mut events).await, state);
+        }
+        let connection = manager.acquire_outbound(&node.host()).await.unwrap();
+        manager.release_outbound(connection).await;
+        let port = node.host().rsplit_once(':').unwrap().1.to_owned();
+        let error = manager
+            .acquire_outbound(&format!("[::ffff:127.0.0.1]:{}", port))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error, format!("127.0.0.1:{} is in time-wait", port));
+
+        // Failed connections are forgotten
+        let node = MockNode::start(Script::new(&[13], 2, Reply::Close))
+            .await
+            .unwrap();
+        assert!(manager.acquire_outbound(&node.host()).await.is_err());
+        assert_eq!(manager.state(node.host().parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+/// Eras of the Cardano hard fork combinator, in chain order. The discriminant
+/// is the era index that tags headers on the wire. Blocks are tagged
+/// differently, see `from_block_tag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Era {
+    Byron = 0,
+    Shelley = 1,
+    Allegra = 2,
+    Mary = 3,
+    Alonzo = 4,
+    Babbage = 5,
+    Conway = 6,
+}
+
+impl Era {
+    pub const ALL: [Era; 7] = [
+        Era::Byron,
+        Era::Shelley,
+        Era::Allegra,
+        Era::Mary,
+        Era::Alonzo,
+        Era::Babbage,
+        Era::Conway,
+    ];
+
+    pub fn from_index(index: u64) -> Result<Era, String> {
+        Era::ALL
+            .get(index as usize)
+            .copied()
+            .ok_or(format!("Unknown era index {}", index))
+    }
+
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Era of a block tag, as cardano-node stores blocks and block-fetch
+    /// sends them: Byron epoch boundary blocks are tagged 0, Byron main
+    /// blocks 1 and the later eras 2 to 7. Also tells epoch boundary blocks.
+    pub fn from_block_tag(tag: u64) -> Result<(Era, bool), String> {
+        match tag {
+            0 => Ok((Era::Byron, true)),
+            1 => Ok((Era::Byron, false)),
+            tag => Era::ALL
+                .get(tag as usize - 1)
+                .map(|era| (*era, false))
+                .ok_or(format!("Unknown block tag {}", tag)),
+        }
+    }
+
+    pub fn block_tag(&self, is_ebb: bool) -> u64 {
+        match (self, is_ebb) {
+            (Era::Byron, true) => 0,
+            (era, _) => era.index() as u64 + 1,
+        }
+    }
+}
+
+impl fmt::Display for Era {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
@@ -0,0 +1,238 @@
+// Slot, epoch and wall clock conversions across eras.
+//
+// Every era has its own slot length and epoch length: Byron has 20 second
+// slots and epochs of 10k slots, every era from Shelley on 1 second slots and
+// longer epochs. Like the hard fork combinator, the history is a list of eras
+// with the slot, epoch and time they start at. The last known era is assumed
+// to last forever.
+
+use super::Era;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraBound {
+    pub slot: u64,
+    pub epoch: u64,
+    // Milliseconds since the system start
+    pub time_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraSummary {
+    pub era: Era,
+    pub start: EraBound,
+    pub slot_length_ms: u64,
+    pub epoch_length: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EraHistory {
+    // Unix time in milliseconds of slot 0
+    system_start_ms: u64,
+    eras: Vec<EraSummary>,
+}
+
+/// Slot and epoch lengths of an era.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraParams {
+    pub slot_length_ms: u64,
+    pub epoch_length: u64,
+}
+
+impl EraHistory {
+    /// Builds the history of a chain starting in Byron at `system_start`
+    /// (Unix seconds). `transitions` are the epochs later eras start at, in
+    /// era order, as found in the node configuration or observed on chain.
+    /// Eras without a transition are skipped over when a later era has one.
+    pub fn new(
+        system_start: u64,
+        byron: EraParams,
+        shelley: EraParams,
+        transitions: &[(Era, u64)],
+    ) -> Result<EraHistory, String> {
+        if byron.slot_length_ms == 0 || byron.epoch_length == 0 {
+            return Err("Byron slot and epoch length must not be 0".to_owned());
+        }
+        if shelley.slot_length_ms == 0 || shelley.epoch_length == 0 {
+            return Err("Shelley slot and epoch length must not be 0".to_owned());
+        }
+        let mut eras = vec![EraSummary {
+            era: Era::Byron,
+            start: EraBound {
+                slot: 0,
+                epoch: 0,
+                time_ms: 0,
+            },
+            slot_length_ms: byron.slot_length_ms,
+            epoch_length: byron.epoch_length,
+        }];
+        for &(era, epoch) in transitions {
+            let previous = eras[eras.len() - 1];
+            if era <= previous.era || epoch < previous.start.epoch {
+                return Err(format!(
+                    "{} at epoch {} does not follow {} at epoch {}",
+                    era, epoch, previous.era, previous.start.epoch
+                ));
+            }
+            let slots = (epoch - previous.start.epoch) * previous.epoch_length;
+            let params = match era {
+                Era::Byron => byron,
+                _ => shelley,
+            };
+            let start = EraBound {
+                slot: previous.start.slot + slots,
+                epoch,
+                time_ms: previous.start.time_ms + slots * previous.slot_length_ms,
+            };
+            // An era starting at the same epoch replaces the previous one
+            if epoch == previous.start.epoch {
+                eras.pop();
+            }
+            eras.push(EraSummary {
+                era,
+                start,
+                slot_length_ms: params.slot_length_ms,
+                epoch_length: params.epoch_length,
+            });
+        }
+        Ok(EraHistory {
+            system_start_ms: system_start * 1000,
+            eras,
+        })
+    }
+
+    pub fn eras(&self) -> &[EraSummary] {
+        &self.eras
+    }
+
+    fn era_of_slot(&self, slot: u64) -> &EraSummary {
+        self.eras
+            .iter()
+            .rev()
+            .find(|era| era.start.slot <= slot)
+            .unwrap_or(&self.eras[0])
+    }
+
+    pub fn era(&self, slot: u64) -> Era {
+        self.era_of_slot(slot).era
+    }
+
+    /// Epoch of a slot and the slot's index within it.
+    pub fn slot_to_epoch(&self, slot: u64) -> (u64, u64) {
+        let era = self.era_of_slot(slot);
+        let slots = slot - era.start.slot;
+        (
+            era.start.epoch + slots / era.epoch_length,
+            slots % era.epoch_length,
+        )
+    }
+
+    pub fn epoch_first_slot(&self, epoch: u64) -> u64 {
+        let era = self
+            .eras
+            .iter()
+            .rev()
+            .find(|era| era.start.epoch <= epoch)
+            .unwrap_or(&self.eras[0]);
+        era.start.slot + (epoch - era.start.epoch) * era.epoch_length
+    }
+
+    /// Unix time in milliseconds at which a slot starts.
+    pub fn slot_to_unix_ms(&self, slot: u64) -> u64 {
+        let era = self.era_of_slot(slot);
+        self.system_start_ms + era.start.time_ms + (slot - era.start.slot) * era.slot_length_ms
+    }
+
+    /// Slot in progress at a Unix time in milliseconds.
+    pub fn unix_ms_to_slot(&self, unix_ms: u64) -> Result<u64, String> {
+        let time_ms = unix_ms.checked_sub(self.system_start_ms).ok_or(format!(
+            "{} ms is before the system start at {} ms",
+            unix_ms, self.system_start_ms
+        ))?;
+        let era = self
+            .eras
+            .iter()
+            .rev()
+            .find(|era| era.start.time_ms <= time_ms)
+            .unwrap_or(&self.eras[0]);
+        Ok(era.start.slot + (time_ms - era.start.time_ms) / era.slot_length_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Network;
+
+    const BYRON: EraParams = EraParams {
+        slot_length_ms: 20_000,
+        epoch_length: 21_600,
+    };
+    const SHELLEY: EraParams = EraParams {
+        slot_length_ms: 1_000,
+        epoch_length: 432_000,
+    };
+
+    fn mainnet() -> EraHistory {
+        let preset = Network::Mainnet.preset();
+        EraHistory::new(preset.system_start, BYRON, SHELLEY, preset.hard_fork_epochs).unwrap()
+    }
+
+    #[test]
+    fn mainnet_conversions() {
+        let history = mainnet();
+        // Last Byron slot and first Shelley slot
+        assert_eq!(history.slot_to_epoch(4_492_799), (207, 21_599));
+        assert_eq!(history.slot_to_epoch(4_492_800), (208, 0));
+        assert_eq!(history.era(4_492_800), Era::Shelley);
+        assert_eq!(history.slot_to_unix_ms(4_492_800), 1_596_059_091_000);
+        assert_eq!(history.epoch_first_slot(365), 72_316_800);
+        assert_eq!(history.era(72_316_800), Era::Babbage);
+        assert_eq!(history.era(72_316_799), Era::Alonzo);
+
+        // Byron slots last 20 seconds
+        assert_eq!(
+            history.unix_ms_to_slot(1_506_203_091_000 + 39_999).unwrap(),
+            1
+        );
+        assert_eq!(
+            history.unix_ms_to_slot(1_596_059_091_000).unwrap(),
+            4_492_800
+        );
+        for slot in [0, 7, 4_492_799, 4_492_800, 150_000_000] {
+            assert_eq!(
+                history
+                    .unix_ms_to_slot(history.slot_to_unix_ms(slot))
+                    .unwrap(),
+                slot
+            );
+        }
+        assert!(history.unix_ms_to_slot(0).is_err());
+    }
+
+    #[test]
+    fn eras_starting_at_the_same_epoch() {
+        // Preview forks to Alonzo at epoch 0, there are no Byron slots
+        let history = EraHistory::new(
+            1_666_656_000,
+            BYRON,
+            EraParams {
+                slot_length_ms: 1_000,
+                epoch_length: 86_400,
+            },
+            &[
+                (Era::Shelley, 0),
+                (Era::Allegra, 0),
+                (Era::Mary, 0),
+                (Era::Alonzo, 0),
+                (Era::Babbage, 3),
+            ],
+        )
+        .unwrap();
+        assert_eq!(history.eras().len(), 2);
+        assert_eq!(history.era(0), Era::Alonzo);
+        assert_eq!(history.slot_to_epoch(259_200), (3, 0));
+        assert_eq!(history.slot_to_unix_ms(10), 1_666_656_010_000);
+
+        assert!(EraHistory::new(0, BYRON, SHELLEY, &[(Era::Mary, 4), (Era::Shelley, 5)]).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::codec::{item_len, split_array};
+use crate::crypto::{
+    blake2b_224, blake2b_256, Hash28, Hash32, KesPublicKey, PublicKey, Signature, VrfOutput,
+    VrfProof, VrfPublicKey,
+};
+use ciborium::{from_reader, Value};
+
+// Babbage and Conway (Praos) block headers:
+//
+// header = [header_body, body_signature : $kes_signature]
+// header_body = [block_number, slot, prev_hash / null, issuer_vkey, vrf_vkey,
+//                vrf_result : [output, proof], block_body_size, block_body_hash,
+//                operational_cert, protocol_version]
+// operational_cert = [hot_vkey, sequence_number, kes_period, sigma]
+// protocol_version = [major, minor]
+//
+// TPraos header bodies of Shelley to Alonzo have two VRF results and the
+// operational certificate and protocol version inlined, 15 fields in all.
+// They are rejected: their VRF and nonce rules are not implemented.
+
+const HEADER_BODY_FIELDS: usize = 10;
+const TPRAOS_HEADER_BODY_FIELDS: usize = 15;
+
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    pub body: HeaderBody,
+    // Raw header_body bytes, as signed with the KES key
+    pub body_raw: Vec<u8>,
+    pub body_signature: Vec<u8>,
+    // Blake2b-256 of the whole raw header
+    pub hash: Hash32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HeaderBody {
+    pub block_number: u64,
+    pub slot: u64,
+    pub prev_hash: Option<Hash32>,
+    pub issuer_vkey: PublicKey,
+    pub vrf_vkey: VrfPublicKey,
+    pub vrf_output: VrfOutput,
+    pub vrf_proof: VrfProof,
+    pub block_body_size: u64,
+    pub block_body_hash: Hash32,
+    pub operational_cert: OperationalCert,
+    pub protocol_version: (u64, u64),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct OperationalCert {
+    pub hot_vkey: KesPublicKey,
+    pub sequence_number: u64,
+    pub kes_period: u64,
+    pub sigma: Signature,
+}
+
+impl Header {
+    pub fn from_cbor(bytes: &[u8]) -> Result<Header, String> {
+        let items = split_array(bytes)?;
+        if items.len() != 2 {
+            return Err(format!(
+                "Header: Do not expect array of {} items!",
+                items.len()
+            ));
+        }
+        let body: Value = from_reader(items[0])
+            .map_err(|error| format!("Could not decode header body: {:?}", error))?;
+        if body
+            .as_array()
+            .is_some_and(|fields| fields.len() == TPRAOS_HEADER_BODY_FIELDS)
+        {
+            return Err("Header: TPraos (Shelley to Alonzo) headers are not supported".to_owned());
+        }
+        let body_signature: Value = from_reader(items[1])
+            .map_err(|error| format!("Could not decode body signature: {:?}", error))?;
+        let header_len = item_len(bytes)?;
+        Ok(Header {
+            body: HeaderBody::from_value(body)?,
+            body_raw: items[0].to_vec(),
+            body_signature: body_signature
+                .into_bytes()
+                .map_err(|error| format!("Could not convert body signature: {:?}", error))?,
+            hash: blake2b_256(&bytes[..header_len]),
+        })
+    }
+
+    /// Pool id of the block issuer: Blake2b-224 of the cold verification key.
+    pub fn issuer_pool_id(&self) -> Hash28 {
+        blake2b_224(&self.body.issuer_vkey)
+    }
+}
+
+impl HeaderBody {
+    fn from_value(value: Value) -> Result<HeaderBody, String> {
+        let mut fields = array(value, HEADER_BODY_FIELDS, "HeaderBody")?.into_iter();
+        let mut next = || fields.next().unwrap_or(Value::Null);
+
+        let block_number = integer(next(), "block_number")?;
+        let slot = integer(next(), "slot")?;
+        let prev_hash = match next() {
+            Value::Null => None,
+            value => Some(fixed_bytes(value, "prev_hash")?),
+        };
+        let issuer_vkey = fixed_bytes(next(), "issuer_vkey")?;
+        let vrf_vkey = fixed_bytes(next(), "vrf_vkey")?;
+        let mut vrf_result = array(next(), 2, "vrf_result")?.into_iter();
+        let vrf_output = fixed_bytes(vrf_result.next().unwrap_or(Value::Null), "vrf_output")?;
+        let vrf_proof = fixed_bytes(vrf_result.next().unwrap_or(Value::Null), "vrf_proof")?;
+        let block_body_size = integer(next(), "block_body_size")?;
+        let block_body_hash = fixed_bytes(next(), "block_body_hash")?;
+        let operational_cert = OperationalCert::from_value(next())?;
+        let mut protocol_version = array(next(), 2, "protocol_version")?.into_iter();
+        let protocol_version = (
+            integer(
+                protocol_version.next().unwrap_or(Value::Null),
+                "protocol_major",
+            )?,
+            integer(
+                protocol_version.next().unwrap_or(Value::Null),
+                "protocol_minor",
+            )?,
+        );
+
+        Ok(HeaderBody {
+            block_number,
+            slot,
+            prev_hash,
+            issuer_vkey,
+            vrf_vkey,
+            vrf_output,
+            vrf_proof,
+            block_body_size,
+            block_body_hash,
+            operational_cert,
+            protocol_version,
+        })
+    }
+}
+
+impl OperationalCert {
+    fn from_value(value: Value) -> Result<OperationalCert, String> {
+        let mut fields = array(value, 4, "OperationalCert")?.into_iter();
+        let mut next = || fields.next().unwrap_or(Value::Null);
+        Ok(OperationalCert {
+            hot_vkey: fixed_bytes(next(), "hot_vkey")?,
+            sequence_number: integer(next(), "sequence_number")?,
+            kes_period: integer(next(), "kes_period")?,
+            sigma: fixed_bytes(next(), "sigma")?,
+        })
+    }
+
+    /// Bytes signed by the cold key: hot key, counter and start KES period.
+    pub fn signable(&self) -> Vec<u8> {
+        let mut signable = self.hot_vkey.to_vec();
+        signable.extend_from_slice(&self.sequence_number.to_be_bytes());
+        signable.extend_from_slice(&self.kes_period.to_be_bytes());
+        signable
+    }
+}
+
+/// Block body hash and size of a Praos block
+/// `[header, transaction_bodies, transaction_witness_sets, auxiliary_data_set, invalid_transactions]`.
+///
+/// The hash is Blake2b-256 over the concatenated hashes of the four raw body
+/// parts, the size is the sum of their lengths.
+pub fn block_body_hash(block: &[u8]) -> Result<(Hash32, u64), String> {
+    let items = split_array(block)?;
+    if items.len() != 5 {
+        return Err(format!(
+            "Block: Do not expect array of {} items!",
+            items.len()
+        ));
+    }
+    let mut hashes = Vec::with_capacity(4 * 32);
+    let mut size = 0;
+    for part in &items[1..] {
+        hashes.extend_from_slice(&blake2b_256(part));
+        size += part.len() as u64;
+    }
+    Ok((blake2b_256(&hashes), size))
+}
+
+fn array(value: Value, count: usize, name: &str) -> Result<Vec<Value>, String> {
+    let array = value
+        .into_array()
+        .map_err(|error| format!("Could not convert {} into array: {:?}", name, error))?;
+    if array.len() != count {
+        return Err(format!(
+            "{}: Expected {} fields, found {}",
+            name,
+            count,
+            array.len()
+        ));
+    }
+    Ok(array)
+}
+
+fn integer(value: Value, name: &str) -> Result<u64, String> {
+    let integer = value
+        .as_integer()
+        .ok_or(format!("Could not convert {} to integer", name))?;
+    u64::try_from(integer).map_err(|_| format!("{} is out of range", name))
+}
+
+fn fixed_bytes<const N: usize>(value: Value, name: &str) -> Result<[u8; N], String> {
+    let bytes = value
+        .into_bytes()
+        .map_err(|error| format!("Could not convert {} into bytes: {:?}", name, error))?;
+    let len = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| format!("{}: Expected {} bytes, found {}", name, N, len))
+}
@@ -0,0 +1,276 @@
+// Praos leader check, following `checkLeaderNatValue` from cardano-ledger.
+//
+// A stake pool with relative stake sigma leads a slot when
+//     leader_value / 2^256 < 1 - (1 - f)^sigma
+// which is evaluated as 1 / (1 - p) < exp(-sigma * ln(1 - f)) on 34 digit
+// fixed point numbers. ln is the continued fraction `ln'` and the comparison
+// `taylorExpCmp` of cardano-ledger's NonIntegral, rounding at the same steps
+// as Data.Fixed does, so results agree with the Haskell node.
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+
+// Iterations of the series and continued fractions, `maxN` of the ledger
+const MAX_N: u32 = 1000;
+const TAYLOR_ERROR_BOUND: u32 = 3;
+
+/// A rational number in [0, 1], as used for stake ratios and the active slot
+/// coefficient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitInterval {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl UnitInterval {
+    pub fn new(numerator: u64, denominator: u64) -> Result<UnitInterval, String> {
+        if denominator == 0 || numerator > denominator {
+            return Err(format!(
+                "{}/{} is not within the unit interval",
+                numerator, denominator
+            ));
+        }
+        Ok(UnitInterval {
+            numerator,
+            denominator,
+        })
+    }
+}
+
+fn precision() -> BigInt {
+    BigInt::from(10u8).pow(34)
+}
+
+fn from_ratio(numerator: &BigInt, denominator: &BigInt) -> BigInt {
+    (numerator * precision()).div_floor(denominator)
+}
+
+fn mul(a: &BigInt, b: &BigInt) -> BigInt {
+    (a * b).div_floor(&precision())
+}
+
+fn div(a: &BigInt, b: &BigInt) -> BigInt {
+    (a * precision()).div_floor(b)
+}
+
+fn integer(n: i64) -> BigInt {
+    BigInt::from(n) * precision()
+}
+
+// 1 / 10^24, `eps` of the ledger
+fn eps() -> BigInt {
+    BigInt::from(10u8).pow(10)
+}
+
+// `ipow'`, by squaring
+fn ipow_unsigned(x: &BigInt, n: u64) -> BigInt {
+    if n == 0 {
+        return precision();
+    }
+    let half = ipow_unsigned(x, n / 2);
+    let square = mul(&half, &half);
+    match n % 2 {
+        0 => square,
+        _ => mul(&square, x),
+    }
+}
+
+fn ipow(x: &BigInt, n: i64) -> BigInt {
+    match n < 0 {
+        true => div(&precision(), &ipow_unsigned(x, n.unsigned_abs())),
+        false => ipow_unsigned(x, n.unsigned_abs()),
+    }
+}
+
+// `exp' 1`: the Taylor series of e^1, which `scaleExp` leaves as is
+fn e() -> BigInt {
+    let one = precision();
+    let (mut last, mut acc, mut divisor) = (one.clone(), one.clone(), one.clone());
+    for _ in 1..MAX_N {
+        let next = div(&mul(&last, &one), &divisor);
+        if next.abs() < eps() {
+            break;
+        }
+        acc += &next;
+        last = next;
+        divisor += &one;
+    }
+    acc
+}
+
+// `findE`: n with e^n <= x < e^(n + 1), bracketing x by squaring 1/e and e
+// before bisecting
+fn find_e(e: &BigInt, x: &BigInt) -> i64 {
+    let (mut lower, mut upper, mut n) = (div(&precision(), e), e.clone(), 1);
+    while !(lower <= *x && *x <= upper) {
+        lower = mul(&lower, &lower);
+        upper = mul(&upper, &upper);
+        n *= 2;
+    }
+    let (mut lower, mut upper) = (-n, n);
+    while upper - lower > 1 {
+        let middle = lower + (upper - lower) / 2;
+        match ipow(e, middle) > *x {
+            true => upper = middle,
+            false => lower = middle,
+        }
+    }
+    lower
+}
+
+// `lncf`: ln(1 + x) for x >= 0 as the continued fraction
+//     x / (1 + x / (2 + x / (3 + 4x / (4 + 4x / (5 + 9x / (6 + ...))))))
+// evaluated with the fundamental recurrence until two convergents are
+// closer than eps
+fn ln_one_plus(x: &BigInt) -> BigInt {
+    let (mut a_previous, mut a) = (precision(), BigInt::zero());
+    let (mut b_previous, mut b) = (BigInt::zero(), precision());
+    let mut last: Option<BigInt> = None;
+    for n in 1..=MAX_N {
+        let a_n = match n {
+            1 => x.clone(),
+            n => x * BigInt::from((n / 2) * (n / 2)),
+        };
+        let b_n = integer(n.into());
+        let a_next = mul(&b_n, &a) + mul(&a_n, &a_previous);
+        let b_next = mul(&b_n, &b) + mul(&a_n, &b_previous);
+        let convergent = div(&a_next, &b_next);
+        if last
+            .as_ref()
+            .is_some_and(|last| (&convergent - last).abs() < eps())
+        {
+            return convergent;
+        }
+        (a_previous, a) = (a, a_next);
+        (b_previous, b) = (b, b_next);
+        last = Some(convergent);
+    }
+    last.unwrap_or_default()
+}
+
+// `ln'` for x > 0: ln(x) = n + ln(1 + (x / e^n - 1)) with e^n <= x < e^(n + 1)
+fn ln(x: &BigInt) -> BigInt {
+    let e = e();
+    let n = find_e(&e, x);
+    integer(n) + ln_one_plus(&(div(x, &ipow(&e, n)) - precision()))
+}
+
+/// Whether `cmp` is below exp(x), summing the Taylor series until the error
+/// bound decides the comparison.
+fn below_exp(cmp: &BigInt, x: &BigInt) -> bool {
+    let bound = BigInt::from(TAYLOR_ERROR_BOUND) * precision();
+    let mut error = x.clone();
+    let mut acc = precision();
+    let mut divisor = precision();
+    for _ in 0..MAX_N {
+        divisor += precision();
+        acc += &error;
+        error = div(&mul(&error, x), &divisor);
+        let error_term = mul(&error, &bound).abs();
+        if *cmp >= &acc + &error_term {
+            return false;
+        }
+        if *cmp < &acc - &error_term {
+            return true;
+        }
+    }
+    false
+}
+
+/// Checks the leader value (Blake2b-256 of "L" and the VRF output) of a
+/// block against the issuer's relative stake.
+pub fn check_leader_value(
+    leader_value: &[u8; 32],
+    sigma: &UnitInterval,
+    active_slot_coeff: &UnitInterval,
+) -> bool {
+    if active_slot_coeff.numerator == active_slot_coeff.denominator {
+        return true;
+    }
+    let cert_nat = BigInt::from_bytes_be(num_bigint::Sign::Plus, leader_value);
+    let cert_nat_max = BigInt::one() << 256;
+    let recip_q = from_ratio(&cert_nat_max, &(&cert_nat_max - cert_nat));
+
+    let f = from_ratio(
+        &BigInt::from(active_slot_coeff.numerator),
+        &BigInt::from(active_slot_coeff.denominator),
+    );
+    let c = ln(&(precision() - f));
+    let sigma = from_ratio(
+        &BigInt::from(sigma.numerator),
+        &BigInt::from(sigma.denominator),
+    );
+    // `-fromRational σ * c` negates the product
+    let x = -mul(&sigma, &c);
+    below_exp(&recip_q, &x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leader_value(first_byte: u8) -> [u8; 32] {
+        let mut value = [0u8; 32];
+        value[0] = first_byte;
+        value
+    }
+
+    #[test]
+    fn threshold_for_full_stake() {
+        // With sigma = 1 and f = 1/20 the threshold is p < 0.05
+        let f = UnitInterval::new(1, 20).unwrap();
+        let sigma = UnitInterval::new(1, 1).unwrap();
+        assert!(check_leader_value(&leader_value(0), &sigma, &f));
+        // 0x0c / 256 = 0.0469
+        assert!(check_leader_value(&leader_value(0x0c), &sigma, &f));
+        // 0x0d / 256 = 0.0508
+        assert!(!check_leader_value(&leader_value(0x0d), &sigma, &f));
+    }
+
+    #[test]
+    fn threshold_scales_with_stake() {
+        // 1 - 0.95^0.5 = 0.0253, 0x06 / 256 = 0.0234, 0x07 / 256 = 0.0273
+        let f = UnitInterval::new(1, 20).unwrap();
+        let sigma = UnitInterval::new(1, 2).unwrap();
+        assert!(check_leader_value(&leader_value(0x06), &sigma, &f));
+        assert!(!check_leader_value(&leader_value(0x07), &sigma, &f));
+
+        let no_stake = UnitInterval::new(0, 1).unwrap();
+        assert!(!check_leader_value(&leader_value(0), &no_stake, &f));
+    }
+
+    // Within 10^-22 of the digits of `expected`
+    fn assert_close(value: &BigInt, expected: &str) {
+        let expected: BigInt = expected.replace('.', "").parse().unwrap();
+        assert!(
+            (value - expected).abs() < BigInt::from(10u8).pow(12),
+            "{}",
+            value
+        );
+    }
+
+    #[test]
+    fn ln_and_e() {
+        assert_close(&e(), "2.7182818284590452353602874713526624");
+        assert_close(&ipow(&e(), -2), "0.1353352832366126918939994949724844");
+        let cases = [
+            (95, 100, "-0.0512932943875505334261961442546872"),
+            (1, 2, "-0.6931471805599453094172321214581765"),
+            (1, 1, "0"),
+            (10, 1, "2.3025850929940456840179914546843642"),
+        ];
+        for (numerator, denominator, expected) in cases {
+            let x = from_ratio(&BigInt::from(numerator), &BigInt::from(denominator));
+            assert_close(&ln(&x), expected);
+        }
+        assert_eq!(find_e(&e(), &integer(10)), 2);
+        assert_eq!(find_e(&e(), &(precision() / 2)), -1);
+    }
+
+    #[test]
+    fn unit_interval_bounds() {
+        assert!(UnitInterval::new(2, 1).is_err());
+        assert!(UnitInterval::new(0, 0).is_err());
+    }
+}
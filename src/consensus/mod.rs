@@ -0,0 +1,14 @@
+mod era;
+mod hard_fork;
+mod header;
+mod leader;
+mod praos;
+
+pub use self::era::Era;
+pub use self::hard_fork::{EraBound, EraHistory, EraParams, EraSummary};
+pub use self::header::{block_body_hash, Header, HeaderBody, OperationalCert};
+pub use self::leader::{check_leader_value, UnitInterval};
+pub use self::praos::{
+    validate_block_body, validate_header, vrf_input, vrf_leader_value, HeaderValidationError,
+    IssuerInfo, PraosParams,
+};
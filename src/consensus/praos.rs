@@ -0,0 +1,492 @@
+use super::{block_body_hash, check_leader_value, Header, UnitInterval};
+use crate::crypto::{blake2b_256, verify_ed25519, verify_kes, verify_vrf, Hash32, KES_DEPTH};
+use std::fmt;
+
+// Praos protocol parameters relevant to header validation, from the Shelley
+// genesis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PraosParams {
+    pub active_slot_coeff: UnitInterval,
+    pub slots_per_kes_period: u64,
+    pub max_kes_evolutions: u64,
+}
+
+// What the ledger knows about the pool that issued a header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssuerInfo {
+    // Relative stake of the pool in the stake distribution of the epoch
+    pub relative_stake: UnitInterval,
+    // Blake2b-256 of the VRF key registered for the pool
+    pub vrf_key_hash: Hash32,
+    // Last operational certificate counter seen, `Some(0)` for registered
+    // pools that have not produced a block yet
+    pub last_opcert_counter: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HeaderValidationError {
+    // VRF key in the header is not the one registered for the pool
+    WrongVrfKey {
+        registered: Hash32,
+        found: Hash32,
+    },
+    // VRF proof does not verify for the slot and epoch nonce
+    InvalidVrfProof(String),
+    // VRF output in the header differs from the one the proof yields
+    VrfOutputMismatch,
+    // Leader value is above the threshold for the pool's stake
+    NotLeader {
+        slot: u64,
+    },
+    // Operational certificate is not yet valid
+    KesBeforeStart {
+        current: u64,
+        start: u64,
+    },
+    // Operational certificate has expired
+    KesAfterEnd {
+        current: u64,
+        start: u64,
+        max_evolutions: u64,
+    },
+    // Cold key signature of the operational certificate does not verify
+    InvalidOpCertSignature(String),
+    // Pool has no operational certificate counter in the ledger
+    NoCounterForIssuer,
+    // Operational certificate counter went backwards
+    CounterTooSmall {
+        last: u64,
+        current: u64,
+    },
+    // Operational certificate counter skipped a value
+    CounterOverIncremented {
+        last: u64,
+        current: u64,
+    },
+    // KES signature over the header body does not verify
+    InvalidKesSignature(String),
+    // Block could not be decoded
+    Decode(String),
+    // Block body does not hash to the value in the header
+    BodyHashMismatch {
+        header: Hash32,
+        actual: Hash32,
+    },
+    // Block body size differs from the value in the header
+    BodySizeMismatch {
+        header: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for HeaderValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderValidationError::WrongVrfKey { .. } => {
+                write!(f, "VRF key is not registered for the issuer")
+            }
+            HeaderValidationError::InvalidVrfProof(error) => write!(f, "{}", error),
+            HeaderValidationError::VrfOutputMismatch => {
+                write!(f, "VRF output does not match the proof")
+            }
+            HeaderValidationError::NotLeader { slot } => {
+                write!(f, "Issuer is not leader of slot {}", slot)
+            }
+            HeaderValidationError::KesBeforeStart { current, start } => write!(
+                f,
+                "KES period {} is before operational certificate start {}",
+                current, start
+            ),
+            HeaderValidationError::KesAfterEnd {
+                current,
+                start,
+                max_evolutions,
+            } => write!(
+                f,
+                "KES period {} is after operational certificate end {}",
+                current,
+                start + max_evolutions
+            ),
+            HeaderValidationError::InvalidOpCertSignature(error) => {
+                write!(f, "Operational certificate: {}", error)
+            }
+            HeaderValidationError::NoCounterForIssuer => {
+                write!(f, "No operational certificate counter for issuer")
+            }
+            HeaderValidationError::CounterTooSmall { last, current } => write!(
+                f,
+                "Operational certificate counter {} is smaller than {}",
+                current, last
+            ),
+            HeaderValidationError::CounterOverIncremented { last, current } => write!(
+                f,
+                "Operational certificate counter {} is more than one above {}",
+                current, last
+            ),
+            HeaderValidationError::InvalidKesSignature(error) => write!(f, "{}", error),
+            HeaderValidationError::Decode(error) => write!(f, "Could not decode block: {}", error),
+            HeaderValidationError::BodyHashMismatch { .. } => {
+                write!(f, "Block body hash does not match the header")
+            }
+            HeaderValidationError::BodySizeMismatch { header, actual } => write!(
+                f,
+                "Block body size {} does not match header size {}",
+                actual, header
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderValidationError {}
+
+/// VRF input of a slot: Blake2b-256 of the big endian slot number and the
+/// epoch nonce (`None` for the neutral nonce).
+pub fn vrf_input(slot: u64, epoch_nonce: Option<&Hash32>) -> Hash32 {
+    let mut input = slot.to_be_bytes().to_vec();
+    if let Some(nonce) = epoch_nonce {
+        input.extend_from_slice(nonce);
+    }
+    blake2b_256(&input)
+}
+
+/// Leader value derived from a VRF output: Blake2b-256 of "L" and the output.
+pub fn vrf_leader_value(vrf_output: &[u8]) -> Hash32 {
+    let mut input = vec![b'L'];
+    input.extend_from_slice(vrf_output);
+    blake2b_256(&input)
+}
+
+/// Validates a header without trusting the peer that sent it: VRF proof and
+/// leadership, operational certificate and KES signature.
+///
+/// Returns the operational certificate counter to record for the issuer.
+pub fn validate_header(
+    header: &Header,
+    params: &PraosParams,
+    epoch_nonce: Option<&Hash32>,
+    issuer: &IssuerInfo,
+) -> Result<u64, HeaderValidationError> {
+    validate_vrf(header, params, epoch_nonce, issuer)?;
+    validate_kes(header, params, issuer)
+}
+
+fn validate_vrf(
+    header: &Header,
+    params: &PraosParams,
+    epoch_nonce: Option<&Hash32>,
+    issuer: &IssuerInfo,
+) -> Result<(), HeaderValidationError> {
+    let body = &header.body;
+    let vrf_key_hash = blake2b_256(&body.vrf_vkey);
+    if vrf_key_hash != issuer.vrf_key_hash {
+        return Err(HeaderValidationError::WrongVrfKey {
+            registered: issuer.vrf_key_hash,
+            found: vrf_key_hash,
+        });
+    }
+
+    let input = vrf_input(body.slot, epoch_nonce);
+    let output = verify_vrf(&body.vrf_vkey, &body.vrf_proof, &input)
+        .map_err(HeaderValidationError::InvalidVrfProof)?;
+    if output != body.vrf_output {
+        return Err(HeaderValidationError::VrfOutputMismatch);
+    }
+
+    let leader_value = vrf_leader_value(&output);
+    if !check_leader_value(
+        &leader_value,
+        &issuer.relative_stake,
+        &params.active_slot_coeff,
+    ) {
+        return Err(HeaderValidationError::NotLeader { slot: body.slot });
+    }
+    Ok(())
+}
+
+fn validate_kes(
+    header: &Header,
+    params: &PraosParams,
+    issuer: &IssuerInfo,
+) -> Result<u64, HeaderValidationError> {
+    let body = &header.body;
+    let opcert = &body.operational_cert;
+    let current = body.slot / params.slots_per_kes_period;
+    let start = opcert.kes_period;
+    if start > current {
+        return Err(HeaderValidationError::KesBeforeStart { current, start });
+    }
+    if current >= start + params.max_kes_evolutions {
+        return Err(HeaderValidationError::KesAfterEnd {
+            current,
+            start,
+            max_evolutions: params.max_kes_evolutions,
+        });
+    }
+
+    verify_ed25519(&body.issuer_vkey, &opcert.signable(), &opcert.sigma)
+        .map_err(HeaderValidationError::InvalidOpCertSignature)?;
+
+    let last = issuer
+        .last_opcert_counter
+        .ok_or(HeaderValidationError::NoCounterForIssuer)?;
+    let counter = opcert.sequence_number;
+    if counter < last {
+        return Err(HeaderValidationError::CounterTooSmall {
+            last,
+            current: counter,
+        });
+    }
+    if counter > last + 1 {
+        return Err(HeaderValidationError::CounterOverIncremented {
+            last,
+            current: counter,
+        });
+    }
+
+    // No KES key evolves past u32 periods
+    let period =
+        u32::try_from(current - start).map_err(|_| HeaderValidationError::KesAfterEnd {
+            current,
+            start,
+            max_evolutions: params.max_kes_evolutions,
+        })?;
+    verify_kes(
+        KES_DEPTH,
+        &opcert.hot_vkey,
+        period,
+        &header.body_raw,
+        &header.body_signature,
+    )
+    .map_err(HeaderValidationError::InvalidKesSignature)?;
+    Ok(counter)
+}
+
+/// Checks the body of a full block against the hash and size in its header.
+pub fn validate_block_body(header: &Header, block: &[u8]) -> Result<(), HeaderValidationError> {
+    let (hash, size) = block_body_hash(block).map_err(HeaderValidationError::Decode)?;
+    if size != header.body.block_body_size {
+        return Err(HeaderValidationError::BodySizeMismatch {
+            header: header.body.block_body_size,
+            actual: size,
+        });
+    }
+    if hash != header.body.block_body_hash {
+        return Err(HeaderValidationError::BodyHashMismatch {
+            header: header.body.block_body_hash,
+            actual: hash,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{vrf_prove, KesSigner};
+    use ciborium::{into_writer, Value};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const SLOT: u64 = 129_600 * 3 + 42;
+    const NONCE: Hash32 = [5; 32];
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn params() -> PraosParams {
+        PraosParams {
+            active_slot_coeff: UnitInterval::new(1, 1).unwrap(),
+            slots_per_kes_period: 129_600,
+            max_kes_evolutions: 62,
+        }
+    }
+
+    fn issuer() -> IssuerInfo {
+        let (vrf_vkey, _) = vrf_prove(&[3; 32], &[]);
+        IssuerInfo {
+            relative_stake: UnitInterval::new(1, 10).unwrap(),
+            vrf_key_hash: blake2b_256(&vrf_vkey),
+            last_opcert_counter: Some(4),
+        }
+    }
+
+    // Block with empty body parts, signed at KES period 3 with an opcert
+    // starting at period 1
+    fn block(counter: u64) -> Vec<u8> {
+        let body_parts = [
+            encode(&Value::Array(vec![])),
+            encode(&Value::Array(vec![])),
+            encode(&Value::Map(vec![])),
+            encode(&Value::Array(vec![])),
+        ];
+        let mut hashes = vec![];
+        for part in &body_parts {
+            hashes.extend_from_slice(&blake2b_256(part));
+        }
+        let body_size: usize = body_parts.iter().map(|part| part.len()).sum();
+
+        let cold_key = SigningKey::from_bytes(&[1; 32]);
+        let kes = KesSigner::new(KES_DEPTH, 2);
+        let (vrf_vkey, vrf_proof) = vrf_prove(&[3; 32], &vrf_input(SLOT, Some(&NONCE)));
+        let vrf_output = verify_vrf(&vrf_vkey, &vrf_proof, &vrf_input(SLOT, Some(&NONCE))).unwrap();
+
+        let mut signable = kes.public_key().to_vec();
+        signable.extend_from_slice(&counter.to_be_bytes());
+        signable.extend_from_slice(&1u64.to_be_bytes());
+        let sigma = cold_key.sign(&signable).to_bytes();
+
+        let header_body = encode(&Value::Array(vec![
+            Value::from(100),
+            Value::from(SLOT),
+            Value::Bytes(vec![9; 32]),
+            Value::Bytes(cold_key.verifying_key().to_bytes().to_vec()),
+            Value::Bytes(vrf_vkey.to_vec()),
+            Value::Array(vec![
+                Value::Bytes(vrf_output.to_vec()),
+                Value::Bytes(vrf_proof.to_vec()),
+            ]),
+            Value::from(body_size as u64),
+            Value::Bytes(blake2b_256(&hashes).to_vec()),
+            Value::Array(vec![
+                Value::Bytes(kes.public_key().to_vec()),
+                Value::from(counter),
+                Value::from(1),
+                Value::Bytes(sigma.to_vec()),
+            ]),
+            Value::Array(vec![Value::from(9), Value::from(0)]),
+        ]));
+        let kes_signature = kes.sign(2, &header_body);
+
+        let mut block = vec![0x85, 0x82];
+        block.extend_from_slice(&header_body);
+        block.extend_from_slice(&encode(&Value::Bytes(kes_signature)));
+        for part in &body_parts {
+            block.extend_from_slice(part);
+        }
+        block
+    }
+
+    fn header(block: &[u8]) -> Header {
+        let items = crate::codec::split_array(block).unwrap();
+        Header::from_cbor(items[0]).unwrap()
+    }
+
+    #[test]
+    fn valid_header_and_body() {
+        let block = block(5);
+        let header = header(&block);
+        assert_eq!(header.body.slot, SLOT);
+        assert_eq!(
+            validate_header(&header, &params(), Some(&NONCE), &issuer()),
+            Ok(5)
+        );
+        assert_eq!(validate_block_body(&header, &block), Ok(()));
+    }
+
+    #[test]
+    fn wrong_nonce_and_leader_check() {
+        let header = header(&block(5));
+        assert!(matches!(
+            validate_header(&header, &params(), None, &issuer()),
+            Err(HeaderValidationError::InvalidVrfProof(_))
+        ));
+
+        let mut params = params();
+        params.active_slot_coeff = UnitInterval::new(1, 20).unwrap();
+        let mut issuer = issuer();
+        issuer.relative_stake = UnitInterval::new(0, 1).unwrap();
+        assert_eq!(
+            validate_header(&header, &params, Some(&NONCE), &issuer),
+            Err(HeaderValidationError::NotLeader { slot: SLOT })
+        );
+    }
+
+    #[test]
+    fn operational_certificate_checks() {
+        assert_eq!(
+            validate_header(&header(&block(3)), &params(), Some(&NONCE), &issuer()),
+            Err(HeaderValidationError::CounterTooSmall {
+                last: 4,
+                current: 3
+            })
+        );
+        assert_eq!(
+            validate_header(&header(&block(6)), &params(), Some(&NONCE), &issuer()),
+            Err(HeaderValidationError::CounterOverIncremented {
+                last: 4,
+                current: 6
+            })
+        );
+
+        let mut params = params();
+        params.max_kes_evolutions = 2;
+        assert_eq!(
+            validate_header(&header(&block(5)), &params, Some(&NONCE), &issuer()),
+            Err(HeaderValidationError::KesAfterEnd {
+                current: 3,
+                start: 1,
+                max_evolutions: 2
+            })
+        );
+
+        // Signed for KES period 3 but claiming another slot breaks the signature
+        params.max_kes_evolutions = 62;
+        params.slots_per_kes_period = 129_600 * 2;
+        assert!(matches!(
+            validate_header(&header(&block(5)), &params, Some(&NONCE), &issuer()),
+            Err(HeaderValidationError::InvalidKesSignature(_))
+        ));
+    }
+
+    #[test]
+    fn tampered_body() {
+        let mut block = block(5);
+        let header = header(&block);
+        let last = block.len() - 1;
+        // Replace the empty invalid_transactions list by an empty map, the
+        // body keeps its size
+        block[last] = 0xa0;
+        assert!(matches!(
+            validate_block_body(&header, &block),
+            Err(HeaderValidationError::BodyHashMismatch { .. })
+        ));
+        // Replace it by a one element list
+        block[last] = 0x81;
+        block.push(0x00);
+        assert!(matches!(
+            validate_block_body(&header, &block),
+            Err(HeaderValidationError::BodySizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn tpraos_header() {
+        // Shelley header body: nonce and leader VRF results, operational
+        // certificate and protocol version inlined
+        let vrf_result = Value::Array(vec![Value::Bytes(vec![0; 64]), Value::Bytes(vec![0; 80])]);
+        let header_body = Value::Array(vec![
+            Value::from(100),
+            Value::from(SLOT),
+            Value::Null,
+            Value::Bytes(vec![0; 32]),
+            Value::Bytes(vec![0; 32]),
+            vrf_result.clone(),
+            vrf_result,
+            Value::from(0),
+            Value::Bytes(vec![0; 32]),
+            Value::Bytes(vec![0; 32]),
+            Value::from(0),
+            Value::from(0),
+            Value::Bytes(vec![0; 64]),
+            Value::from(2),
+            Value::from(0),
+        ]);
+        let header = encode(&Value::Array(vec![header_body, Value::Bytes(vec![0; 448])]));
+        assert_eq!(
+            Header::from_cbor(&header),
+            Err("Header: TPraos (Shelley to Alonzo) headers are not supported".to_owned())
+        );
+    }
+}
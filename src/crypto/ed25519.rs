@@ -0,0 +1,22 @@
+use ed25519_dalek::{Signature as DalekSignature, VerifyingKey};
+
+pub type PublicKey = [u8; 32];
+pub type Signature = [u8; 64];
+
+/// Verifies an Ed25519 signature the way libsodium does for cardano-node.
+///
+/// BIP32-Ed25519 extended keys (Byron and HD wallets) sign with the same
+/// equation, so their signatures are checked against the 32 byte public part
+/// of the extended key; the chain code only matters for key derivation.
+pub fn verify_ed25519(
+    public_key: &PublicKey,
+    message: &[u8],
+    signature: &Signature,
+) -> Result<(), String> {
+    let verifying_key = VerifyingKey::from_bytes(public_key)
+        .map_err(|error| format!("Invalid Ed25519 public key: {}", error))?;
+    let signature = DalekSignature::from_bytes(signature);
+    verifying_key
+        .verify_strict(message, &signature)
+        .map_err(|error| format!("Invalid Ed25519 signature: {}", error))
+}
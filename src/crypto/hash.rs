@@ -0,0 +1,41 @@
+use blake2::{
+    digest::consts::{U28, U32},
+    Blake2b, Digest,
+};
+
+pub type Hash28 = [u8; 28];
+pub type Hash32 = [u8; 32];
+
+/// Blake2b-224, used for key hashes and Byron address roots.
+pub fn blake2b_224(data: &[u8]) -> Hash28 {
+    Blake2b::<U28>::digest(data).into()
+}
+
+/// Blake2b-256, used for transaction ids, block and header hashes.
+pub fn blake2b_256(data: &[u8]) -> Hash32 {
+    Blake2b::<U32>::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_digests() {
+        assert_eq!(
+            blake2b_256(&[]),
+            [
+                0x0e, 0x57, 0x51, 0xc0, 0x26, 0xe5, 0x43, 0xb2, 0xe8, 0xab, 0x2e, 0xb0, 0x60, 0x99,
+                0xda, 0xa1, 0xd1, 0xe5, 0xdf, 0x47, 0x77, 0x8f, 0x77, 0x87, 0xfa, 0xab, 0x45, 0xcd,
+                0xf1, 0x2f, 0xe3, 0xa8
+            ]
+        );
+        assert_eq!(
+            blake2b_224(&[]),
+            [
+                0x83, 0x6c, 0xc6, 0x89, 0x31, 0xc2, 0xe4, 0xe3, 0xe8, 0x38, 0x60, 0x2e, 0xca, 0x19,
+                0x02, 0x59, 0x1d, 0x21, 0x68, 0x37, 0xba, 0xfd, 0xdf, 0xe6, 0xf0, 0xc8, 0xcb, 0x07
+            ]
+        );
+    }
+}
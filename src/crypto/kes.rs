@@ -0,0 +1,161 @@
+// Sum composition KES (MMM "sum" construction) over Ed25519, with Blake2b-256
+// hashing the pair of child verification keys at every level. Block headers
+// are signed with depth 6, i.e. 64 periods.
+
+use super::{blake2b_256, verify_ed25519};
+
+pub type KesPublicKey = [u8; 32];
+
+pub const KES_DEPTH: u32 = 6;
+
+const ED25519_SIGNATURE_SIZE: usize = 64;
+const VKEY_SIZE: usize = 32;
+
+/// Size in bytes of a Sum-KES signature of the given depth.
+pub fn kes_signature_size(depth: u32) -> usize {
+    ED25519_SIGNATURE_SIZE + depth as usize * 2 * VKEY_SIZE
+}
+
+/// Verifies a Sum-KES signature made at `period` (counted from the start of
+/// the operational certificate).
+///
+/// A signature of depth `d` is the signature of depth `d - 1` followed by both
+/// child verification keys. The left child covers the first half of the
+/// periods and the right child the second half.
+pub fn verify_kes(
+    depth: u32,
+    public_key: &KesPublicKey,
+    period: u32,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    if signature.len() != kes_signature_size(depth) {
+        return Err(format!(
+            "KES signature of depth {} must be {} bytes, found {}",
+            depth,
+            kes_signature_size(depth),
+            signature.len()
+        ));
+    }
+    if u64::from(period) >= 1u64 << depth {
+        return Err(format!(
+            "KES period {} is beyond the {} periods of depth {}",
+            period,
+            1u64 << depth,
+            depth
+        ));
+    }
+    verify_sum(depth, public_key, period, message, signature)
+}
+
+fn verify_sum(
+    depth: u32,
+    public_key: &KesPublicKey,
+    period: u32,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    if depth == 0 {
+        let signature: [u8; ED25519_SIGNATURE_SIZE] = signature
+            .try_into()
+            .map_err(|_| "Malformed KES leaf signature")?;
+        return verify_ed25519(public_key, message, &signature)
+            .map_err(|error| format!("KES leaf signature: {}", error));
+    }
+
+    let (inner, keys) = signature.split_at(signature.len() - 2 * VKEY_SIZE);
+    if blake2b_256(keys) != *public_key {
+        return Err(format!(
+            "KES verification keys at depth {} do not hash to the expected key",
+            depth
+        ));
+    }
+    let (left, right) = keys.split_at(VKEY_SIZE);
+    let half = 1u32 << (depth - 1);
+    let (child, period) = match period < half {
+        true => (left, period),
+        false => (right, period - half),
+    };
+    let child: KesPublicKey = child.try_into().map_err(|_| "Malformed KES child key")?;
+    verify_sum(depth - 1, &child, period, message, inner)
+}
+
+/// Sum-KES signing key holding every leaf key, so any period can be signed.
+/// Real nodes evolve and forget past keys; this is only for test fixtures.
+#[cfg(test)]
+pub(crate) struct KesSigner {
+    depth: u32,
+    leaves: Vec<ed25519_dalek::SigningKey>,
+}
+
+#[cfg(test)]
+impl KesSigner {
+    pub(crate) fn new(depth: u32, seed: u8) -> KesSigner {
+        let leaves = (0..1u32 << depth)
+            .map(|i| {
+                let mut secret = [seed; 32];
+                secret[..4].copy_from_slice(&i.to_be_bytes());
+                ed25519_dalek::SigningKey::from_bytes(&secret)
+            })
+            .collect();
+        KesSigner { depth, leaves }
+    }
+
+    pub(crate) fn public_key(&self) -> KesPublicKey {
+        self.subtree_key(self.depth, 0)
+    }
+
+    pub(crate) fn sign(&self, period: u32, message: &[u8]) -> Vec<u8> {
+        use ed25519_dalek::Signer;
+        let mut signature = self.leaves[period as usize]
+            .sign(message)
+            .to_bytes()
+            .to_vec();
+        for level in 1..=self.depth {
+            let first = (period >> level) << level;
+            let half = 1u32 << (level - 1);
+            signature.extend_from_slice(&self.subtree_key(level - 1, first));
+            signature.extend_from_slice(&self.subtree_key(level - 1, first + half));
+        }
+        signature
+    }
+
+    fn subtree_key(&self, depth: u32, first: u32) -> KesPublicKey {
+        match depth {
+            0 => self.leaves[first as usize].verifying_key().to_bytes(),
+            _ => {
+                let half = 1u32 << (depth - 1);
+                let mut keys = self.subtree_key(depth - 1, first).to_vec();
+                keys.extend_from_slice(&self.subtree_key(depth - 1, first + half));
+                blake2b_256(&keys)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify_every_period() {
+        let signer = KesSigner::new(KES_DEPTH, 9);
+        let public_key = signer.public_key();
+        for period in 0..64 {
+            let signature = signer.sign(period, b"header body");
+            assert_eq!(signature.len(), 448);
+            assert!(verify_kes(KES_DEPTH, &public_key, period, b"header body", &signature).is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_period_and_message() {
+        let signer = KesSigner::new(KES_DEPTH, 9);
+        let public_key = signer.public_key();
+        let signature = signer.sign(5, b"header body");
+        assert!(verify_kes(KES_DEPTH, &public_key, 6, b"header body", &signature).is_err());
+        assert!(verify_kes(KES_DEPTH, &public_key, 5, b"other body", &signature).is_err());
+        assert!(verify_kes(KES_DEPTH, &public_key, 64, b"header body", &signature).is_err());
+        assert!(verify_kes(KES_DEPTH, &public_key, 5, b"header body", &signature[1..]).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+mod ed25519;
+mod hash;
+mod kes;
+mod vrf;
+
+pub use self::ed25519::{verify_ed25519, PublicKey, Signature};
+pub use self::hash::{blake2b_224, blake2b_256, Hash28, Hash32};
+pub use self::kes::{kes_signature_size, verify_kes, KesPublicKey, KES_DEPTH};
+pub use self::vrf::{verify_vrf, VrfOutput, VrfProof, VrfPublicKey};
+
+#[cfg(test)]
+pub(crate) use self::{kes::KesSigner, vrf::prove as vrf_prove};
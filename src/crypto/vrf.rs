@@ -0,0 +1,228 @@
+// ECVRF-ED25519-SHA512-Elligator2 as in draft-irtf-cfrg-vrf-03, which is
+// the variant implemented by the libsodium fork used by cardano-node.
+
+use curve25519_dalek::{
+    edwards::{CompressedEdwardsY, EdwardsPoint},
+    montgomery::MontgomeryPoint,
+    scalar::Scalar,
+};
+use num_bigint::BigUint;
+use sha2::{Digest, Sha512};
+
+pub type VrfPublicKey = [u8; 32];
+pub type VrfProof = [u8; 80];
+pub type VrfOutput = [u8; 64];
+
+const SUITE: u8 = 0x04;
+const HASH_TO_CURVE: u8 = 0x01;
+const HASH_POINTS: u8 = 0x02;
+const PROOF_TO_HASH: u8 = 0x03;
+// Montgomery curve25519 coefficient
+const CURVE_A: u32 = 486662;
+
+/// Verifies `proof` for `alpha` under `public_key` and returns the VRF output.
+pub fn verify_vrf(
+    public_key: &VrfPublicKey,
+    proof: &VrfProof,
+    alpha: &[u8],
+) -> Result<VrfOutput, String> {
+    let y_point = CompressedEdwardsY(*public_key)
+        .decompress()
+        .ok_or("VRF public key is not a valid point")?;
+    if y_point.is_small_order() {
+        return Err("VRF public key has small order".to_owned());
+    }
+
+    let (gamma, c, s) = decode_proof(proof)?;
+    let h_point = hash_to_curve(public_key, alpha)?;
+
+    // U = s*B - c*Y, V = s*H - c*Gamma
+    let u_point = EdwardsPoint::vartime_double_scalar_mul_basepoint(&-c, &y_point, &s);
+    let v_point = s * h_point - c * gamma;
+
+    if hash_points(&h_point, &gamma, &u_point, &v_point) != proof[32..48] {
+        return Err("VRF proof does not verify".to_owned());
+    }
+    Ok(proof_to_hash(&gamma))
+}
+
+fn decode_proof(proof: &VrfProof) -> Result<(EdwardsPoint, Scalar, Scalar), String> {
+    let mut gamma = [0u8; 32];
+    gamma.copy_from_slice(&proof[0..32]);
+    let gamma = CompressedEdwardsY(gamma)
+        .decompress()
+        .ok_or("VRF proof gamma is not a valid point")?;
+
+    let mut c = [0u8; 32];
+    c[..16].copy_from_slice(&proof[32..48]);
+
+    let mut s = [0u8; 32];
+    s.copy_from_slice(&proof[48..80]);
+    if s[31] & 0xf0 != 0 {
+        return Err("VRF proof scalar s is not reduced".to_owned());
+    }
+    Ok((
+        gamma,
+        Scalar::from_bytes_mod_order(c),
+        Scalar::from_bytes_mod_order(s),
+    ))
+}
+
+fn hash_to_curve(public_key: &VrfPublicKey, alpha: &[u8]) -> Result<EdwardsPoint, String> {
+    let mut hasher = Sha512::new();
+    hasher.update([SUITE, HASH_TO_CURVE]);
+    hasher.update(public_key);
+    hasher.update(alpha);
+    let digest = hasher.finalize();
+
+    let mut r = [0u8; 32];
+    r.copy_from_slice(&digest[..32]);
+    r[31] &= 0x7f;
+
+    let u = elligator2(&r);
+    MontgomeryPoint(u)
+        .to_edwards(0)
+        .map(|point| point.mul_by_cofactor())
+        .ok_or("Elligator2 produced an invalid point".to_owned())
+}
+
+// Maps a field element to the Montgomery u-coordinate of a curve point, as
+// libsodium's `ge25519_from_uniform` does before converting to Edwards form.
+fn elligator2(r: &[u8; 32]) -> [u8; 32] {
+    let p = (BigUint::from(1u8) << 255u32) - BigUint::from(19u8);
+    let a = BigUint::from(CURVE_A);
+    let r = BigUint::from_bytes_le(r) % &p;
+
+    // x = -A / (1 + 2 r^2)
+    let denominator = (BigUint::from(2u8) * &r * &r + BigUint::from(1u8)) % &p;
+    let inverse = denominator.modpow(&(&p - BigUint::from(2u8)), &p);
+    let mut x = (&p - (&a * inverse) % &p) % &p;
+
+    // e = x^3 + A x^2 + x, if e is not a square use x = -x - A
+    let e = (&x * &x * &x + &a * &x * &x + &x) % &p;
+    let chi = e.modpow(&((&p - BigUint::from(1u8)) >> 1u32), &p);
+    if chi == &p - BigUint::from(1u8) {
+        x = (&p - &x + &p - &a) % &p;
+    }
+
+    let mut u = [0u8; 32];
+    let bytes = x.to_bytes_le();
+    u[..bytes.len()].copy_from_slice(&bytes);
+    u
+}
+
+fn hash_points(
+    h_point: &EdwardsPoint,
+    gamma: &EdwardsPoint,
+    u_point: &EdwardsPoint,
+    v_point: &EdwardsPoint,
+) -> [u8; 16] {
+    let mut hasher = Sha512::new();
+    hasher.update([SUITE, HASH_POINTS]);
+    for point in [h_point, gamma, u_point, v_point] {
+        hasher.update(point.compress().as_bytes());
+    }
+    let mut c = [0u8; 16];
+    c.copy_from_slice(&hasher.finalize()[..16]);
+    c
+}
+
+fn proof_to_hash(gamma: &EdwardsPoint) -> VrfOutput {
+    let mut hasher = Sha512::new();
+    hasher.update([SUITE, PROOF_TO_HASH]);
+    hasher.update(gamma.mul_by_cofactor().compress().as_bytes());
+    hasher.finalize().into()
+}
+
+/// Produces a proof with an Ed25519 style secret key seed. Only needed to
+/// build fixtures for tests, the node itself never proves.
+#[cfg(test)]
+pub(crate) fn prove(seed: &[u8; 32], alpha: &[u8]) -> (VrfPublicKey, VrfProof) {
+    use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+
+    let expanded = Sha512::digest(seed);
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&expanded[..32]);
+    secret[0] &= 248;
+    secret[31] &= 127;
+    secret[31] |= 64;
+    let x = Scalar::from_bytes_mod_order(secret);
+    let public_key = (x * ED25519_BASEPOINT_POINT).compress().to_bytes();
+
+    let h_point = hash_to_curve(&public_key, alpha).unwrap();
+    let gamma = x * h_point;
+    let mut nonce = Sha512::new();
+    nonce.update(&expanded[32..]);
+    nonce.update(h_point.compress().as_bytes());
+    let k = Scalar::from_bytes_mod_order_wide(&nonce.finalize().into());
+    let c = hash_points(
+        &h_point,
+        &gamma,
+        &(k * ED25519_BASEPOINT_POINT),
+        &(k * h_point),
+    );
+    let mut c_bytes = [0u8; 32];
+    c_bytes[..16].copy_from_slice(&c);
+    let s = k + Scalar::from_bytes_mod_order(c_bytes) * x;
+
+    let mut proof = [0u8; 80];
+    proof[..32].copy_from_slice(gamma.compress().as_bytes());
+    proof[32..48].copy_from_slice(&c);
+    proof[48..].copy_from_slice(s.as_bytes());
+    (public_key, proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        bytes
+    }
+
+    // draft-irtf-cfrg-vrf-03, Appendix A.4
+    #[test]
+    fn draft_03_test_vectors() {
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "b6b4699f87d56126c9117a7da55bd0085246f4c56dbc95d20172612e9d38e8d7ca65e573a126ed88d4e30a46f80a666854d675cf3ba81de0de043c3774f061560f55edc256a787afe701677c0f602900",
+                "5b49b554d05c0cd5a5325376b3387de59d924fd1e13ded44648ab33c21349a603f25b84ec5ed887995b33da5e3bfcb87cd2f64521c4c62cf825cffabbe5d31cc",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "ae5b66bdf04b4c010bfe32b2fc126ead2107b697634f6f7337b9bff8785ee111200095ece87dde4dbe87343f6df3b107d91798c8a7eb1245d3bb9c5aafb093358c13e6ae1111a55717e895fd15f99f07",
+                "94f4487e1b2fec954309ef1289ecb2e15043a2461ecc7b2ae7d4470607ef82eb1cfa97d84991fe4a7bfdfd715606bc27e2967a6c557cfb5875879b671740b7d8",
+            ),
+        ];
+        for (seed, public_key, alpha, proof, output) in vectors {
+            let alpha: Vec<u8> = (0..alpha.len() / 2)
+                .map(|i| u8::from_str_radix(&alpha[2 * i..2 * i + 2], 16).unwrap())
+                .collect();
+            let (pk, pi) = prove(&from_hex(seed), &alpha);
+            assert_eq!(pk, from_hex::<32>(public_key));
+            assert_eq!(pi, from_hex::<80>(proof));
+            assert_eq!(
+                verify_vrf(&pk, &pi, &alpha).unwrap(),
+                from_hex::<64>(output)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_input_and_tampered_proof() {
+        let (public_key, mut proof) = prove(&[7; 32], b"slot");
+        assert!(verify_vrf(&public_key, &proof, b"slot").is_ok());
+        assert!(verify_vrf(&public_key, &proof, b"other").is_err());
+        proof[40] ^= 1;
+        assert!(verify_vrf(&public_key, &proof, b"slot").is_err());
+    }
+}
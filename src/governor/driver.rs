@@ -0,0 +1,205 @@
+use super::peers::PeerStatus;
+use super::selection::{Action, Governor};
+use crate::connection::PeerConnection;
+use crate::connection_manager::ConnectionManager;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::{sync::mpsc, task::JoinSet};
+use tracing::{debug, warn};
+
+/// What the application running the mini-protocols is asked to do.
+pub enum Request {
+    // Start chain-sync and block-fetch on the connection, answered with
+    // `Report::Activated`
+    Activate(Arc<PeerConnection>),
+    // Stop them, the connection stays established
+    Deactivate(String),
+    // Ask the peer for up to this many peers with peer sharing, answered
+    // with `Report::SharedPeers`
+    SharePeers(Arc<PeerConnection>, u8),
+}
+
+/// Outcomes and measurements the application reports about peers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Report {
+    Activated(String, Result<(), String>),
+    // The mini-protocols of an established peer failed
+    Failed(String),
+    SharedPeers(String, Vec<String>),
+    // Keep-alive round trip
+    Rtt(String, Duration),
+    // Block-fetch request to the last block of its range
+    FetchLatency(String, Duration),
+    // First to deliver a new tip with chain-sync
+    TipContribution(String),
+}
+
+/// Runs `governor` until `reports` is closed, then returns it. Connections
+/// are opened and released with `manager`, what needs mini-protocols is
+/// passed on through `requests`. Decisions are taken after every outcome
+/// and report, and every `interval` for back-off and churn.
+pub async fn run(
+    mut governor: Governor,
+    manager: ConnectionManager,
+    interval: Duration,
+    requests: mpsc::UnboundedSender<Request>,
+    mut reports: mpsc::UnboundedReceiver<Report>,
+) -> Governor {
+    let mut connections: HashMap<String, Arc<PeerConnection>> = HashMap::new();
+    let mut connecting = JoinSet::new();
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        for action in governor.decide(Instant::now()) {
+            debug!("Governor: {:?}", action);
+            match action {
+                Action::Connect(host) => {
+                    let manager = manager.clone();
+                    connecting.spawn(async move {
+                        let connection = manager.acquire_outbound(&host).await;
+                        (host, connection)
+                    });
+                }
+                Action::Activate(host) => {
+                    let sent = match connections.get(&host) {
+                        Some(connection) => requests
+                            .send(Request::Activate(connection.clone()))
+                            .map_err(|_| "Requests closed".to_owned()),
+                        None => Err(format!("Not connected to {}", host)),
+                    };
+                    if let Err(error) = sent {
+                        governor.activated(&host, Err(error), Instant::now());
+                    }
+                }
+                Action::Deactivate(host) => {
+                    let _ = requests.send(Request::Deactivate(host));
+                }
+                Action::SharePeers(host, amount) => {
+                    let sent = connections.get(&host).is_some_and(|connection| {
+                        let request = Request::SharePeers(connection.clone(), amount);
+                        requests.send(request).is_ok()
+                    });
+                    if !sent {
+                        governor.shared_peers(&host, vec![]);
+                    }
+                }
+                // Released below with every connection of a cold peer
+                Action::Disconnect(_) | Action::Forget(_) => {}
+            }
+        }
+        let cold: Vec<String> = connections
+            .keys()
+            .filter(|host| {
+                let status = governor.status(host);
+                status.is_none_or(|status| status == PeerStatus::Cold)
+            })
+            .cloned()
+            .collect();
+        for host in cold {
+            if let Some(connection) = connections.remove(&host) {
+                manager.release_outbound(connection).await;
+            }
+        }
+
+        tokio::select! {
+            _ = ticks.tick() => {}
+            Some(joined) = connecting.join_next() => match joined {
+                Ok((host, Ok(connection))) => {
+                    connections.insert(host.clone(), connection);
+                    governor.connected(&host, Ok(()), Instant::now());
+                }
+                Ok((host, Err(error))) => governor.connected(&host, Err(error), Instant::now()),
+                Err(error) => warn!("Governor: connecting failed: {}", error),
+            },
+            report = reports.recv() => match report {
+                Some(report) => apply(&mut governor, report),
+                None => break,
+            },
+        }
+    }
+
+    while let Some(joined) = connecting.join_next().await {
+        if let Ok((_, Ok(connection))) = joined {
+            manager.release_outbound(connection).await;
+        }
+    }
+    for (_, connection) in connections {
+        manager.release_outbound(connection).await;
+    }
+    governor
+}
+
+fn apply(governor: &mut Governor, report: Report) {
+    let now = Instant::now();
+    match report {
+        Report::Activated(host, result) => governor.activated(&host, result, now),
+        Report::Failed(host) => governor.failed(&host, now),
+        Report::SharedPeers(host, peers) => governor.shared_peers(&host, peers),
+        Report::Rtt(host, rtt) => governor.record_rtt(&host, rtt),
+        Report::FetchLatency(host, latency) => governor.record_fetch_latency(&host, latency),
+        Report::TipContribution(host) => governor.record_tip_contribution(&host),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection_manager::{ConnectionState, ManagerConfig};
+    use crate::governor::{Source, Targets};
+    use crate::mock::{MockNode, Reply, Script};
+
+    async fn activated(requests: &mut mpsc::UnboundedReceiver<Request>) -> String {
+        match requests.recv().await.unwrap() {
+            Request::Activate(connection) => connection.peer().to_owned(),
+            _ => panic!("Expected an activation"),
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_peers() {
+        let local = MockNode::start(Script::new(&[13], 2, Reply::Accept))
+            .await
+            .unwrap();
+        let public = Script::new(&[13], 2, Reply::Accept).with_delay(Duration::from_millis(100));
+        let public = MockNode::start(public).await.unwrap();
+        let manager = ConnectionManager::new(ManagerConfig::new(2, &[13], &[]));
+        let mut governor = Governor::new(Targets {
+            known: 2,
+            established: 2,
+            active: 1,
+        });
+        governor.add_peers([local.host()], Source::LocalRoot);
+        governor.add_peers([public.host()], Source::PublicRoot);
+
+        let (requests, mut requested) = mpsc::unbounded_channel();
+        let (report, reports) = mpsc::unbounded_channel();
+        let interval = Duration::from_secs(1);
+        let driver = tokio::spawn(run(governor, manager.clone(), interval, requests, reports));
+
+        assert_eq!(activated(&mut requested).await, local.host());
+        report
+            .send(Report::Activated(local.host(), Ok(())))
+            .unwrap();
+        report
+            .send(Report::Rtt(local.host(), Duration::from_millis(5)))
+            .unwrap();
+        // The other established peer takes over
+        report.send(Report::Failed(local.host())).unwrap();
+        assert_eq!(activated(&mut requested).await, public.host());
+        report
+            .send(Report::Activated(public.host(), Ok(())))
+            .unwrap();
+
+        drop(report);
+        let governor = driver.await.unwrap();
+        assert_eq!(governor.status(&local.host()), Some(PeerStatus::Cold));
+        assert!(governor.peer(&local.host()).unwrap().failures == 1);
+        assert_eq!(governor.status(&public.host()), Some(PeerStatus::Hot));
+        for host in [local.host(), public.host()] {
+            assert_eq!(
+                manager.state(host.parse().unwrap()),
+                Some(ConnectionState::TimeWait)
+            );
+        }
+    }
+}
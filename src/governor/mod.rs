@@ -0,0 +1,7 @@
+mod driver;
+mod peers;
+mod selection;
+
+pub use self::driver::{run, Report, Request};
+pub use self::peers::{KnownPeer, PeerStatus, Performance, Source};
+pub use self::selection::{Action, Governor, Targets};
@@ -0,0 +1,165 @@
+use crate::config::PeerSource;
+use std::time::{Duration, Instant};
+
+// Weight of a new sample in the moving averages of round-trip times and
+// latencies
+const SAMPLE_WEIGHT: f64 = 0.2;
+
+/// Where the governor learnt about a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    // Configured peers we always keep connected
+    LocalRoot,
+    PublicRoot,
+    // Stake pool relays announced on the ledger
+    Ledger,
+    // Returned by another peer with peer sharing
+    PeerSharing,
+}
+
+impl Source {
+    /// Roots are configured and never forgotten.
+    pub fn is_root(self) -> bool {
+        matches!(self, Source::LocalRoot | Source::PublicRoot)
+    }
+}
+
+impl From<PeerSource> for Source {
+    fn from(source: PeerSource) -> Source {
+        match source {
+            PeerSource::LocalRoot => Source::LocalRoot,
+            PeerSource::Producer | PeerSource::PublicRoot | PeerSource::BootstrapPeer => {
+                Source::PublicRoot
+            }
+        }
+    }
+}
+
+/// Cold peers are only known, warm ones have an established connection,
+/// hot ones also run the mini-protocols following the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    Cold,
+    Warm,
+    Hot,
+}
+
+/// Measurements reported for a peer. Higher scores are better peers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Performance {
+    // Moving average of keep-alive round trips
+    pub rtt: Option<Duration>,
+    // Moving average of the time from block-fetch request to its last block
+    pub fetch_latency: Option<Duration>,
+    // Headers of new tips this peer delivered first, decayed at each churn
+    pub tip_contributions: u32,
+}
+
+impl Performance {
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        self.rtt = Some(average(self.rtt, rtt));
+    }
+
+    pub fn record_fetch_latency(&mut self, latency: Duration) {
+        self.fetch_latency = Some(average(self.fetch_latency, latency));
+    }
+
+    /// Tip contributions count most, then the speed of the peer. Peers
+    /// without measurements rank below measured ones of up to a second.
+    pub fn score(&self) -> f64 {
+        let rtt = self.rtt.map_or(1.0, |rtt| rtt.as_secs_f64());
+        let fetch = self.fetch_latency.map_or(1.0, |fetch| fetch.as_secs_f64());
+        self.tip_contributions as f64 - rtt - fetch
+    }
+}
+
+fn average(current: Option<Duration>, sample: Duration) -> Duration {
+    match current {
+        Some(current) => current.mul_f64(1.0 - SAMPLE_WEIGHT) + sample.mul_f64(SAMPLE_WEIGHT),
+        None => sample,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownPeer {
+    pub source: Source,
+    pub status: PeerStatus,
+    // A promotion was started and not reported yet
+    pub in_progress: bool,
+    pub performance: Performance,
+    // Failures since the last successful connection
+    pub failures: u32,
+    // Not promoted before this time, after failures or churn
+    pub retry_at: Option<Instant>,
+    // Last peer sharing request sent to the peer
+    pub last_shared: Option<Instant>,
+}
+
+impl KnownPeer {
+    pub fn new(source: Source) -> KnownPeer {
+        KnownPeer {
+            source,
+            status: PeerStatus::Cold,
+            in_progress: false,
+            performance: Performance::default(),
+            failures: 0,
+            retry_at: None,
+            last_shared: None,
+        }
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.status != PeerStatus::Cold
+    }
+
+    pub fn is_backing_off(&self, now: Instant) -> bool {
+        self.retry_at.is_some_and(|retry_at| retry_at > now)
+    }
+
+    /// Counts a failure and backs off for `backoff` doubled with every
+    /// failure before, up to `max_backoff`.
+    pub fn fail(&mut self, now: Instant, backoff: Duration, max_backoff: Duration) {
+        self.failures += 1;
+        let factor = 2u32.saturating_pow(self.failures - 1);
+        self.retry_at = Some(now + backoff.saturating_mul(factor).min(max_backoff));
+        self.status = PeerStatus::Cold;
+        self.in_progress = false;
+        self.performance = Performance::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles() {
+        let now = Instant::now();
+        let mut peer = KnownPeer::new(Source::PeerSharing);
+        let (backoff, max) = (Duration::from_secs(5), Duration::from_secs(60));
+        let waits: Vec<Duration> = (0..6)
+            .map(|_| {
+                peer.fail(now, backoff, max);
+                peer.retry_at.unwrap() - now
+            })
+            .collect();
+        let secs: Vec<u64> = waits.iter().map(Duration::as_secs).collect();
+        assert_eq!(secs, vec![5, 10, 20, 40, 60, 60]);
+        assert!(peer.is_backing_off(now + Duration::from_secs(59)));
+        assert!(!peer.is_backing_off(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn scores() {
+        let mut fast = Performance::default();
+        fast.record_rtt(Duration::from_millis(50));
+        fast.record_fetch_latency(Duration::from_millis(100));
+        let mut slow = fast.clone();
+        slow.record_rtt(Duration::from_millis(1050));
+        assert_eq!(slow.rtt, Some(Duration::from_millis(250)));
+        assert!(fast.score() > slow.score());
+        assert!(slow.score() > Performance::default().score());
+        slow.tip_contributions = 1;
+        assert!(slow.score() > fast.score());
+    }
+}
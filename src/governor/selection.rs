@@ -0,0 +1,697 @@
+use super::peers::{KnownPeer, PeerStatus, Source};
+use crate::config::{AppConfig, Topology};
+use crate::ledger::{pick_peers, LedgerPool};
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+// An established peer is asked for peers at most this often
+const SHARE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Peers the governor keeps in each set. Established peers include the
+/// active ones, known peers the established ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Targets {
+    pub known: usize,
+    pub established: usize,
+    pub active: usize,
+}
+
+impl Default for Targets {
+    fn default() -> Targets {
+        Targets {
+            known: 100,
+            established: 40,
+            active: 15,
+        }
+    }
+}
+
+/// What the governor decided to do with a peer. Demotions take effect in
+/// the governor at once, promotions and peer sharing once reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    // Open a connection, cold to warm, reported with `connected`
+    Connect(String),
+    // Start the mini-protocols following the chain, warm to hot, reported
+    // with `activated`
+    Activate(String),
+    // Stop them, the peer is warm again
+    Deactivate(String),
+    // Close the connection, the peer is cold again
+    Disconnect(String),
+    // Ask the peer for up to this many peers, reported with `shared_peers`
+    SharePeers(String, u8),
+    // The peer left the known set
+    Forget(String),
+}
+
+/// Peer selection: keeps the numbers of known, established and active peers
+/// at their targets by promoting the best candidates and demoting the worst
+/// peers. Local roots are always kept established and active, roots are
+/// never forgotten. Every churn interval the worst active and the worst
+/// established peer are demoted and rest for an interval, making room for
+/// others. Failing peers back off exponentially, other than roots they are
+/// forgotten after `max_failures` in a row.
+///
+/// The governor does no I/O: `decide` returns the actions to carry out and
+/// their outcomes and measurements are reported back.
+pub struct Governor {
+    targets: Targets,
+    churn_interval: Duration,
+    backoff: Duration,
+    max_backoff: Duration,
+    max_failures: u32,
+    peers: HashMap<String, KnownPeer>,
+    next_churn: Option<Instant>,
+    // Peer asked for peers and not answered yet
+    sharing: Option<String>,
+    // Stake pools to discover ledger peers from
+    ledger_pools: Vec<LedgerPool>,
+    // State of the draws of ledger peers
+    seed: u64,
+}
+
+impl Governor {
+    pub fn new(targets: Targets) -> Governor {
+        Governor {
+            targets,
+            churn_interval: Duration::from_secs(3600),
+            backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(3600),
+            max_failures: 5,
+            peers: HashMap::new(),
+            next_churn: None,
+            sharing: None,
+            ledger_pools: vec![],
+            seed: RandomState::new().hash_one("governor"),
+        }
+    }
+
+    pub fn with_churn_interval(mut self, churn_interval: Duration) -> Governor {
+        self.churn_interval = churn_interval;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration, max_backoff: Duration) -> Governor {
+        self.backoff = backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_max_failures(mut self, max_failures: u32) -> Governor {
+        self.max_failures = max_failures.max(1);
+        self
+    }
+
+    /// Seeds the draws of ledger peers, for repeatable selections.
+    pub fn with_seed(mut self, seed: u64) -> Governor {
+        self.seed = seed;
+        self
+    }
+
+    pub fn targets(&self) -> Targets {
+        self.targets
+    }
+
+    /// Adds `hosts` to the known peers. A peer known from several sources
+    /// keeps the most trusted one, local roots first.
+    pub fn add_peers<I: IntoIterator<Item = String>>(&mut self, hosts: I, source: Source) {
+        for host in hosts {
+            let peer = self
+                .peers
+                .entry(host)
+                .or_insert_with(|| KnownPeer::new(source));
+            peer.source = peer.source.min(source);
+        }
+    }
+
+    /// Adds the local and public roots of a topology file.
+    pub fn add_topology(&mut self, topology: &Topology) {
+        for peer in &topology.peers {
+            self.add_peers([peer.host()], peer.source.into());
+        }
+    }
+
+    /// Sets the stake pools whose relays are discovered while fewer peers
+    /// than the target are known, drawn by stake.
+    pub fn add_ledger_pools(&mut self, pools: Vec<LedgerPool>) {
+        self.ledger_pools.extend(pools);
+    }
+
+    /// Adds the peers of the application's configuration: the roots of its
+    /// topology file, its hosts as public roots and its ledger pools.
+    pub fn add_config(&mut self, app_config: &AppConfig) -> Result<(), String> {
+        if let Some(config) = &app_config.topology {
+            self.add_topology(&Topology::from_file(&config.path)?);
+        }
+        let hosts = app_config.root_hosts()?;
+        self.add_peers(hosts.into_iter().map(|host| host.host), Source::PublicRoot);
+        self.add_ledger_pools(app_config.ledger_pools()?);
+        Ok(())
+    }
+
+    pub fn peer(&self, host: &str) -> Option<&KnownPeer> {
+        self.peers.get(host)
+    }
+
+    pub fn status(&self, host: &str) -> Option<PeerStatus> {
+        self.peers.get(host).map(|peer| peer.status)
+    }
+
+    /// Known, established and active peers.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let established = self.count(|peer| peer.is_established());
+        let active = self.count(|peer| peer.status == PeerStatus::Hot);
+        (self.peers.len(), established, active)
+    }
+
+    /// Outcome of `Action::Connect`.
+    pub fn connected(&mut self, host: &str, result: Result<(), String>, now: Instant) {
+        let (backoff, max_backoff) = (self.backoff, self.max_backoff);
+        let Some(peer) = self.peers.get_mut(host) else {
+            return;
+        };
+        match result {
+            Ok(()) => {
+                peer.status = PeerStatus::Warm;
+                peer.in_progress = false;
+                peer.failures = 0;
+                peer.retry_at = None;
+            }
+            Err(error) => {
+                info!("Could not connect to {}: {}", host, error);
+                peer.fail(now, backoff, max_backoff);
+            }
+        }
+    }
+
+    /// Outcome of `Action::Activate`. Peers failing to activate are cold, the
+    /// connection is to be closed.
+    pub fn activated(&mut self, host: &str, result: Result<(), String>, now: Instant) {
+        match result {
+            Ok(()) => {
+                if let Some(peer) = self.peers.get_mut(host) {
+                    peer.status = PeerStatus::Hot;
+                    peer.in_progress = false;
+                }
+            }
+            Err(error) => {
+                info!("Could not activate {}: {}", host, error);
+                self.failed(host, now);
+            }
+        }
+    }
+
+    /// The connection to an established peer failed, it is cold.
+    pub fn failed(&mut self, host: &str, now: Instant) {
+        let (backoff, max_backoff) = (self.backoff, self.max_backoff);
+        if let Some(peer) = self.peers.get_mut(host) {
+            peer.fail(now, backoff, max_backoff);
+        }
+        if self.sharing.as_deref() == Some(host) {
+            self.sharing = None;
+        }
+    }
+
+    /// Outcome of `Action::SharePeers`.
+    pub fn shared_peers(&mut self, host: &str, peers: Vec<String>) {
+        if self.sharing.as_deref() == Some(host) {
+            self.sharing = None;
+        }
+        debug!("{} shared {} peers", host, peers.len());
+        self.add_peers(peers, Source::PeerSharing);
+    }
+
+    /// Keep-alive round trip to the peer.
+    pub fn record_rtt(&mut self, host: &str, rtt: Duration) {
+        if let Some(peer) = self.peers.get_mut(host) {
+            peer.performance.record_rtt(rtt);
+        }
+    }
+
+    /// Time block-fetch took from request to the last block of the range.
+    pub fn record_fetch_latency(&mut self, host: &str, latency: Duration) {
+        if let Some(peer) = self.peers.get_mut(host) {
+            peer.performance.record_fetch_latency(latency);
+        }
+    }
+
+    /// The peer was the first to deliver a new tip with chain-sync.
+    pub fn record_tip_contribution(&mut self, host: &str) {
+        if let Some(peer) = self.peers.get_mut(host) {
+            peer.performance.tip_contributions += 1;
+        }
+    }
+
+    /// Actions bringing the peer sets towards their targets.
+    pub fn decide(&mut self, now: Instant) -> Vec<Action> {
+        let mut actions = vec![];
+        let next_churn = *self.next_churn.get_or_insert(now + self.churn_interval);
+        if now >= next_churn {
+            self.churn(now, &mut actions);
+            self.next_churn = Some(now + self.churn_interval);
+        }
+        self.forget(&mut actions);
+        self.discover();
+        self.share(now, &mut actions);
+        self.establish(now, &mut actions);
+        self.activate(now, &mut actions);
+        actions
+    }
+
+    fn count(&self, predicate: impl Fn(&KnownPeer) -> bool) -> usize {
+        self.peers.values().filter(|peer| predicate(peer)).count()
+    }
+
+    /// Hosts of the peers matching `predicate`, best first.
+    fn ranked(&self, predicate: impl Fn(&KnownPeer) -> bool) -> Vec<String> {
+        let mut hosts: Vec<(&String, &KnownPeer)> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| predicate(peer))
+            .collect();
+        hosts.sort_by(|(a, peer_a), (b, peer_b)| {
+            let (score_a, score_b) = (peer_a.performance.score(), peer_b.performance.score());
+            score_b
+                .partial_cmp(&score_a)
+                .unwrap_or(Ordering::Equal)
+                .then(peer_a.failures.cmp(&peer_b.failures))
+                .then(peer_a.source.cmp(&peer_b.source))
+                .then(a.cmp(b))
+        });
+        hosts.into_iter().map(|(host, _)| host.clone()).collect()
+    }
+
+    fn set(&mut self, host: &str, update: impl FnOnce(&mut KnownPeer)) {
+        if let Some(peer) = self.peers.get_mut(host) {
+            update(peer);
+        }
+    }
+
+    fn churn(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        let rest = now + self.churn_interval;
+        let idle = |status| {
+            move |peer: &KnownPeer| {
+                peer.status == status && !peer.in_progress && peer.source != Source::LocalRoot
+            }
+        };
+        if let Some(host) = self.ranked(idle(PeerStatus::Warm)).pop() {
+            info!("Churn: disconnecting {}", host);
+            self.set(&host, |peer| {
+                peer.status = PeerStatus::Cold;
+                peer.retry_at = Some(rest);
+            });
+            actions.push(Action::Disconnect(host));
+        }
+        if let Some(host) = self.ranked(idle(PeerStatus::Hot)).pop() {
+            info!("Churn: deactivating {}", host);
+            self.set(&host, |peer| {
+                peer.status = PeerStatus::Warm;
+                peer.retry_at = Some(rest);
+            });
+            actions.push(Action::Deactivate(host));
+        }
+        for peer in self.peers.values_mut() {
+            peer.performance.tip_contributions /= 2;
+        }
+    }
+
+    /// Forgets non-root peers that keep failing and, over the target, cold
+    /// non-root peers, the least useful first.
+    fn forget(&mut self, actions: &mut Vec<Action>) {
+        let max_failures = self.max_failures;
+        let forgettable = |peer: &KnownPeer| {
+            peer.status == PeerStatus::Cold && !peer.in_progress && !peer.source.is_root()
+        };
+        let mut forgotten: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| forgettable(peer) && peer.failures >= max_failures)
+            .map(|(host, _)| host.clone())
+            .collect();
+        let excess = self
+            .peers
+            .len()
+            .saturating_sub(self.targets.known + forgotten.len());
+        let mut candidates = self.ranked(|peer| forgettable(peer) && peer.failures < max_failures);
+        candidates.reverse();
+        forgotten.extend(candidates.into_iter().take(excess));
+        for host in forgotten {
+            debug!("Forgetting {}", host);
+            self.peers.remove(&host);
+            actions.push(Action::Forget(host));
+        }
+    }
+
+    /// Draws relays of the ledger pools while fewer peers than the target
+    /// are known.
+    fn discover(&mut self) {
+        let missing = self.targets.known.saturating_sub(self.peers.len());
+        if missing == 0 {
+            return;
+        }
+        let peers = pick_peers(&self.ledger_pools, missing, &mut self.seed);
+        let hosts: Vec<String> = peers
+            .into_iter()
+            .filter_map(|peer| peer.relay.host())
+            .collect();
+        debug!("Discovered {} ledger peers", hosts.len());
+        self.add_peers(hosts, Source::Ledger);
+    }
+
+    /// Asks the established peer asked least recently for peers while fewer
+    /// than the target are known.
+    fn share(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        let missing = self.targets.known.saturating_sub(self.peers.len());
+        if missing == 0 || self.sharing.is_some() {
+            return;
+        }
+        let candidate = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| {
+                peer.is_established()
+                    && !peer.in_progress
+                    && peer
+                        .last_shared
+                        .is_none_or(|last| now >= last + SHARE_INTERVAL)
+            })
+            .min_by_key(|(host, peer)| (peer.last_shared, host.to_string()))
+            .map(|(host, _)| host.clone());
+        if let Some(host) = candidate {
+            self.set(&host, |peer| peer.last_shared = Some(now));
+            self.sharing = Some(host.clone());
+            actions.push(Action::SharePeers(
+                host,
+                missing.min(u8::MAX as usize) as u8,
+            ));
+        }
+    }
+
+    /// Connects to local roots and the best cold peers up to the target of
+    /// established peers, disconnects the worst warm ones over it.
+    fn establish(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        let connectable = |peer: &KnownPeer| {
+            peer.status == PeerStatus::Cold && !peer.in_progress && !peer.is_backing_off(now)
+        };
+        let mut hosts = self.ranked(|peer| connectable(peer) && peer.source == Source::LocalRoot);
+        let established = self.count(|peer| peer.is_established() || peer.in_progress);
+        let missing = self
+            .targets
+            .established
+            .saturating_sub(established + hosts.len());
+        let others = self.ranked(|peer| connectable(peer) && peer.source != Source::LocalRoot);
+        hosts.extend(others.into_iter().take(missing));
+        for host in hosts {
+            self.set(&host, |peer| peer.in_progress = true);
+            actions.push(Action::Connect(host));
+        }
+
+        let established = self.count(|peer| peer.is_established() || peer.in_progress);
+        let excess = established.saturating_sub(self.targets.established);
+        let mut warm = self.ranked(|peer| {
+            peer.status == PeerStatus::Warm && !peer.in_progress && peer.source != Source::LocalRoot
+        });
+        warm.reverse();
+        for host in warm.into_iter().take(excess) {
+            self.set(&host, |peer| peer.status = PeerStatus::Cold);
+            actions.push(Action::Disconnect(host));
+        }
+    }
+
+    /// Activates warm local roots and the best warm peers up to the target
+    /// of active peers, deactivates the worst hot ones over it.
+    fn activate(&mut self, now: Instant, actions: &mut Vec<Action>) {
+        let activatable = |peer: &KnownPeer| {
+            peer.status == PeerStatus::Warm && !peer.in_progress && !peer.is_backing_off(now)
+        };
+        let active = |peer: &KnownPeer| {
+            peer.status == PeerStatus::Hot || (peer.status == PeerStatus::Warm && peer.in_progress)
+        };
+        let mut hosts = self.ranked(|peer| activatable(peer) && peer.source == Source::LocalRoot);
+        let missing = self
+            .targets
+            .active
+            .saturating_sub(self.count(active) + hosts.len());
+        let others = self.ranked(|peer| activatable(peer) && peer.source != Source::LocalRoot);
+        hosts.extend(others.into_iter().take(missing));
+        for host in hosts {
+            self.set(&host, |peer| peer.in_progress = true);
+            actions.push(Action::Activate(host));
+        }
+
+        let excess = self.count(active).saturating_sub(self.targets.active);
+        let mut hot = self.ranked(|peer| {
+            peer.status == PeerStatus::Hot && !peer.in_progress && peer.source != Source::LocalRoot
+        });
+        hot.reverse();
+        for host in hot.into_iter().take(excess) {
+            self.set(&host, |peer| peer.status = PeerStatus::Warm);
+            actions.push(Action::Deactivate(host));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::Relay;
+
+    fn hosts(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn connect_all(governor: &mut Governor, actions: &[Action], now: Instant) {
+        for action in actions {
+            match action {
+                Action::Connect(host) => governor.connected(host, Ok(()), now),
+                Action::Activate(host) => governor.activated(host, Ok(()), now),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn reaches_targets() {
+        let now = Instant::now();
+        let targets = Targets {
+            known: 6,
+            established: 3,
+            active: 2,
+        };
+        let mut governor = Governor::new(targets);
+        governor.add_peers(hosts(&["local"]), Source::LocalRoot);
+        governor.add_peers(hosts(&["a", "b", "c", "local"]), Source::PublicRoot);
+        assert_eq!(governor.peer("local").unwrap().source, Source::LocalRoot);
+
+        let actions = governor.decide(now);
+        assert_eq!(
+            actions,
+            vec![
+                Action::Connect("local".to_owned()),
+                Action::Connect("a".to_owned()),
+                Action::Connect("b".to_owned()),
+            ]
+        );
+        assert!(governor.decide(now).is_empty());
+        connect_all(&mut governor, &actions, now);
+        governor.record_rtt("b", Duration::from_millis(20));
+
+        // The local root and the fastest peer
+        let actions = governor.decide(now);
+        assert_eq!(
+            actions,
+            vec![
+                Action::SharePeers("a".to_owned(), 2),
+                Action::Activate("local".to_owned()),
+                Action::Activate("b".to_owned()),
+            ]
+        );
+        connect_all(&mut governor, &actions, now);
+        assert_eq!(governor.counts(), (4, 3, 2));
+
+        // Peer sharing fills the known set, over the target is forgotten
+        governor.shared_peers("a", hosts(&["d", "e", "f", "b"]));
+        let actions = governor.decide(now);
+        assert_eq!(actions, vec![Action::Forget("f".to_owned())]);
+        assert_eq!(governor.peer("b").unwrap().source, Source::PublicRoot);
+        assert_eq!(governor.counts(), (6, 3, 2));
+    }
+
+    #[test]
+    fn roots_from_topology() {
+        let topology = Topology::from_json(
+            r#"{"localRoots": [{"accessPoints": [{"address": "10.0.0.1", "port": 3001}]}],
+                "publicRoots": [{"accessPoints": [{"address": "relay", "port": 3001},
+                                                  {"address": "10.0.0.1", "port": 3001}]}]}"#,
+        )
+        .unwrap();
+        let mut governor = Governor::new(Targets::default());
+        governor.add_topology(&topology);
+        let source = |host: &str| governor.peer(host).unwrap().source;
+        assert_eq!(source("10.0.0.1:3001"), Source::LocalRoot);
+        assert_eq!(source("relay:3001"), Source::PublicRoot);
+    }
+
+    #[test]
+    fn discovers_ledger_peers() {
+        let relay = |dns_name: &str, port| Relay::SingleHostName {
+            port,
+            dns_name: dns_name.to_owned(),
+        };
+        let pools = vec![
+            LedgerPool {
+                pool: None,
+                relative_stake: 0.4,
+                relays: vec![relay("small", Some(3001)), relay("unreachable", None)],
+            },
+            LedgerPool {
+                pool: None,
+                relative_stake: 0.6,
+                relays: vec![relay("big", Some(3001))],
+            },
+        ];
+        let mut governor = Governor::new(Targets {
+            known: 3,
+            established: 0,
+            active: 0,
+        })
+        .with_seed(7);
+        governor.add_peers(hosts(&["root"]), Source::PublicRoot);
+        governor.add_ledger_pools(pools);
+        for _ in 0..20 {
+            assert!(governor.decide(Instant::now()).is_empty());
+        }
+        assert_eq!(governor.counts(), (3, 0, 0));
+        for host in ["small:3001", "big:3001"] {
+            assert_eq!(governor.peer(host).unwrap().source, Source::Ledger);
+        }
+
+        // Nothing is drawn once enough peers are known
+        let mut governor = Governor::new(Targets {
+            known: 1,
+            established: 0,
+            active: 0,
+        });
+        governor.add_peers(hosts(&["root"]), Source::PublicRoot);
+        governor.add_ledger_pools(vec![LedgerPool {
+            pool: None,
+            relative_stake: 1.0,
+            relays: vec![relay("big", Some(3001))],
+        }]);
+        governor.decide(Instant::now());
+        assert_eq!(governor.counts(), (1, 0, 0));
+    }
+
+    #[test]
+    fn failures_back_off() {
+        let now = Instant::now();
+        let targets = Targets {
+            known: 10,
+            established: 1,
+            active: 0,
+        };
+        let mut governor = Governor::new(targets)
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(8))
+            .with_max_failures(3);
+        governor.add_peers(hosts(&["root"]), Source::PublicRoot);
+        governor.add_peers(hosts(&["shared"]), Source::PeerSharing);
+
+        let mut now = now;
+        for round in 1..=3 {
+            assert_eq!(
+                governor.decide(now),
+                vec![Action::Connect("root".to_owned())]
+            );
+            governor.connected("root", Err("refused".to_owned()), now);
+            // The other peer is tried while the root backs off
+            assert_eq!(
+                governor.decide(now),
+                vec![Action::Connect("shared".to_owned())]
+            );
+            governor.connected("shared", Err("refused".to_owned()), now);
+            let actions = governor.decide(now);
+            match round {
+                3 => assert_eq!(actions, vec![Action::Forget("shared".to_owned())]),
+                _ => assert!(actions.is_empty()),
+            }
+            now += governor.peer("root").unwrap().retry_at.unwrap() - now;
+        }
+        // Roots are kept
+        assert_eq!(governor.peer("root").unwrap().failures, 3);
+        assert_eq!(
+            governor.decide(now),
+            vec![Action::Connect("root".to_owned())]
+        );
+
+        // A success resets the failures, a failing connection backs off
+        governor.connected("root", Ok(()), now);
+        assert_eq!(governor.peer("root").unwrap().failures, 0);
+        governor.failed("root", now);
+        assert_eq!(governor.status("root"), Some(PeerStatus::Cold));
+        assert!(governor.peer("root").unwrap().is_backing_off(now));
+    }
+
+    #[test]
+    fn churns_the_worst_peers() {
+        let start = Instant::now();
+        let targets = Targets {
+            known: 6,
+            established: 4,
+            active: 3,
+        };
+        let interval = Duration::from_secs(60);
+        let mut governor = Governor::new(targets).with_churn_interval(interval);
+        governor.add_peers(hosts(&["local"]), Source::LocalRoot);
+        governor.add_peers(hosts(&["a", "b", "c", "d", "e"]), Source::PublicRoot);
+        for host in ["a", "b", "c"] {
+            governor.record_rtt(host, Duration::from_millis(10));
+        }
+        while {
+            let actions = governor.decide(start);
+            connect_all(&mut governor, &actions, start);
+            !actions.is_empty()
+        } {}
+        assert_eq!(governor.counts(), (6, 4, 3));
+        assert_eq!(governor.status("b"), Some(PeerStatus::Hot));
+        assert_eq!(governor.status("c"), Some(PeerStatus::Warm));
+
+        // The active peer contributing to the chain stays, the slower one and
+        // the warm one rest for an interval
+        governor.record_tip_contribution("a");
+        governor.record_rtt("b", Duration::from_secs(2));
+        let now = start + interval;
+        let actions = governor.decide(now);
+        assert_eq!(
+            actions,
+            vec![
+                Action::Disconnect("c".to_owned()),
+                Action::Deactivate("b".to_owned()),
+                Action::Connect("d".to_owned()),
+            ]
+        );
+        connect_all(&mut governor, &actions, now);
+        let actions = governor.decide(now);
+        assert_eq!(actions, vec![Action::Activate("d".to_owned())]);
+        connect_all(&mut governor, &actions, now);
+        assert_eq!(governor.peer("a").unwrap().performance.tip_contributions, 0);
+
+        let now = now + interval;
+        let actions = governor.decide(now);
+        assert_eq!(
+            actions,
+            vec![
+                Action::Disconnect("b".to_owned()),
+                Action::Deactivate("d".to_owned()),
+                Action::Connect("c".to_owned()),
+            ]
+        );
+        connect_all(&mut governor, &actions, now);
+        assert_eq!(governor.decide(now), vec![Action::Activate("c".to_owned())]);
+        assert_eq!(governor.status("local"), Some(PeerStatus::Hot));
+    }
+}
@@ -1,7 +1,8 @@
+use crate::bearer::{self, Bearer};
 use ciborium::Value;
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 use tokio::{
-    io::{self, AsyncRead, AsyncWrite},
+    io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     sync::Mutex,
 };
@@ -10,15 +11,20 @@ use tracing::{error, info};
 // 3.6 Handshake mini-protocol implementation
 
 pub const MINI_PROTOCOL_ID_HANDSHAKE: u16 = 0;
+// Node-to-client version numbers have bit 15 set
+pub const NODE_TO_CLIENT_VERSIONS: std::ops::Range<VersionNumber> = 0x8000..0x10000;
 
 type Index = i128;
 type VersionNumber = i128;
 type RefuseReasonMessage = String;
 type NetworkMagic = u32;
 type InitiatorAndResponderDiffusionMode = bool;
-type VersionTable = Vec<(VersionNumber, Vec<NodeToNodeVersionData>)>;
+type PeerSharing = u8;
+type Query = bool;
+pub type VersionTable = Vec<(VersionNumber, Vec<NodeToNodeVersionData>)>;
+pub type NodeToClientVersionTable = Vec<(VersionNumber, Vec<NodeToClientVersionData>)>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum Message {
     // MsgProposeVersions
@@ -27,6 +33,10 @@ pub enum Message {
     AcceptVersion(Vec<AcceptVersion>),
     // MsgRefuse
     Refuse(RefuseReason),
+    // MsgQueryReply
+    QueryReply(VersionTable),
+    // MsgQueryReply of the node-to-client handshake
+    NodeToClientQueryReply(NodeToClientVersionTable),
 }
 
 impl Message {
@@ -36,7 +46,47 @@ impl Message {
                 let values = propose_versions.iter().map(|v| v.to_value()).collect();
                 Ok(Value::Array(values))
             }
-            _ => Err("Not expecting other value".to_owned()),
+            Message::AcceptVersion(accept_version) => {
+                let values = accept_version.iter().map(|v| v.to_value()).collect();
+                Ok(Value::Array(values))
+            }
+            Message::Refuse(refuse_reason) => {
+                Ok(Value::Array(vec![Value::from(2), refuse_reason.to_value()]))
+            }
+            Message::QueryReply(version_table) => Ok(Value::Array(vec![
+                Value::from(3),
+                ProposeVersion::VersionTable(version_table.clone()).to_value(),
+            ])),
+            Message::NodeToClientQueryReply(version_table) => Ok(Value::Array(vec![
+                Value::from(3),
+                ProposeVersion::NodeToClientVersionTable(version_table.clone()).to_value(),
+            ])),
+        }
+    }
+
+    /// Version accepted by the peer, if it accepted one.
+    pub fn accepted_version(&self) -> Option<VersionNumber> {
+        match self {
+            Message::AcceptVersion(accept_version) => {
+                accept_version.iter().find_map(|value| match value {
+                    AcceptVersion::VersionNumber(version) => Some(*version),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Version data of the accepted version, if the peer accepted one.
+    pub fn accepted_version_data(&self) -> Option<&[NodeToNodeVersionData]> {
+        match self {
+            Message::AcceptVersion(accept_version) => {
+                accept_version.iter().find_map(|value| match value {
+                    AcceptVersion::NodeToNodeVersionData(data) => Some(&data[..]),
+                    _ => None,
+                })
+            }
+            _ => None,
         }
     }
 
@@ -46,12 +96,22 @@ impl Message {
             .into_array()
             .map_err(|error| format!("Could not convert Message into array: {:?}", error))?;
 
-        let index = array.get(0).ok_or("No value found at message index 0")?;
+        let index = array.first().ok_or("No value found at message index 0")?;
         let index = index
             .as_integer()
             .ok_or("Could not convert index to integer")?;
         let index = i128::from(index);
         match index {
+            0 => {
+                info!("ProposeVersions::from_value");
+                let version_table = array
+                    .get(1)
+                    .ok_or("No value found at ProposeVersions index 1")?;
+                Ok(Message::ProposeVersions(vec![
+                    ProposeVersion::Index(0),
+                    version_table_from_value(version_table)?,
+                ]))
+            }
             1 => {
                 info!("AcceptVersion::from_value");
                 let version_number = array
@@ -69,17 +129,19 @@ impl Message {
                             return Err(error);
                         }
                     };
-                let node_to_node_version_data_val =
-                    match AcceptVersion::nodes_data_from_value(node_to_node_version_data) {
-                        Ok(nd) => nd,
-                        Err(error) => {
-                            error!(
-                                "Failed to convert {:?}: {}",
-                                node_to_node_version_data, error
-                            );
-                            return Err(error);
-                        }
-                    };
+                let node_to_node_version_data_val = match AcceptVersion::nodes_data_from_value(
+                    &version_number_val,
+                    node_to_node_version_data,
+                ) {
+                    Ok(nd) => nd,
+                    Err(error) => {
+                        error!(
+                            "Failed to convert {:?}: {}",
+                            node_to_node_version_data, error
+                        );
+                        return Err(error);
+                    }
+                };
                 Ok(Message::AcceptVersion(vec![
                     AcceptVersion::Index(1),
                     version_number_val,
@@ -98,21 +160,79 @@ impl Message {
                     }
                 }
             }
+            3 => {
+                info!("QueryReply::from_value");
+                let version_table = array.get(1).ok_or("No value found at QueryReply index 1")?;
+                match version_table_from_value(version_table)? {
+                    ProposeVersion::NodeToClientVersionTable(version_table) => {
+                        Ok(Message::NodeToClientQueryReply(version_table))
+                    }
+                    ProposeVersion::VersionTable(version_table) => {
+                        Ok(Message::QueryReply(version_table))
+                    }
+                    ProposeVersion::Index(_) => unreachable!(),
+                }
+            }
             _ => Err(format!("Message: Do not expect any other index {}!", index)),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+// The version numbers tell a node-to-node table from a node-to-client one,
+// an empty table is taken for node-to-node
+fn version_table_from_value(value: &Value) -> Result<ProposeVersion, String> {
+    let map = value
+        .as_map()
+        .ok_or("Could not convert version table to map")?;
+    let mut version_numbers = vec![];
+    for (version_number, _) in map {
+        let version_number = version_number
+            .as_integer()
+            .ok_or("Could not convert version_number as integer")?;
+        version_numbers.push(VersionNumber::from(version_number));
+    }
+    let node_to_client = version_numbers
+        .iter()
+        .filter(|version| NODE_TO_CLIENT_VERSIONS.contains(version))
+        .count();
+    if node_to_client == 0 {
+        let mut version_table: VersionTable = vec![];
+        for (version_number, (_, version_data)) in version_numbers.into_iter().zip(map) {
+            version_table.push((
+                version_number,
+                NodeToNodeVersionData::list_from_value(version_data)?,
+            ));
+        }
+        Ok(ProposeVersion::VersionTable(version_table))
+    } else if node_to_client == map.len() {
+        let mut version_table: NodeToClientVersionTable = vec![];
+        for (version_number, (_, version_data)) in version_numbers.into_iter().zip(map) {
+            version_table.push((
+                version_number,
+                NodeToClientVersionData::list_from_value(version_data)?,
+            ));
+        }
+        Ok(ProposeVersion::NodeToClientVersionTable(version_table))
+    } else {
+        Err("Version table mixes node-to-node and node-to-client versions".to_owned())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ProposeVersion {
     Index(Index),
     VersionTable(VersionTable),
+    NodeToClientVersionTable(NodeToClientVersionTable),
 }
 
 impl ProposeVersion {
+    /// Version table proposing `supported_versions`. From version 11 on the
+    /// version data also carries peer sharing and the query flag, asking the
+    /// peer to reply with its own version table instead of accepting one.
     pub fn create_version_table(
         supported_versions: &Vec<i64>,
         network_magic: u32,
+        query: bool,
     ) -> ProposeVersion {
         let mut version_table: VersionTable = vec![];
         for version in supported_versions {
@@ -120,22 +240,45 @@ impl ProposeVersion {
             match version_table.binary_search_by_key(&version, |(a, _b)| *a) {
                 Ok(_) => {}
                 Err(idx) => {
-                    version_table.insert(
-                        idx,
-                        (
-                            version,
-                            vec![
-                                NodeToNodeVersionData::NetworkMagic(network_magic),
-                                NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(false),
-                            ],
-                        ),
-                    );
+                    let mut version_data = vec![
+                        NodeToNodeVersionData::NetworkMagic(network_magic),
+                        NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(false),
+                    ];
+                    if version >= 11 {
+                        version_data.push(NodeToNodeVersionData::PeerSharing(0));
+                        version_data.push(NodeToNodeVersionData::Query(query));
+                    }
+                    version_table.insert(idx, (version, version_data));
                 }
             };
         }
         ProposeVersion::VersionTable(version_table)
     }
 
+    /// Enables peer sharing in the version data of version 11 and later.
+    pub fn with_peer_sharing(self, peer_sharing: bool) -> ProposeVersion {
+        match self {
+            ProposeVersion::VersionTable(version_table) => ProposeVersion::VersionTable(
+                version_table
+                    .into_iter()
+                    .map(|(version, data)| {
+                        let data = data
+                            .into_iter()
+                            .map(|field| match field {
+                                NodeToNodeVersionData::PeerSharing(_) => {
+                                    NodeToNodeVersionData::PeerSharing(peer_sharing as u8)
+                                }
+                                field => field,
+                            })
+                            .collect();
+                        (version, data)
+                    })
+                    .collect(),
+            ),
+            proposal => proposal,
+        }
+    }
+
     fn to_value(&self) -> Value {
         match self {
             ProposeVersion::Index(index) => Value::from(*index),
@@ -151,15 +294,27 @@ impl ProposeVersion {
                 }
                 Value::Map(values)
             }
+            ProposeVersion::NodeToClientVersionTable(version_table) => Value::Map(
+                version_table
+                    .iter()
+                    .map(|(version_number, data)| {
+                        (
+                            Value::from(*version_number),
+                            Value::Array(data.iter().map(|d| d.to_value()).collect()),
+                        )
+                    })
+                    .collect(),
+            ),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AcceptVersion {
     Index(Index),
     VersionNumber(VersionNumber),
     NodeToNodeVersionData(Vec<NodeToNodeVersionData>),
+    NodeToClientVersionData(Vec<NodeToClientVersionData>),
 }
 
 impl AcceptVersion {
@@ -172,24 +327,37 @@ impl AcceptVersion {
         )))
     }
 
-    fn nodes_data_from_value(value: &Value) -> Result<AcceptVersion, String> {
-        let array = value.as_array().ok_or("Could not convert to array")?;
-        let mut data: Vec<NodeToNodeVersionData> = vec![];
-        for dr in array {
-            let dr_val = match NodeToNodeVersionData::from_value(dr.clone()) {
-                Ok(val) => val,
-                Err(error) => {
-                    error!("Error in converting value of {:?}: {}", dr, error);
-                    return Err(error);
-                }
-            };
-            data.push(dr_val);
+    fn nodes_data_from_value(
+        version_number: &AcceptVersion,
+        value: &Value,
+    ) -> Result<AcceptVersion, String> {
+        match version_number {
+            AcceptVersion::VersionNumber(version) if NODE_TO_CLIENT_VERSIONS.contains(version) => {
+                Ok(AcceptVersion::NodeToClientVersionData(
+                    NodeToClientVersionData::list_from_value(value)?,
+                ))
+            }
+            _ => Ok(AcceptVersion::NodeToNodeVersionData(
+                NodeToNodeVersionData::list_from_value(value)?,
+            )),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            AcceptVersion::Index(index) => Value::from(*index),
+            AcceptVersion::VersionNumber(version_number) => Value::from(*version_number),
+            AcceptVersion::NodeToNodeVersionData(data) => {
+                Value::Array(data.iter().map(|d| d.to_value()).collect())
+            }
+            AcceptVersion::NodeToClientVersionData(data) => {
+                Value::Array(data.iter().map(|d| d.to_value()).collect())
+            }
         }
-        Ok(AcceptVersion::NodeToNodeVersionData(data))
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RefuseReason {
     // RefuseReasonVersionMismatch
     VersionMismatch(Vec<VersionNumber>),
@@ -200,11 +368,39 @@ pub enum RefuseReason {
 }
 
 impl RefuseReason {
+    /// Name of the reason without its details.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RefuseReason::VersionMismatch(_) => "VersionMismatch",
+            RefuseReason::HandshakeDecodeError(_, _) => "HandshakeDecodeError",
+            RefuseReason::Refused(_, _) => "Refused",
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            RefuseReason::VersionMismatch(version_numbers) => Value::Array(vec![
+                Value::from(0),
+                Value::Array(version_numbers.iter().map(|v| Value::from(*v)).collect()),
+            ]),
+            RefuseReason::HandshakeDecodeError(version_number, message) => Value::Array(vec![
+                Value::from(1),
+                Value::from(*version_number),
+                Value::Text(message.clone()),
+            ]),
+            RefuseReason::Refused(version_number, message) => Value::Array(vec![
+                Value::from(2),
+                Value::from(*version_number),
+                Value::Text(message.clone()),
+            ]),
+        }
+    }
+
     fn from_value(value: &Value) -> Result<RefuseReason, String> {
         let value = value.as_array().ok_or("No value found!")?;
 
         let index = value
-            .get(0)
+            .first()
             .ok_or("No value found at RefuseReason index 0")?;
         let index = index
             .as_integer()
@@ -271,13 +467,49 @@ impl RefuseReason {
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for RefuseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefuseReason::VersionMismatch(version_numbers) => {
+                write!(f, "VersionMismatch {:?}", version_numbers)
+            }
+            RefuseReason::HandshakeDecodeError(version_number, message) => {
+                write!(f, "HandshakeDecodeError {}: {}", version_number, message)
+            }
+            RefuseReason::Refused(version_number, message) => {
+                write!(f, "Refused {}: {}", version_number, message)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeToNodeVersionData {
     NetworkMagic(NetworkMagic),
+    // `initiatorOnlyDiffusionMode` on the wire: false when the node runs the
+    // responder side too, which allows duplex connections
     InitiatorAndResponderDiffusionMode(InitiatorAndResponderDiffusionMode),
+    // Version 11 and later
+    PeerSharing(PeerSharing),
+    // Version 11 and later
+    Query(Query),
 }
 
 impl NodeToNodeVersionData {
+    /// Whether accepted version data makes the connection duplex, which
+    /// needs both sides in `InitiatorAndResponderDiffusionMode`.
+    pub fn is_duplex(data: &[NodeToNodeVersionData]) -> bool {
+        data.contains(&NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(
+            false,
+        ))
+    }
+
+    /// Peer sharing is enabled, which needs version 11 or later.
+    pub fn is_peer_sharing(data: &[NodeToNodeVersionData]) -> bool {
+        data.iter()
+            .any(|field| matches!(field, NodeToNodeVersionData::PeerSharing(peer_sharing) if *peer_sharing != 0))
+    }
+
     fn to_value(&self) -> Value {
         match self {
             NodeToNodeVersionData::NetworkMagic(network_magic) => {
@@ -286,24 +518,98 @@ impl NodeToNodeVersionData {
             NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(
                 initiator_and_responder_diffusion_mode,
             ) => Value::Bool(*initiator_and_responder_diffusion_mode),
+            NodeToNodeVersionData::PeerSharing(peer_sharing) => Value::from(*peer_sharing),
+            NodeToNodeVersionData::Query(query) => Value::Bool(*query),
         }
     }
 
-    fn from_value(value: Value) -> Result<NodeToNodeVersionData, String> {
-        if value.is_integer() {
-            let network_magic: i128 = value
+    // The fields are told apart by their position in the version data
+    fn from_value(position: usize, value: &Value) -> Result<NodeToNodeVersionData, String> {
+        let integer = |field: &str| {
+            value
                 .as_integer()
-                .ok_or("Could not convert network_magic to integer.")?
-                .into();
-            Ok(NodeToNodeVersionData::NetworkMagic(network_magic as u32))
-        } else if value.is_bool() {
-            Ok(NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(
-                value.as_bool().ok_or("Could not convert mode to bool")?,
-            ))
-        } else {
-            Err("Do not expect any other value!".to_owned())
+                .ok_or(format!("Could not convert {} to integer.", field))
+        };
+        let boolean = |field: &str| {
+            value
+                .as_bool()
+                .ok_or(format!("Could not convert {} to bool", field))
+        };
+        match position {
+            0 => Ok(NodeToNodeVersionData::NetworkMagic(
+                u32::try_from(integer("network_magic")?)
+                    .map_err(|_| format!("Network magic {:?} out of range", value))?,
+            )),
+            1 => Ok(NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(
+                boolean("mode")?,
+            )),
+            2 => Ok(NodeToNodeVersionData::PeerSharing(
+                u8::try_from(integer("peer_sharing")?)
+                    .map_err(|_| format!("Peer sharing {:?} out of range", value))?,
+            )),
+            3 => Ok(NodeToNodeVersionData::Query(boolean("query")?)),
+            _ => Err("Do not expect any other value!".to_owned()),
         }
     }
+
+    fn list_from_value(value: &Value) -> Result<Vec<NodeToNodeVersionData>, String> {
+        let array = value.as_array().ok_or("Could not convert to array")?;
+        let mut data: Vec<NodeToNodeVersionData> = vec![];
+        for (position, dr) in array.iter().enumerate() {
+            let dr_val = match NodeToNodeVersionData::from_value(position, dr) {
+                Ok(val) => val,
+                Err(error) => {
+                    error!("Error in converting value of {:?}: {}", dr, error);
+                    return Err(error);
+                }
+            };
+            data.push(dr_val);
+        }
+        Ok(data)
+    }
+}
+
+/// Version data of the node-to-client handshake, from version 15 on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeToClientVersionData {
+    NetworkMagic(NetworkMagic),
+    Query(Query),
+}
+
+impl NodeToClientVersionData {
+    fn to_value(&self) -> Value {
+        match self {
+            NodeToClientVersionData::NetworkMagic(network_magic) => {
+                Value::from(*network_magic as i128)
+            }
+            NodeToClientVersionData::Query(query) => Value::Bool(*query),
+        }
+    }
+
+    // The fields are told apart by their position in the version data
+    fn from_value(position: usize, value: &Value) -> Result<NodeToClientVersionData, String> {
+        match position {
+            0 => Ok(NodeToClientVersionData::NetworkMagic(
+                value
+                    .as_integer()
+                    .and_then(|magic| u32::try_from(magic).ok())
+                    .ok_or(format!("Network magic {:?} out of range", value))?,
+            )),
+            1 => Ok(NodeToClientVersionData::Query(
+                value.as_bool().ok_or("Could not convert query to bool")?,
+            )),
+            _ => Err("Do not expect any other value!".to_owned()),
+        }
+    }
+
+    fn list_from_value(value: &Value) -> Result<Vec<NodeToClientVersionData>, String> {
+        let array = value.as_array().ok_or("Could not convert to array")?;
+        array
+            .iter()
+            .enumerate()
+            .map(|(position, value)| NodeToClientVersionData::from_value(position, value))
+            .collect()
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -325,6 +631,7 @@ pub enum StateMachine {
     Done,
 }
 
+#[allow(dead_code)]
 pub struct NodeConfig<'a> {
     pub host: &'a str,
     pub magic: u32,
@@ -348,14 +655,35 @@ impl<'a> NodeConfig<'a> {
             }
         };
         info!("Stream created for {}", network_id);
-        let (read, write) = io::split(stream);
-        Ok(NodeConfig {
+        Ok(NodeConfig::with_bearer(host, magic, network_id, stream))
+    }
+
+    /// Connects to the Unix domain socket at `path`.
+    #[cfg(unix)]
+    pub async fn init_unix(
+        path: &'a str,
+        magic: u32,
+        network_id: &'a str,
+    ) -> Result<NodeConfig<'a>, String> {
+        let stream = bearer::connect_unix(path).await?;
+        Ok(NodeConfig::with_bearer(path, magic, network_id, stream))
+    }
+
+    /// Runs over an already connected bearer, `host` only names the peer.
+    pub fn with_bearer<B: Bearer>(
+        host: &'a str,
+        magic: u32,
+        network_id: &'a str,
+        bearer: B,
+    ) -> NodeConfig<'a> {
+        let (read, write) = bearer.split();
+        NodeConfig {
             host,
             magic,
             network_id,
             read: Arc::new(Mutex::new(Box::new(read))),
             write: Mutex::new(Box::new(write)),
-        })
+        }
     }
 }
 
@@ -367,7 +695,7 @@ mod tests {
     async fn propose_versions() {
         let message = Message::ProposeVersions(vec![
             ProposeVersion::Index(0),
-            ProposeVersion::create_version_table(&vec![7, 8, 9, 10], 1),
+            ProposeVersion::create_version_table(&vec![7, 8, 9, 10], 1, false),
         ]);
         assert!(format!("{:?}", message).eq("ProposeVersions([Index(0), VersionTable([(7, [NetworkMagic(1), InitiatorAndResponderDiffusionMode(false)]), (8, [NetworkMagic(1), InitiatorAndResponderDiffusionMode(false)]), (9, [NetworkMagic(1), InitiatorAndResponderDiffusionMode(false)]), (10, [NetworkMagic(1), InitiatorAndResponderDiffusionMode(false)])])])"));
 
@@ -388,6 +716,27 @@ mod tests {
         assert!(format!("{:?}", message).eq("AcceptVersion([Index(1), VersionNumber(10), NodeToNodeVersionData([NetworkMagic(1), InitiatorAndResponderDiffusionMode(false)])])"));
     }
 
+    #[test]
+    fn proposal_with_out_of_range_version_data() {
+        let proposal = |data: Vec<Value>| {
+            Value::Array(vec![
+                Value::from(0),
+                Value::Map(vec![(Value::from(13), Value::Array(data))]),
+            ])
+        };
+        // Magic 2 on 32 bits
+        let magic = Value::from((1u64 << 32) + 2);
+        assert!(Message::from_value(proposal(vec![magic, Value::Bool(false)])).is_err());
+        let peer_sharing = vec![
+            Value::from(2),
+            Value::Bool(false),
+            Value::from(256),
+            Value::Bool(false),
+        ];
+        assert!(Message::from_value(proposal(peer_sharing)).is_err());
+        assert!(Message::from_value(proposal(vec![Value::from(-1), Value::Bool(false)])).is_err());
+    }
+
     #[tokio::test]
     async fn refuse_reason_version_mismatch() {
         let value = Value::Array(vec![
@@ -442,4 +791,226 @@ mod tests {
         println!("{:?}", message);
         assert!(format!("{:?}", message).eq("Refuse(Refused(10, \"unknown reason\"))"));
     }
+
+    #[tokio::test]
+    async fn query_reply() {
+        let message = Message::ProposeVersions(vec![
+            ProposeVersion::Index(0),
+            ProposeVersion::create_version_table(&vec![10, 13], 2, true),
+        ]);
+        let value = message.to_value().unwrap();
+        assert!(format!("{:?}", value).eq("Array([Integer(Integer(0)), Map([(Integer(Integer(10)), Array([Integer(Integer(2)), Bool(false)])), (Integer(Integer(13)), Array([Integer(Integer(2)), Bool(false), Integer(Integer(0)), Bool(true)]))])])"));
+        assert_eq!(Message::from_value(value).unwrap(), message);
+
+        let reply = Message::QueryReply(vec![(
+            14,
+            vec![
+                NodeToNodeVersionData::NetworkMagic(2),
+                NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(false),
+                NodeToNodeVersionData::PeerSharing(1),
+                NodeToNodeVersionData::Query(false),
+            ],
+        )]);
+        assert_eq!(
+            Message::from_value(reply.to_value().unwrap()).unwrap(),
+            reply
+        );
+    }
+
+    #[test]
+    fn mixed_version_table() {
+        let table = Value::Map(vec![
+            (Value::from(13), Value::Array(vec![Value::from(1)])),
+            (Value::from(32784), Value::Array(vec![Value::from(1)])),
+        ]);
+        let proposal = Value::Array(vec![Value::from(0), table]);
+        assert!(Message::from_value(proposal)
+            .unwrap_err()
+            .contains("mixes node-to-node and node-to-client"));
+    }
+
+    #[tokio::test]
+    async fn responses_round_trip() {
+        for message in [
+            Message::AcceptVersion(vec![
+                AcceptVersion::Index(1),
+                AcceptVersion::VersionNumber(13),
+                AcceptVersion::NodeToNodeVersionData(vec![
+                    NodeToNodeVersionData::NetworkMagic(1),
+                    NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(true),
+                    NodeToNodeVersionData::PeerSharing(0),
+                    NodeToNodeVersionData::Query(false),
+                ]),
+            ]),
+            Message::AcceptVersion(vec![
+                AcceptVersion::Index(1),
+                AcceptVersion::VersionNumber(32784),
+                AcceptVersion::NodeToClientVersionData(vec![
+                    NodeToClientVersionData::NetworkMagic(1),
+                    NodeToClientVersionData::Query(false),
+                ]),
+            ]),
+            Message::Refuse(RefuseReason::VersionMismatch(vec![13, 14])),
+            Message::Refuse(RefuseReason::Refused(13, "magic mismatch".to_owned())),
+        ] {
+            assert_eq!(
+                Message::from_value(message.to_value().unwrap()).unwrap(),
+                message
+            );
+        }
+    }
+
+    mod properties {
+        use super::*;
+        use ciborium::{from_reader, into_writer};
+        use proptest::prelude::*;
+
+        // Version numbers are unsigned on the wire
+        fn version_number() -> impl Strategy<Value = VersionNumber> {
+            any::<u64>().prop_map(VersionNumber::from)
+        }
+
+        fn node_to_node_version() -> impl Strategy<Value = VersionNumber> {
+            version_number().prop_filter("node-to-client version", |version| {
+                !NODE_TO_CLIENT_VERSIONS.contains(version)
+            })
+        }
+
+        fn node_to_client_version() -> impl Strategy<Value = VersionNumber> {
+            NODE_TO_CLIENT_VERSIONS
+        }
+
+        // Fields are positional, version data is a prefix of all of them
+        fn version_data() -> impl Strategy<Value = Vec<NodeToNodeVersionData>> {
+            (
+                any::<u32>(),
+                any::<bool>(),
+                any::<u8>(),
+                any::<bool>(),
+                0..=4usize,
+            )
+                .prop_map(|(magic, mode, peer_sharing, query, len)| {
+                    let mut data = vec![
+                        NodeToNodeVersionData::NetworkMagic(magic),
+                        NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(mode),
+                        NodeToNodeVersionData::PeerSharing(peer_sharing),
+                        NodeToNodeVersionData::Query(query),
+                    ];
+                    data.truncate(len);
+                    data
+                })
+        }
+
+        fn version_table() -> impl Strategy<Value = VersionTable> {
+            prop::collection::vec((node_to_node_version(), version_data()), 0..8)
+        }
+
+        fn node_to_client_version_data() -> impl Strategy<Value = Vec<NodeToClientVersionData>> {
+            (any::<u32>(), any::<bool>(), 0..=2usize).prop_map(|(magic, query, len)| {
+                let mut data = vec![
+                    NodeToClientVersionData::NetworkMagic(magic),
+                    NodeToClientVersionData::Query(query),
+                ];
+                data.truncate(len);
+                data
+            })
+        }
+
+        // Empty tables decode as node-to-node ones
+        fn node_to_client_version_table() -> impl Strategy<Value = NodeToClientVersionTable> {
+            prop::collection::vec(
+                (node_to_client_version(), node_to_client_version_data()),
+                1..8,
+            )
+        }
+
+        fn refuse_reason() -> impl Strategy<Value = RefuseReason> {
+            prop_oneof![
+                prop::collection::vec(version_number(), 0..8)
+                    .prop_map(RefuseReason::VersionMismatch),
+                (version_number(), any::<String>())
+                    .prop_map(|(v, m)| RefuseReason::HandshakeDecodeError(v, m)),
+                (version_number(), any::<String>()).prop_map(|(v, m)| RefuseReason::Refused(v, m)),
+            ]
+        }
+
+        // Messages in the shape the decoder produces
+        fn message() -> impl Strategy<Value = Message> {
+            prop_oneof![
+                version_table().prop_map(|table| Message::ProposeVersions(vec![
+                    ProposeVersion::Index(0),
+                    ProposeVersion::VersionTable(table),
+                ])),
+                (node_to_node_version(), version_data()).prop_map(|(version, data)| {
+                    Message::AcceptVersion(vec![
+                        AcceptVersion::Index(1),
+                        AcceptVersion::VersionNumber(version),
+                        AcceptVersion::NodeToNodeVersionData(data),
+                    ])
+                }),
+                refuse_reason().prop_map(Message::Refuse),
+                version_table().prop_map(Message::QueryReply),
+                node_to_client_version_table().prop_map(|table| Message::ProposeVersions(vec![
+                    ProposeVersion::Index(0),
+                    ProposeVersion::NodeToClientVersionTable(table),
+                ])),
+                (node_to_client_version(), node_to_client_version_data()).prop_map(
+                    |(version, data)| {
+                        Message::AcceptVersion(vec![
+                            AcceptVersion::Index(1),
+                            AcceptVersion::VersionNumber(version),
+                            AcceptVersion::NodeToClientVersionData(data),
+                        ])
+                    }
+                ),
+                node_to_client_version_table().prop_map(Message::NodeToClientQueryReply),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn message_round_trip(message in message()) {
+                let mut bytes = vec![];
+                into_writer(&message.to_value().unwrap(), &mut bytes).unwrap();
+                let value: Value = from_reader(&bytes[..]).unwrap();
+                prop_assert_eq!(Message::from_value(value).unwrap(), message);
+            }
+
+            #[test]
+            fn refuse_reason_round_trip(reason in refuse_reason()) {
+                prop_assert_eq!(RefuseReason::from_value(&reason.to_value()).unwrap(), reason);
+            }
+
+            #[test]
+            fn version_data_round_trip(data in version_data()) {
+                let value = Value::Array(data.iter().map(|d| d.to_value()).collect());
+                prop_assert_eq!(NodeToNodeVersionData::list_from_value(&value).unwrap(), data);
+            }
+
+            #[test]
+            fn out_of_range_version_data(
+                magic in (u32::MAX as u64 + 1)..=u64::MAX,
+                negative in i64::MIN..0,
+                peer_sharing in (u8::MAX as u64 + 1)..=u64::MAX,
+            ) {
+                for magic in [Value::from(magic), Value::from(negative)] {
+                    let value = Value::Array(vec![magic]);
+                    prop_assert!(NodeToNodeVersionData::list_from_value(&value).is_err());
+                }
+                let value = Value::Array(vec![
+                    Value::from(1),
+                    Value::Bool(false),
+                    Value::from(peer_sharing),
+                ]);
+                prop_assert!(NodeToNodeVersionData::list_from_value(&value).is_err());
+            }
+
+            #[test]
+            fn decoding_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+                if let Ok(value) = from_reader::<Value, _>(&bytes[..]) {
+                    let _ = Message::from_value(value);
+                }
+            }
+        }
+    }
 }
@@ -2,6 +2,8 @@ mod messages;
 mod workflows;
 
 pub use self::messages::{
-    Message, NodeConfig, ProposeVersion, StateMachine, MINI_PROTOCOL_ID_HANDSHAKE,
+    AcceptVersion, Message, NodeConfig, NodeToClientVersionData, NodeToClientVersionTable,
+    NodeToNodeVersionData, ProposeVersion, RefuseReason, StateMachine, MINI_PROTOCOL_ID_HANDSHAKE,
+    NODE_TO_CLIENT_VERSIONS,
 };
-pub use self::workflows::negotiate;
+pub use self::workflows::{accept, negotiate, propose};
@@ -1,11 +1,17 @@
-use super::{Message, NodeConfig, ProposeVersion, StateMachine, MINI_PROTOCOL_ID_HANDSHAKE};
+use super::{
+    messages::{AcceptVersion, NodeToNodeVersionData, RefuseReason},
+    Message, NodeConfig, ProposeVersion, StateMachine, MINI_PROTOCOL_ID_HANDSHAKE,
+};
+use crate::codec::{item_len, ItemError};
+use crate::metrics::metrics;
+use crate::mux::{read_segment, Channel, MAX_MESSAGE_SIZE, SEGMENT_HEADER_SIZE};
 use ciborium::Value;
 use ciborium::{from_reader, into_writer};
 use core::panic;
 use std::sync::Arc;
 use std::vec;
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     join,
     sync::Mutex,
     time::{Duration, Instant},
@@ -15,7 +21,7 @@ use tracing::{debug, error, info, warn};
 pub async fn negotiate<'a>(
     node_config: NodeConfig<'a>,
     supported_versions: &Vec<i64>,
-) -> Result<(Duration, Duration), String> {
+) -> Result<(Message, Duration, Duration), String> {
     let start = Instant::now();
 
     let mut message = Vec::new();
@@ -28,12 +34,130 @@ pub async fn negotiate<'a>(
     );
 
     let negotiate_start = Instant::now();
-    join!(
+    let (response, _) = join!(
         receive(node_config.read, node_config.network_id),
         send(node_config.write, message, node_config.network_id)
     );
+    let response = response.ok_or(format!(
+        "No handshake response from {}",
+        node_config.network_id
+    ))?;
+
+    Ok((response, negotiate_start.elapsed(), start.elapsed()))
+}
+
+/// Proposes `supported_versions` on a multiplexed connection and returns the
+/// peer's reply: the accepted version, a refusal or, when `query` is set, the
+/// peer's version table. `peer_sharing` offers the peer sharing protocol.
+pub async fn propose(
+    channel: &mut Channel,
+    supported_versions: &Vec<i64>,
+    network_magic: u32,
+    peer_sharing: bool,
+    query: bool,
+) -> Result<Message, String> {
+    let propose_versions = Message::ProposeVersions(vec![
+        ProposeVersion::Index(0),
+        ProposeVersion::create_version_table(supported_versions, network_magic, query)
+            .with_peer_sharing(peer_sharing),
+    ]);
+    debug!("Sending {:?}", propose_versions);
+    channel.send_value(&propose_versions.to_value()?)?;
+    let response = Message::from_value(channel.recv_value().await?)?;
+    debug!("Received {:?}", response);
+    Ok(response)
+}
+
+/// Answers the proposal of an initiator with the highest version both sides
+/// support, provided the network magic matches, and returns that version
+/// with the version data accepted. Peer sharing is accepted when both sides
+/// enable it. Queries are answered with our version table and end the
+/// handshake with an error, as do refusals.
+pub async fn accept(
+    channel: &mut Channel,
+    supported_versions: &Vec<i64>,
+    network_magic: u32,
+    peer_sharing: bool,
+) -> Result<(i128, Vec<NodeToNodeVersionData>), String> {
+    let proposed = match Message::from_value(channel.recv_value().await?)? {
+        Message::ProposeVersions(propose_versions) => propose_versions
+            .into_iter()
+            .find_map(|value| match value {
+                ProposeVersion::VersionTable(version_table) => Some(version_table),
+                _ => None,
+            })
+            .ok_or("MsgProposeVersions without version table")?,
+        message => return Err(format!("Expected MsgProposeVersions, got {:?}", message)),
+    };
+    let ours = match ProposeVersion::create_version_table(supported_versions, network_magic, false)
+        .with_peer_sharing(peer_sharing)
+    {
+        ProposeVersion::VersionTable(version_table) => version_table,
+        _ => unreachable!(),
+    };
 
-    Ok((negotiate_start.elapsed(), start.elapsed()))
+    if proposed
+        .iter()
+        .any(|(_, data)| data.contains(&NodeToNodeVersionData::Query(true)))
+    {
+        channel.send_value(&Message::QueryReply(ours).to_value()?)?;
+        return Err("Answered version query".to_owned());
+    }
+
+    let common = proposed
+        .iter()
+        .filter_map(|(version, data)| {
+            let (_, our_data) = ours.iter().find(|(ours, _)| ours == version)?;
+            Some((*version, data, our_data))
+        })
+        .max_by_key(|(version, _, _)| *version);
+    let refuse = |reason: RefuseReason| -> Result<(i128, Vec<NodeToNodeVersionData>), String> {
+        channel.send_value(&Message::Refuse(reason.clone()).to_value()?)?;
+        Err(format!("Refused handshake: {:?}", reason))
+    };
+    let (version, data, our_data) = match common {
+        Some(common) => common,
+        None => {
+            return refuse(RefuseReason::VersionMismatch(
+                ours.iter().map(|(version, _)| *version).collect(),
+            ))
+        }
+    };
+    if !data.contains(&NodeToNodeVersionData::NetworkMagic(network_magic)) {
+        return refuse(RefuseReason::Refused(
+            version,
+            format!(
+                "version data mismatch: expected network magic {}",
+                network_magic
+            ),
+        ));
+    }
+
+    // The connection is duplex only when both sides run both directions
+    let duplex =
+        NodeToNodeVersionData::is_duplex(data) && NodeToNodeVersionData::is_duplex(our_data);
+    let peer_sharing = NodeToNodeVersionData::is_peer_sharing(data)
+        && NodeToNodeVersionData::is_peer_sharing(our_data);
+    let accepted: Vec<NodeToNodeVersionData> = our_data
+        .iter()
+        .map(|field| match field {
+            NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(_) => {
+                NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(!duplex)
+            }
+            NodeToNodeVersionData::PeerSharing(_) => {
+                NodeToNodeVersionData::PeerSharing(peer_sharing as u8)
+            }
+            field => field.clone(),
+        })
+        .collect();
+    let accept_version = Message::AcceptVersion(vec![
+        AcceptVersion::Index(1),
+        AcceptVersion::VersionNumber(version),
+        AcceptVersion::NodeToNodeVersionData(accepted.clone()),
+    ]);
+    channel.send_value(&accept_version.to_value()?)?;
+    info!("Accepted version {}", version);
+    Ok((version, accepted))
 }
 
 fn prepare_message(
@@ -47,7 +171,7 @@ fn prepare_message(
         StateMachine::Propose => {
             let propose_versions = Message::ProposeVersions(vec![
                 ProposeVersion::Index(0),
-                ProposeVersion::create_version_table(supported_versions, network_magic),
+                ProposeVersion::create_version_table(supported_versions, network_magic, false),
             ]);
             info!("Sending {} : {:?}", network_id, propose_versions);
             let propose_versions = match propose_versions.to_value() {
@@ -82,62 +206,197 @@ async fn send(
     message_len.into_iter().for_each(|m| write_buffer.push(m));
     message.into_iter().for_each(|m| write_buffer.push(m));
     match write.write_all(&write_buffer).await {
-        Ok(_) => info!("Successfully sent request to {}", network_id),
+        Ok(_) => {
+            metrics().bytes_sent(MINI_PROTOCOL_ID_HANDSHAKE, write_buffer.len());
+            info!("Successfully sent request to {}", network_id)
+        }
         Err(error) => error!("Error sending request to server: {:?}", error),
     }
 }
 
-async fn receive(read: Arc<Mutex<Box<dyn AsyncRead + Send + Unpin>>>, network_id: &str) {
+async fn receive(
+    read: Arc<Mutex<Box<dyn AsyncRead + Send + Unpin>>>,
+    network_id: &str,
+) -> Option<Message> {
     info!("Reading response: {}", network_id);
     let mut read = read.lock().await;
 
+    // The reply may span segments, it is complete once it holds a CBOR item
     let mut response_received: Vec<u8> = Vec::new();
-    match read.read_buf(&mut response_received).await {
-        Ok(count) => info!("Bytes read: {}", count),
-        Err(error) => {
-            warn!("Network Id: {}, Error: {:?}", network_id, error);
-            error!("Error in reading response from server");
-            return;
+    loop {
+        let (header, payload) = match read_segment(&mut *read).await {
+            Ok(Some(segment)) => segment,
+            Ok(None) => {
+                error!("Connection closed by {}", network_id);
+                return None;
+            }
+            Err(error) => {
+                warn!("Network Id: {}, Error: {}", network_id, error);
+                error!("Error in reading response from server");
+                return None;
+            }
+        };
+        metrics().bytes_received(header.protocol, SEGMENT_HEADER_SIZE + payload.len());
+        info!(
+            "transmission_time: {}, protocol_id: {}, message_len: {}",
+            header.timestamp, header.protocol, header.length
+        );
+        if header.protocol != MINI_PROTOCOL_ID_HANDSHAKE {
+            continue;
         }
-    };
+        response_received.extend_from_slice(&payload);
+        match item_len(&response_received) {
+            Ok(_) => break,
+            Err(error @ ItemError::Invalid(_)) => {
+                error!("Invalid response from {}: {}", network_id, error);
+                return None;
+            }
+            Err(error) if response_received.len() > MAX_MESSAGE_SIZE => {
+                error!("Invalid response from {}: {}", network_id, error);
+                return None;
+            }
+            Err(_) => {}
+        }
+    }
     info!(
         "Read success {} Response Received: {:?}",
         network_id, response_received
     );
 
-    match response_received[0..4].try_into() {
-        Ok(bytes) => {
-            let val = u32::from_be_bytes(bytes);
-            info!("transmission_time: {}", val);
+    let response_message: Value = match from_reader(&response_received[..]) {
+        Ok(value) => value,
+        Err(error) => {
+            error!("Could not decode response from {}: {:?}", network_id, error);
+            return None;
+        }
+    };
+    debug!("response_message {}: {:?}", network_id, response_message);
+    let response_message = match Message::from_value(response_message) {
+        Ok(response_message) => {
+            info!("response_message {}: {:?}", network_id, response_message);
+            Some(response_message)
+        }
+        Err(error) => {
+            error!("Error message: {}", error);
+            None
         }
-        Err(error) => error!(
-            "Error reading transmission_time for {}: {}",
-            network_id, error
-        ),
+    };
+
+    info!("Reading Complete: {}", network_id);
+    response_message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bearer::duplex;
+    use crate::mux::{Mode, Mux};
+
+    fn channels() -> (Mux, Channel, Mux, Channel) {
+        let (client, server) = duplex();
+        let protocols = [MINI_PROTOCOL_ID_HANDSHAKE];
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &protocols);
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &protocols);
+        let client = initiator.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+        let server = responder.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+        (initiator, client, responder, server)
     }
 
-    match response_received[4..6].try_into() {
-        Ok(bytes) => {
-            let val = u16::from_be_bytes(bytes);
-            info!("protocol_id: {}", val);
+    #[tokio::test]
+    async fn propose_and_accept() {
+        let (_initiator, mut client, _responder, mut server) = channels();
+        let server =
+            tokio::spawn(async move { accept(&mut server, &vec![10, 13, 14], 2, false).await });
+        let response = propose(&mut client, &vec![7, 13], 2, false, false)
+            .await
+            .unwrap();
+        assert_eq!(response.accepted_version(), Some(13));
+        let (version, data) = server.await.unwrap().unwrap();
+        assert_eq!(version, 13);
+        assert_eq!(response.accepted_version_data(), Some(&data[..]));
+        assert!(NodeToNodeVersionData::is_duplex(&data));
+
+        // An initiator-only peer makes the connection unidirectional
+        let (_initiator, client, _responder, mut server) = channels();
+        let server = tokio::spawn(async move { accept(&mut server, &vec![13], 2, false).await });
+        let proposal = Message::ProposeVersions(vec![
+            ProposeVersion::Index(0),
+            ProposeVersion::VersionTable(vec![(
+                13,
+                vec![
+                    NodeToNodeVersionData::NetworkMagic(2),
+                    NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(true),
+                    NodeToNodeVersionData::PeerSharing(0),
+                    NodeToNodeVersionData::Query(false),
+                ],
+            )]),
+        ]);
+        client.send_value(&proposal.to_value().unwrap()).unwrap();
+        let (_, data) = server.await.unwrap().unwrap();
+        assert!(
+            data.contains(&NodeToNodeVersionData::InitiatorAndResponderDiffusionMode(
+                true
+            ))
+        );
+        assert!(!NodeToNodeVersionData::is_duplex(&data));
+
+        let (_initiator, mut client, _responder, mut server) = channels();
+        let server =
+            tokio::spawn(async move { accept(&mut server, &vec![13, 14], 2, false).await });
+        let response = propose(&mut client, &vec![13, 14], 1, false, false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            Message::Refuse(RefuseReason::Refused(14, _))
+        ));
+        assert!(server.await.unwrap().is_err());
+
+        let (_initiator, mut client, _responder, mut server) = channels();
+        let server =
+            tokio::spawn(async move { accept(&mut server, &vec![13, 14], 2, false).await });
+        let response = propose(&mut client, &vec![13], 2, false, true)
+            .await
+            .unwrap();
+        match response {
+            Message::QueryReply(version_table) => {
+                let versions: Vec<i128> = version_table.iter().map(|(v, _)| *v).collect();
+                assert_eq!(versions, vec![13, 14]);
+            }
+            message => std::panic!("Unexpected {:?}", message),
         }
-        Err(error) => error!("Error reading protocol_id for {}: {}", network_id, error),
+        assert!(server.await.unwrap().is_err());
     }
 
-    match response_received[6..8].try_into() {
-        Ok(bytes) => {
-            let val = u16::from_be_bytes(bytes);
-            info!("message_len: {}", val);
+    #[tokio::test]
+    async fn peer_sharing_needs_both_sides() {
+        for (ours, theirs) in [(true, true), (true, false), (false, true)] {
+            let (_initiator, mut client, _responder, mut server) = channels();
+            let server =
+                tokio::spawn(async move { accept(&mut server, &vec![13], 2, theirs).await });
+            let response = propose(&mut client, &vec![13], 2, ours, false)
+                .await
+                .unwrap();
+            let (_, data) = server.await.unwrap().unwrap();
+            assert_eq!(response.accepted_version_data(), Some(&data[..]));
+            assert_eq!(
+                NodeToNodeVersionData::is_peer_sharing(&data),
+                ours && theirs
+            );
         }
-        Err(error) => error!("Error reading message_len for {}: {}", network_id, error),
-    };
-
-    let response_message: Value = from_reader(&response_received[8..]).unwrap();
-    debug!("response_message {}: {:?}", network_id, response_message);
-    match Message::from_value(response_message) {
-        Ok(response_message) => info!("response_message {}: {:?}", network_id, response_message),
-        Err(error) => error!("Error message: {}", error),
-    };
+    }
 
-    info!("Reading Complete: {}", network_id);
+    #[tokio::test]
+    async fn negotiate_in_memory() {
+        let (client, server) = duplex();
+        let mut responder =
+            Mux::with_bearer(server, Mode::Responder, &[MINI_PROTOCOL_ID_HANDSHAKE]);
+        let mut server = responder.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+        let server =
+            tokio::spawn(async move { accept(&mut server, &vec![13, 14], 2, false).await });
+        let node_config = NodeConfig::with_bearer("memory", 2, "Memory", client);
+        let (response, _, _) = negotiate(node_config, &vec![14]).await.unwrap();
+        assert_eq!(response.accepted_version(), Some(14));
+        assert_eq!(server.await.unwrap().map(|(version, _)| version), Ok(14));
+    }
 }
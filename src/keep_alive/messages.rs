@@ -0,0 +1,70 @@
+use ciborium::Value;
+
+// Keep-alive mini-protocol: the client sends a cookie and the server
+// echoes it back, which tells both the connection is alive and gives the
+// client the round trip time.
+
+pub const MINI_PROTOCOL_ID_KEEP_ALIVE: u16 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // MsgKeepAlive
+    KeepAlive(u16),
+    // MsgKeepAliveResponse
+    KeepAliveResponse(u16),
+    // MsgDone
+    Done,
+}
+
+impl Message {
+    pub fn to_value(&self) -> Value {
+        match self {
+            Message::KeepAlive(cookie) => Value::Array(vec![Value::from(0), Value::from(*cookie)]),
+            Message::KeepAliveResponse(cookie) => {
+                Value::Array(vec![Value::from(1), Value::from(*cookie)])
+            }
+            Message::Done => Value::Array(vec![Value::from(2)]),
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Result<Message, String> {
+        let array = value
+            .as_array()
+            .ok_or("Could not convert Message into array")?;
+        let index = array
+            .first()
+            .and_then(|index| index.as_integer())
+            .ok_or("No index found at message index 0")?;
+        let cookie = || -> Result<u16, String> {
+            let cookie = array
+                .get(1)
+                .and_then(|cookie| cookie.as_integer())
+                .ok_or("No cookie found at message index 1")?;
+            u16::try_from(cookie).map_err(|_| format!("Cookie {:?} out of range", cookie))
+        };
+        match i128::from(index) {
+            0 => Ok(Message::KeepAlive(cookie()?)),
+            1 => Ok(Message::KeepAliveResponse(cookie()?)),
+            2 => Ok(Message::Done),
+            index => Err(format!("Message: Do not expect any other index {}!", index)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::KeepAlive(0),
+            Message::KeepAliveResponse(u16::MAX),
+            Message::Done,
+        ] {
+            assert_eq!(Message::from_value(&message.to_value()).unwrap(), message);
+        }
+        let cookie = Value::Array(vec![Value::from(0), Value::from(65536)]);
+        assert!(Message::from_value(&cookie).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+mod messages;
+mod workflows;
+
+pub use self::messages::{Message, MINI_PROTOCOL_ID_KEEP_ALIVE};
+pub use self::workflows::{done, keep_alive, serve};
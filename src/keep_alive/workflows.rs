@@ -0,0 +1,73 @@
+use super::Message;
+use crate::mux::Channel;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+async fn recv(channel: &mut Channel) -> Result<Message, String> {
+    let message = Message::from_value(&channel.recv_value().await?)?;
+    debug!("Keep-alive received {:?}", message);
+    Ok(message)
+}
+
+/// Sends `cookie` and waits for the server to echo it, returning the round
+/// trip time.
+pub async fn keep_alive(channel: &mut Channel, cookie: u16) -> Result<Duration, String> {
+    let start = Instant::now();
+    channel.send_value(&Message::KeepAlive(cookie).to_value())?;
+    match recv(channel).await? {
+        Message::KeepAliveResponse(echoed) if echoed == cookie => Ok(start.elapsed()),
+        Message::KeepAliveResponse(echoed) => Err(format!(
+            "Keep-alive cookie {} answered with {}",
+            cookie, echoed
+        )),
+        message => Err(format!("Expected keep-alive response, got {:?}", message)),
+    }
+}
+
+pub fn done(channel: &Channel) -> Result<(), String> {
+    channel.send_value(&Message::Done.to_value())
+}
+
+/// Answers keep-alive messages until the client is done or the connection
+/// closes.
+pub async fn serve(channel: &mut Channel) -> Result<(), String> {
+    loop {
+        let message = match channel.recv_value().await {
+            Ok(value) => Message::from_value(&value)?,
+            // Clients do not always say they are done
+            Err(_) => return Ok(()),
+        };
+        match message {
+            Message::KeepAlive(cookie) => {
+                channel.send_value(&Message::KeepAliveResponse(cookie).to_value())?
+            }
+            Message::Done => return Ok(()),
+            message => return Err(format!("Keep-alive server: Unexpected {:?}", message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bearer;
+    use crate::keep_alive::MINI_PROTOCOL_ID_KEEP_ALIVE;
+    use crate::mux::{Mode, Mux};
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (client, server) = bearer::duplex();
+        let protocols = [MINI_PROTOCOL_ID_KEEP_ALIVE];
+        let mut server = Mux::with_bearer(server, Mode::Responder, &protocols);
+        let mut channel = server.channel(MINI_PROTOCOL_ID_KEEP_ALIVE).unwrap();
+        let served = tokio::spawn(async move { serve(&mut channel).await });
+
+        let mut client = Mux::with_bearer(client, Mode::Initiator, &protocols);
+        let mut channel = client.channel(MINI_PROTOCOL_ID_KEEP_ALIVE).unwrap();
+        for cookie in [0, 7, u16::MAX] {
+            keep_alive(&mut channel, cookie).await.unwrap();
+        }
+        done(&channel).unwrap();
+        assert_eq!(served.await.unwrap(), Ok(()));
+    }
+}
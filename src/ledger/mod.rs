@@ -0,0 +1,10 @@
+mod pools;
+mod transaction;
+mod witness;
+
+pub use self::pools::{
+    pick_peers, pool_certificates, stake_snapshot_from_json, LedgerPeer, LedgerPeerSnapshot,
+    LedgerPeers, LedgerPool, PoolCertificate, PoolRegistration, Relay,
+};
+pub use self::transaction::{BootstrapWitness, Transaction, VKeyWitness, WitnessSet};
+pub use self::witness::{verify_transaction, verify_witnesses, VerificationError, WitnessKind};
@@ -0,0 +1,863 @@
+use super::transaction::set_items;
+use crate::codec::split_array;
+use crate::consensus::Era;
+use crate::crypto::Hash28;
+use crate::storage::split_era;
+use ciborium::{from_reader, Value};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+// Stake pools announce their relays with certificates in transaction bodies:
+//
+// pool_registration = [3, operator, vrf_keyhash, pledge, cost, margin,
+//                      reward_account, pool_owners, relays, pool_metadata / null]
+// pool_retirement = [4, pool_keyhash, epoch]
+// relay = [0, port / null, ipv4 / null, ipv6 / null]
+//       / [1, port / null, dns_name]
+//       / [2, dns_name]
+// pool_metadata = [url, metadata_hash]
+
+const TRANSACTION_BODY_CERTIFICATES: i128 = 4;
+const POOL_REGISTRATION: i128 = 3;
+const POOL_RETIREMENT: i128 = 4;
+
+/// Where a stake pool can be reached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Relay {
+    SingleHostAddr {
+        port: Option<u16>,
+        ipv4: Option<Ipv4Addr>,
+        ipv6: Option<Ipv6Addr>,
+    },
+    // Resolved with A and AAAA records
+    SingleHostName {
+        port: Option<u16>,
+        dns_name: String,
+    },
+    // Resolved with SRV records
+    MultiHostName {
+        dns_name: String,
+    },
+}
+
+impl Relay {
+    pub fn from_value(value: Value) -> Result<Relay, String> {
+        let items = value
+            .into_array()
+            .map_err(|error| format!("Could not convert relay into array: {:?}", error))?;
+        let kind = items.first().and_then(Value::as_integer).map(i128::from);
+        match (kind, &items[..]) {
+            (Some(0), [_, port, ipv4, ipv6]) => Ok(Relay::SingleHostAddr {
+                port: relay_port(port)?,
+                ipv4: ipv4_address(ipv4)?,
+                ipv6: ipv6_address(ipv6)?,
+            }),
+            (Some(1), [_, port, dns_name]) => Ok(Relay::SingleHostName {
+                port: relay_port(port)?,
+                dns_name: text(dns_name, "dns_name")?,
+            }),
+            (Some(2), [_, dns_name]) => Ok(Relay::MultiHostName {
+                dns_name: text(dns_name, "dns_name")?,
+            }),
+            _ => Err(format!("Unexpected relay {:?}", items)),
+        }
+    }
+
+    /// Host to connect to, preferring IPv4. `None` without a port and for
+    /// SRV records, which are not resolved.
+    pub fn host(&self) -> Option<String> {
+        match self {
+            Relay::SingleHostAddr {
+                port: Some(port),
+                ipv4,
+                ipv6,
+            } => {
+                let address = ipv4.map(IpAddr::V4).or(ipv6.map(IpAddr::V6))?;
+                Some(SocketAddr::new(address, *port).to_string())
+            }
+            Relay::SingleHostName {
+                port: Some(port),
+                dns_name,
+            } => Some(format!("{}:{}", dns_name, port)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Relay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let port = |port: &Option<u16>| port.map_or("?".to_owned(), |port| port.to_string());
+        match self {
+            Relay::SingleHostAddr {
+                port: p,
+                ipv4,
+                ipv6,
+            } => {
+                let addresses: Vec<String> = ipv4
+                    .map(|ipv4| format!("{}:{}", ipv4, port(p)))
+                    .into_iter()
+                    .chain(ipv6.map(|ipv6| format!("[{}]:{}", ipv6, port(p))))
+                    .collect();
+                match addresses.is_empty() {
+                    true => write!(f, "no address"),
+                    false => write!(f, "{}", addresses.join(" ")),
+                }
+            }
+            Relay::SingleHostName { port: p, dns_name } => write!(f, "{}:{}", dns_name, port(p)),
+            Relay::MultiHostName { dns_name } => write!(f, "SRV {}", dns_name),
+        }
+    }
+}
+
+fn relay_port(value: &Value) -> Result<Option<u16>, String> {
+    match value {
+        Value::Null => Ok(None),
+        value => value
+            .as_integer()
+            .and_then(|port| u16::try_from(port).ok())
+            .map(Some)
+            .ok_or(format!("Invalid relay port {:?}", value)),
+    }
+}
+
+fn ipv4_address(value: &Value) -> Result<Option<Ipv4Addr>, String> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bytes(bytes) => <[u8; 4]>::try_from(&bytes[..])
+            .map(|octets| Some(Ipv4Addr::from(octets)))
+            .map_err(|_| format!("IPv4 address of {} bytes", bytes.len())),
+        value => Err(format!("Invalid IPv4 address {:?}", value)),
+    }
+}
+
+// The ledger writes the four 32 bit words of an IPv6 address little endian
+fn ipv6_address(value: &Value) -> Result<Option<Ipv6Addr>, String> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bytes(bytes) if bytes.len() == 16 => {
+            let mut octets = [0; 16];
+            for (word, bytes) in octets.chunks_mut(4).zip(bytes.chunks(4)) {
+                word.copy_from_slice(bytes);
+                word.reverse();
+            }
+            Ok(Some(Ipv6Addr::from(octets)))
+        }
+        value => Err(format!("Invalid IPv6 address {:?}", value)),
+    }
+}
+
+fn text(value: &Value, name: &str) -> Result<String, String> {
+    value
+        .as_text()
+        .map(str::to_owned)
+        .ok_or(format!("Could not convert {} into text", name))
+}
+
+fn unsigned(value: &Value, name: &str) -> Result<u64, String> {
+    value
+        .as_integer()
+        .and_then(|integer| u64::try_from(integer).ok())
+        .ok_or(format!("Could not convert {} into unsigned integer", name))
+}
+
+fn pool_keyhash(value: &Value) -> Result<Hash28, String> {
+    value
+        .as_bytes()
+        .and_then(|bytes| Hash28::try_from(&bytes[..]).ok())
+        .ok_or("Pool key hash is not 28 bytes".to_owned())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolRegistration {
+    pub operator: Hash28,
+    pub pledge: u64,
+    pub cost: u64,
+    pub relays: Vec<Relay>,
+    pub metadata_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolCertificate {
+    // Registers a pool or updates its parameters
+    Registration(PoolRegistration),
+    // The pool retires at the start of the epoch
+    Retirement(Hash28, u64),
+}
+
+impl PoolCertificate {
+    /// Decodes a certificate, `None` for those not about stake pools.
+    pub fn from_value(value: Value) -> Result<Option<PoolCertificate>, String> {
+        let items = value
+            .into_array()
+            .map_err(|error| format!("Could not convert certificate into array: {:?}", error))?;
+        let kind = items.first().and_then(Value::as_integer).map(i128::from);
+        match (kind, items) {
+            (Some(POOL_REGISTRATION), items) => {
+                let [_, operator, _, pledge, cost, _, _, _, relays, metadata] =
+                    <[Value; 10]>::try_from(items).map_err(|items| {
+                        format!(
+                            "Pool registration: Expected 10 fields, found {}",
+                            items.len()
+                        )
+                    })?;
+                let relays = relays
+                    .into_array()
+                    .map_err(|error| format!("Could not convert relays into array: {:?}", error))?
+                    .into_iter()
+                    .map(Relay::from_value)
+                    .collect::<Result<_, _>>()?;
+                let metadata_url = match metadata {
+                    Value::Null => None,
+                    metadata => Some(text(
+                        metadata
+                            .as_array()
+                            .and_then(|metadata| metadata.first())
+                            .ok_or("Pool metadata without url")?,
+                        "metadata url",
+                    )?),
+                };
+                Ok(Some(PoolCertificate::Registration(PoolRegistration {
+                    operator: pool_keyhash(&operator)?,
+                    pledge: unsigned(&pledge, "pledge")?,
+                    cost: unsigned(&cost, "cost")?,
+                    relays,
+                    metadata_url,
+                })))
+            }
+            (Some(POOL_RETIREMENT), items) => match &items[..] {
+                [_, pool, epoch] => Ok(Some(PoolCertificate::Retirement(
+                    pool_keyhash(pool)?,
+                    unsigned(epoch, "epoch")?,
+                ))),
+                _ => Err(format!(
+                    "Pool retirement: Expected 3 fields, found {}",
+                    items.len()
+                )),
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Pool certificates of the valid transactions of a block as stored by
+/// cardano-node, tagged with its era. Byron blocks carry none.
+pub fn pool_certificates(block: &[u8]) -> Result<Vec<PoolCertificate>, String> {
+    let (era, _, inner) = split_era(block)?;
+    if era == Era::Byron {
+        return Ok(vec![]);
+    }
+    let items = split_array(inner)?;
+    if !(4..=5).contains(&items.len()) {
+        return Err(format!(
+            "Block: Do not expect array of {} items!",
+            items.len()
+        ));
+    }
+    let bodies: Value = from_reader(items[1])
+        .map_err(|error| format!("Could not decode transaction bodies: {:?}", error))?;
+    // From Alonzo on, certificates of transactions that failed script
+    // validation take no effect
+    let invalid: Vec<u64> = match items.get(4) {
+        Some(invalid) => from_reader::<Value, _>(*invalid)
+            .map_err(|error| format!("Could not decode invalid transactions: {:?}", error))?
+            .into_array()
+            .map_err(|error| format!("Could not convert invalid transactions: {:?}", error))?
+            .iter()
+            .map(|index| unsigned(index, "invalid transaction"))
+            .collect::<Result<_, _>>()?,
+        None => vec![],
+    };
+
+    let bodies = bodies
+        .into_array()
+        .map_err(|error| format!("Could not convert transaction bodies: {:?}", error))?;
+    let mut certificates = vec![];
+    for (index, body) in bodies.into_iter().enumerate() {
+        if invalid.contains(&(index as u64)) {
+            continue;
+        }
+        let body = body
+            .into_map()
+            .map_err(|error| format!("Could not convert transaction body: {:?}", error))?;
+        for (key, value) in body {
+            if key.as_integer().map(i128::from) == Some(TRANSACTION_BODY_CERTIFICATES) {
+                for certificate in set_items(value)? {
+                    certificates.extend(PoolCertificate::from_value(certificate)?);
+                }
+            }
+        }
+    }
+    Ok(certificates)
+}
+
+/// The relays of a stake pool and its share of the total stake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerPool {
+    // Unknown for the pools of a ledger peer snapshot
+    pub pool: Option<Hash28>,
+    pub relative_stake: f64,
+    pub relays: Vec<Relay>,
+}
+
+/// A relay picked to discover peers from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerPeer {
+    pub pool: Option<Hash28>,
+    pub relay: Relay,
+    pub relative_stake: f64,
+}
+
+// SplitMix64, enough to spread draws and repeatable from a seed
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Draws `count` times a pool with a probability proportional to its stake,
+/// then one of its relays uniformly, like `pickPeers` of cardano-node. Draws
+/// are independent, so peers are spread over pools instead of every node
+/// picking the largest ones. Without any known stake, pools are equally
+/// likely. Relays picked twice are returned once. `seed` is advanced so the
+/// next call draws other peers.
+pub fn pick_peers(pools: &[LedgerPool], count: usize, seed: &mut u64) -> Vec<LedgerPeer> {
+    let pools: Vec<&LedgerPool> = pools
+        .iter()
+        .filter(|pool| !pool.relays.is_empty())
+        .collect();
+    let total: f64 = pools.iter().map(|pool| pool.relative_stake).sum();
+    let weight = |pool: &LedgerPool| match total > 0.0 {
+        true => pool.relative_stake / total,
+        false => 1.0 / pools.len() as f64,
+    };
+    let mut accumulated = 0.0;
+    let accumulated: Vec<f64> = pools
+        .iter()
+        .map(|pool| {
+            accumulated += weight(pool);
+            accumulated
+        })
+        .collect();
+
+    let mut picked: Vec<LedgerPeer> = vec![];
+    for _ in 0..count {
+        let (draw, index) = (next_random(seed), next_random(seed));
+        let x = draw as f64 / u64::MAX as f64;
+        // The last pool also takes what rounding left above its sum
+        let position = accumulated.partition_point(|stake| *stake < x);
+        let Some(pool) = pools.get(position.min(pools.len().saturating_sub(1))) else {
+            break;
+        };
+        let relay = &pool.relays[index as usize % pool.relays.len()];
+        if picked.iter().all(|peer| peer.relay != *relay) {
+            picked.push(LedgerPeer {
+                pool: pool.pool,
+                relay: relay.clone(),
+                relative_stake: pool.relative_stake,
+            });
+        }
+    }
+    picked
+}
+
+/// Stake of every pool in the `set` snapshot of `cardano-cli query
+/// stake-snapshot --all-stake-pools`, the one leaders are elected with in
+/// the current epoch.
+pub fn stake_snapshot_from_json(json: &str) -> Result<HashMap<Hash28, u64>, String> {
+    #[derive(Deserialize)]
+    struct Snapshot {
+        pools: HashMap<String, PoolStake>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PoolStake {
+        stake_set: u64,
+    }
+    let snapshot: Snapshot = serde_json::from_str(json)
+        .map_err(|error| format!("Could not parse stake snapshot: {}", error))?;
+    snapshot
+        .pools
+        .into_iter()
+        .map(|(pool, stake)| Ok((decode_pool_id(&pool)?, stake.stake_set)))
+        .collect()
+}
+
+fn decode_pool_id(hex: &str) -> Result<Hash28, String> {
+    let mut pool = [0; 28];
+    if hex.len() != 56 || !hex.is_ascii() {
+        return Err(format!("Expected 28 bytes hex pool id, got {:?}", hex));
+    }
+    for (i, byte) in pool.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| format!("Invalid hex in pool id {:?}", hex))?;
+    }
+    Ok(pool)
+}
+
+/// The registered stake pools, kept up to date by applying blocks in chain
+/// order, and the stake delegated to them.
+#[derive(Debug, Default)]
+pub struct LedgerPeers {
+    pools: BTreeMap<Hash28, PoolRegistration>,
+    // Epoch at whose start a pool retires
+    retiring: HashMap<Hash28, u64>,
+    stake: HashMap<Hash28, u64>,
+}
+
+impl LedgerPeers {
+    pub fn new() -> LedgerPeers {
+        LedgerPeers::default()
+    }
+
+    pub fn apply_block(&mut self, block: &[u8]) -> Result<(), String> {
+        for certificate in pool_certificates(block)? {
+            self.apply(certificate);
+        }
+        Ok(())
+    }
+
+    /// A registration replaces the parameters of a pool and cancels its
+    /// retirement, retirements of unknown pools are ignored.
+    pub fn apply(&mut self, certificate: PoolCertificate) {
+        match certificate {
+            PoolCertificate::Registration(pool) => {
+                self.retiring.remove(&pool.operator);
+                self.pools.insert(pool.operator, pool);
+            }
+            PoolCertificate::Retirement(pool, epoch) => {
+                if self.pools.contains_key(&pool) {
+                    self.retiring.insert(pool, epoch);
+                }
+            }
+        }
+    }
+
+    /// Removes the pools retiring at or before `epoch`.
+    pub fn retire(&mut self, epoch: u64) {
+        let retired: Vec<Hash28> = self
+            .retiring
+            .iter()
+            .filter(|(_, retiring)| **retiring <= epoch)
+            .map(|(pool, _)| *pool)
+            .collect();
+        for pool in retired {
+            self.retiring.remove(&pool);
+            self.pools.remove(&pool);
+        }
+    }
+
+    /// Sets the stake delegated to each pool, as of the stake distribution
+    /// of the current epoch.
+    pub fn set_stake(&mut self, stake: HashMap<Hash28, u64>) {
+        self.stake = stake;
+    }
+
+    pub fn pools(&self) -> impl Iterator<Item = &PoolRegistration> {
+        self.pools.values()
+    }
+
+    pub fn retiring(&self, pool: &Hash28) -> Option<u64> {
+        self.retiring.get(pool).copied()
+    }
+
+    /// Relative stake of a registered pool, 0 without a stake distribution.
+    pub fn relative_stake(&self, pool: &Hash28) -> f64 {
+        let total: u64 = self
+            .pools
+            .keys()
+            .filter_map(|pool| self.stake.get(pool))
+            .sum();
+        match total {
+            0 => 0.0,
+            total => self.stake.get(pool).copied().unwrap_or(0) as f64 / total as f64,
+        }
+    }
+
+    /// The registered pools with their relays and stake, to pick peers from.
+    pub fn stake_pools(&self) -> Vec<LedgerPool> {
+        self.pools
+            .values()
+            .map(|pool| LedgerPool {
+                pool: Some(pool.operator),
+                relative_stake: self.relative_stake(&pool.operator),
+                relays: pool.relays.clone(),
+            })
+            .collect()
+    }
+}
+
+/// The big ledger pools of `cardano-cli query ledger-peer-snapshot`, which
+/// cardano-node reads from the `peerSnapshotFile` of its topology.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerPeerSnapshot {
+    // Slot of the ledger state, `None` at origin
+    pub slot: Option<u64>,
+    pub pools: Vec<LedgerPool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotJson {
+    slot_no: serde_json::Value,
+    big_ledger_pools: Vec<SnapshotPool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SnapshotPool {
+    relative_stake: f64,
+    relays: Vec<SnapshotRelay>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotRelay {
+    address: Option<IpAddr>,
+    domain: Option<String>,
+    srv: Option<String>,
+    port: Option<u16>,
+}
+
+impl SnapshotRelay {
+    fn relay(self) -> Result<Relay, String> {
+        match (self.address, self.domain, self.srv) {
+            (Some(IpAddr::V4(ipv4)), None, None) => Ok(Relay::SingleHostAddr {
+                port: self.port,
+                ipv4: Some(ipv4),
+                ipv6: None,
+            }),
+            (Some(IpAddr::V6(ipv6)), None, None) => Ok(Relay::SingleHostAddr {
+                port: self.port,
+                ipv4: None,
+                ipv6: Some(ipv6),
+            }),
+            (None, Some(dns_name), None) => Ok(Relay::SingleHostName {
+                port: self.port,
+                dns_name,
+            }),
+            (None, None, Some(dns_name)) => Ok(Relay::MultiHostName { dns_name }),
+            _ => Err("Relay: Expect one of address, domain or srv".to_owned()),
+        }
+    }
+}
+
+impl LedgerPeerSnapshot {
+    pub fn from_json(json: &str) -> Result<LedgerPeerSnapshot, String> {
+        let snapshot: SnapshotJson = serde_json::from_str(json)
+            .map_err(|error| format!("Could not parse ledger peer snapshot: {}", error))?;
+        let slot = match &snapshot.slot_no {
+            serde_json::Value::String(origin) if origin == "origin" => None,
+            slot => Some(
+                slot.as_u64()
+                    .ok_or(format!("Invalid slotNo {} of ledger peer snapshot", slot))?,
+            ),
+        };
+        let pools = snapshot
+            .big_ledger_pools
+            .into_iter()
+            .map(|pool| {
+                Ok(LedgerPool {
+                    pool: None,
+                    relative_stake: pool.relative_stake,
+                    relays: pool
+                        .relays
+                        .into_iter()
+                        .map(|relay| relay.relay())
+                        .collect::<Result<_, String>>()?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(LedgerPeerSnapshot { slot, pools })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<LedgerPeerSnapshot, String> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {:?}: {}", path, error))?;
+        LedgerPeerSnapshot::from_json(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_blocks::conway_block;
+
+    fn relay(kind: u64, fields: Vec<Value>) -> Value {
+        Value::Array([vec![Value::from(kind)], fields].concat())
+    }
+
+    fn registration(operator: u8, relays: Vec<Value>) -> Value {
+        Value::Array(vec![
+            Value::from(3),
+            Value::Bytes(vec![operator; 28]),
+            Value::Bytes(vec![0; 32]),
+            Value::from(1000),
+            Value::from(340),
+            Value::Tag(
+                30,
+                Box::new(Value::Array(vec![Value::from(1), Value::from(100)])),
+            ),
+            Value::Bytes(vec![0xe1; 29]),
+            Value::Tag(258, Box::new(Value::Array(vec![]))),
+            Value::Array(relays),
+            Value::Null,
+        ])
+    }
+
+    fn retirement(operator: u8, epoch: u64) -> Value {
+        Value::Array(vec![
+            Value::from(4),
+            Value::Bytes(vec![operator; 28]),
+            Value::from(epoch),
+        ])
+    }
+
+    // A Conway block whose transactions carry `certificates`, the ones
+    // listed in `invalid` failed script validation
+    fn block(certificates: Vec<Vec<Value>>, invalid: Vec<u64>) -> Vec<u8> {
+        let bodies: Vec<Value> = certificates
+            .into_iter()
+            .map(|certificates| {
+                Value::Map(vec![(
+                    Value::from(4),
+                    Value::Tag(258, Box::new(Value::Array(certificates))),
+                )])
+            })
+            .collect();
+        conway_block(1, 1, None, 0, bodies, invalid)
+    }
+
+    fn single_host_name(dns_name: &str) -> Value {
+        relay(1, vec![Value::from(3001), Value::Text(dns_name.to_owned())])
+    }
+
+    #[test]
+    fn decodes_relays() {
+        let ipv6 = Value::Bytes(vec![
+            0xb8, 0x0d, 0x01, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0, 0, 0,
+        ]);
+        let relays = [
+            relay(
+                0,
+                vec![
+                    Value::from(3001),
+                    Value::Bytes(vec![10, 0, 0, 1]),
+                    ipv6.clone(),
+                ],
+            ),
+            relay(0, vec![Value::from(6000), Value::Null, ipv6]),
+            relay(
+                0,
+                vec![Value::Null, Value::Bytes(vec![10, 0, 0, 1]), Value::Null],
+            ),
+            single_host_name("relay.example.com"),
+            relay(2, vec![Value::Text("_cardano._tcp.example.com".to_owned())]),
+        ];
+        let relays: Vec<Relay> = relays
+            .into_iter()
+            .map(|relay| Relay::from_value(relay).unwrap())
+            .collect();
+        let hosts: Vec<Option<String>> = relays.iter().map(Relay::host).collect();
+        assert_eq!(
+            hosts,
+            vec![
+                Some("10.0.0.1:3001".to_owned()),
+                Some("[2001:db8::1]:6000".to_owned()),
+                None,
+                Some("relay.example.com:3001".to_owned()),
+                None,
+            ]
+        );
+        assert_eq!(relays[2].to_string(), "10.0.0.1:?");
+        assert_eq!(relays[4].to_string(), "SRV _cardano._tcp.example.com");
+
+        assert!(Relay::from_value(relay(0, vec![Value::Null, Value::Null])).is_err());
+        let short = relay(
+            0,
+            vec![Value::Null, Value::Bytes(vec![10, 0, 1]), Value::Null],
+        );
+        assert!(Relay::from_value(short).is_err());
+    }
+
+    #[test]
+    fn follows_registrations_and_retirements() {
+        let mut ledger = LedgerPeers::new();
+        let first = block(
+            vec![
+                vec![
+                    registration(1, vec![single_host_name("a1"), single_host_name("shared")]),
+                    registration(2, vec![single_host_name("b1"), single_host_name("shared")]),
+                ],
+                vec![registration(3, vec![single_host_name("c1")])],
+                // Neither a pool certificate nor valid
+                vec![Value::Array(vec![Value::from(0), Value::Array(vec![])])],
+            ],
+            vec![1],
+        );
+        ledger.apply_block(&first).unwrap();
+        assert_eq!(ledger.pools().count(), 2);
+        ledger.set_stake(HashMap::from([
+            ([1; 28], 100),
+            ([2; 28], 300),
+            ([3; 28], 600),
+        ]));
+
+        let hosts = |ledger: &LedgerPeers| -> Vec<(Vec<String>, f64)> {
+            ledger
+                .stake_pools()
+                .into_iter()
+                .map(|pool| {
+                    let hosts = pool.relays.iter().map(|relay| relay.host().unwrap());
+                    (hosts.collect(), pool.relative_stake)
+                })
+                .collect()
+        };
+        let with_stake = |pools: &[(&[&str], f64)]| -> Vec<(Vec<String>, f64)> {
+            pools
+                .iter()
+                .map(|(hosts, stake)| {
+                    let hosts = hosts.iter().map(|host| format!("{}:3001", host));
+                    (hosts.collect(), *stake)
+                })
+                .collect()
+        };
+        assert_eq!(
+            hosts(&ledger),
+            with_stake(&[(&["a1", "shared"], 0.25), (&["b1", "shared"], 0.75)])
+        );
+
+        ledger
+            .apply_block(&block(
+                vec![vec![retirement(2, 10), retirement(3, 10)]],
+                vec![],
+            ))
+            .unwrap();
+        assert_eq!(ledger.retiring(&[2; 28]), Some(10));
+        assert_eq!(ledger.retiring(&[3; 28]), None);
+        ledger.retire(9);
+        assert_eq!(ledger.pools().count(), 2);
+        ledger.retire(10);
+        assert_eq!(hosts(&ledger), with_stake(&[(&["a1", "shared"], 1.0)]));
+
+        // Registering again cancels the retirement
+        ledger
+            .apply_block(&block(vec![vec![retirement(1, 11)]], vec![]))
+            .unwrap();
+        let update = registration(1, vec![single_host_name("a2")]);
+        ledger
+            .apply_block(&block(vec![vec![update]], vec![]))
+            .unwrap();
+        ledger.retire(11);
+        assert_eq!(hosts(&ledger), with_stake(&[(&["a2"], 1.0)]));
+
+        let byron = [0x82, 0x01, 0x80];
+        assert_eq!(pool_certificates(&byron).unwrap(), vec![]);
+    }
+
+    fn pool(relative_stake: f64, hosts: &[&str]) -> LedgerPool {
+        let relays = hosts.iter().map(|host| Relay::SingleHostName {
+            port: Some(3001),
+            dns_name: host.to_string(),
+        });
+        LedgerPool {
+            pool: None,
+            relative_stake,
+            relays: relays.collect(),
+        }
+    }
+
+    #[test]
+    fn picks_peers_by_stake() {
+        let pools = [
+            pool(0.1, &["small"]),
+            pool(0.0, &[]),
+            pool(0.3, &["big1", "big2"]),
+        ];
+        let mut seed = 1;
+        let mut draws: HashMap<String, usize> = HashMap::new();
+        for _ in 0..4000 {
+            let peers = pick_peers(&pools, 1, &mut seed);
+            assert_eq!(peers.len(), 1);
+            *draws.entry(peers[0].relay.host().unwrap()).or_default() += 1;
+        }
+        // A quarter of the stake, split over two relays for the big pool
+        assert!((800..1200).contains(&draws["small:3001"]), "{:?}", draws);
+        assert!((1300..1700).contains(&draws["big1:3001"]), "{:?}", draws);
+        assert!((1300..1700).contains(&draws["big2:3001"]), "{:?}", draws);
+
+        // Repeated picks are returned once, without stake pools are equal
+        let peers = pick_peers(&pools, 100, &mut seed);
+        assert_eq!(peers.len(), 3);
+        let pools = [pool(0.0, &["a"]), pool(0.0, &["b"])];
+        assert_eq!(pick_peers(&pools, 100, &mut seed).len(), 2);
+        assert_eq!(pick_peers(&[], 10, &mut seed), vec![]);
+        let (mut a, mut b) = (5, 5);
+        assert_eq!(pick_peers(&pools, 1, &mut a), pick_peers(&pools, 1, &mut b));
+    }
+
+    #[test]
+    fn reads_stake_snapshot() {
+        let json = format!(
+            r#"{{"pools": {{"{}": {{"stakeGo": 1, "stakeMark": 2, "stakeSet": 3}}}},
+                "total": {{"stakeGo": 1, "stakeMark": 2, "stakeSet": 3}}}}"#,
+            "01".repeat(28)
+        );
+        let stake = stake_snapshot_from_json(&json).unwrap();
+        assert_eq!(stake, HashMap::from([([1; 28], 3)]));
+        assert!(stake_snapshot_from_json(r#"{"pools": {"01": {"stakeSet": 3}}}"#).is_err());
+    }
+
+    #[test]
+    fn reads_snapshot() {
+        let snapshot = LedgerPeerSnapshot::from_json(
+            r#"{
+                "bigLedgerPools": [
+                    { "accumulatedStake": 0.1, "relativeStake": 0.1,
+                      "relays": [ { "address": "10.0.0.1", "port": 3001 },
+                                  { "domain": "relay.example.com", "port": 3001 } ] },
+                    { "accumulatedStake": 0.3, "relativeStake": 0.2,
+                      "relays": [ { "address": "2001:db8::1", "port": 3001 },
+                                  { "srv": "_cardano._tcp.example.com" } ] }
+                ],
+                "slotNo": 75000000,
+                "version": 2
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(snapshot.slot, Some(75000000));
+        let hosts: Vec<(f64, Vec<Option<String>>)> = snapshot
+            .pools
+            .iter()
+            .map(|pool| {
+                (
+                    pool.relative_stake,
+                    pool.relays.iter().map(Relay::host).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            hosts,
+            vec![
+                (
+                    0.1,
+                    vec![
+                        Some("10.0.0.1:3001".to_owned()),
+                        Some("relay.example.com:3001".to_owned()),
+                    ]
+                ),
+                (0.2, vec![Some("[2001:db8::1]:3001".to_owned()), None]),
+            ]
+        );
+
+        let origin = r#"{"bigLedgerPools": [], "slotNo": "origin", "version": 2}"#;
+        assert_eq!(LedgerPeerSnapshot::from_json(origin).unwrap().slot, None);
+        let both = r#"{"bigLedgerPools": [{"relativeStake": 1,
+            "relays": [{"address": "10.0.0.1", "domain": "a", "port": 1}]}], "slotNo": 1}"#;
+        assert!(LedgerPeerSnapshot::from_json(both).is_err());
+    }
+}
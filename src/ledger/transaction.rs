@@ -0,0 +1,159 @@
+use crate::codec::split_array;
+use crate::crypto::{blake2b_256, Hash32};
+use ciborium::{from_reader, Value};
+
+// transaction = [transaction_body, transaction_witness_set, bool, auxiliary_data / null]
+// Shelley to Mary transactions do not carry the `is_valid` flag.
+
+const WITNESS_SET_VKEYS: i128 = 0;
+const WITNESS_SET_BOOTSTRAP: i128 = 2;
+const SET_TAG: u64 = 258;
+
+#[derive(Debug, PartialEq)]
+pub struct Transaction {
+    // Raw transaction_body bytes, as signed by the witnesses
+    pub body: Vec<u8>,
+    pub witness_set: WitnessSet,
+    pub is_valid: bool,
+}
+
+impl Transaction {
+    pub fn from_cbor(bytes: &[u8]) -> Result<Transaction, String> {
+        let items = split_array(bytes)?;
+        if !(3..=4).contains(&items.len()) {
+            return Err(format!(
+                "Transaction: Do not expect array of {} items!",
+                items.len()
+            ));
+        }
+        let witness_set: Value = from_reader(items[1])
+            .map_err(|error| format!("Could not decode witness set: {:?}", error))?;
+        let is_valid = match items.len() {
+            4 => from_reader::<Value, _>(items[2])
+                .map_err(|error| format!("Could not decode is_valid: {:?}", error))?
+                .as_bool()
+                .ok_or("Could not convert is_valid to bool")?,
+            _ => true,
+        };
+        Ok(Transaction {
+            body: items[0].to_vec(),
+            witness_set: WitnessSet::from_value(witness_set)?,
+            is_valid,
+        })
+    }
+
+    /// Transaction id: Blake2b-256 of the raw body bytes.
+    pub fn id(&self) -> Hash32 {
+        blake2b_256(&self.body)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct WitnessSet {
+    pub vkey_witnesses: Vec<VKeyWitness>,
+    pub bootstrap_witnesses: Vec<BootstrapWitness>,
+}
+
+impl WitnessSet {
+    pub fn from_value(value: Value) -> Result<WitnessSet, String> {
+        let map = value
+            .into_map()
+            .map_err(|error| format!("Could not convert witness set into map: {:?}", error))?;
+        let mut witness_set = WitnessSet::default();
+        for (key, value) in map {
+            let key = key
+                .as_integer()
+                .ok_or("Could not convert witness set key to integer")?;
+            match i128::from(key) {
+                WITNESS_SET_VKEYS => {
+                    for witness in set_items(value)? {
+                        witness_set
+                            .vkey_witnesses
+                            .push(VKeyWitness::from_value(witness)?);
+                    }
+                }
+                WITNESS_SET_BOOTSTRAP => {
+                    for witness in set_items(value)? {
+                        witness_set
+                            .bootstrap_witnesses
+                            .push(BootstrapWitness::from_value(witness)?);
+                    }
+                }
+                // Scripts, datums and redeemers are not needed for signature checks
+                _ => {}
+            }
+        }
+        Ok(witness_set)
+    }
+}
+
+// vkeywitness = [vkey, signature]
+#[derive(Debug, PartialEq)]
+pub struct VKeyWitness {
+    pub vkey: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl VKeyWitness {
+    fn from_value(value: Value) -> Result<VKeyWitness, String> {
+        let mut fields = fields(value, 2, "VKeyWitness")?.into_iter();
+        Ok(VKeyWitness {
+            vkey: bytes(fields.next(), "vkey")?,
+            signature: bytes(fields.next(), "signature")?,
+        })
+    }
+}
+
+// bootstrap_witness = [public_key, signature, chain_code, attributes]
+#[derive(Debug, PartialEq)]
+pub struct BootstrapWitness {
+    pub vkey: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub chain_code: Vec<u8>,
+    pub attributes: Vec<u8>,
+}
+
+impl BootstrapWitness {
+    fn from_value(value: Value) -> Result<BootstrapWitness, String> {
+        let mut fields = fields(value, 4, "BootstrapWitness")?.into_iter();
+        Ok(BootstrapWitness {
+            vkey: bytes(fields.next(), "public_key")?,
+            signature: bytes(fields.next(), "signature")?,
+            chain_code: bytes(fields.next(), "chain_code")?,
+            attributes: bytes(fields.next(), "attributes")?,
+        })
+    }
+}
+
+// Conway encodes witness lists as `#6.258([* a])`, earlier eras as plain arrays
+pub(super) fn set_items(value: Value) -> Result<Vec<Value>, String> {
+    let value = match value {
+        Value::Tag(SET_TAG, value) => *value,
+        value => value,
+    };
+    value
+        .into_array()
+        .map_err(|error| format!("Could not convert set into array: {:?}", error))
+}
+
+fn fields(value: Value, count: usize, name: &str) -> Result<Vec<Value>, String> {
+    let array = value
+        .into_array()
+        .map_err(|error| format!("Could not convert {} into array: {:?}", name, error))?;
+    if array.len() != count {
+        return Err(format!(
+            "{}: Expected {} fields, found {}",
+            name,
+            count,
+            array.len()
+        ));
+    }
+    Ok(array)
+}
+
+fn bytes(value: Option<Value>, name: &str) -> Result<Vec<u8>, String> {
+    value
+        .ok_or(format!("No value found for {}", name))?
+        .into_bytes()
+        .map_err(|error| format!("Could not convert {} into bytes: {:?}", name, error))
+}
@@ -0,0 +1,291 @@
+use super::{BootstrapWitness, Transaction, VKeyWitness};
+use crate::crypto::{blake2b_224, verify_ed25519, Hash28, PublicKey, Signature};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+
+// CBOR prefix of `[0, [0, bytes .size 64], attributes]` that Byron hashes
+// to get the address root of a bootstrap witness.
+const BOOTSTRAP_ROOT_PREFIX: [u8; 6] = [0x83, 0x00, 0x82, 0x00, 0x58, 0x40];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WitnessKind {
+    VKey,
+    Bootstrap,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VerificationError {
+    // Transaction bytes could not be decoded
+    Decode(String),
+    // Verification key is not 32 bytes or not a valid curve point
+    MalformedKey {
+        kind: WitnessKind,
+        index: usize,
+    },
+    // Signature is not 64 bytes
+    MalformedSignature {
+        kind: WitnessKind,
+        index: usize,
+    },
+    // Bootstrap chain code is not 32 bytes
+    MalformedChainCode {
+        index: usize,
+    },
+    // Signature does not match the transaction body hash
+    InvalidSignature {
+        kind: WitnessKind,
+        index: usize,
+        key_hash: Hash28,
+    },
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationError::Decode(error) => {
+                write!(f, "Could not decode transaction: {}", error)
+            }
+            VerificationError::MalformedKey { kind, index } => {
+                write!(f, "Malformed {:?} witness key at index {}", kind, index)
+            }
+            VerificationError::MalformedSignature { kind, index } => {
+                write!(
+                    f,
+                    "Malformed {:?} witness signature at index {}",
+                    kind, index
+                )
+            }
+            VerificationError::MalformedChainCode { index } => {
+                write!(
+                    f,
+                    "Malformed Bootstrap witness chain code at index {}",
+                    index
+                )
+            }
+            VerificationError::InvalidSignature {
+                kind,
+                index,
+                key_hash,
+            } => write!(
+                f,
+                "Invalid {:?} witness signature at index {} for key hash {}",
+                kind,
+                index,
+                to_hex(key_hash)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerificationError {}
+
+impl VKeyWitness {
+    /// Blake2b-224 of the verification key, as referenced by addresses.
+    pub fn key_hash(&self) -> Hash28 {
+        blake2b_224(&self.vkey)
+    }
+}
+
+impl BootstrapWitness {
+    /// Byron address root for the extended key and attributes of this witness.
+    pub fn key_hash(&self) -> Hash28 {
+        let mut hasher = Sha3_256::new();
+        hasher.update(BOOTSTRAP_ROOT_PREFIX);
+        hasher.update(&self.vkey);
+        hasher.update(&self.chain_code);
+        hasher.update(&self.attributes);
+        blake2b_224(&hasher.finalize())
+    }
+}
+
+/// Decodes a transaction and verifies all of its key witnesses.
+pub fn verify_transaction(bytes: &[u8]) -> Result<Vec<Hash28>, VerificationError> {
+    let transaction = Transaction::from_cbor(bytes).map_err(VerificationError::Decode)?;
+    verify_witnesses(&transaction)
+}
+
+/// Verifies vkey and bootstrap witnesses against the transaction body hash.
+///
+/// Returns the key hashes of all witnesses, so callers can check them against
+/// the signers required by inputs, certificates and withdrawals.
+pub fn verify_witnesses(transaction: &Transaction) -> Result<Vec<Hash28>, VerificationError> {
+    let tx_id = transaction.id();
+    let mut key_hashes = vec![];
+
+    for (index, witness) in transaction.witness_set.vkey_witnesses.iter().enumerate() {
+        let kind = WitnessKind::VKey;
+        let (vkey, signature) = key_and_signature(&witness.vkey, &witness.signature, kind, index)?;
+        let key_hash = witness.key_hash();
+        verify_ed25519(&vkey, &tx_id, &signature).map_err(|_| {
+            VerificationError::InvalidSignature {
+                kind,
+                index,
+                key_hash,
+            }
+        })?;
+        key_hashes.push(key_hash);
+    }
+
+    for (index, witness) in transaction
+        .witness_set
+        .bootstrap_witnesses
+        .iter()
+        .enumerate()
+    {
+        let kind = WitnessKind::Bootstrap;
+        let (vkey, signature) = key_and_signature(&witness.vkey, &witness.signature, kind, index)?;
+        if witness.chain_code.len() != 32 {
+            return Err(VerificationError::MalformedChainCode { index });
+        }
+        let key_hash = witness.key_hash();
+        verify_ed25519(&vkey, &tx_id, &signature).map_err(|_| {
+            VerificationError::InvalidSignature {
+                kind,
+                index,
+                key_hash,
+            }
+        })?;
+        key_hashes.push(key_hash);
+    }
+
+    Ok(key_hashes)
+}
+
+fn key_and_signature(
+    vkey: &[u8],
+    signature: &[u8],
+    kind: WitnessKind,
+    index: usize,
+) -> Result<(PublicKey, Signature), VerificationError> {
+    let vkey: PublicKey = vkey
+        .try_into()
+        .map_err(|_| VerificationError::MalformedKey { kind, index })?;
+    let signature: Signature = signature
+        .try_into()
+        .map_err(|_| VerificationError::MalformedSignature { kind, index })?;
+    Ok((vkey, signature))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::blake2b_256;
+    use ciborium::{into_writer, Value};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut bytes = vec![];
+        into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn body() -> Vec<u8> {
+        encode(&Value::Map(vec![
+            (
+                Value::from(0),
+                Value::Array(vec![Value::Array(vec![
+                    Value::Bytes(vec![0x11; 32]),
+                    Value::from(0),
+                ])]),
+            ),
+            (Value::from(1), Value::Array(vec![])),
+            (Value::from(2), Value::from(170_000)),
+        ]))
+    }
+
+    fn vkey_witness(key: &SigningKey, body: &[u8]) -> Value {
+        Value::Array(vec![
+            Value::Bytes(key.verifying_key().to_bytes().to_vec()),
+            Value::Bytes(key.sign(&blake2b_256(body)).to_bytes().to_vec()),
+        ])
+    }
+
+    fn transaction(body: &[u8], witness_set: Value) -> Vec<u8> {
+        // [body, witness_set, true, null]
+        let mut bytes = vec![0x84];
+        bytes.extend_from_slice(body);
+        bytes.extend_from_slice(&encode(&witness_set));
+        bytes.extend_from_slice(&[0xf5, 0xf6]);
+        bytes
+    }
+
+    #[test]
+    fn vkey_and_bootstrap_witnesses() {
+        let body = body();
+        let payment_key = SigningKey::from_bytes(&[1; 32]);
+        let byron_key = SigningKey::from_bytes(&[2; 32]);
+        let bootstrap = match vkey_witness(&byron_key, &body) {
+            Value::Array(mut fields) => {
+                fields.push(Value::Bytes(vec![3; 32]));
+                fields.push(Value::Bytes(vec![0xa0]));
+                Value::Array(fields)
+            }
+            _ => unreachable!(),
+        };
+        let witness_set = Value::Map(vec![
+            (
+                Value::from(0),
+                Value::Tag(
+                    258,
+                    Box::new(Value::Array(vec![vkey_witness(&payment_key, &body)])),
+                ),
+            ),
+            (Value::from(2), Value::Array(vec![bootstrap])),
+        ]);
+
+        let key_hashes = verify_transaction(&transaction(&body, witness_set)).unwrap();
+        assert_eq!(key_hashes.len(), 2);
+        assert_eq!(
+            key_hashes[0],
+            blake2b_224(payment_key.verifying_key().as_bytes())
+        );
+    }
+
+    #[test]
+    fn signature_over_other_body() {
+        let body = body();
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let witness_set = Value::Map(vec![(
+            Value::from(0),
+            Value::Array(vec![vkey_witness(&key, &body[1..])]),
+        )]);
+
+        let error = verify_transaction(&transaction(&body, witness_set)).unwrap_err();
+        assert_eq!(
+            error,
+            VerificationError::InvalidSignature {
+                kind: WitnessKind::VKey,
+                index: 0,
+                key_hash: blake2b_224(key.verifying_key().as_bytes()),
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_witnesses() {
+        let body = body();
+        let witness_set = Value::Map(vec![(
+            Value::from(0),
+            Value::Array(vec![Value::Array(vec![
+                Value::Bytes(vec![0; 31]),
+                Value::Bytes(vec![0; 64]),
+            ])]),
+        )]);
+        assert_eq!(
+            verify_transaction(&transaction(&body, witness_set)).unwrap_err(),
+            VerificationError::MalformedKey {
+                kind: WitnessKind::VKey,
+                index: 0
+            }
+        );
+
+        assert!(matches!(
+            verify_transaction(&body),
+            Err(VerificationError::Decode(_))
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+//! Cardano node-to-node networking in Rust.
+//!
+//! The crate implements the connection layer and mini-protocols of the
+//! Ouroboros network specification, and the block, ledger and storage
+//! formats needed to follow and serve a chain:
+//!
+//! * [`bearer`]: TCP, Unix domain socket and in-memory connections
+//! * [`mux`]: multiplexing of mini-protocols over one bearer
+//! * [`capture`]: recording sessions to files and playing them back
+//! * [`connection`]: connections to peers after the handshake, with handles
+//!   to their mini-protocols
+//! * [`connection_manager`]: one connection per peer, shared by both
+//!   directions when duplex, with inbound limits and time-wait
+//! * [`governor`]: P2P peer selection, keeping targets of known, established
+//!   and active peers
+//! * [`handshake`]: version negotiation, the first mini-protocol of every
+//!   connection
+//! * [`chain_sync`] and [`block_fetch`]: following a chain and downloading
+//!   its blocks, as client and, with the `storage` feature, as server
+//! * [`keep_alive`]: liveness and round trip times of established peers
+//! * [`peer_sharing`]: asking established peers for more peers
+//! * [`codec`]: CBOR helpers shared by the message and block decoders
+//! * [`consensus`], [`crypto`] and [`ledger`]: headers, hard fork history,
+//!   Praos checks, transaction witnesses and the stake pool relays of
+//!   ledger peer discovery
+//! * [`storage`]: blocks and chain points, and with the `storage` feature
+//!   cardano-node's ImmutableDB, VolatileDB and ChainDB
+//! * [`config`]: App.yaml, cardano-node configuration, genesis and topology
+//!   files, network presets and logging
+//! * [`metrics`]: Prometheus metrics of connections and mini-protocols
+//! * `mock`: with the `test-support` feature, a scriptable peer for tests
+//!
+//! # Features
+//!
+//! * `storage`: the on-disk databases and the chain-sync and block-fetch
+//!   servers
+//! * `cli` (default): the `cardano_rust_node` binary, implies `storage`
+//! * `test-support`: the `mock` module, for integration tests of code
+//!   built on the crate
+//!
+//! Clients that only talk to peers depend on the crate with
+//! `default-features = false`.
+//!
+//! # Example
+//!
+//! Handshake with a relay and read the tip of its chain:
+//!
+//! ```no_run
+//! use cardano_rust_node::bearer;
+//! use cardano_rust_node::chain_sync::{self, MINI_PROTOCOL_ID_CHAIN_SYNC};
+//! use cardano_rust_node::handshake::{self, MINI_PROTOCOL_ID_HANDSHAKE};
+//! use cardano_rust_node::mux::{Mode, Mux};
+//! use cardano_rust_node::storage::Point;
+//!
+//! # async fn tip() -> Result<(), String> {
+//! let stream = bearer::connect_tcp("preview-node.world.dev.cardano.org:30002").await?;
+//! let protocols = [MINI_PROTOCOL_ID_HANDSHAKE, MINI_PROTOCOL_ID_CHAIN_SYNC];
+//! let mut mux = Mux::with_bearer(stream, Mode::Initiator, &protocols);
+//!
+//! let mut channel = mux.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+//! let reply = handshake::propose(&mut channel, &vec![13, 14], 2, false, false).await?;
+//! println!("Accepted version {:?}", reply.accepted_version());
+//!
+//! let mut channel = mux.channel(MINI_PROTOCOL_ID_CHAIN_SYNC).unwrap();
+//! let (_, tip) = chain_sync::find_intersect(&mut channel, &[Point::Origin]).await?;
+//! println!("Tip {:?}", tip);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod bearer;
+pub mod block_fetch;
+pub mod capture;
+pub mod chain_sync;
+pub mod codec;
+pub mod config;
+#[cfg(test)]
+mod conformance;
+pub mod connection;
+pub mod connection_manager;
+pub mod consensus;
+pub mod crypto;
+pub mod governor;
+pub mod handshake;
+pub mod keep_alive;
+pub mod ledger;
+pub mod metrics;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+pub mod mux;
+pub mod peer_sharing;
+pub mod storage;
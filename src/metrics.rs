@@ -0,0 +1,429 @@
+// Prometheus metrics of connections and mini-protocols
+//
+// Everything is recorded into one process wide registry, `metrics()`, and
+// exported in the Prometheus text format by a small HTTP server answering
+// `GET /metrics`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, warn};
+
+const PREFIX: &str = "cardano_rust_node";
+// Upper bounds in seconds of the latency histograms
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// Longest request head read before answering
+const MAX_REQUEST_SIZE: usize = 8192;
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    // Observations per bucket of BUCKETS, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    handshake_attempts: BTreeMap<String, u64>,
+    handshake_accepted: BTreeMap<(String, i64), u64>,
+    handshake_refused: BTreeMap<(String, String), u64>,
+    handshake_failed: BTreeMap<(String, String), u64>,
+    connect_duration: BTreeMap<String, Histogram>,
+    negotiate_duration: BTreeMap<String, Histogram>,
+    bytes_received: BTreeMap<u16, u64>,
+    bytes_sent: BTreeMap<u16, u64>,
+    active_connections: i64,
+    tip: Option<(u64, u64)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+/// The registry of this process.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    fn update(&self, f: impl FnOnce(&mut Registry)) {
+        // A panic while holding the lock leaves consistent counters behind
+        let mut registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut registry)
+    }
+
+    pub fn handshake_attempt(&self, host: &str) {
+        self.update(|r| *r.handshake_attempts.entry(host.to_owned()).or_default() += 1)
+    }
+
+    pub fn handshake_accepted(&self, host: &str, version: i64) {
+        self.update(|r| {
+            *r.handshake_accepted
+                .entry((host.to_owned(), version))
+                .or_default() += 1
+        })
+    }
+
+    pub fn handshake_refused(&self, host: &str, reason: &str) {
+        self.update(|r| {
+            *r.handshake_refused
+                .entry((host.to_owned(), reason.to_owned()))
+                .or_default() += 1
+        })
+    }
+
+    /// Handshakes that ended without an answer, `class` tells where.
+    pub fn handshake_failed(&self, host: &str, class: &str) {
+        self.update(|r| {
+            *r.handshake_failed
+                .entry((host.to_owned(), class.to_owned()))
+                .or_default() += 1
+        })
+    }
+
+    pub fn observe_connect(&self, host: &str, duration: Duration) {
+        self.update(|r| {
+            r.connect_duration
+                .entry(host.to_owned())
+                .or_default()
+                .observe(duration.as_secs_f64())
+        })
+    }
+
+    pub fn observe_negotiate(&self, host: &str, duration: Duration) {
+        self.update(|r| {
+            r.negotiate_duration
+                .entry(host.to_owned())
+                .or_default()
+                .observe(duration.as_secs_f64())
+        })
+    }
+
+    /// Bytes of segments received for a mini-protocol, headers included.
+    pub fn bytes_received(&self, protocol: u16, bytes: usize) {
+        self.update(|r| *r.bytes_received.entry(protocol).or_default() += bytes as u64)
+    }
+
+    /// Bytes of segments sent for a mini-protocol, headers included.
+    pub fn bytes_sent(&self, protocol: u16, bytes: usize) {
+        self.update(|r| *r.bytes_sent.entry(protocol).or_default() += bytes as u64)
+    }
+
+    pub fn connection_opened(&self) {
+        self.update(|r| r.active_connections += 1)
+    }
+
+    pub fn connection_closed(&self) {
+        self.update(|r| r.active_connections -= 1)
+    }
+
+    /// Tip of the local chain, exported once set.
+    pub fn set_tip(&self, slot: u64, block_number: u64) {
+        self.update(|r| r.tip = Some((slot, block_number)))
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "handshake_attempts_total",
+            "counter",
+            "Handshakes proposed to a host",
+        );
+        for (host, count) in &registry.handshake_attempts {
+            sample(
+                &mut out,
+                "handshake_attempts_total",
+                &[("host", host)],
+                *count,
+            );
+        }
+        family(
+            &mut out,
+            "handshake_accepted_total",
+            "counter",
+            "Handshakes accepted by a host, by version",
+        );
+        for ((host, version), count) in &registry.handshake_accepted {
+            let version = version.to_string();
+            let labels = [("host", host.as_str()), ("version", &version)];
+            sample(&mut out, "handshake_accepted_total", &labels, *count);
+        }
+        family(
+            &mut out,
+            "handshake_refused_total",
+            "counter",
+            "Handshakes refused by a host, by reason",
+        );
+        for ((host, reason), count) in &registry.handshake_refused {
+            let labels = [("host", host.as_str()), ("reason", reason)];
+            sample(&mut out, "handshake_refused_total", &labels, *count);
+        }
+        family(
+            &mut out,
+            "handshake_failed_total",
+            "counter",
+            "Handshakes that failed without an answer, by class",
+        );
+        for ((host, class), count) in &registry.handshake_failed {
+            let labels = [("host", host.as_str()), ("class", class)];
+            sample(&mut out, "handshake_failed_total", &labels, *count);
+        }
+
+        for (name, help, histograms) in [
+            (
+                "connect_duration_seconds",
+                "Time to establish the TCP connection",
+                &registry.connect_duration,
+            ),
+            (
+                "negotiate_duration_seconds",
+                "Time from proposing versions to the answer",
+                &registry.negotiate_duration,
+            ),
+        ] {
+            family(&mut out, name, "histogram", help);
+            for (host, histogram) in histograms {
+                histogram_samples(&mut out, name, host, histogram);
+            }
+        }
+
+        for (name, help, bytes) in [
+            (
+                "mux_bytes_received_total",
+                "Bytes of segments received, by mini-protocol",
+                &registry.bytes_received,
+            ),
+            (
+                "mux_bytes_sent_total",
+                "Bytes of segments sent, by mini-protocol",
+                &registry.bytes_sent,
+            ),
+        ] {
+            family(&mut out, name, "counter", help);
+            for (protocol, count) in bytes {
+                sample(
+                    &mut out,
+                    name,
+                    &[("protocol", &protocol.to_string())],
+                    *count,
+                );
+            }
+        }
+
+        family(
+            &mut out,
+            "active_connections",
+            "gauge",
+            "Open multiplexed connections",
+        );
+        sample(
+            &mut out,
+            "active_connections",
+            &[],
+            registry.active_connections,
+        );
+
+        if let Some((slot, block_number)) = registry.tip {
+            family(&mut out, "chain_tip_slot", "gauge", "Slot of the local tip");
+            sample(&mut out, "chain_tip_slot", &[], slot);
+            family(
+                &mut out,
+                "chain_tip_block_number",
+                "gauge",
+                "Block number of the local tip",
+            );
+            sample(&mut out, "chain_tip_block_number", &[], block_number);
+        }
+        out
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{}_{}{} {}", PREFIX, name, label_set(labels), value);
+}
+
+fn histogram_samples(out: &mut String, name: &str, host: &str, histogram: &Histogram) {
+    let bucket = format!("{}_bucket", name);
+    let mut cumulative = 0;
+    for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+        cumulative += count;
+        let bound = bound.to_string();
+        sample(out, &bucket, &[("host", host), ("le", &bound)], cumulative);
+    }
+    sample(
+        out,
+        &bucket,
+        &[("host", host), ("le", "+Inf")],
+        histogram.count,
+    );
+    sample(
+        out,
+        &format!("{}_sum", name),
+        &[("host", host)],
+        histogram.sum,
+    );
+    sample(
+        out,
+        &format!("{}_count", name),
+        &[("host", host)],
+        histogram.count,
+    );
+}
+
+fn label_set(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Answers `GET /metrics` on `listener` with the registry of this process
+/// until the task is dropped.
+pub async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("Metrics scrape from {}", peer);
+                tokio::spawn(async move {
+                    if let Err(error) = respond(stream).await {
+                        debug!("Metrics request from {}: {}", peer, error);
+                    }
+                });
+            }
+            Err(error) => warn!("Could not accept metrics connection: {}", error),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let count = stream.read(&mut buffer).await?;
+        if count == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..count]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics().render()),
+        (Some("GET"), _) => ("404 Not Found", "Not found, try /metrics\n".to_owned()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_text_format() {
+        let metrics = Metrics::default();
+        metrics.handshake_attempt("a:1");
+        metrics.handshake_attempt("a:1");
+        metrics.handshake_accepted("a:1", 13);
+        metrics.handshake_refused("a:1", "VersionMismatch");
+        metrics.observe_connect("a:1", Duration::from_millis(20));
+        metrics.observe_connect("a:1", Duration::from_secs(20));
+        metrics.bytes_sent(2, 100);
+        metrics.connection_opened();
+        metrics.set_tip(100, 7);
+
+        let text = metrics.render();
+        for line in [
+            "# TYPE cardano_rust_node_handshake_attempts_total counter",
+            "cardano_rust_node_handshake_attempts_total{host=\"a:1\"} 2",
+            "cardano_rust_node_handshake_accepted_total{host=\"a:1\",version=\"13\"} 1",
+            "cardano_rust_node_handshake_refused_total{host=\"a:1\",reason=\"VersionMismatch\"} 1",
+            "cardano_rust_node_connect_duration_seconds_bucket{host=\"a:1\",le=\"0.01\"} 0",
+            "cardano_rust_node_connect_duration_seconds_bucket{host=\"a:1\",le=\"0.025\"} 1",
+            "cardano_rust_node_connect_duration_seconds_bucket{host=\"a:1\",le=\"10\"} 1",
+            "cardano_rust_node_connect_duration_seconds_bucket{host=\"a:1\",le=\"+Inf\"} 2",
+            "cardano_rust_node_connect_duration_seconds_count{host=\"a:1\"} 2",
+            "cardano_rust_node_mux_bytes_sent_total{protocol=\"2\"} 100",
+            "cardano_rust_node_active_connections 1",
+            "cardano_rust_node_chain_tip_slot 100",
+            "cardano_rust_node_chain_tip_block_number 7",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{} missing in\n{}",
+                line,
+                text
+            );
+        }
+        assert_eq!(label_set(&[("a", "x\"y")]), "{a=\"x\\\"y\"}");
+    }
+
+    #[tokio::test]
+    async fn scrape() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener));
+
+        let get = |path: &str| {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            async move {
+                let mut stream = TcpStream::connect(address).await.unwrap();
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE cardano_rust_node_active_connections gauge"));
+        assert!(get("/").await.starts_with("HTTP/1.1 404"));
+        server.abort();
+    }
+}
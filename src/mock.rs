@@ -0,0 +1,338 @@
+// Scriptable in-process peer for tests, enabled with the `test-support`
+// feature. It listens on a loopback TCP socket and answers the handshake of
+// every connection as its `Script` says: accept, refuse, send arbitrary
+// bytes, hang or close, optionally late and split into small segments.
+
+use crate::codec::item_len;
+use crate::handshake::{
+    AcceptVersion, Message, ProposeVersion, RefuseReason, MINI_PROTOCOL_ID_HANDSHAKE,
+};
+use crate::mux::{read_segment, SegmentHeader, MAX_SEGMENT_PAYLOAD};
+use ciborium::{from_reader, into_writer};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    task::{JoinHandle, JoinSet},
+};
+use tracing::debug;
+
+/// How the mock answers a proposal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// Accepts the highest version proposed and supported, refuses with
+    /// `VersionMismatch` when there is none. The network magic is not
+    /// checked.
+    Accept,
+    Refuse(RefuseReason),
+    /// Sends these bytes as the payload, e.g. malformed CBOR.
+    Raw(Vec<u8>),
+    /// Never answers and keeps the connection open.
+    Hang,
+    /// Closes the connection without answering.
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub versions: Vec<i64>,
+    pub network_magic: u32,
+    pub reply: Reply,
+    // Waited for before replying
+    pub delay: Duration,
+    // Largest payload of a reply segment, smaller sizes split the reply
+    pub segment_size: usize,
+}
+
+impl Script {
+    pub fn new(versions: &[i64], network_magic: u32, reply: Reply) -> Script {
+        Script {
+            versions: versions.to_vec(),
+            network_magic,
+            reply,
+            delay: Duration::ZERO,
+            segment_size: MAX_SEGMENT_PAYLOAD,
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Script {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_segment_size(mut self, segment_size: usize) -> Script {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+}
+
+/// A mock peer listening on 127.0.0.1. Dropping it closes the listener and
+/// every connection.
+pub struct MockNode {
+    address: SocketAddr,
+    proposals: Arc<Mutex<Vec<Message>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockNode {
+    pub async fn start(script: Script) -> Result<MockNode, String> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|error| format!("Could not listen: {}", error))?;
+        let address = listener.local_addr().map_err(|e| e.to_string())?;
+        let proposals = Arc::new(Mutex::new(vec![]));
+        let task = tokio::spawn(listen(listener, script, proposals.clone()));
+        Ok(MockNode {
+            address,
+            proposals,
+            task,
+        })
+    }
+
+    /// Address to connect to, `127.0.0.1:port`.
+    pub fn host(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// Proposals received so far, in the order they were read.
+    pub fn proposals(&self) -> Vec<Message> {
+        self.proposals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl Drop for MockNode {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn listen(listener: TcpListener, script: Script, proposals: Arc<Mutex<Vec<Message>>>) {
+    // Dropped with this task, which aborts the connections
+    let mut connections = JoinSet::new();
+    while let Ok((stream, peer)) = listener.accept().await {
+        debug!("Mock node: connection from {}", peer);
+        let script = script.clone();
+        let proposals = proposals.clone();
+        connections.spawn(async move {
+            if let Err(error) = respond(stream, &script, &proposals).await {
+                debug!("Mock node: {}", error);
+            }
+        });
+    }
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    script: &Script,
+    proposals: &Mutex<Vec<Message>>,
+) -> Result<(), String> {
+    let proposal = read_proposal(&mut stream).await?;
+    proposals
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(proposal.clone());
+    tokio::time::sleep(script.delay).await;
+
+    let payload = match &script.reply {
+        Reply::Accept => encode(&accept(script, &proposal)?)?,
+        Reply::Refuse(reason) => encode(&Message::Refuse(reason.clone()))?,
+        Reply::Raw(bytes) => bytes.clone(),
+        Reply::Hang => return hang(stream).await,
+        Reply::Close => return Ok(()),
+    };
+    for chunk in payload.chunks(script.segment_size) {
+        let header = SegmentHeader {
+            timestamp: 0,
+            protocol: MINI_PROTOCOL_ID_HANDSHAKE,
+            responder: true,
+            length: chunk.len() as u16,
+        };
+        let mut segment = header.to_bytes().to_vec();
+        segment.extend_from_slice(chunk);
+        stream
+            .write_all(&segment)
+            .await
+            .map_err(|e| e.to_string())?;
+        stream.flush().await.map_err(|e| e.to_string())?;
+    }
+    // Other mini-protocols are not served, the client closes
+    hang(stream).await
+}
+
+async fn read_proposal(stream: &mut TcpStream) -> Result<Message, String> {
+    let mut message = vec![];
+    loop {
+        match read_segment(stream).await? {
+            Some((header, payload)) if header.protocol == MINI_PROTOCOL_ID_HANDSHAKE => {
+                message.extend_from_slice(&payload)
+            }
+            Some(_) => continue,
+            None => return Err("Closed before proposing".to_owned()),
+        }
+        if let Ok(len) = item_len(&message) {
+            let value = from_reader(&message[..len])
+                .map_err(|error| format!("Could not decode proposal: {:?}", error))?;
+            return Message::from_value(value);
+        }
+    }
+}
+
+fn accept(script: &Script, proposal: &Message) -> Result<Message, String> {
+    let ours =
+        match ProposeVersion::create_version_table(&script.versions, script.network_magic, false) {
+            ProposeVersion::VersionTable(version_table) => version_table,
+            _ => unreachable!(),
+        };
+    let proposed: Vec<i128> = match proposal {
+        Message::ProposeVersions(propose_versions) => propose_versions
+            .iter()
+            .filter_map(|value| match value {
+                ProposeVersion::VersionTable(version_table) => Some(version_table),
+                _ => None,
+            })
+            .flatten()
+            .map(|(version, _)| *version)
+            .collect(),
+        message => return Err(format!("Expected MsgProposeVersions, got {:?}", message)),
+    };
+    let common = ours
+        .into_iter()
+        .filter(|(version, _)| proposed.contains(version))
+        .max_by_key(|(version, _)| *version);
+    Ok(match common {
+        Some((version, data)) => Message::AcceptVersion(vec![
+            AcceptVersion::Index(1),
+            AcceptVersion::VersionNumber(version),
+            AcceptVersion::NodeToNodeVersionData(data),
+        ]),
+        None => Message::Refuse(RefuseReason::VersionMismatch(
+            script.versions.iter().map(|v| *v as i128).collect(),
+        )),
+    })
+}
+
+fn encode(message: &Message) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    into_writer(&message.to_value()?, &mut bytes).map_err(|e| format!("{:?}", e))?;
+    Ok(bytes)
+}
+
+/// Keeps the connection open until the client closes it.
+async fn hang(mut stream: TcpStream) -> Result<(), String> {
+    while read_segment(&mut stream).await?.is_some() {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::PeerConnection;
+    use crate::handshake::{self, NodeConfig};
+    use tokio::time::{timeout, Instant};
+
+    async fn propose(node: &MockNode, versions: &[i64]) -> Result<Message, String> {
+        let stream = crate::bearer::connect_tcp(&node.host()).await?;
+        let mut mux = crate::mux::Mux::with_bearer(
+            stream,
+            crate::mux::Mode::Initiator,
+            &[MINI_PROTOCOL_ID_HANDSHAKE],
+        );
+        let mut channel = mux.channel(MINI_PROTOCOL_ID_HANDSHAKE).unwrap();
+        handshake::propose(&mut channel, &versions.to_vec(), 2, false, false).await
+    }
+
+    #[tokio::test]
+    async fn accepts() {
+        let node = MockNode::start(Script::new(&[10, 13], 2, Reply::Accept))
+            .await
+            .unwrap();
+        let connection = PeerConnection::connect(&node.host(), 2, &vec![13, 14], &[])
+            .await
+            .unwrap();
+        assert_eq!(connection.version(), 13);
+        connection.close().await;
+
+        let host = node.host();
+        let node_config = NodeConfig::init(&host, 2, "Mock").await.unwrap();
+        let (response, _, _) = handshake::negotiate(node_config, &vec![7, 10])
+            .await
+            .unwrap();
+        assert_eq!(response.accepted_version(), Some(10));
+        assert_eq!(node.proposals().len(), 2);
+
+        let response = propose(&node, &[14]).await.unwrap();
+        assert_eq!(
+            response,
+            Message::Refuse(RefuseReason::VersionMismatch(vec![10, 13]))
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses() {
+        for reason in [
+            RefuseReason::VersionMismatch(vec![13]),
+            RefuseReason::HandshakeDecodeError(13, "bad".to_owned()),
+            RefuseReason::Refused(13, "no".to_owned()),
+        ] {
+            let node = MockNode::start(Script::new(&[13], 2, Reply::Refuse(reason.clone())))
+                .await
+                .unwrap();
+            assert_eq!(
+                propose(&node, &[13]).await,
+                Ok(Message::Refuse(reason.clone()))
+            );
+
+            let host = node.host();
+            let node_config = NodeConfig::init(&host, 2, "Mock").await.unwrap();
+            let (response, _, _) = handshake::negotiate(node_config, &vec![13]).await.unwrap();
+            assert_eq!(response, Message::Refuse(reason));
+        }
+    }
+
+    #[tokio::test]
+    async fn misbehaves() {
+        let malformed = Script::new(&[13], 2, Reply::Raw(vec![0x82, 0x01, 0xff]));
+        let node = MockNode::start(malformed).await.unwrap();
+        assert!(propose(&node, &[13]).await.is_err());
+        let host = node.host();
+        let node_config = NodeConfig::init(&host, 2, "Mock").await.unwrap();
+        assert!(handshake::negotiate(node_config, &vec![13]).await.is_err());
+
+        let split = Script::new(&[13], 2, Reply::Accept).with_segment_size(3);
+        let node = MockNode::start(split).await.unwrap();
+        assert_eq!(
+            propose(&node, &[13]).await.unwrap().accepted_version(),
+            Some(13)
+        );
+        let host = node.host();
+        let node_config = NodeConfig::init(&host, 2, "Mock").await.unwrap();
+        let (response, _, _) = handshake::negotiate(node_config, &vec![13]).await.unwrap();
+        assert_eq!(response.accepted_version(), Some(13));
+
+        let late = Script::new(&[13], 2, Reply::Accept).with_delay(Duration::from_millis(50));
+        let node = MockNode::start(late).await.unwrap();
+        let start = Instant::now();
+        propose(&node, &[13]).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let node = MockNode::start(Script::new(&[13], 2, Reply::Hang))
+            .await
+            .unwrap();
+        let hanging = timeout(Duration::from_millis(50), propose(&node, &[13])).await;
+        assert!(hanging.is_err());
+
+        let node = MockNode::start(Script::new(&[13], 2, Reply::Close))
+            .await
+            .unwrap();
+        assert_eq!(
+            propose(&node, &[13]).await,
+            Err("Connection closed".to_owned())
+        );
+        assert_eq!(node.proposals().len(), 1);
+    }
+}
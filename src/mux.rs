@@ -0,0 +1,524 @@
+// 2.1 Multiplexing of mini-protocols over a single bearer
+//
+// Every message is carried in one or more segments with an 8 byte header:
+// transmission time (lower 32 bits of a microsecond clock), mode bit and
+// mini-protocol id, payload length. The mode bit is set on segments sent by
+// the responder side of a mini-protocol. A message can span segments, the
+// receiving side reassembles it by decoding CBOR items from the payloads of
+// its mini-protocol. On a duplex bearer each side runs both the initiator and
+// the responder of the mini-protocols, the mode bit tells them apart.
+//
+// The bytes queued per mini-protocol and direction are bounded. A peer
+// overrunning an ingress queue is dropped, senders wait for their egress
+// queue to drain with `Channel::writable`.
+
+use crate::bearer::Bearer;
+use crate::codec::{item_len, ItemError};
+use crate::metrics::metrics;
+use ciborium::{from_reader, into_writer, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    select,
+    sync::{mpsc, oneshot, Notify},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+pub const SEGMENT_HEADER_SIZE: usize = 8;
+// Largest payload sent in one segment, the SDU size of socket bearers
+pub const MAX_SEGMENT_PAYLOAD: usize = 12288;
+// Messages of any mini-protocol fit in this many bytes, a peer buffering more
+// without completing a message is not followed any further
+pub const MAX_MESSAGE_SIZE: usize = 2_500_000;
+// Bytes queued at most per mini-protocol and direction, received but not yet
+// taken by the channel, or sent but not yet written
+pub const MAX_QUEUED_BYTES: usize = 4 * MAX_MESSAGE_SIZE;
+
+const MODE_BIT: u16 = 0x8000;
+
+// Bytes waiting in the queue of one mini-protocol direction
+#[derive(Default)]
+struct Queue {
+    bytes: AtomicUsize,
+    drained: Notify,
+}
+
+impl Queue {
+    fn push(&self, len: usize) -> usize {
+        self.bytes.fetch_add(len, Ordering::AcqRel) + len
+    }
+
+    fn pop(&self, len: usize) {
+        self.bytes.fetch_sub(len, Ordering::AcqRel);
+        self.drained.notify_waiters();
+    }
+
+    fn len(&self) -> usize {
+        self.bytes.load(Ordering::Acquire)
+    }
+}
+
+type Outgoing = (SegmentHeader, Vec<u8>, Arc<Queue>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    // Runs the client side of the mini-protocols
+    Initiator,
+    // Runs the server side of the mini-protocols
+    Responder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentHeader {
+    pub timestamp: u32,
+    pub protocol: u16,
+    // Sent by the responder side of the mini-protocol
+    pub responder: bool,
+    pub length: u16,
+}
+
+impl SegmentHeader {
+    pub fn to_bytes(self) -> [u8; SEGMENT_HEADER_SIZE] {
+        let mut bytes = [0; SEGMENT_HEADER_SIZE];
+        let protocol = match self.responder {
+            true => self.protocol | MODE_BIT,
+            false => self.protocol,
+        };
+        bytes[0..4].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes[4..6].copy_from_slice(&protocol.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.length.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; SEGMENT_HEADER_SIZE]) -> SegmentHeader {
+        let protocol = u16::from_be_bytes([bytes[4], bytes[5]]);
+        SegmentHeader {
+            timestamp: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            protocol: protocol & !MODE_BIT,
+            responder: protocol & MODE_BIT != 0,
+            length: u16::from_be_bytes([bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// Reads the next segment, `None` when the bearer was closed between
+/// segments.
+pub async fn read_segment<R: AsyncRead + Unpin>(
+    read: &mut R,
+) -> Result<Option<(SegmentHeader, Vec<u8>)>, String> {
+    let mut header = [0; SEGMENT_HEADER_SIZE];
+    match read.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(format!("Could not read segment header: {}", error)),
+    }
+    let header = SegmentHeader::from_bytes(&header);
+    let mut payload = vec![0; header.length as usize];
+    read.read_exact(&mut payload)
+        .await
+        .map_err(|error| format!("Could not read segment payload: {}", error))?;
+    Ok(Some((header, payload)))
+}
+
+/// Mini-protocols running over one bearer. Reading and writing happen in
+/// background tasks, every mini-protocol is served through its `Channel`.
+pub struct Mux {
+    mode: Mode,
+    channels: HashMap<(u16, Mode), Channel>,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl Mux {
+    /// Starts multiplexing `protocols` on the bearer. Segments of other
+    /// mini-protocols or sent in our own direction are dropped.
+    pub fn start<R, W>(read: R, write: W, mode: Mode, protocols: &[u16]) -> Mux
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Mux::start_modes(read, write, mode, &[mode], protocols)
+    }
+
+    /// Starts multiplexing `protocols` on both halves of `bearer`.
+    pub fn with_bearer<B: Bearer>(bearer: B, mode: Mode, protocols: &[u16]) -> Mux {
+        let (read, write) = bearer.split();
+        Mux::start(read, write, mode, protocols)
+    }
+
+    /// Starts multiplexing `protocols` in both directions on `bearer`: we run
+    /// the initiator and the responder side of each, so the peer can use the
+    /// connection as its outbound connection too. `mode` is the side of the
+    /// one that opened the bearer, taken by `channel`.
+    pub fn duplex<B: Bearer>(bearer: B, mode: Mode, protocols: &[u16]) -> Mux {
+        let (read, write) = bearer.split();
+        let modes = [Mode::Initiator, Mode::Responder];
+        Mux::start_modes(read, write, mode, &modes, protocols)
+    }
+
+    fn start_modes<R, W>(read: R, write: W, mode: Mode, modes: &[Mode], protocols: &[u16]) -> Mux
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // Bounded by the egress queues of the channels
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let mut senders = HashMap::new();
+        let mut channels = HashMap::new();
+        for &protocol in protocols {
+            for &mode in modes {
+                let (sender, incoming) = mpsc::unbounded_channel();
+                let ingress = Arc::new(Queue::default());
+                senders.insert((protocol, mode), (sender, ingress.clone()));
+                channels.insert(
+                    (protocol, mode),
+                    Channel {
+                        protocol,
+                        mode,
+                        incoming,
+                        ingress,
+                        outgoing: outgoing.clone(),
+                        egress: Arc::new(Queue::default()),
+                        buffer: vec![],
+                    },
+                );
+            }
+        }
+
+        // The writer stops once every channel is dropped or on `close`,
+        // after writing the messages already sent
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        metrics().connection_opened();
+        Mux {
+            mode,
+            channels,
+            reader: tokio::spawn(demux(read, senders)),
+            writer: tokio::spawn(mux(write, outgoing_rx, shutdown_rx)),
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Takes the channel of a mini-protocol, each can be taken once.
+    pub fn channel(&mut self, protocol: u16) -> Option<Channel> {
+        self.channel_in(self.mode, protocol)
+    }
+
+    /// Takes the channel of the `mode` side of a mini-protocol, both exist on
+    /// duplex bearers.
+    pub fn channel_in(&mut self, mode: Mode, protocol: u16) -> Option<Channel> {
+        self.channels.remove(&(protocol, mode))
+    }
+
+    /// Writes the messages sent so far, shuts the bearer down for writing
+    /// and stops reading. Channels fail to send from then on.
+    pub async fn close(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = (&mut self.writer).await;
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        self.reader.abort();
+        metrics().connection_closed();
+    }
+}
+
+type Ingress = (mpsc::UnboundedSender<Vec<u8>>, Arc<Queue>);
+
+async fn demux<R: AsyncRead + Unpin>(mut read: R, senders: HashMap<(u16, Mode), Ingress>) {
+    loop {
+        let (header, payload) = match read_segment(&mut read).await {
+            Ok(Some(segment)) => segment,
+            Ok(None) => {
+                debug!("Bearer closed");
+                return;
+            }
+            Err(error) => {
+                warn!("{}", error);
+                return;
+            }
+        };
+        metrics().bytes_received(header.protocol, SEGMENT_HEADER_SIZE + payload.len());
+        // Segments are for the other side of the mini-protocol than the one
+        // that sent them
+        let mode = match header.responder {
+            true => Mode::Initiator,
+            false => Mode::Responder,
+        };
+        match senders.get(&(header.protocol, mode)) {
+            Some((sender, ingress)) => {
+                let len = payload.len();
+                // The channel may have been dropped once its protocol is done
+                if sender.send(payload).is_ok() && ingress.push(len) > MAX_QUEUED_BYTES {
+                    warn!(
+                        "Protocol {}: Peer overran the ingress queue of {} bytes",
+                        header.protocol, MAX_QUEUED_BYTES
+                    );
+                    return;
+                }
+            }
+            None => debug!(
+                "Dropping segment of protocol {} for the {:?}",
+                header.protocol, mode
+            ),
+        }
+    }
+}
+
+async fn mux<W: AsyncWrite + Unpin>(
+    mut write: W,
+    mut outgoing: mpsc::UnboundedReceiver<Outgoing>,
+    mut shutdown: oneshot::Receiver<()>,
+) {
+    let start = Instant::now();
+    let mut shutdown_done = false;
+    loop {
+        let (mut header, message, egress) = select! {
+            next = outgoing.recv() => match next {
+                Some(next) => next,
+                None => break,
+            },
+            closed = &mut shutdown, if !shutdown_done => {
+                shutdown_done = true;
+                // Dropping the mux without closing it leaves the channels
+                // working, messages already queued are received either way
+                if closed.is_ok() {
+                    outgoing.close();
+                }
+                continue;
+            }
+        };
+        for payload in message.chunks(MAX_SEGMENT_PAYLOAD) {
+            header.timestamp = start.elapsed().as_micros() as u32;
+            header.length = payload.len() as u16;
+            let mut segment = header.to_bytes().to_vec();
+            segment.extend_from_slice(payload);
+            if let Err(error) = write.write_all(&segment).await {
+                warn!("Could not write segment: {}", error);
+                return;
+            }
+            metrics().bytes_sent(header.protocol, segment.len());
+        }
+        egress.pop(message.len());
+        if let Err(error) = write.flush().await {
+            warn!("Could not flush bearer: {}", error);
+            return;
+        }
+    }
+    if let Err(error) = write.shutdown().await {
+        debug!("Could not shut bearer down: {}", error);
+    }
+}
+
+/// Sends and receives whole messages of one mini-protocol.
+pub struct Channel {
+    protocol: u16,
+    mode: Mode,
+    incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+    ingress: Arc<Queue>,
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+    egress: Arc<Queue>,
+    // Received bytes not yet returned as a message
+    buffer: Vec<u8>,
+}
+
+impl Channel {
+    /// Queues `message` for writing. Fails when the egress queue is full,
+    /// senders of many messages in a row wait for `writable` first.
+    pub fn send(&self, message: Vec<u8>) -> Result<(), String> {
+        if self.egress.len() >= MAX_QUEUED_BYTES {
+            return Err(format!(
+                "Protocol {}: Egress queue of {} bytes is full",
+                self.protocol, MAX_QUEUED_BYTES
+            ));
+        }
+        let header = SegmentHeader {
+            timestamp: 0,
+            protocol: self.protocol,
+            responder: self.mode == Mode::Responder,
+            length: 0,
+        };
+        self.egress.push(message.len());
+        self.outgoing
+            .send((header, message, self.egress.clone()))
+            .map_err(|_| "Connection closed".to_owned())
+    }
+
+    /// Waits until the egress queue has room for a message, or the
+    /// connection is closed.
+    pub async fn writable(&self) {
+        loop {
+            let drained = self.egress.drained.notified();
+            if self.egress.len() < MAX_QUEUED_BYTES {
+                return;
+            }
+            select! {
+                _ = drained => {}
+                _ = self.outgoing.closed() => return,
+            }
+        }
+    }
+
+    pub fn send_value(&self, value: &Value) -> Result<(), String> {
+        let mut message = vec![];
+        into_writer(value, &mut message)
+            .map_err(|error| format!("Could not encode message: {:?}", error))?;
+        self.send(message)
+    }
+
+    /// Waits for the next message and returns its CBOR bytes.
+    pub async fn recv(&mut self) -> Result<Vec<u8>, String> {
+        loop {
+            if !self.buffer.is_empty() {
+                match item_len(&self.buffer) {
+                    Ok(len) => {
+                        let rest = self.buffer.split_off(len);
+                        return Ok(std::mem::replace(&mut self.buffer, rest));
+                    }
+                    Err(ItemError::Invalid(error)) => {
+                        return Err(format!("Protocol {}: {}", self.protocol, error))
+                    }
+                    Err(ItemError::Incomplete(_)) if self.buffer.len() > MAX_MESSAGE_SIZE => {
+                        return Err(format!(
+                            "Protocol {}: Message exceeds {} bytes",
+                            self.protocol, MAX_MESSAGE_SIZE
+                        ))
+                    }
+                    Err(ItemError::Incomplete(_)) => {}
+                }
+            }
+            let payload = self
+                .incoming
+                .recv()
+                .await
+                .ok_or("Connection closed".to_owned())?;
+            self.ingress.pop(payload.len());
+            self.buffer.extend_from_slice(&payload);
+        }
+    }
+
+    pub async fn recv_value(&mut self) -> Result<Value, String> {
+        let message = self.recv().await?;
+        from_reader(&message[..]).map_err(|error| format!("Could not decode message: {:?}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[test]
+    fn segment_header_round_trip() {
+        let header = SegmentHeader {
+            timestamp: 0x01020304,
+            protocol: 2,
+            responder: true,
+            length: 300,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes, [1, 2, 3, 4, 0x80, 2, 1, 44]);
+        assert_eq!(SegmentHeader::from_bytes(&bytes), header);
+    }
+
+    #[tokio::test]
+    async fn messages_span_segments() {
+        let (client, server) = crate::bearer::duplex();
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &[2, 3]);
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &[2, 3]);
+
+        let large = Value::Bytes(vec![7; 3 * MAX_SEGMENT_PAYLOAD]);
+        let mut client_chain_sync = initiator.channel(2).unwrap();
+        let client_block_fetch = initiator.channel(3).unwrap();
+        assert!(initiator.channel(2).is_none());
+        client_chain_sync.send_value(&large).unwrap();
+        client_block_fetch.send_value(&Value::from(1)).unwrap();
+        client_chain_sync.send_value(&Value::from(2)).unwrap();
+
+        let mut chain_sync = responder.channel(2).unwrap();
+        let mut block_fetch = responder.channel(3).unwrap();
+        assert_eq!(block_fetch.recv_value().await.unwrap(), Value::from(1));
+        assert_eq!(chain_sync.recv_value().await.unwrap(), large);
+        assert_eq!(chain_sync.recv_value().await.unwrap(), Value::from(2));
+
+        // Replies carry the mode bit and reach the initiator
+        chain_sync.send_value(&Value::from(3)).unwrap();
+        assert_eq!(
+            client_chain_sync.recv_value().await.unwrap(),
+            Value::from(3)
+        );
+
+        drop((chain_sync, block_fetch, responder));
+        assert!(client_chain_sync.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn duplex_runs_both_directions() {
+        let (client, server) = crate::bearer::duplex();
+        let mut ours = Mux::duplex(client, Mode::Initiator, &[2]);
+        let mut theirs = Mux::duplex(server, Mode::Responder, &[2]);
+
+        let our_client = ours.channel(2).unwrap();
+        let mut our_server = ours.channel_in(Mode::Responder, 2).unwrap();
+        assert!(ours.channel_in(Mode::Initiator, 2).is_none());
+        let mut their_client = theirs.channel_in(Mode::Initiator, 2).unwrap();
+        let mut their_server = theirs.channel(2).unwrap();
+
+        our_client.send_value(&Value::from(1)).unwrap();
+        their_client.send_value(&Value::from(2)).unwrap();
+        assert_eq!(their_server.recv_value().await.unwrap(), Value::from(1));
+        assert_eq!(our_server.recv_value().await.unwrap(), Value::from(2));
+        our_server.send_value(&Value::from(3)).unwrap();
+        assert_eq!(their_client.recv_value().await.unwrap(), Value::from(3));
+    }
+
+    #[tokio::test]
+    async fn ingress_overrun_drops_peer() {
+        let (client, server) = crate::bearer::duplex();
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &[2, 3]);
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &[2, 3]);
+
+        // Chain-sync is never read on the responder side
+        let chain_sync = initiator.channel(2).unwrap();
+        let message = Value::Bytes(vec![7; MAX_MESSAGE_SIZE - 16]);
+        for _ in 0..=MAX_QUEUED_BYTES / MAX_MESSAGE_SIZE {
+            chain_sync.writable().await;
+            chain_sync.send_value(&message).unwrap();
+        }
+        let mut block_fetch = responder.channel(3).unwrap();
+        assert_eq!(block_fetch.recv().await.unwrap_err(), "Connection closed");
+    }
+
+    #[tokio::test]
+    async fn egress_waits_for_writes() {
+        let (client, server) = crate::bearer::duplex();
+        let mut initiator = Mux::with_bearer(client, Mode::Initiator, &[2]);
+        let chain_sync = initiator.channel(2).unwrap();
+
+        // Nothing reads the bearer, the queue fills up
+        let message = Value::Bytes(vec![7; MAX_MESSAGE_SIZE - 16]);
+        while chain_sync.send_value(&message).is_ok() {}
+        let wait = Duration::from_millis(50);
+        assert!(timeout(wait, chain_sync.writable()).await.is_err());
+
+        let mut responder = Mux::with_bearer(server, Mode::Responder, &[2]);
+        let mut server_chain_sync = responder.channel(2).unwrap();
+        assert_eq!(server_chain_sync.recv_value().await.unwrap(), message);
+        timeout(Duration::from_secs(5), chain_sync.writable())
+            .await
+            .unwrap();
+    }
+}
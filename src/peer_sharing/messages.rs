@@ -0,0 +1,167 @@
+use ciborium::Value;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// Peer sharing mini-protocol: the client asks for up to an amount of peer
+// addresses and the server answers with some of the peers it knows. It runs
+// on node-to-node versions 11 and later when both sides enabled it in the
+// handshake. IPv6 addresses travel as four words in network byte order.
+
+pub const MINI_PROTOCOL_ID_PEER_SHARING: u16 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // MsgShareRequest
+    ShareRequest(u8),
+    // MsgSharePeers
+    SharePeers(Vec<SocketAddr>),
+    // MsgDone
+    Done,
+}
+
+impl Message {
+    pub fn to_value(&self) -> Value {
+        match self {
+            Message::ShareRequest(amount) => {
+                Value::Array(vec![Value::from(0), Value::from(*amount)])
+            }
+            Message::SharePeers(peers) => Value::Array(vec![
+                Value::from(1),
+                Value::Array(peers.iter().map(address_to_value).collect()),
+            ]),
+            Message::Done => Value::Array(vec![Value::from(2)]),
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Result<Message, String> {
+        let array = value
+            .as_array()
+            .ok_or("Could not convert Message into array")?;
+        let index = array
+            .first()
+            .and_then(|index| index.as_integer())
+            .ok_or("No index found at message index 0")?;
+        match i128::from(index) {
+            0 => {
+                let amount = array
+                    .get(1)
+                    .and_then(|amount| amount.as_integer())
+                    .ok_or("No amount found at message index 1")?;
+                let amount = u8::try_from(amount)
+                    .map_err(|_| format!("Amount {:?} out of range", amount))?;
+                Ok(Message::ShareRequest(amount))
+            }
+            1 => {
+                let peers = array
+                    .get(1)
+                    .and_then(|peers| peers.as_array())
+                    .ok_or("No peers found at message index 1")?;
+                Ok(Message::SharePeers(
+                    peers
+                        .iter()
+                        .map(address_from_value)
+                        .collect::<Result<_, _>>()?,
+                ))
+            }
+            2 => Ok(Message::Done),
+            index => Err(format!("Message: Do not expect any other index {}!", index)),
+        }
+    }
+}
+
+fn address_to_value(address: &SocketAddr) -> Value {
+    let mut fields = match address {
+        SocketAddr::V4(address) => {
+            vec![Value::from(0), Value::from(u32::from(*address.ip()))]
+        }
+        SocketAddr::V6(address) => {
+            let mut fields = vec![Value::from(1)];
+            fields.extend(
+                address
+                    .ip()
+                    .octets()
+                    .chunks(4)
+                    .map(|word| Value::from(u32::from_be_bytes(word.try_into().unwrap()))),
+            );
+            fields
+        }
+    };
+    fields.push(Value::from(address.port()));
+    Value::Array(fields)
+}
+
+fn address_from_value(value: &Value) -> Result<SocketAddr, String> {
+    let array = value
+        .as_array()
+        .ok_or("Could not convert peer address into array")?;
+    let words = array
+        .iter()
+        .skip(1)
+        .map(|word| {
+            word.as_integer()
+                .and_then(|word| u32::try_from(word).ok())
+                .ok_or_else(|| format!("Invalid peer address {:?}", value))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    let port = |word: u32| u16::try_from(word).map_err(|_| format!("Port {} out of range", word));
+    match (
+        array.first().and_then(|tag| tag.as_integer()),
+        words.as_slice(),
+    ) {
+        (Some(tag), [ip, port_word]) if i128::from(tag) == 0 => Ok(SocketAddr::new(
+            Ipv4Addr::from(*ip).into(),
+            port(*port_word)?,
+        )),
+        (Some(tag), [a, b, c, d, port_word]) if i128::from(tag) == 1 => {
+            let mut octets = [0; 16];
+            for (chunk, word) in octets.chunks_mut(4).zip([a, b, c, d]) {
+                chunk.copy_from_slice(&word.to_be_bytes());
+            }
+            Ok(SocketAddr::new(
+                Ipv6Addr::from(octets).into(),
+                port(*port_word)?,
+            ))
+        }
+        _ => Err(format!("Invalid peer address {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::ShareRequest(u8::MAX),
+            Message::SharePeers(vec![]),
+            Message::SharePeers(vec![
+                "192.0.2.1:3001".parse().unwrap(),
+                "[2001:db8::1]:6000".parse().unwrap(),
+            ]),
+            Message::Done,
+        ] {
+            assert_eq!(Message::from_value(&message.to_value()).unwrap(), message);
+        }
+        let ipv4 = Message::SharePeers(vec!["192.0.2.1:3001".parse().unwrap()]);
+        let address = Value::Array(vec![
+            Value::from(0),
+            Value::from(0xc000_0201u32),
+            Value::from(3001),
+        ]);
+        assert_eq!(
+            ipv4.to_value(),
+            Value::Array(vec![Value::from(1), Value::Array(vec![address])])
+        );
+        let amount = Value::Array(vec![Value::from(0), Value::from(256)]);
+        assert!(Message::from_value(&amount).is_err());
+        let port = Value::Array(vec![
+            Value::from(1),
+            Value::Array(vec![Value::Array(vec![
+                Value::from(0),
+                Value::from(1),
+                Value::from(65536),
+            ])]),
+        ]);
+        assert!(Message::from_value(&port).is_err());
+    }
+}
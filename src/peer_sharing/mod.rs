@@ -0,0 +1,5 @@
+mod messages;
+mod workflows;
+
+pub use self::messages::{Message, MINI_PROTOCOL_ID_PEER_SHARING};
+pub use self::workflows::{done, serve, share_request};
@@ -0,0 +1,78 @@
+use super::Message;
+use crate::mux::Channel;
+use std::net::SocketAddr;
+use tracing::debug;
+
+async fn recv(channel: &mut Channel) -> Result<Message, String> {
+    let message = Message::from_value(&channel.recv_value().await?)?;
+    debug!("Peer sharing received {:?}", message);
+    Ok(message)
+}
+
+/// Asks the server for up to `amount` peer addresses.
+pub async fn share_request(channel: &mut Channel, amount: u8) -> Result<Vec<SocketAddr>, String> {
+    channel.send_value(&Message::ShareRequest(amount).to_value())?;
+    match recv(channel).await? {
+        Message::SharePeers(peers) if peers.len() <= amount as usize => Ok(peers),
+        Message::SharePeers(peers) => {
+            Err(format!("Asked for {} peers, got {}", amount, peers.len()))
+        }
+        message => Err(format!("Expected shared peers, got {:?}", message)),
+    }
+}
+
+pub fn done(channel: &Channel) -> Result<(), String> {
+    channel.send_value(&Message::Done.to_value())
+}
+
+/// Answers each request with up to the amount asked of `peers` until the
+/// client is done or the connection closes.
+pub async fn serve(channel: &mut Channel, peers: &[SocketAddr]) -> Result<(), String> {
+    loop {
+        let message = match channel.recv_value().await {
+            Ok(value) => Message::from_value(&value)?,
+            // Clients do not always say they are done
+            Err(_) => return Ok(()),
+        };
+        match message {
+            Message::ShareRequest(amount) => {
+                let shared = peers.iter().take(amount as usize).copied().collect();
+                channel.send_value(&Message::SharePeers(shared).to_value())?
+            }
+            Message::Done => return Ok(()),
+            message => return Err(format!("Peer sharing server: Unexpected {:?}", message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bearer;
+    use crate::mux::{Mode, Mux};
+    use crate::peer_sharing::MINI_PROTOCOL_ID_PEER_SHARING;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let peers: Vec<SocketAddr> = vec![
+            "192.0.2.1:3001".parse().unwrap(),
+            "192.0.2.2:3001".parse().unwrap(),
+        ];
+        let (client, server) = bearer::duplex();
+        let protocols = [MINI_PROTOCOL_ID_PEER_SHARING];
+        let mut server = Mux::with_bearer(server, Mode::Responder, &protocols);
+        let mut channel = server.channel(MINI_PROTOCOL_ID_PEER_SHARING).unwrap();
+        let served = {
+            let peers = peers.clone();
+            tokio::spawn(async move { serve(&mut channel, &peers).await })
+        };
+
+        let mut client = Mux::with_bearer(client, Mode::Initiator, &protocols);
+        let mut channel = client.channel(MINI_PROTOCOL_ID_PEER_SHARING).unwrap();
+        assert_eq!(share_request(&mut channel, 1).await.unwrap(), peers[..1]);
+        assert_eq!(share_request(&mut channel, 5).await.unwrap(), peers);
+        assert_eq!(share_request(&mut channel, 0).await.unwrap(), vec![]);
+        done(&channel).unwrap();
+        assert_eq!(served.await.unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,342 @@
+use super::Point;
+use crate::codec::{item_len, split_array};
+use crate::consensus::{Era, Header};
+use crate::crypto::{blake2b_224, blake2b_256, Hash28, Hash32};
+use ciborium::{from_reader, Value};
+
+// Slots per Byron epoch, 10k with the security parameter k = 2160 of every
+// network that has Byron blocks.
+pub const BYRON_EPOCH_LENGTH: u64 = 21600;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockInfo {
+    pub slot: u64,
+    pub hash: Hash32,
+    // Byron epoch boundary block, stored in relative slot 0 of its chunk
+    pub is_ebb: bool,
+    // Position of the header within the block bytes
+    pub header_offset: u16,
+    pub header_size: u16,
+}
+
+impl BlockInfo {
+    pub fn point(&self) -> Point {
+        Point::Specific(self.slot, self.hash)
+    }
+}
+
+// What the stores need to know about a block beyond its bytes. The volatile
+// store keeps no index on disk, so it is recomputed from the blocks on open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockSummary {
+    pub info: BlockInfo,
+    // `None` for the first block after genesis
+    pub prev_hash: Option<Hash32>,
+    pub block_number: u64,
+    // `None` for Byron blocks
+    pub tiebreaker: Option<Tiebreaker>,
+}
+
+// What Praos chain selection compares of the tips of chains of equal length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tiebreaker {
+    // Pool id of the issuer, Blake2b-224 of its cold key
+    pub issuer: Hash28,
+    pub opcert_counter: u64,
+    // Leader VRF output
+    pub vrf_output: Vec<u8>,
+}
+
+/// Extracts the summary of a block from its bytes.
+pub type BlockParser = fn(&[u8]) -> Result<BlockSummary, String>;
+
+/// Parses a Praos (Babbage, Conway) block
+/// `[header, transaction_bodies, transaction_witness_sets, auxiliary_data_set, invalid_transactions]`.
+pub fn parse_praos_block(block: &[u8]) -> Result<BlockSummary, String> {
+    let items = split_array(block)?;
+    let header_bytes = items.first().ok_or("Block without header")?;
+    let header_offset = header_bytes.as_ptr() as usize - block.as_ptr() as usize;
+    let header_size = item_len(header_bytes)?;
+    let header = Header::from_cbor(header_bytes)?;
+    Ok(BlockSummary {
+        info: BlockInfo {
+            slot: header.body.slot,
+            hash: header.hash,
+            is_ebb: false,
+            header_offset: u16::try_from(header_offset)
+                .map_err(|_| "Header offset out of range")?,
+            header_size: u16::try_from(header_size).map_err(|_| "Header size out of range")?,
+        },
+        prev_hash: header.body.prev_hash,
+        block_number: header.body.block_number,
+        tiebreaker: Some(Tiebreaker {
+            issuer: header.issuer_pool_id(),
+            opcert_counter: header.body.operational_cert.sequence_number,
+            vrf_output: header.body.vrf_output.to_vec(),
+        }),
+    })
+}
+
+/// Parses a block as cardano-node stores it: tagged with its era,
+/// `[tag, block]`, see `Era::from_block_tag`. Only the fields the stores need
+/// are decoded, so every era is supported.
+pub fn parse_cardano_block(block: &[u8]) -> Result<BlockSummary, String> {
+    let (era, is_ebb, inner) = split_era(block)?;
+    let header = *split_array(inner)?.first().ok_or("Block without header")?;
+    let mut summary = parse_cardano_header(era, is_ebb, header)?;
+    summary.info.header_offset = header_offset(block, header)?;
+    Ok(summary)
+}
+
+/// Parses a block header of any era, as sent by chain-sync. `is_ebb` tells
+/// Byron epoch boundary headers from main block headers. The header position
+/// is left at the start of `header`.
+pub fn parse_cardano_header(era: Era, is_ebb: bool, header: &[u8]) -> Result<BlockSummary, String> {
+    if era == Era::Byron {
+        return parse_byron_header(is_ebb, header);
+    }
+
+    // The header body of every Shelley based era starts with block number,
+    // slot and previous hash
+    let header_body = *split_array(header)?.first().ok_or("Header without body")?;
+    let fields = split_array(header_body)?;
+    if fields.len() < 3 {
+        return Err(format!("Header body of {} items", fields.len()));
+    }
+    Ok(BlockSummary {
+        info: BlockInfo {
+            slot: decode_integer(fields[1], "slot")?,
+            hash: blake2b_256(header),
+            is_ebb: false,
+            header_offset: 0,
+            header_size: u16::try_from(header.len()).map_err(|_| "Header size out of range")?,
+        },
+        prev_hash: decode_hash(fields[2], "prev_hash")?,
+        block_number: decode_integer(fields[0], "block_number")?,
+        tiebreaker: parse_tiebreaker(&fields)?,
+    })
+}
+
+// Praos header bodies of Babbage and Conway have 10 fields, with the VRF
+// result at 5 and the operational certificate at 8. TPraos ones of Shelley
+// to Alonzo have 15, with the leader VRF result at 6 and the counter at 10.
+fn parse_tiebreaker(fields: &[&[u8]]) -> Result<Option<Tiebreaker>, String> {
+    let (vrf_result, counter) = match fields.len() {
+        10 => (fields[5], split_array(fields[8])?.get(1).copied()),
+        15 => (fields[6], Some(fields[10])),
+        _ => return Ok(None),
+    };
+    let issuer: Value = from_reader(fields[3])
+        .map_err(|error| format!("Could not decode issuer_vkey: {:?}", error))?;
+    let issuer = issuer.as_bytes().ok_or("Invalid issuer_vkey")?;
+    let vrf_output: Value = from_reader(vrf_result)
+        .map_err(|error| format!("Could not decode vrf_result: {:?}", error))?;
+    let vrf_output = vrf_output
+        .as_array()
+        .and_then(|result| result.first())
+        .and_then(|output| output.as_bytes())
+        .ok_or("Invalid vrf_result")?;
+    Ok(Some(Tiebreaker {
+        issuer: blake2b_224(issuer),
+        opcert_counter: decode_integer(
+            counter.ok_or("Invalid operational_cert")?,
+            "sequence_number",
+        )?,
+        vrf_output: vrf_output.clone(),
+    }))
+}
+
+/// Era of a block as cardano-node stores it, whether it is a Byron epoch
+/// boundary block, and the raw bytes of the block.
+pub fn split_era(block: &[u8]) -> Result<(Era, bool, &[u8]), String> {
+    match split_array(block)?[..] {
+        [tag, inner] => {
+            let (era, is_ebb) = Era::from_block_tag(decode_integer(tag, "block tag")?)?;
+            Ok((era, is_ebb, inner))
+        }
+        ref items => Err(format!(
+            "Tagged block: Do not expect array of {} items!",
+            items.len()
+        )),
+    }
+}
+
+// The hash of a Byron block covers its header tagged like the block:
+// `[0, ebb_header]` or `[1, main_header]`.
+fn parse_byron_header(is_ebb: bool, header: &[u8]) -> Result<BlockSummary, String> {
+    let fields = split_array(header)?;
+    if fields.len() != 5 {
+        return Err(format!(
+            "Byron header: Do not expect array of {} items!",
+            fields.len()
+        ));
+    }
+    let consensus: Value = from_reader(fields[3])
+        .map_err(|error| format!("Could not decode consensus data: {:?}", error))?;
+    let consensus = consensus
+        .into_array()
+        .map_err(|_| "Byron consensus data is not an array")?;
+    let integer = |value: Option<&Value>, field: &str| -> Result<u64, String> {
+        value
+            .and_then(|value| value.as_integer())
+            .and_then(|value| u64::try_from(value).ok())
+            .ok_or(format!("Invalid {}", field))
+    };
+    let difficulty = |value: Option<&Value>| {
+        integer(
+            value
+                .and_then(|value| value.as_array())
+                .and_then(|d| d.first()),
+            "difficulty",
+        )
+    };
+    let (slot, block_number) = match is_ebb {
+        true => (
+            integer(consensus.first(), "epoch")? * BYRON_EPOCH_LENGTH,
+            difficulty(consensus.get(1))?,
+        ),
+        false => {
+            let slot_id = consensus
+                .first()
+                .and_then(|value| value.as_array())
+                .ok_or("Invalid slot id")?;
+            let epoch = integer(slot_id.first(), "epoch")?;
+            let slot = integer(slot_id.get(1), "slot")?;
+            (
+                epoch * BYRON_EPOCH_LENGTH + slot,
+                difficulty(consensus.get(2))?,
+            )
+        }
+    };
+
+    let mut tagged = vec![0x82, if is_ebb { 0x00 } else { 0x01 }];
+    tagged.extend_from_slice(header);
+    Ok(BlockSummary {
+        info: BlockInfo {
+            slot,
+            hash: blake2b_256(&tagged),
+            is_ebb,
+            header_offset: 0,
+            header_size: u16::try_from(header.len()).map_err(|_| "Header size out of range")?,
+        },
+        prev_hash: decode_hash(fields[1], "prev_hash")?,
+        block_number,
+        tiebreaker: None,
+    })
+}
+
+fn header_offset(block: &[u8], header: &[u8]) -> Result<u16, String> {
+    u16::try_from(header.as_ptr() as usize - block.as_ptr() as usize)
+        .map_err(|_| "Header offset out of range".to_owned())
+}
+
+fn decode_integer(bytes: &[u8], field: &str) -> Result<u64, String> {
+    from_reader(bytes).map_err(|error| format!("Could not decode {}: {:?}", field, error))
+}
+
+fn decode_hash(bytes: &[u8], field: &str) -> Result<Option<Hash32>, String> {
+    let value: Value =
+        from_reader(bytes).map_err(|error| format!("Could not decode {}: {:?}", field, error))?;
+    match value {
+        Value::Null => Ok(None),
+        Value::Bytes(bytes) => Ok(Some(
+            bytes
+                .try_into()
+                .map_err(|_| format!("Invalid {} length", field))?,
+        )),
+        _ => Err(format!("Invalid {}", field)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::into_writer;
+
+    fn byron_block(tag: u64, consensus: Value) -> Vec<u8> {
+        let header = Value::Array(vec![
+            Value::from(764824073),
+            Value::Bytes(vec![1; 32]),
+            Value::Array(vec![]),
+            consensus,
+            Value::Array(vec![]),
+        ]);
+        let block = Value::Array(vec![
+            Value::from(tag),
+            Value::Array(vec![header, Value::Array(vec![]), Value::Array(vec![])]),
+        ]);
+        let mut bytes = vec![];
+        into_writer(&block, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn parse_byron_blocks() {
+        let ebb = byron_block(
+            0,
+            Value::Array(vec![
+                Value::from(3),
+                Value::Array(vec![Value::from(64_000)]),
+            ]),
+        );
+        let summary = parse_cardano_block(&ebb).unwrap();
+        assert!(summary.info.is_ebb);
+        assert_eq!(summary.info.slot, 3 * BYRON_EPOCH_LENGTH);
+        assert_eq!(summary.block_number, 64_000);
+        assert_eq!(summary.prev_hash, Some([1; 32]));
+
+        let main = byron_block(
+            1,
+            Value::Array(vec![
+                Value::Array(vec![Value::from(3), Value::from(7)]),
+                Value::Bytes(vec![0; 64]),
+                Value::Array(vec![Value::from(64_001)]),
+                Value::Array(vec![]),
+            ]),
+        );
+        let summary = parse_cardano_block(&main).unwrap();
+        assert!(!summary.info.is_ebb);
+        assert_eq!(summary.info.slot, 3 * BYRON_EPOCH_LENGTH + 7);
+        assert_eq!(summary.block_number, 64_001);
+
+        // The hash covers the header tagged as main block
+        let header =
+            &main[summary.info.header_offset as usize..][..summary.info.header_size as usize];
+        let mut tagged = vec![0x82, 0x01];
+        tagged.extend_from_slice(header);
+        assert_eq!(summary.info.hash, blake2b_256(&tagged));
+        assert_eq!(split_era(&main).unwrap().0, Era::Byron);
+        assert!(split_era(&ebb).unwrap().1);
+    }
+
+    #[test]
+    fn block_tags() {
+        let tagged = |tag: u8| [0x82, tag, 0x80];
+        let eras: Vec<(Era, bool)> = (0..=7)
+            .map(|tag| {
+                let block = tagged(tag);
+                let (era, is_ebb, inner) = split_era(&block).unwrap();
+                assert_eq!(inner, [0x80]);
+                (era, is_ebb)
+            })
+            .collect();
+        assert_eq!(
+            eras,
+            vec![
+                (Era::Byron, true),
+                (Era::Byron, false),
+                (Era::Shelley, false),
+                (Era::Allegra, false),
+                (Era::Mary, false),
+                (Era::Alonzo, false),
+                (Era::Babbage, false),
+                (Era::Conway, false),
+            ]
+        );
+        for (era, is_ebb) in eras {
+            let tag = era.block_tag(is_ebb);
+            assert_eq!(Era::from_block_tag(tag).unwrap(), (era, is_ebb));
+        }
+        assert!(split_era(&tagged(8)).is_err());
+    }
+}
@@ -0,0 +1,621 @@
+// ChainDB: owns the selected chain on top of the immutable and volatile
+// stores.
+//
+// The selected chain is the immutable chain followed by a fragment of at most
+// `security_param` (k) volatile blocks. Whenever a block is added, the chain
+// is re-selected among all chains through the volatile store that start at
+// the immutable tip, following the Praos rule: the longest chain wins, ties
+// are broken by the operational certificate counter or the VRF output of the
+// tips (see `breaks_tie`) and no switch may roll back more than k blocks. Blocks that fall more than k blocks behind the tip are copied into
+// the ImmutableDB and the volatile store is garbage collected.
+
+use super::{
+    BlockInfo, BlockParser, BlockSummary, DbError, ImmutableDb, ImmutableIterator, Point, Tip,
+    VolatileDb, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_BLOCKS_PER_FILE,
+};
+use crate::crypto::Hash32;
+use std::{collections::HashSet, path::Path};
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+const EVENTS_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    // The selected chain has a new tip. `rollback` is the intersection with
+    // the previous chain when blocks were rolled back first.
+    TipChanged { tip: Tip, rollback: Option<Point> },
+    // Blocks up to this point were copied into the ImmutableDB
+    ImmutableTipChanged(Point),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FollowerUpdate {
+    RollForward(BlockInfo, Vec<u8>),
+    RollBackward(Point),
+}
+
+pub struct ChainDb {
+    immutable: ImmutableDb,
+    volatile: VolatileDb,
+    parse: BlockParser,
+    security_param: u64,
+    // Immutable tip, `None` at genesis
+    anchor: Option<BlockSummary>,
+    // Selected blocks after the anchor, oldest first
+    chain: Vec<BlockSummary>,
+    events: broadcast::Sender<ChainEvent>,
+}
+
+impl ChainDb {
+    /// Opens `immutable/` and `volatile/` under `dir`, the layout used by
+    /// cardano-node, and selects the best chain from the stored blocks.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        security_param: u64,
+        parse: BlockParser,
+    ) -> Result<ChainDb, DbError> {
+        let dir = dir.as_ref();
+        let immutable = ImmutableDb::open(dir.join("immutable"), DEFAULT_CHUNK_SIZE)?;
+        let volatile = VolatileDb::open(dir.join("volatile"), DEFAULT_MAX_BLOCKS_PER_FILE, parse)?;
+        let anchor = match immutable.tip() {
+            Some(tip) => {
+                let block = immutable
+                    .get_block(&tip.point())?
+                    .ok_or(DbError::MissingBlock(tip.point()))?;
+                Some(parse(&block).map_err(DbError::Corrupt)?)
+            }
+            None => None,
+        };
+
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        let mut chain_db = ChainDb {
+            immutable,
+            volatile,
+            parse,
+            security_param,
+            anchor,
+            chain: vec![],
+            events,
+        };
+        chain_db.select_chain()?;
+        info!("Opened ChainDB {:?} at tip {:?}", dir, chain_db.tip());
+        Ok(chain_db)
+    }
+
+    pub fn tip(&self) -> Tip {
+        match self.chain.last().or(self.anchor.as_ref()) {
+            Some(summary) => Tip {
+                point: summary.info.point(),
+                block_number: summary.block_number,
+            },
+            None => Tip {
+                point: Point::Origin,
+                block_number: 0,
+            },
+        }
+    }
+
+    pub fn immutable_tip(&self) -> Point {
+        self.anchor
+            .as_ref()
+            .map_or(Point::Origin, |anchor| anchor.info.point())
+    }
+
+    /// Receives tip changes of the selected chain.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Adds a block received from a peer and re-selects the chain.
+    /// Blocks older than the immutable tip are ignored.
+    pub fn add_block(&mut self, block: &[u8]) -> Result<Tip, DbError> {
+        let summary = (self.parse)(block).map_err(DbError::Corrupt)?;
+        let immutable_slot = self.anchor.as_ref().map(|anchor| anchor.info.slot);
+        if immutable_slot.is_some_and(|slot| summary.info.slot <= slot) {
+            debug!(
+                "Ignoring block at slot {} before immutable tip",
+                summary.info.slot
+            );
+            return Ok(self.tip());
+        }
+        self.volatile.put_block(block)?;
+        self.select_chain()?;
+        Ok(self.tip())
+    }
+
+    fn select_chain(&mut self) -> Result<(), DbError> {
+        let anchor_hash = self.anchor.as_ref().map(|anchor| anchor.info.hash);
+
+        // Every block reachable from the anchor is the tip of a candidate
+        let mut candidates = vec![];
+        let mut pending = self.volatile.successors(anchor_hash.as_ref());
+        while let Some(hash) = pending.pop() {
+            if let Some(summary) = self.volatile.summary(&hash) {
+                candidates.push(summary.clone());
+            }
+            pending.extend(self.volatile.successors(Some(&hash)));
+        }
+        // Longest first, then by the lowest VRF output of equally long ones
+        candidates.sort_by_key(|candidate| {
+            (
+                std::cmp::Reverse(candidate.block_number),
+                candidate.tiebreaker.as_ref().map(|t| t.vrf_output.clone()),
+            )
+        });
+
+        let current = self.chain.last().or(self.anchor.as_ref()).cloned();
+        let current_number = current.as_ref().map_or(0, |tip| tip.block_number);
+        for candidate in candidates {
+            if candidate.block_number < current_number {
+                break;
+            }
+            if candidate.block_number == current_number
+                && current
+                    .as_ref()
+                    .is_some_and(|tip| !breaks_tie(&candidate, tip))
+            {
+                continue;
+            }
+            let fragment = self.fragment_to(candidate);
+            let common = self
+                .chain
+                .iter()
+                .zip(fragment.iter())
+                .take_while(|(a, b)| a.info.hash == b.info.hash)
+                .count();
+            let rollback = (self.chain.len() - common) as u64;
+            if rollback > self.security_param {
+                continue;
+            }
+            let intersection = match common {
+                0 => self.immutable_tip(),
+                _ => self.chain[common - 1].info.point(),
+            };
+            self.chain = fragment;
+            let event = ChainEvent::TipChanged {
+                tip: self.tip(),
+                rollback: (rollback > 0).then_some(intersection),
+            };
+            debug!("Selected new chain: {:?}", event);
+            let _ = self.events.send(event);
+            return self.copy_to_immutable();
+        }
+        self.copy_to_immutable()
+    }
+
+    // Chain from the anchor to `tip`, following predecessors in the store
+    fn fragment_to(&self, tip: BlockSummary) -> Vec<BlockSummary> {
+        let anchor_hash = self.anchor.as_ref().map(|anchor| anchor.info.hash);
+        let mut fragment = vec![tip];
+        while let Some(prev) = fragment.last().and_then(|block| block.prev_hash) {
+            // Copied blocks stay in the volatile store until collected
+            if Some(prev) == anchor_hash {
+                break;
+            }
+            match self.volatile.summary(&prev) {
+                Some(summary) => fragment.push(summary.clone()),
+                None => break,
+            }
+        }
+        fragment.reverse();
+        fragment
+    }
+
+    fn copy_to_immutable(&mut self) -> Result<(), DbError> {
+        let excess = (self.chain.len() as u64).saturating_sub(self.security_param) as usize;
+        if excess == 0 {
+            return Ok(());
+        }
+        for summary in self.chain.drain(..excess) {
+            let block = self
+                .volatile
+                .get_block(&summary.info.hash)?
+                .ok_or(DbError::MissingBlock(summary.info.point()))?;
+            self.immutable.append_block(summary.info.clone(), &block)?;
+            self.anchor = Some(summary);
+        }
+        self.immutable.sync()?;
+        let immutable_tip = self.immutable_tip();
+        if let Some(slot) = immutable_tip.slot() {
+            self.volatile.garbage_collect(slot)?;
+        }
+        let _ = self
+            .events
+            .send(ChainEvent::ImmutableTipChanged(immutable_tip));
+        Ok(())
+    }
+
+    fn fragment_index(&self, point: &Point) -> Option<usize> {
+        self.chain
+            .iter()
+            .position(|summary| summary.info.point() == *point)
+    }
+
+    /// Whether `point` is on the selected chain.
+    pub fn is_on_chain(&self, point: &Point) -> Result<bool, DbError> {
+        Ok(*point == Point::Origin
+            || self.fragment_index(point).is_some()
+            || self.immutable.contains(point)?)
+    }
+
+    /// First of `points` on the selected chain, as chain-sync's find
+    /// intersect expects.
+    pub fn find_intersect(&self, points: &[Point]) -> Result<Option<Point>, DbError> {
+        for point in points {
+            if self.is_on_chain(point)? {
+                return Ok(Some(*point));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_block(&self, point: &Point) -> Result<Option<Vec<u8>>, DbError> {
+        let hash = match point {
+            Point::Origin => return Ok(None),
+            Point::Specific(_, hash) => hash,
+        };
+        match self.volatile.get_block(hash)? {
+            Some(block) => Ok(Some(block)),
+            None => self.immutable.get_block(point),
+        }
+    }
+
+    /// A follower starting at `point`, which must be on the selected chain.
+    pub fn follower(&self, point: Point) -> Result<Follower, DbError> {
+        if !self.is_on_chain(&point)? {
+            return Err(DbError::MissingBlock(point));
+        }
+        Ok(Follower {
+            point,
+            immutable: None,
+        })
+    }
+
+    /// Blocks of the selected chain from `from` to `to`, both inclusive, as
+    /// block-fetch serves them.
+    pub fn stream(&self, from: &Point, to: &Point) -> Result<ChainIterator, DbError> {
+        let inverted =
+            || DbError::Corrupt(format!("Stream start {:?} is after its end {:?}", from, to));
+        let end = match self.fragment_index(to) {
+            Some(index) => Some(index),
+            None if self.immutable.contains(to)? => None,
+            None => return Err(DbError::MissingBlock(*to)),
+        };
+        let (immutable, start) = match self.fragment_index(from) {
+            // Volatile blocks all come after the immutable ones
+            Some(_) if end.is_none() => return Err(inverted()),
+            Some(index) => (None, index),
+            None => {
+                let immutable_to = match end {
+                    Some(_) => self.immutable_tip(),
+                    None => *to,
+                };
+                let iterator = match immutable_to {
+                    Point::Origin => None,
+                    _ => Some(self.immutable.stream(from, &immutable_to)?),
+                };
+                (iterator, 0)
+            }
+        };
+        let volatile = match end {
+            Some(end) if start <= end => self.chain[start..=end]
+                .iter()
+                .map(|summary| summary.info.clone())
+                .collect(),
+            Some(_) => return Err(inverted()),
+            None => vec![],
+        };
+        Ok(ChainIterator {
+            immutable,
+            volatile,
+        })
+    }
+
+    fn next_on_chain(
+        &self,
+        point: &Point,
+        cached: &mut Option<ImmutableIterator>,
+    ) -> Result<Option<(BlockInfo, Vec<u8>)>, DbError> {
+        if let Some(index) = self.fragment_index(point) {
+            return match self.chain.get(index + 1) {
+                Some(next) => self.read_volatile(&next.info).map(Some),
+                None => Ok(None),
+            };
+        }
+
+        // In the immutable part, continue with the cached iterator when it
+        // is positioned right after `point`
+        if let Some(iterator) = cached {
+            if let Some(next) = iterator.next() {
+                return next.map(Some);
+            }
+        }
+        let immutable_tip = self.immutable_tip();
+        if *point == immutable_tip {
+            *cached = None;
+            return match self.chain.first() {
+                Some(first) => self.read_volatile(&first.info).map(Some),
+                None => Ok(None),
+            };
+        }
+        let mut iterator = self.immutable.stream(point, &immutable_tip)?;
+        if *point != Point::Origin {
+            iterator.next().transpose()?;
+        }
+        let next = iterator.next().transpose()?;
+        *cached = Some(iterator);
+        Ok(next)
+    }
+
+    fn read_volatile(&self, info: &BlockInfo) -> Result<(BlockInfo, Vec<u8>), DbError> {
+        let block = self
+            .get_block(&info.point())?
+            .ok_or(DbError::MissingBlock(info.point()))?;
+        Ok((info.clone(), block))
+    }
+}
+
+/// Reader of the selected chain for serving chain-sync. It rolls back to the
+/// intersection when the chain it was following is switched away from.
+pub struct Follower {
+    // Last point sent
+    point: Point,
+    immutable: Option<ImmutableIterator>,
+}
+
+impl Follower {
+    pub fn point(&self) -> Point {
+        self.point
+    }
+
+    /// Next instruction for the follower, `None` when it is at the tip.
+    pub fn next(&mut self, chain_db: &ChainDb) -> Result<Option<FollowerUpdate>, DbError> {
+        if !chain_db.is_on_chain(&self.point)? {
+            // Walk back on the abandoned fork until the selected chain
+            let mut point = self.point;
+            let mut visited = HashSet::new();
+            while !chain_db.is_on_chain(&point)? {
+                let hash: Hash32 = match point {
+                    Point::Specific(_, hash) if visited.insert(hash) => hash,
+                    _ => break,
+                };
+                point = match chain_db.volatile.summary(&hash) {
+                    Some(summary) => match summary.prev_hash {
+                        Some(prev) => chain_db
+                            .volatile
+                            .summary(&prev)
+                            .map_or(chain_db.immutable_tip(), |s| s.info.point()),
+                        None => Point::Origin,
+                    },
+                    None => chain_db.immutable_tip(),
+                };
+            }
+            self.point = point;
+            self.immutable = None;
+            return Ok(Some(FollowerUpdate::RollBackward(point)));
+        }
+
+        match chain_db.next_on_chain(&self.point, &mut self.immutable)? {
+            Some((info, block)) => {
+                self.point = info.point();
+                Ok(Some(FollowerUpdate::RollForward(info, block)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+// Praos tie-break between tips of chains of equal length: of blocks the
+// same pool issued for the same slot the one with the higher operational
+// certificate counter wins, else the lower VRF output. The current tip stays
+// on ties.
+fn breaks_tie(candidate: &BlockSummary, tip: &BlockSummary) -> bool {
+    let (Some(ours), Some(theirs)) = (&candidate.tiebreaker, &tip.tiebreaker) else {
+        return false;
+    };
+    if ours.issuer == theirs.issuer
+        && candidate.info.slot == tip.info.slot
+        && ours.opcert_counter != theirs.opcert_counter
+    {
+        return ours.opcert_counter > theirs.opcert_counter;
+    }
+    ours.vrf_output < theirs.vrf_output
+}
+
+/// Iterator over a range of the selected chain for serving block-fetch.
+pub struct ChainIterator {
+    immutable: Option<ImmutableIterator>,
+    volatile: Vec<BlockInfo>,
+}
+
+impl ChainIterator {
+    pub fn next(&mut self, chain_db: &ChainDb) -> Result<Option<(BlockInfo, Vec<u8>)>, DbError> {
+        if let Some(iterator) = &mut self.immutable {
+            match iterator.next() {
+                Some(next) => return next.map(Some),
+                None => self.immutable = None,
+            }
+        }
+        if self.volatile.is_empty() {
+            return Ok(None);
+        }
+        let info = self.volatile.remove(0);
+        // The block may have been copied to the ImmutableDB meanwhile
+        chain_db.read_volatile(&info).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::parse_cardano_block;
+    use crate::storage::test_blocks::{chain, point, with_opcert_counter};
+
+    const K: u64 = 3;
+
+    #[test]
+    fn longest_chain_and_copy_to_immutable() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = ChainDb::open(dir.path(), K, parse_cardano_block).unwrap();
+        let mut events = db.subscribe();
+        let main = chain(None, 6, 0);
+        for block in &main {
+            db.add_block(block).unwrap();
+        }
+        assert_eq!(db.tip().point, point(&main[5]));
+        assert_eq!(db.tip().block_number, 6);
+        // Only the last k blocks stay volatile
+        assert_eq!(db.immutable_tip(), point(&main[2]));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ChainEvent::TipChanged {
+                tip: Tip {
+                    point: point(&main[0]),
+                    block_number: 1
+                },
+                rollback: None
+            }
+        );
+
+        // Reopening restores the same chain
+        drop(db);
+        let db = ChainDb::open(dir.path(), K, parse_cardano_block).unwrap();
+        assert_eq!(db.tip().point, point(&main[5]));
+        assert_eq!(db.immutable_tip(), point(&main[2]));
+        assert_eq!(
+            db.get_block(&point(&main[1])).unwrap(),
+            Some(main[1].clone())
+        );
+    }
+
+    #[test]
+    fn switch_to_longer_fork_within_k() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = ChainDb::open(dir.path(), K, parse_cardano_block).unwrap();
+        let main = chain(None, 4, 0);
+        for block in &main {
+            db.add_block(block).unwrap();
+        }
+        let mut events = db.subscribe();
+
+        // Equal length fork does not win
+        let fork = chain(Some(&main[1]), 3, 1);
+        for block in &fork[..2] {
+            db.add_block(block).unwrap();
+        }
+        assert_eq!(db.tip().point, point(&main[3]));
+
+        // Longer fork rolls back two blocks
+        db.add_block(&fork[2]).unwrap();
+        assert_eq!(db.tip().point, point(&fork[2]));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ChainEvent::TipChanged {
+                tip: Tip {
+                    point: point(&fork[2]),
+                    block_number: 5
+                },
+                rollback: Some(point(&main[1]))
+            }
+        );
+    }
+
+    #[test]
+    fn equal_length_tie_break() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = ChainDb::open(dir.path(), K, parse_cardano_block).unwrap();
+        let main = chain(None, 3, 2);
+        for block in &main {
+            db.add_block(block).unwrap();
+        }
+
+        // A fork of the same length wins with a lower VRF output
+        let fork = chain(Some(&main[0]), 2, 1);
+        for block in &fork {
+            db.add_block(block).unwrap();
+        }
+        assert_eq!(db.tip().point, point(&fork[1]));
+        for block in chain(Some(&main[0]), 2, 3) {
+            db.add_block(&block).unwrap();
+        }
+        assert_eq!(db.tip().point, point(&fork[1]));
+
+        // The same pool's block for the same slot wins with a higher
+        // operational certificate counter, whatever its VRF output
+        let reissued = with_opcert_counter(&fork[1], 1);
+        db.add_block(&reissued).unwrap();
+        assert_eq!(db.tip().point, point(&reissued));
+        db.add_block(&fork[1]).unwrap();
+        assert_eq!(db.tip().point, point(&reissued));
+    }
+
+    #[test]
+    fn fork_deeper_than_k_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = ChainDb::open(dir.path(), 10, parse_cardano_block).unwrap();
+        let main = chain(None, 6, 0);
+        for block in &main {
+            db.add_block(block).unwrap();
+        }
+        db.security_param = 2;
+        for block in chain(Some(&main[1]), 6, 1) {
+            db.add_block(&block).unwrap();
+        }
+        assert_eq!(db.tip().point, point(&main[5]));
+    }
+
+    #[test]
+    fn follower_rolls_back_on_switch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = ChainDb::open(dir.path(), K, parse_cardano_block).unwrap();
+        let main = chain(None, 6, 0);
+        for block in &main {
+            db.add_block(block).unwrap();
+        }
+
+        let mut follower = db.follower(Point::Origin).unwrap();
+        let mut followed = vec![];
+        while let Some(update) = follower.next(&db).unwrap() {
+            match update {
+                FollowerUpdate::RollForward(info, _) => followed.push(info.point()),
+                FollowerUpdate::RollBackward(_) => panic!("Unexpected rollback"),
+            }
+        }
+        assert_eq!(followed, main.iter().map(|b| point(b)).collect::<Vec<_>>());
+
+        for block in chain(Some(&main[3]), 3, 1) {
+            db.add_block(&block).unwrap();
+        }
+        assert_eq!(
+            follower.next(&db).unwrap(),
+            Some(FollowerUpdate::RollBackward(point(&main[3])))
+        );
+        assert!(matches!(
+            follower.next(&db).unwrap(),
+            Some(FollowerUpdate::RollForward(..))
+        ));
+    }
+
+    #[test]
+    fn stream_across_immutable_and_volatile() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = ChainDb::open(dir.path(), K, parse_cardano_block).unwrap();
+        let main = chain(None, 8, 0);
+        for block in &main {
+            db.add_block(block).unwrap();
+        }
+
+        let mut iterator = db.stream(&point(&main[2]), &point(&main[6])).unwrap();
+        let mut blocks = vec![];
+        while let Some((_, block)) = iterator.next(&db).unwrap() {
+            blocks.push(block);
+        }
+        assert_eq!(blocks, main[2..=6].to_vec());
+        assert!(db.stream(&point(&main[6]), &point(&main[5])).is_err());
+        // From the volatile fragment back to the immutable part
+        assert_eq!(db.immutable_tip(), point(&main[4]));
+        let error = db.stream(&point(&main[6]), &point(&main[2])).err().unwrap();
+        assert!(matches!(error, DbError::Corrupt(_)));
+    }
+}
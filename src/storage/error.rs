@@ -0,0 +1,48 @@
+use super::Point;
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum DbError {
+    Io(io::Error),
+    // Index or chunk content is inconsistent
+    Corrupt(String),
+    // Block bytes do not match the CRC recorded in the secondary index
+    ChecksumMismatch { chunk: u64, slot: u64 },
+    // Appended block does not come after the current tip
+    AppendBeforeTip { tip_slot: u64, slot: u64 },
+    // Requested point is not in the database
+    MissingBlock(Point),
+    // No chunk is finalized and the network of the magic has no known size
+    UnknownChunkSize(Option<u32>),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(error) => write!(f, "I/O error: {}", error),
+            DbError::Corrupt(error) => write!(f, "Corrupt database: {}", error),
+            DbError::ChecksumMismatch { chunk, slot } => {
+                write!(f, "Checksum mismatch in chunk {} at slot {}", chunk, slot)
+            }
+            DbError::AppendBeforeTip { tip_slot, slot } => write!(
+                f,
+                "Can not append block at slot {} after tip at slot {}",
+                slot, tip_slot
+            ),
+            DbError::MissingBlock(point) => write!(f, "Block not found: {:?}", point),
+            DbError::UnknownChunkSize(magic) => write!(
+                f,
+                "Chunk size unknown: no chunk is finalized and network magic {:?} has none",
+                magic
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<io::Error> for DbError {
+    fn from(error: io::Error) -> Self {
+        DbError::Io(error)
+    }
+}
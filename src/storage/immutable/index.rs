@@ -0,0 +1,156 @@
+// On-disk index formats of cardano-node's ImmutableDB.
+//
+// Every chunk `NNNNN` has three files:
+//
+// * `NNNNN.chunk`: the raw blocks, one after the other.
+// * `NNNNN.secondary`: one 56 byte entry per block, in chain order:
+//   block offset (u64), header offset (u16), header size (u16), CRC32 of the
+//   block (u32), header hash (32 bytes), slot or epoch for EBBs (u64). All
+//   integers are big endian.
+// * `NNNNN.primary`: a version byte followed by big endian u32 offsets into
+//   the secondary index, one per relative slot plus one. Relative slot `s` is
+//   filled when `offsets[s] < offsets[s + 1]`. Relative slot 0 is reserved
+//   for the epoch boundary block of Byron.
+
+use crate::crypto::Hash32;
+use crate::storage::DbError;
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+pub const PRIMARY_VERSION: u8 = 1;
+pub const SECONDARY_ENTRY_SIZE: u64 = 56;
+
+pub fn chunk_path(dir: &Path, chunk: u64, extension: &str) -> PathBuf {
+    dir.join(format!("{:05}.{}", chunk, extension))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecondaryEntry {
+    pub block_offset: u64,
+    pub header_offset: u16,
+    pub header_size: u16,
+    pub checksum: u32,
+    pub hash: Hash32,
+    pub block_or_ebb: u64,
+}
+
+impl SecondaryEntry {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SECONDARY_ENTRY_SIZE as usize);
+        bytes.extend_from_slice(&self.block_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.header_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.header_size.to_be_bytes());
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+        bytes.extend_from_slice(&self.hash);
+        bytes.extend_from_slice(&self.block_or_ebb.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> SecondaryEntry {
+        let u64_at = |at: usize| u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap());
+        let u16_at = |at: usize| u16::from_be_bytes(bytes[at..at + 2].try_into().unwrap());
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&bytes[16..48]);
+        SecondaryEntry {
+            block_offset: u64_at(0),
+            header_offset: u16_at(8),
+            header_size: u16_at(10),
+            checksum: u32::from_be_bytes(bytes[12..16].try_into().unwrap()),
+            hash,
+            block_or_ebb: u64_at(48),
+        }
+    }
+}
+
+/// Reads the primary index offsets. A trailing partial offset is ignored.
+pub fn read_primary(path: &Path) -> Result<Vec<u32>, DbError> {
+    let bytes = fs::read(path)?;
+    match bytes.first() {
+        Some(&PRIMARY_VERSION) => {}
+        Some(version) => {
+            return Err(DbError::Corrupt(format!(
+                "Unknown primary index version {} in {:?}",
+                version, path
+            )))
+        }
+        None => return Err(DbError::Corrupt(format!("Empty primary index {:?}", path))),
+    }
+    Ok(bytes[1..]
+        .chunks_exact(4)
+        .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()))
+        .collect())
+}
+
+pub fn primary_bytes(offsets: &[u32]) -> Vec<u8> {
+    let mut bytes = vec![PRIMARY_VERSION];
+    offsets
+        .iter()
+        .for_each(|offset| bytes.extend_from_slice(&offset.to_be_bytes()));
+    bytes
+}
+
+/// Filled relative slots of a primary index, in order.
+pub fn filled_slots(offsets: &[u32]) -> Vec<u64> {
+    offsets
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < pair[1])
+        .map(|(slot, _)| slot as u64)
+        .collect()
+}
+
+/// Reads all complete entries of a secondary index.
+pub fn read_secondary(path: &Path) -> Result<Vec<SecondaryEntry>, DbError> {
+    let bytes = fs::read(path)?;
+    Ok(bytes
+        .chunks_exact(SECONDARY_ENTRY_SIZE as usize)
+        .map(SecondaryEntry::from_bytes)
+        .collect())
+}
+
+/// Reads up to `count` secondary entries starting at entry `index`.
+pub fn read_secondary_entries(
+    path: &Path,
+    index: u64,
+    count: u64,
+) -> Result<Vec<SecondaryEntry>, DbError> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(index * SECONDARY_ENTRY_SIZE))?;
+    let mut bytes = vec![];
+    file.take(count * SECONDARY_ENTRY_SIZE)
+        .read_to_end(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(SECONDARY_ENTRY_SIZE as usize)
+        .map(SecondaryEntry::from_bytes)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secondary_entry_round_trip() {
+        let entry = SecondaryEntry {
+            block_offset: 1 << 40,
+            header_offset: 3,
+            header_size: 860,
+            checksum: 0xdeadbeef,
+            hash: [7; 32],
+            block_or_ebb: 4_492_800,
+        };
+        let bytes = entry.to_bytes();
+        assert_eq!(bytes.len(), SECONDARY_ENTRY_SIZE as usize);
+        assert_eq!(SecondaryEntry::from_bytes(&bytes), entry);
+    }
+
+    #[test]
+    fn filled_slots_of_primary() {
+        // EBB at relative slot 0, blocks at relative slots 1 and 4
+        assert_eq!(filled_slots(&[0, 56, 112, 112, 112, 168]), vec![0, 1, 4]);
+        assert_eq!(filled_slots(&[0]), Vec::<u64>::new());
+    }
+}
@@ -0,0 +1,767 @@
+// Append-only store for blocks that can no longer be rolled back, laid out
+// like cardano-node's ImmutableDB so tools for one can read the other.
+
+mod index;
+
+use self::index::{
+    chunk_path, filled_slots, primary_bytes, read_primary, read_secondary, read_secondary_entries,
+    SecondaryEntry, SECONDARY_ENTRY_SIZE,
+};
+use super::{BlockInfo, DbError, Point};
+use crate::codec::item_len;
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+// Slots per chunk used by cardano-node on every network: one Byron epoch.
+pub const DEFAULT_CHUNK_SIZE: u64 = 21600;
+
+const CHUNK: &str = "chunk";
+const PRIMARY: &str = "primary";
+const SECONDARY: &str = "secondary";
+
+// A block located in a chunk
+#[derive(Debug, Clone)]
+struct Located {
+    info: BlockInfo,
+    chunk: u64,
+    offset: u64,
+    // Offset of the next block in the chunk, if any
+    next_offset: Option<u64>,
+    checksum: u32,
+}
+
+pub struct ImmutableDb {
+    dir: PathBuf,
+    chunk_size: u64,
+    // Current chunk, the only one still appended to
+    chunk: u64,
+    chunk_file: File,
+    chunk_len: u64,
+    secondary_file: File,
+    primary_file: File,
+    offsets: Vec<u32>,
+    tip: Option<BlockInfo>,
+}
+
+impl ImmutableDb {
+    /// Opens the database in `dir`, creating it when missing.
+    ///
+    /// The most recent chunk is validated against its secondary index and
+    /// CRCs. Anything after the last intact block, e.g. a block that was only
+    /// partially written before a crash, is truncated away.
+    pub fn open(dir: impl AsRef<Path>, chunk_size: u64) -> Result<ImmutableDb, DbError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let chunk = match list_chunks(&dir)?.last() {
+            Some(chunk) => *chunk,
+            None => {
+                create_chunk(&dir, 0)?;
+                0
+            }
+        };
+
+        let (offsets, chunk_len, last) = recover_chunk(&dir, chunk, chunk_size)?;
+        let tip = match last {
+            Some(last) => Some(last.info),
+            None => (0..chunk)
+                .rev()
+                .find_map(|c| match load_chunk(&dir, c, chunk_size) {
+                    Ok(blocks) => blocks.last().map(|b| Ok(b.info.clone())),
+                    Err(error) => Some(Err(error)),
+                })
+                .transpose()?,
+        };
+        info!(
+            "Opened ImmutableDB {:?} at chunk {}, tip: {:?}",
+            dir, chunk, tip
+        );
+
+        Ok(ImmutableDb {
+            chunk_file: append_file(&chunk_path(&dir, chunk, CHUNK))?,
+            secondary_file: append_file(&chunk_path(&dir, chunk, SECONDARY))?,
+            primary_file: append_file(&chunk_path(&dir, chunk, PRIMARY))?,
+            dir,
+            chunk_size,
+            chunk,
+            chunk_len,
+            offsets,
+            tip,
+        })
+    }
+
+    pub fn tip(&self) -> Option<&BlockInfo> {
+        self.tip.as_ref()
+    }
+
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Appends a block after the current tip.
+    pub fn append_block(&mut self, info: BlockInfo, block: &[u8]) -> Result<(), DbError> {
+        if let Some(tip) = &self.tip {
+            let after_tip =
+                info.slot > tip.slot || (tip.is_ebb && !info.is_ebb && info.slot == tip.slot);
+            if !after_tip {
+                return Err(DbError::AppendBeforeTip {
+                    tip_slot: tip.slot,
+                    slot: info.slot,
+                });
+            }
+        }
+        if info.is_ebb && !info.slot.is_multiple_of(self.chunk_size) {
+            return Err(DbError::Corrupt(format!(
+                "EBB at slot {} is not at a chunk boundary",
+                info.slot
+            )));
+        }
+
+        let chunk = info.slot / self.chunk_size;
+        while self.chunk < chunk {
+            self.start_next_chunk()?;
+        }
+
+        let relative_slot = match info.is_ebb {
+            true => 0,
+            false => info.slot % self.chunk_size + 1,
+        };
+        let entry = SecondaryEntry {
+            block_offset: self.chunk_len,
+            header_offset: info.header_offset,
+            header_size: info.header_size,
+            checksum: crc32fast::hash(block),
+            hash: info.hash,
+            block_or_ebb: match info.is_ebb {
+                true => chunk,
+                false => info.slot,
+            },
+        };
+
+        // Same order as cardano-node: block, secondary entry, primary offsets
+        self.chunk_file.write_all(block)?;
+        self.secondary_file.write_all(&entry.to_bytes())?;
+        let written = self.offsets.len();
+        let current = *self.offsets.last().unwrap_or(&0);
+        while (self.offsets.len() as u64) <= relative_slot {
+            self.offsets.push(current);
+        }
+        self.offsets.push(current + SECONDARY_ENTRY_SIZE as u32);
+        self.primary_file
+            .write_all(&primary_bytes(&self.offsets[written..])[1..])?;
+
+        self.chunk_len += block.len() as u64;
+        self.tip = Some(info);
+        Ok(())
+    }
+
+    /// Flushes the files of the current chunk to disk.
+    pub fn sync(&mut self) -> Result<(), DbError> {
+        self.chunk_file.sync_data()?;
+        self.secondary_file.sync_data()?;
+        self.primary_file.sync_data()?;
+        Ok(())
+    }
+
+    fn start_next_chunk(&mut self) -> Result<(), DbError> {
+        // Backfill the primary index up to the last relative slot
+        let written = self.offsets.len();
+        let current = *self.offsets.last().unwrap_or(&0);
+        while (self.offsets.len() as u64) < self.chunk_size + 2 {
+            self.offsets.push(current);
+        }
+        self.primary_file
+            .write_all(&primary_bytes(&self.offsets[written..])[1..])?;
+        self.sync()?;
+
+        self.chunk += 1;
+        create_chunk(&self.dir, self.chunk)?;
+        self.chunk_file = append_file(&chunk_path(&self.dir, self.chunk, CHUNK))?;
+        self.secondary_file = append_file(&chunk_path(&self.dir, self.chunk, SECONDARY))?;
+        self.primary_file = append_file(&chunk_path(&self.dir, self.chunk, PRIMARY))?;
+        self.chunk_len = 0;
+        self.offsets = vec![0];
+        Ok(())
+    }
+
+    fn locate(&self, point: &Point) -> Result<Option<Located>, DbError> {
+        let (slot, hash) = match point {
+            Point::Origin => return Ok(None),
+            Point::Specific(slot, hash) => (*slot, hash),
+        };
+        let chunk = slot / self.chunk_size;
+        if chunk > self.chunk {
+            return Ok(None);
+        }
+        let offsets = match chunk == self.chunk {
+            true => self.offsets.clone(),
+            false => match read_primary(&chunk_path(&self.dir, chunk, PRIMARY)) {
+                Ok(offsets) => offsets,
+                Err(DbError::Io(_)) => return Ok(None),
+                Err(error) => return Err(error),
+            },
+        };
+
+        let mut relative_slots = vec![slot % self.chunk_size + 1];
+        if slot.is_multiple_of(self.chunk_size) {
+            relative_slots.push(0);
+        }
+        for relative_slot in relative_slots {
+            let relative_slot = relative_slot as usize;
+            if relative_slot + 1 >= offsets.len()
+                || offsets[relative_slot] >= offsets[relative_slot + 1]
+            {
+                continue;
+            }
+            let index = offsets[relative_slot] as u64 / SECONDARY_ENTRY_SIZE;
+            let entries =
+                read_secondary_entries(&chunk_path(&self.dir, chunk, SECONDARY), index, 2)?;
+            let entry = entries.first().ok_or(DbError::Corrupt(format!(
+                "Missing secondary entry {}",
+                index
+            )))?;
+            if entry.hash != *hash {
+                continue;
+            }
+            return Ok(Some(Located {
+                info: BlockInfo {
+                    slot,
+                    hash: entry.hash,
+                    is_ebb: relative_slot == 0,
+                    header_offset: entry.header_offset,
+                    header_size: entry.header_size,
+                },
+                chunk,
+                offset: entry.block_offset,
+                next_offset: entries.get(1).map(|next| next.block_offset),
+                checksum: entry.checksum,
+            }));
+        }
+        Ok(None)
+    }
+
+    pub fn contains(&self, point: &Point) -> Result<bool, DbError> {
+        Ok(self.locate(point)?.is_some())
+    }
+
+    pub fn get_block(&self, point: &Point) -> Result<Option<Vec<u8>>, DbError> {
+        match self.locate(point)? {
+            Some(located) => Ok(Some(read_block(&self.dir, &located)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterates over the blocks from `from` to `to`, both inclusive.
+    /// Starting at `Point::Origin` streams from the first block.
+    pub fn stream(&self, from: &Point, to: &Point) -> Result<ImmutableIterator, DbError> {
+        let end = self.locate(to)?.ok_or(DbError::MissingBlock(*to))?;
+        let start_chunk = match from {
+            Point::Origin => 0,
+            Point::Specific(slot, _) => {
+                if self.locate(from)?.is_none() {
+                    return Err(DbError::MissingBlock(*from));
+                }
+                if *slot > end.info.slot {
+                    return Err(DbError::Corrupt(format!(
+                        "Stream start {:?} is after its end {:?}",
+                        from, to
+                    )));
+                }
+                slot / self.chunk_size
+            }
+        };
+        Ok(ImmutableIterator {
+            dir: self.dir.clone(),
+            chunk_size: self.chunk_size,
+            next_chunk: start_chunk,
+            blocks: VecDeque::new(),
+            from: *from,
+            started: *from == Point::Origin,
+            end: Some(end.info.point()),
+            end_chunk: end.chunk,
+            done: false,
+        })
+    }
+}
+
+/// Iterator over a range of immutable blocks, yielding their info and bytes.
+pub struct ImmutableIterator {
+    dir: PathBuf,
+    chunk_size: u64,
+    next_chunk: u64,
+    blocks: VecDeque<Located>,
+    from: Point,
+    started: bool,
+    // Last block to yield, `None` to read until the last chunk
+    end: Option<Point>,
+    end_chunk: u64,
+    done: bool,
+}
+
+impl Iterator for ImmutableIterator {
+    type Item = Result<(BlockInfo, Vec<u8>), DbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let located = match self.blocks.pop_front() {
+                Some(located) => located,
+                None if self.next_chunk > self.end_chunk => return None,
+                None => {
+                    match load_chunk(&self.dir, self.next_chunk, self.chunk_size) {
+                        Ok(blocks) => self.blocks.extend(blocks),
+                        Err(error) => {
+                            self.done = true;
+                            return Some(Err(error));
+                        }
+                    }
+                    self.next_chunk += 1;
+                    continue;
+                }
+            };
+            if !self.started {
+                self.started = located.info.point() == self.from;
+                if !self.started {
+                    continue;
+                }
+            }
+            self.done = Some(located.info.point()) == self.end;
+            return Some(read_block(&self.dir, &located).map(|block| (located.info, block)));
+        }
+        None
+    }
+}
+
+/// Tip of the ImmutableDB in `dir`, read without opening it for writing.
+pub fn read_tip(dir: &Path, chunk_size: u64) -> Result<Option<BlockInfo>, DbError> {
+    for chunk in list_chunks(dir)?.iter().rev() {
+        if let Some(last) = load_chunk(dir, *chunk, chunk_size)?.pop() {
+            return Ok(Some(last.info));
+        }
+    }
+    Ok(None)
+}
+
+/// Iterates over the blocks of the ImmutableDB in `dir` from `from` on,
+/// without opening it for writing. Chunks appended while iterating are
+/// not picked up.
+pub fn read_from(dir: &Path, chunk_size: u64, from: &Point) -> Result<ImmutableIterator, DbError> {
+    let end_chunk = list_chunks(dir)?.last().copied();
+    let next_chunk = match from {
+        Point::Origin => 0,
+        Point::Specific(slot, _) => {
+            let chunk = slot / chunk_size;
+            if !load_chunk(dir, chunk, chunk_size)?
+                .iter()
+                .any(|located| located.info.point() == *from)
+            {
+                return Err(DbError::MissingBlock(*from));
+            }
+            chunk
+        }
+    };
+    Ok(ImmutableIterator {
+        dir: dir.to_path_buf(),
+        chunk_size,
+        next_chunk,
+        blocks: VecDeque::new(),
+        from: *from,
+        started: *from == Point::Origin,
+        end: None,
+        end_chunk: end_chunk.unwrap_or(0),
+        done: end_chunk.is_none(),
+    })
+}
+
+/// Chunk size of the ImmutableDB in `dir`, known once a chunk was finalized:
+/// its primary index then has an offset per relative slot plus one.
+pub fn infer_chunk_size(dir: &Path) -> Result<Option<u64>, DbError> {
+    let chunks = list_chunks(dir)?;
+    match chunks.len() {
+        0 | 1 => Ok(None),
+        _ => {
+            let offsets = read_primary(&chunk_path(dir, chunks[0], PRIMARY))?;
+            match offsets.len() {
+                0..=2 => Err(DbError::Corrupt(format!(
+                    "Primary index of finalized chunk {} has {} offsets",
+                    chunks[0],
+                    offsets.len()
+                ))),
+                len => Ok(Some(len as u64 - 2)),
+            }
+        }
+    }
+}
+
+fn append_file(path: &Path) -> Result<File, DbError> {
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+fn create_chunk(dir: &Path, chunk: u64) -> Result<(), DbError> {
+    File::create(chunk_path(dir, chunk, CHUNK))?;
+    File::create(chunk_path(dir, chunk, SECONDARY))?;
+    fs::write(chunk_path(dir, chunk, PRIMARY), primary_bytes(&[0]))?;
+    Ok(())
+}
+
+fn list_chunks(dir: &Path) -> Result<Vec<u64>, DbError> {
+    let mut chunks = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(CHUNK) {
+            continue;
+        }
+        if let Some(chunk) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        {
+            chunks.push(chunk);
+        }
+    }
+    chunks.sort();
+    Ok(chunks)
+}
+
+// Blocks of a chunk in chain order, from its primary and secondary index
+fn load_chunk(dir: &Path, chunk: u64, chunk_size: u64) -> Result<Vec<Located>, DbError> {
+    let offsets = match read_primary(&chunk_path(dir, chunk, PRIMARY)) {
+        Ok(offsets) => offsets,
+        // Chunks skipped over by empty slots may be missing altogether
+        Err(DbError::Io(_)) => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+    let entries = read_secondary(&chunk_path(dir, chunk, SECONDARY))?;
+    let slots = filled_slots(&offsets);
+    if slots.len() > entries.len() {
+        return Err(DbError::Corrupt(format!(
+            "Primary index of chunk {} has {} blocks, secondary {}",
+            chunk,
+            slots.len(),
+            entries.len()
+        )));
+    }
+    Ok(slots
+        .iter()
+        .zip(entries.iter())
+        .enumerate()
+        .map(|(i, (relative_slot, entry))| Located {
+            info: block_info(chunk, chunk_size, *relative_slot, entry),
+            chunk,
+            offset: entry.block_offset,
+            next_offset: entries.get(i + 1).map(|next| next.block_offset),
+            checksum: entry.checksum,
+        })
+        .collect())
+}
+
+fn block_info(
+    chunk: u64,
+    chunk_size: u64,
+    relative_slot: u64,
+    entry: &SecondaryEntry,
+) -> BlockInfo {
+    BlockInfo {
+        slot: match relative_slot {
+            0 => chunk * chunk_size,
+            _ => entry.block_or_ebb,
+        },
+        hash: entry.hash,
+        is_ebb: relative_slot == 0,
+        header_offset: entry.header_offset,
+        header_size: entry.header_size,
+    }
+}
+
+// Reads the bytes of a block and checks them against the recorded CRC. The
+// size of the last block of a chunk is taken from its CBOR encoding, so bytes
+// appended concurrently or left over by a crash are never included.
+fn read_block(dir: &Path, located: &Located) -> Result<Vec<u8>, DbError> {
+    let mut file = File::open(chunk_path(dir, located.chunk, CHUNK))?;
+    file.seek(SeekFrom::Start(located.offset))?;
+    let mut block = vec![];
+    match located.next_offset {
+        Some(next) => {
+            let size = next
+                .checked_sub(located.offset)
+                .ok_or(DbError::Corrupt(format!(
+                    "Block offsets out of order in chunk {}",
+                    located.chunk
+                )))?;
+            file.take(size).read_to_end(&mut block)?;
+        }
+        None => {
+            file.read_to_end(&mut block)?;
+            let size = item_len(&block).map_err(|error| DbError::Corrupt(error.into()))?;
+            block.truncate(size);
+        }
+    }
+    if crc32fast::hash(&block) != located.checksum {
+        return Err(DbError::ChecksumMismatch {
+            chunk: located.chunk,
+            slot: located.info.slot,
+        });
+    }
+    Ok(block)
+}
+
+// Keeps the longest prefix of intact blocks of a chunk, truncates its files
+// after it and rewrites the primary index. Returns the primary offsets, the
+// chunk length and the last block.
+fn recover_chunk(
+    dir: &Path,
+    chunk: u64,
+    chunk_size: u64,
+) -> Result<(Vec<u32>, u64, Option<Located>), DbError> {
+    let secondary_path = chunk_path(dir, chunk, SECONDARY);
+    let entries = read_secondary(&secondary_path).unwrap_or_default();
+    let has_ebb = match read_primary(&chunk_path(dir, chunk, PRIMARY)) {
+        Ok(offsets) => filled_slots(&offsets).first() == Some(&0),
+        Err(_) => false,
+    };
+
+    let chunk_file = chunk_path(dir, chunk, CHUNK);
+    let chunk_len = fs::metadata(&chunk_file)?.len();
+    let mut valid: Vec<Located> = vec![];
+    let mut last_relative_slot = None;
+    let mut end = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.block_offset != end {
+            break;
+        }
+        let relative_slot = match i == 0 && has_ebb {
+            true => 0,
+            false => match entry.block_or_ebb.checked_sub(chunk * chunk_size) {
+                Some(slot) if slot < chunk_size => slot + 1,
+                _ => break,
+            },
+        };
+        if last_relative_slot.is_some_and(|last| relative_slot <= last) {
+            break;
+        }
+        let located = Located {
+            info: block_info(chunk, chunk_size, relative_slot, entry),
+            chunk,
+            offset: entry.block_offset,
+            next_offset: entries
+                .get(i + 1)
+                .map(|next| next.block_offset)
+                .filter(|next| *next > entry.block_offset && *next <= chunk_len),
+            checksum: entry.checksum,
+        };
+        match read_block(dir, &located) {
+            Ok(block) => end += block.len() as u64,
+            Err(_) => break,
+        }
+        last_relative_slot = Some(relative_slot);
+        valid.push(located);
+    }
+    if let Some(last) = valid.last_mut() {
+        last.next_offset = None;
+    }
+
+    if valid.len() < entries.len() || chunk_len > end {
+        warn!(
+            "Truncating chunk {} to {} blocks ({} bytes), was {} blocks ({} bytes)",
+            chunk,
+            valid.len(),
+            end,
+            entries.len(),
+            chunk_len
+        );
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(&chunk_file)?
+        .set_len(end)?;
+    OpenOptions::new()
+        .write(true)
+        .open(&secondary_path)?
+        .set_len(valid.len() as u64 * SECONDARY_ENTRY_SIZE)?;
+
+    let mut offsets = vec![0u32];
+    for (i, located) in valid.iter().enumerate() {
+        let relative_slot = match located.info.is_ebb {
+            true => 0,
+            false => located.info.slot % chunk_size + 1,
+        };
+        let current = i as u32 * SECONDARY_ENTRY_SIZE as u32;
+        while (offsets.len() as u64) <= relative_slot {
+            offsets.push(current);
+        }
+        offsets.push(current + SECONDARY_ENTRY_SIZE as u32);
+    }
+    fs::write(chunk_path(dir, chunk, PRIMARY), primary_bytes(&offsets))?;
+
+    Ok((offsets, end, valid.pop()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::{into_writer, Value};
+
+    const CHUNK_SIZE: u64 = 10;
+
+    fn block(slot: u64) -> (BlockInfo, Vec<u8>) {
+        let mut bytes = vec![];
+        into_writer(
+            &Value::Array(vec![Value::from(slot), Value::Bytes(vec![slot as u8; 100])]),
+            &mut bytes,
+        )
+        .unwrap();
+        let info = BlockInfo {
+            slot,
+            hash: crate::crypto::blake2b_256(&bytes),
+            is_ebb: false,
+            header_offset: 1,
+            header_size: 9,
+        };
+        (info, bytes)
+    }
+
+    fn populated(dir: &Path, slots: &[u64]) -> ImmutableDb {
+        let mut db = ImmutableDb::open(dir, CHUNK_SIZE).unwrap();
+        for slot in slots {
+            let (info, bytes) = block(*slot);
+            db.append_block(info, &bytes).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn append_get_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let slots = [1, 2, 5, 9, 10, 27, 31];
+        let db = populated(dir.path(), &slots);
+        let (info, bytes) = block(27);
+        assert_eq!(db.get_block(&info.point()).unwrap(), Some(bytes));
+        assert_eq!(db.get_block(&Point::Specific(27, [0; 32])).unwrap(), None);
+        assert_eq!(db.tip().unwrap().slot, 31);
+        drop(db);
+
+        // Chunk 2 was skipped over but still exists, finalized
+        assert!(chunk_path(dir.path(), 1, CHUNK).exists());
+        assert_eq!(
+            read_primary(&chunk_path(dir.path(), 1, PRIMARY))
+                .unwrap()
+                .len(),
+            CHUNK_SIZE as usize + 2
+        );
+
+        let db = ImmutableDb::open(dir.path(), CHUNK_SIZE).unwrap();
+        assert_eq!(db.tip(), Some(&block(31).0));
+        let (info, bytes) = block(5);
+        assert_eq!(db.get_block(&info.point()).unwrap(), Some(bytes));
+    }
+
+    #[test]
+    fn stream_point_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = populated(dir.path(), &[1, 2, 5, 9, 10, 27, 31]);
+
+        let slots = |from: &Point, to: &Point| -> Vec<u64> {
+            db.stream(from, to)
+                .unwrap()
+                .map(|block| block.unwrap().0.slot)
+                .collect()
+        };
+        assert_eq!(
+            slots(&Point::Origin, &block(27).0.point()),
+            vec![1, 2, 5, 9, 10, 27]
+        );
+        assert_eq!(
+            slots(&block(5).0.point(), &block(10).0.point()),
+            vec![5, 9, 10]
+        );
+        assert!(matches!(
+            db.stream(&Point::Origin, &Point::Specific(3, [0; 32])),
+            Err(DbError::MissingBlock(_))
+        ));
+    }
+
+    #[test]
+    fn ebb_and_ordering() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = populated(dir.path(), &[3]);
+        let (mut ebb, bytes) = block(100);
+        ebb.slot = 10;
+        ebb.is_ebb = true;
+        db.append_block(ebb.clone(), &bytes).unwrap();
+        let (info, bytes) = block(10);
+        db.append_block(info.clone(), &bytes).unwrap();
+        assert!(matches!(
+            db.append_block(block(7).0, &block(7).1),
+            Err(DbError::AppendBeforeTip { .. })
+        ));
+
+        let infos: Vec<BlockInfo> = db
+            .stream(&Point::Origin, &info.point())
+            .unwrap()
+            .map(|block| block.unwrap().0)
+            .collect();
+        assert_eq!(infos, vec![block(3).0, ebb, info]);
+    }
+
+    #[test]
+    fn recover_partially_written_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(populated(dir.path(), &[11, 12, 14]));
+
+        // Crash in the middle of appending a block at slot 15: the block is
+        // only partially written and its index entries are missing
+        let chunk_file = chunk_path(dir.path(), 1, CHUNK);
+        let mut file = OpenOptions::new().append(true).open(&chunk_file).unwrap();
+        file.write_all(&block(15).1[..50]).unwrap();
+        // And the last complete block got corrupted
+        let len = fs::metadata(&chunk_file).unwrap().len();
+        let mut file = OpenOptions::new().write(true).open(&chunk_file).unwrap();
+        file.seek(SeekFrom::Start(len - 60)).unwrap();
+        file.write_all(&[0xff; 4]).unwrap();
+
+        let mut db = ImmutableDb::open(dir.path(), CHUNK_SIZE).unwrap();
+        assert_eq!(db.tip(), Some(&block(12).0));
+        assert_eq!(
+            fs::metadata(&chunk_file).unwrap().len(),
+            2 * block(12).1.len() as u64
+        );
+
+        let (info, bytes) = block(15);
+        db.append_block(info.clone(), &bytes).unwrap();
+        assert_eq!(db.get_block(&info.point()).unwrap(), Some(bytes));
+        assert_eq!(db.get_block(&block(14).0.point()).unwrap(), None);
+    }
+
+    #[test]
+    fn read_without_opening() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(populated(dir.path(), &[1, 2, 13, 27]));
+
+        assert_eq!(infer_chunk_size(dir.path()).unwrap(), Some(CHUNK_SIZE));
+        assert_eq!(read_tip(dir.path(), CHUNK_SIZE).unwrap(), Some(block(27).0));
+        let slots: Vec<u64> = read_from(dir.path(), CHUNK_SIZE, &block(2).0.point())
+            .unwrap()
+            .map(|block| block.unwrap().0.slot)
+            .collect();
+        assert_eq!(slots, vec![2, 13, 27]);
+        assert!(read_from(dir.path(), CHUNK_SIZE, &Point::Specific(3, [0; 32])).is_err());
+    }
+
+    #[test]
+    fn checksum_mismatch_on_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = populated(dir.path(), &[1, 2]);
+        let chunk_file = chunk_path(dir.path(), 0, CHUNK);
+        let mut file = OpenOptions::new().write(true).open(&chunk_file).unwrap();
+        file.seek(SeekFrom::Start(20)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+
+        assert!(matches!(
+            db.get_block(&block(1).0.point()),
+            Err(DbError::ChecksumMismatch { chunk: 0, slot: 1 })
+        ));
+    }
+}
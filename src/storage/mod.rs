@@ -0,0 +1,64 @@
+// Blocks and chain points, plus with the `storage` feature the on-disk
+// databases of cardano-node: ImmutableDB, VolatileDB and the ChainDB on top
+// of them.
+
+mod block;
+#[cfg(feature = "storage")]
+mod chain_db;
+#[cfg(feature = "storage")]
+mod error;
+#[cfg(feature = "storage")]
+mod immutable;
+#[cfg(feature = "storage")]
+mod node_db;
+#[cfg(test)]
+pub(crate) mod test_blocks;
+#[cfg(feature = "storage")]
+mod volatile;
+
+pub use self::block::{
+    parse_cardano_block, parse_cardano_header, parse_praos_block, split_era, BlockInfo,
+    BlockParser, BlockSummary, Tiebreaker, BYRON_EPOCH_LENGTH,
+};
+#[cfg(feature = "storage")]
+pub use self::chain_db::{ChainDb, ChainEvent, ChainIterator, Follower, FollowerUpdate};
+#[cfg(feature = "storage")]
+pub use self::error::DbError;
+#[cfg(feature = "storage")]
+pub use self::immutable::{ImmutableDb, ImmutableIterator, DEFAULT_CHUNK_SIZE};
+#[cfg(feature = "storage")]
+pub use self::node_db::{LedgerSnapshot, NodeDb, SnapshotMeta};
+#[cfg(feature = "storage")]
+pub use self::volatile::{VolatileDb, DEFAULT_MAX_BLOCKS_PER_FILE};
+
+use crate::crypto::Hash32;
+
+// cardano-node keeps its database under `./db`, run from `cardano-local/`
+// as described in the README.
+#[cfg(feature = "storage")]
+pub const DEFAULT_DB_PATH: &str = "cardano-local/db";
+
+/// A point on the chain: either genesis or a block identified by slot and
+/// header hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Point {
+    Origin,
+    Specific(u64, Hash32),
+}
+
+impl Point {
+    pub fn slot(&self) -> Option<u64> {
+        match self {
+            Point::Origin => None,
+            Point::Specific(slot, _) => Some(*slot),
+        }
+    }
+}
+
+/// Tip of a chain: its last point and block number. Origin has block number
+/// 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tip {
+    pub point: Point,
+    pub block_number: u64,
+}
@@ -0,0 +1,430 @@
+// Read-only access to the database directory of a Haskell cardano-node, the
+// `db/` the README runs it with.
+//
+//   db/protocolMagicId          network magic, as text
+//   db/immutable/NNNNN.*        chunks with primary and secondary indexes
+//   db/volatile/blocks-N.dat    the blocks of the last k slots
+//   db/ledger/<slot>[_<suffix>] ledger state snapshots, a file or, since
+//                               UTxO-HD, a directory with a `meta` file
+//
+// Nothing is ever written, so the node may keep running. Blocks are stored
+// tagged with their era, see `parse_cardano_block`.
+
+use super::immutable::{infer_chunk_size, read_from, read_tip};
+use super::{
+    parse_cardano_block, BlockInfo, BlockSummary, DbError, ImmutableDb, ImmutableIterator, Point,
+};
+use crate::codec::item_len;
+use crate::config::Network;
+use crate::crypto::Hash32;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone)]
+struct VolatileEntry {
+    summary: BlockSummary,
+    file: PathBuf,
+    offset: usize,
+    size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerSnapshot {
+    pub slot: u64,
+    // E.g. `db-analyser` for snapshots written by other tools
+    pub suffix: Option<String>,
+    pub path: PathBuf,
+    // Total size in bytes, of all files for directory snapshots
+    pub size: u64,
+    // Only known for directory snapshots
+    pub meta: Option<SnapshotMeta>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotMeta {
+    pub backend: Option<String>,
+    pub checksum: Option<u32>,
+    pub tables_codec_version: Option<u32>,
+}
+
+pub struct NodeDb {
+    dir: PathBuf,
+    chunk_size: u64,
+    protocol_magic: Option<u32>,
+    volatile: HashMap<Hash32, VolatileEntry>,
+    // Volatile blocks by the hash of their predecessor
+    successors: HashMap<Option<Hash32>, Vec<Hash32>>,
+}
+
+impl NodeDb {
+    /// Opens the database in `dir`. The chunk size is taken from a finalized
+    /// chunk, or else from the network of the protocol magic.
+    pub fn open(dir: impl AsRef<Path>) -> Result<NodeDb, DbError> {
+        let chunk_size = match infer_chunk_size(&dir.as_ref().join("immutable"))? {
+            Some(chunk_size) => chunk_size,
+            None => {
+                let magic = read_protocol_magic(dir.as_ref())?;
+                magic
+                    .and_then(Network::from_magic)
+                    .and_then(|network| network.preset().chunk_size)
+                    .ok_or(DbError::UnknownChunkSize(magic))?
+            }
+        };
+        NodeDb::open_with_chunk_size(dir, chunk_size)
+    }
+
+    pub fn open_with_chunk_size(dir: impl AsRef<Path>, chunk_size: u64) -> Result<NodeDb, DbError> {
+        let dir = dir.as_ref().to_path_buf();
+        if !dir.join("immutable").is_dir() {
+            return Err(DbError::Corrupt(format!(
+                "{:?} is not a cardano-node database: no immutable directory",
+                dir
+            )));
+        }
+        let protocol_magic = read_protocol_magic(&dir)?;
+
+        let mut db = NodeDb {
+            dir,
+            chunk_size,
+            protocol_magic,
+            volatile: HashMap::new(),
+            successors: HashMap::new(),
+        };
+        db.load_volatile()?;
+        info!(
+            "Opened cardano-node database {:?}: chunk size {}, magic {:?}, {} volatile blocks",
+            db.dir,
+            db.chunk_size,
+            db.protocol_magic,
+            db.volatile.len()
+        );
+        Ok(db)
+    }
+
+    // Indexes the volatile files up to the first block that does not parse,
+    // which may still be being written
+    fn load_volatile(&mut self) -> Result<(), DbError> {
+        let dir = self.dir.join("volatile");
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        let mut files = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let is_block_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("blocks-") && name.ends_with(".dat"));
+            if is_block_file {
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        for file in files {
+            let bytes = fs::read(&file)?;
+            let mut offset = 0;
+            while offset < bytes.len() {
+                let rest = &bytes[offset..];
+                let parsed = item_len(rest).map_err(String::from).and_then(|size| {
+                    parse_cardano_block(&rest[..size]).map(|summary| (size, summary))
+                });
+                let (size, summary) = match parsed {
+                    Ok(parsed) => parsed,
+                    Err(error) => {
+                        warn!(
+                            "Skipping rest of {:?} from offset {}: {}",
+                            file, offset, error
+                        );
+                        break;
+                    }
+                };
+                let hash = summary.info.hash;
+                if !self.volatile.contains_key(&hash) {
+                    self.successors
+                        .entry(summary.prev_hash)
+                        .or_default()
+                        .push(hash);
+                    self.volatile.insert(
+                        hash,
+                        VolatileEntry {
+                            summary,
+                            file: file.clone(),
+                            offset,
+                            size,
+                        },
+                    );
+                }
+                offset += size;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Network magic the node was started with, if it recorded it.
+    pub fn protocol_magic(&self) -> Option<u32> {
+        self.protocol_magic
+    }
+
+    pub fn immutable_tip(&self) -> Result<Option<BlockInfo>, DbError> {
+        read_tip(&self.dir.join("immutable"), self.chunk_size)
+    }
+
+    /// Immutable blocks from `from` on, `Point::Origin` for all of them.
+    pub fn immutable_blocks(&self, from: &Point) -> Result<ImmutableIterator, DbError> {
+        read_from(&self.dir.join("immutable"), self.chunk_size, from)
+    }
+
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    pub fn volatile_summary(&self, hash: &Hash32) -> Option<&BlockSummary> {
+        self.volatile.get(hash).map(|entry| &entry.summary)
+    }
+
+    pub fn volatile_block(&self, hash: &Hash32) -> Result<Option<Vec<u8>>, DbError> {
+        let entry = match self.volatile.get(hash) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let bytes = fs::read(&entry.file)?;
+        bytes
+            .get(entry.offset..entry.offset + entry.size)
+            .map(|block| Some(block.to_vec()))
+            .ok_or(DbError::Corrupt(format!(
+                "{:?} shrank while reading it",
+                entry.file
+            )))
+    }
+
+    /// Longest chain of volatile blocks on top of the immutable tip, oldest
+    /// first. This is the node's selected chain unless it was switching
+    /// forks when the files were read.
+    pub fn volatile_chain(&self) -> Result<Vec<BlockSummary>, DbError> {
+        let anchor = self.immutable_tip()?.map(|tip| tip.hash);
+        let mut best: Vec<&BlockSummary> = vec![];
+        let mut pending: Vec<Vec<&BlockSummary>> = vec![vec![]];
+        while let Some(path) = pending.pop() {
+            let tip = path
+                .last()
+                .map_or(anchor, |summary| Some(summary.info.hash));
+            let successors = self.successors.get(&tip).map_or(&[][..], |s| &s[..]);
+            if successors.is_empty() && path.len() > best.len() {
+                best = path.clone();
+            }
+            for hash in successors {
+                let mut next = path.clone();
+                next.push(&self.volatile[hash].summary);
+                pending.push(next);
+            }
+        }
+        Ok(best.into_iter().cloned().collect())
+    }
+
+    /// Ledger state snapshots, oldest first.
+    pub fn ledger_snapshots(&self) -> Result<Vec<LedgerSnapshot>, DbError> {
+        let dir = self.dir.join("ledger");
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut snapshots = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let (slot, suffix) = match name.split_once('_') {
+                Some((slot, suffix)) => (slot, Some(suffix.to_owned())),
+                None => (name.as_str(), None),
+            };
+            let slot = match slot.parse::<u64>() {
+                Ok(slot) => slot,
+                // Temporary files and the like
+                Err(_) => continue,
+            };
+            let meta = match fs::read(path.join("meta")) {
+                Ok(meta) => Some(serde_json::from_slice(&meta).map_err(|error| {
+                    DbError::Corrupt(format!("Invalid snapshot meta {:?}: {}", path, error))
+                })?),
+                Err(_) => None,
+            };
+            snapshots.push(LedgerSnapshot {
+                slot,
+                suffix,
+                size: disk_size(&path)?,
+                path,
+                meta,
+            });
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.slot);
+        Ok(snapshots)
+    }
+
+    /// Appends the immutable blocks after the tip of `target` to it, which is
+    /// how our node bootstraps from a Haskell node's database. Returns the
+    /// number of blocks copied.
+    pub fn import_immutable(&self, target: &mut ImmutableDb) -> Result<u64, DbError> {
+        let from = target.tip().map_or(Point::Origin, |tip| tip.point());
+        let mut blocks = self.immutable_blocks(&from)?;
+        if from != Point::Origin {
+            blocks.next().transpose()?;
+        }
+        let mut copied = 0;
+        for block in blocks {
+            let (info, bytes) = block?;
+            target.append_block(info, &bytes)?;
+            copied += 1;
+        }
+        target.sync()?;
+        info!("Imported {} blocks from {:?}", copied, self.dir);
+        Ok(copied)
+    }
+}
+
+fn disk_size(path: &Path) -> Result<u64, DbError> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += disk_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+fn read_protocol_magic(dir: &Path) -> Result<Option<u32>, DbError> {
+    match fs::read_to_string(dir.join("protocolMagicId")) {
+        Ok(magic) => Ok(Some(magic.trim().parse().map_err(|_| {
+            DbError::Corrupt(format!("Invalid protocol magic {:?}", magic.trim()))
+        })?)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_blocks::{block, hash};
+
+    const CHUNK_SIZE: u64 = 10;
+
+    #[test]
+    fn read_node_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_dir = dir.path();
+        fs::write(db_dir.join("protocolMagicId"), "2").unwrap();
+
+        // Immutable blocks at slots 1 to 12, the chain continues in the
+        // volatile files, with a shorter fork at slot 14
+        let mut blocks = vec![block(1, 1, None)];
+        for slot in 2..=14 {
+            let prev = blocks.last().unwrap().clone();
+            blocks.push(block(slot, slot, Some(&prev)));
+        }
+        let fork = block(14, 13, Some(&blocks[11]));
+        let mut immutable = ImmutableDb::open(db_dir.join("immutable"), CHUNK_SIZE).unwrap();
+        for bytes in &blocks[..12] {
+            let info = parse_cardano_block(bytes).unwrap().info;
+            immutable.append_block(info, bytes).unwrap();
+        }
+        drop(immutable);
+        fs::create_dir(db_dir.join("volatile")).unwrap();
+        let mut volatile = blocks[11..].concat();
+        volatile.extend_from_slice(&fork);
+        // Partially written block
+        volatile.extend_from_slice(&blocks[0][..10]);
+        fs::write(db_dir.join("volatile/blocks-0.dat"), &volatile).unwrap();
+
+        fs::create_dir_all(db_dir.join("ledger/200_db-analyser")).unwrap();
+        fs::write(db_dir.join("ledger/100"), [0; 64]).unwrap();
+        fs::write(
+            db_dir.join("ledger/200_db-analyser/meta"),
+            r#"{"backend":"utxohd-mem","checksum":1234,"tablesCodecVersion":1}"#,
+        )
+        .unwrap();
+        fs::write(db_dir.join("ledger/200_db-analyser/state"), [0; 32]).unwrap();
+
+        let db = NodeDb::open(db_dir).unwrap();
+        assert_eq!(db.chunk_size(), CHUNK_SIZE);
+        assert_eq!(db.protocol_magic(), Some(2));
+        assert_eq!(db.immutable_tip().unwrap().unwrap().slot, 12);
+        let immutable: Vec<Vec<u8>> = db
+            .immutable_blocks(&Point::Origin)
+            .unwrap()
+            .map(|block| block.unwrap().1)
+            .collect();
+        assert_eq!(immutable, blocks[..12].to_vec());
+
+        assert_eq!(db.volatile_len(), 4);
+        let chain: Vec<u64> = db
+            .volatile_chain()
+            .unwrap()
+            .iter()
+            .map(|summary| summary.info.slot)
+            .collect();
+        assert_eq!(chain, vec![13, 14]);
+        assert_eq!(
+            db.volatile_summary(&hash(&fork))
+                .map(|summary| summary.block_number),
+            Some(13)
+        );
+
+        let snapshots = db.ledger_snapshots().unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!((snapshots[0].slot, snapshots[0].size), (100, 64));
+        assert_eq!(snapshots[1].suffix.as_deref(), Some("db-analyser"));
+        assert_eq!(
+            snapshots[1].meta.as_ref().unwrap().backend.as_deref(),
+            Some("utxohd-mem")
+        );
+
+        // Bootstrap our own ImmutableDB, in two steps
+        let target_dir = tempfile::tempdir().unwrap();
+        let mut target = ImmutableDb::open(target_dir.path(), CHUNK_SIZE).unwrap();
+        let first = parse_cardano_block(&blocks[0]).unwrap().info;
+        target.append_block(first, &blocks[0]).unwrap();
+        assert_eq!(db.import_immutable(&mut target).unwrap(), 11);
+        assert_eq!(target.tip().unwrap().slot, 12);
+    }
+
+    #[test]
+    fn chunk_size_of_the_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_dir = dir.path();
+        let mut immutable = ImmutableDb::open(db_dir.join("immutable"), CHUNK_SIZE).unwrap();
+        let first = block(1, 1, None);
+        let info = parse_cardano_block(&first).unwrap().info;
+        immutable.append_block(info, &first).unwrap();
+        drop(immutable);
+
+        // A single chunk does not tell its size
+        assert!(matches!(
+            NodeDb::open(db_dir),
+            Err(DbError::UnknownChunkSize(None))
+        ));
+        fs::write(db_dir.join("protocolMagicId"), "4").unwrap();
+        assert!(matches!(
+            NodeDb::open(db_dir),
+            Err(DbError::UnknownChunkSize(Some(4)))
+        ));
+        fs::write(db_dir.join("protocolMagicId"), "2").unwrap();
+        assert_eq!(NodeDb::open(db_dir).unwrap().chunk_size(), 4320);
+    }
+}
@@ -0,0 +1,126 @@
+// Blocks as cardano-node stores them, for tests: Conway blocks
+// `[7, [header, transaction_bodies, transaction_witness_sets,
+// auxiliary_data_set, invalid_transactions]]` with a complete header. The
+// header body hashes and sizes the block body, the keys and signatures are
+// well-formed but not valid. The VRF output repeats the issuer byte, so that
+// chains of lower issuers win ties.
+
+use super::parse_cardano_block;
+use crate::crypto::{blake2b_256, Hash32};
+use ciborium::{into_writer, Value};
+
+const CONWAY_TAG: u64 = 7;
+const KES_SIGNATURE_SIZE: usize = 448;
+
+fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = vec![];
+    into_writer(value, &mut bytes).unwrap();
+    bytes
+}
+
+/// Block at `slot` with `block_number` after `prev`, issued by `issuer`,
+/// carrying `transaction_bodies` of which those at the `invalid` indices
+/// failed script validation.
+pub fn conway_block(
+    slot: u64,
+    block_number: u64,
+    prev: Option<&[u8]>,
+    issuer: u8,
+    transaction_bodies: Vec<Value>,
+    invalid: Vec<u64>,
+) -> Vec<u8> {
+    let witness_sets = vec![Value::Map(vec![]); transaction_bodies.len()];
+    let body = [
+        Value::Array(transaction_bodies),
+        Value::Array(witness_sets),
+        Value::Map(vec![]),
+        Value::Array(invalid.into_iter().map(Value::from).collect()),
+    ];
+    let mut hashes = vec![];
+    let mut body_size = 0;
+    for part in body.iter().map(encode) {
+        hashes.extend_from_slice(&blake2b_256(&part));
+        body_size += part.len() as u64;
+    }
+
+    let prev_hash = match prev {
+        Some(prev) => Value::Bytes(hash(prev).to_vec()),
+        None => Value::Null,
+    };
+    let header_body = Value::Array(vec![
+        Value::from(block_number),
+        Value::from(slot),
+        prev_hash,
+        Value::Bytes(vec![issuer; 32]),
+        Value::Bytes(vec![issuer; 32]),
+        Value::Array(vec![
+            Value::Bytes(vec![issuer; 64]),
+            Value::Bytes(vec![0; 80]),
+        ]),
+        Value::from(body_size),
+        Value::Bytes(blake2b_256(&hashes).to_vec()),
+        Value::Array(vec![
+            Value::Bytes(vec![0; 32]),
+            Value::from(0),
+            Value::from(0),
+            Value::Bytes(vec![0; 64]),
+        ]),
+        Value::Array(vec![Value::from(10), Value::from(0)]),
+    ]);
+    let header = Value::Array(vec![header_body, Value::Bytes(vec![0; KES_SIGNATURE_SIZE])]);
+    let mut block = vec![header];
+    block.extend(body);
+    encode(&Value::Array(vec![
+        Value::from(CONWAY_TAG),
+        Value::Array(block),
+    ]))
+}
+
+#[cfg(feature = "storage")]
+/// Block without transactions at `slot` with `block_number` after `prev`.
+pub fn block(slot: u64, block_number: u64, prev: Option<&[u8]>) -> Vec<u8> {
+    conway_block(slot, block_number, prev, 0, vec![], vec![])
+}
+
+#[cfg(feature = "storage")]
+/// `length` blocks after `prev`, one per slot. Chains of another `issuer`
+/// fork from the same `prev` with other blocks.
+pub fn chain(prev: Option<&[u8]>, length: u64, issuer: u8) -> Vec<Vec<u8>> {
+    let (mut slot, mut number) = match prev {
+        Some(prev) => {
+            let summary = parse_cardano_block(prev).unwrap();
+            (summary.info.slot, summary.block_number)
+        }
+        None => (0, 0),
+    };
+    let mut blocks: Vec<Vec<u8>> = vec![];
+    for _ in 0..length {
+        slot += 1;
+        number += 1;
+        let prev = blocks.last().map(Vec::as_slice).or(prev);
+        blocks.push(conway_block(slot, number, prev, issuer, vec![], vec![]));
+    }
+    blocks
+}
+
+#[cfg(feature = "storage")]
+/// `block` with another operational certificate counter.
+pub fn with_opcert_counter(block: &[u8], counter: u64) -> Vec<u8> {
+    let mut value: Value = ciborium::from_reader(block).unwrap();
+    let header_body = &mut value.as_array_mut().unwrap()[1].as_array_mut().unwrap()[0]
+        .as_array_mut()
+        .unwrap()[0];
+    header_body.as_array_mut().unwrap()[8]
+        .as_array_mut()
+        .unwrap()[1] = Value::from(counter);
+    encode(&value)
+}
+
+pub fn hash(block: &[u8]) -> Hash32 {
+    parse_cardano_block(block).unwrap().info.hash
+}
+
+#[cfg(feature = "storage")]
+pub fn point(block: &[u8]) -> super::Point {
+    parse_cardano_block(block).unwrap().info.point()
+}
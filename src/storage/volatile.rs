@@ -0,0 +1,432 @@
+// Store for the blocks of the last k slots, which may still be rolled back.
+//
+// Like cardano-node's VolatileDB, blocks are appended to `blocks-N.dat` files
+// holding a bounded number of blocks each, and all indexes live in memory:
+// they are rebuilt by parsing the files when the store is opened. A file is
+// deleted once every block in it is older than the immutable tip.
+
+use super::{BlockParser, BlockSummary, DbError};
+use crate::codec::{item_len, ItemError};
+use crate::crypto::Hash32;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+pub const DEFAULT_MAX_BLOCKS_PER_FILE: usize = 1000;
+
+#[derive(Debug, Clone)]
+struct Location {
+    summary: BlockSummary,
+    file: u64,
+    offset: u64,
+    size: u64,
+}
+
+#[derive(Debug, Default)]
+struct FileInfo {
+    max_slot: Option<u64>,
+    hashes: Vec<Hash32>,
+}
+
+pub struct VolatileDb {
+    dir: PathBuf,
+    max_blocks_per_file: usize,
+    parse: BlockParser,
+    blocks: HashMap<Hash32, Location>,
+    // Blocks by the hash of their predecessor, `None` for genesis
+    successors: HashMap<Option<Hash32>, HashSet<Hash32>>,
+    files: BTreeMap<u64, FileInfo>,
+    current_file: u64,
+    current: File,
+    current_len: u64,
+}
+
+fn file_path(dir: &Path, file: u64) -> PathBuf {
+    dir.join(format!("blocks-{}.dat", file))
+}
+
+impl VolatileDb {
+    /// Opens the store in `dir`, creating it when missing.
+    ///
+    /// Every file is parsed block by block. A file is truncated after the
+    /// last block that parses, which drops a block partially written before
+    /// a crash.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        max_blocks_per_file: usize,
+        parse: BlockParser,
+    ) -> Result<VolatileDb, DbError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut db_files = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            if let Some(file) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("blocks-"))
+                .and_then(|name| name.strip_suffix(".dat"))
+                .and_then(|id| id.parse::<u64>().ok())
+            {
+                db_files.push(file);
+            }
+        }
+        db_files.sort();
+
+        let current_file = db_files.last().copied().unwrap_or(0);
+        let mut db = VolatileDb {
+            current: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path(&dir, current_file))?,
+            dir,
+            max_blocks_per_file,
+            parse,
+            blocks: HashMap::new(),
+            successors: HashMap::new(),
+            files: BTreeMap::new(),
+            current_file,
+            current_len: 0,
+        };
+        db.files.insert(current_file, FileInfo::default());
+        for file in db_files {
+            let len = db.load_file(file)?;
+            if file == current_file {
+                db.current_len = len;
+            }
+        }
+        info!(
+            "Opened VolatileDB {:?} with {} blocks in {} files",
+            db.dir,
+            db.blocks.len(),
+            db.files.len()
+        );
+        Ok(db)
+    }
+
+    // Indexes the blocks of a file and returns its valid length. Only a
+    // block cut short by a crash is cut off the file, blocks the parser
+    // rejects are left in place and skipped.
+    fn load_file(&mut self, file: u64) -> Result<u64, DbError> {
+        let path = file_path(&self.dir, file);
+        let bytes = fs::read(&path)?;
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let rest = &bytes[offset..];
+            let size = match item_len(rest) {
+                Ok(size) => size,
+                Err(ItemError::Incomplete(error)) => {
+                    warn!(
+                        "Truncating {:?} at offset {} of {}: {}",
+                        path,
+                        offset,
+                        bytes.len(),
+                        error
+                    );
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(offset as u64)?;
+                    break;
+                }
+                Err(ItemError::Invalid(error)) => {
+                    return Err(DbError::Corrupt(format!(
+                        "{:?} at offset {}: {}",
+                        path, offset, error
+                    )))
+                }
+            };
+            match (self.parse)(&rest[..size]) {
+                Ok(summary) => {
+                    if !self.blocks.contains_key(&summary.info.hash) {
+                        self.index(summary, file, offset as u64, size as u64);
+                    }
+                }
+                Err(error) => warn!(
+                    "Skipping block of {:?} at offset {}: {}",
+                    path, offset, error
+                ),
+            }
+            offset += size;
+        }
+        self.files.entry(file).or_default();
+        Ok(offset as u64)
+    }
+
+    fn index(&mut self, summary: BlockSummary, file: u64, offset: u64, size: u64) {
+        let hash = summary.info.hash;
+        let file_info = self.files.entry(file).or_default();
+        file_info.max_slot = file_info.max_slot.max(Some(summary.info.slot));
+        file_info.hashes.push(hash);
+        self.successors
+            .entry(summary.prev_hash)
+            .or_default()
+            .insert(hash);
+        self.blocks.insert(
+            hash,
+            Location {
+                summary,
+                file,
+                offset,
+                size,
+            },
+        );
+    }
+
+    /// Stores a block, returning its summary. Storing a block twice is a no-op.
+    pub fn put_block(&mut self, block: &[u8]) -> Result<BlockSummary, DbError> {
+        let summary = (self.parse)(block).map_err(DbError::Corrupt)?;
+        if self.blocks.contains_key(&summary.info.hash) {
+            return Ok(summary);
+        }
+
+        let blocks_in_file = self
+            .files
+            .get(&self.current_file)
+            .map_or(0, |file| file.hashes.len());
+        if blocks_in_file >= self.max_blocks_per_file {
+            self.current.sync_data()?;
+            self.current_file += 1;
+            self.current = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path(&self.dir, self.current_file))?;
+            self.current_len = 0;
+        }
+
+        self.current.write_all(block)?;
+        self.index(
+            summary.clone(),
+            self.current_file,
+            self.current_len,
+            block.len() as u64,
+        );
+        self.current_len += block.len() as u64;
+        Ok(summary)
+    }
+
+    pub fn sync(&mut self) -> Result<(), DbError> {
+        Ok(self.current.sync_data()?)
+    }
+
+    pub fn contains(&self, hash: &Hash32) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn summary(&self, hash: &Hash32) -> Option<&BlockSummary> {
+        self.blocks.get(hash).map(|location| &location.summary)
+    }
+
+    pub fn get_block(&self, hash: &Hash32) -> Result<Option<Vec<u8>>, DbError> {
+        let location = match self.blocks.get(hash) {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        let mut file = File::open(file_path(&self.dir, location.file))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut block = vec![];
+        file.take(location.size).read_to_end(&mut block)?;
+        if block.len() as u64 != location.size {
+            return Err(DbError::Corrupt(format!(
+                "Block {:?} is truncated in file {}",
+                hash, location.file
+            )));
+        }
+        Ok(Some(block))
+    }
+
+    /// Hashes of the blocks whose predecessor is `prev_hash`.
+    pub fn successors(&self, prev_hash: Option<&Hash32>) -> Vec<Hash32> {
+        let mut successors: Vec<Hash32> = self
+            .successors
+            .get(&prev_hash.copied())
+            .map(|hashes| hashes.iter().copied().collect())
+            .unwrap_or_default();
+        successors.sort();
+        successors
+    }
+
+    pub fn max_slot(&self) -> Option<u64> {
+        self.files.values().filter_map(|file| file.max_slot).max()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Deletes every file whose blocks are all older than `slot`. The file
+    /// currently appended to is kept, as in cardano-node, and so are files
+    /// without any block the parser accepts: their slots are unknown.
+    pub fn garbage_collect(&mut self, slot: u64) -> Result<usize, DbError> {
+        let collectable: Vec<u64> = self
+            .files
+            .iter()
+            .filter(|(file, info)| {
+                **file != self.current_file && info.max_slot.is_some_and(|max| max < slot)
+            })
+            .map(|(file, _)| *file)
+            .collect();
+
+        let mut removed = 0;
+        for file in collectable {
+            fs::remove_file(file_path(&self.dir, file))?;
+            let file_info = self.files.remove(&file).unwrap_or_default();
+            for hash in file_info.hashes {
+                if let Some(location) = self.blocks.remove(&hash) {
+                    if let Some(successors) = self.successors.get_mut(&location.summary.prev_hash) {
+                        successors.remove(&hash);
+                        if successors.is_empty() {
+                            self.successors.remove(&location.summary.prev_hash);
+                        }
+                    }
+                    removed += 1;
+                }
+            }
+        }
+        if removed > 0 {
+            info!(
+                "VolatileDB garbage collected {} blocks before slot {}",
+                removed, slot
+            );
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::parse_cardano_block;
+    use crate::storage::test_blocks::{block, hash};
+    use ciborium::{into_writer, Value};
+
+    #[test]
+    fn forks_and_successors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = VolatileDb::open(dir.path(), 2, parse_cardano_block).unwrap();
+        let a = block(1, 1, None);
+        let b = block(2, 2, Some(&a));
+        let c = block(3, 2, Some(&a));
+        let d = block(4, 3, Some(&c));
+        for block in [&a, &b, &c, &d, &b] {
+            db.put_block(block).unwrap();
+        }
+
+        assert_eq!(db.len(), 4);
+        assert_eq!(db.successors(None), vec![hash(&a)]);
+        let mut forks = vec![hash(&b), hash(&c)];
+        forks.sort();
+        assert_eq!(db.successors(Some(&hash(&a))), forks);
+        assert_eq!(db.get_block(&hash(&d)).unwrap(), Some(d.clone()));
+        assert_eq!(db.max_slot(), Some(4));
+        assert!(dir.path().join("blocks-1.dat").exists());
+    }
+
+    #[test]
+    fn reopen_after_partial_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = block(1, 1, None);
+        let b = block(2, 2, Some(&a));
+        {
+            let mut db = VolatileDb::open(dir.path(), 10, parse_cardano_block).unwrap();
+            db.put_block(&a).unwrap();
+            db.put_block(&b).unwrap();
+        }
+        let c = block(3, 3, Some(&b));
+        let path = dir.path().join("blocks-0.dat");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&c[..c.len() - 5]).unwrap();
+
+        let mut db = VolatileDb::open(dir.path(), 10, parse_cardano_block).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            (a.len() + b.len()) as u64
+        );
+        db.put_block(&c).unwrap();
+        assert_eq!(db.get_block(&hash(&c)).unwrap(), Some(c));
+        assert_eq!(db.summary(&hash(&b)).unwrap().block_number, 2);
+    }
+
+    #[test]
+    fn keeps_blocks_it_cannot_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = block(1, 1, None);
+        let mut unknown = vec![];
+        into_writer(&Value::Text("unknown".to_owned()), &mut unknown).unwrap();
+        let b = block(2, 2, Some(&a));
+        let path = dir.path().join("blocks-0.dat");
+        fs::write(&path, [a.clone(), unknown, b.clone()].concat()).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+
+        let db = VolatileDb::open(dir.path(), 10, parse_cardano_block).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.get_block(&hash(&b)).unwrap(), Some(b));
+        drop(db);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        // Bytes that are no CBOR item are an error, not a torn write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0x1c, 0x00]).unwrap();
+        assert!(matches!(
+            VolatileDb::open(dir.path(), 10, parse_cardano_block),
+            Err(DbError::Corrupt(_))
+        ));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len + 2);
+    }
+
+    #[test]
+    fn garbage_collect_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = VolatileDb::open(dir.path(), 2, parse_cardano_block).unwrap();
+        let mut blocks = vec![block(1, 1, None)];
+        for slot in 2..=6 {
+            let next = block(slot, slot, blocks.last().map(Vec::as_slice));
+            blocks.push(next);
+        }
+        for block in &blocks {
+            db.put_block(block).unwrap();
+        }
+
+        // Files hold slots [1, 2], [3, 4] and [5, 6]
+        assert_eq!(db.garbage_collect(4).unwrap(), 2);
+        assert!(!db.contains(&hash(&blocks[0])));
+        assert!(db.contains(&hash(&blocks[2])));
+        assert!(db.successors(None).is_empty());
+        assert!(!dir.path().join("blocks-0.dat").exists());
+
+        // The current file is never collected
+        assert_eq!(db.garbage_collect(100).unwrap(), 2);
+        assert_eq!(db.len(), 2);
+
+        drop(db);
+        let db = VolatileDb::open(dir.path(), 2, parse_cardano_block).unwrap();
+        assert_eq!(db.len(), 2);
+    }
+
+    #[test]
+    fn garbage_collect_keeps_unparsed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut unknown = vec![];
+        into_writer(&Value::Text("unknown".to_owned()), &mut unknown).unwrap();
+        let a = block(1, 1, None);
+        let b = block(2, 2, Some(&a));
+        for (file, bytes) in [(0, &unknown), (1, &a), (2, &b)] {
+            fs::write(file_path(dir.path(), file), bytes).unwrap();
+        }
+
+        let mut db = VolatileDb::open(dir.path(), 2, parse_cardano_block).unwrap();
+        assert_eq!(db.garbage_collect(100).unwrap(), 1);
+        assert!(dir.path().join("blocks-0.dat").exists());
+        assert!(!dir.path().join("blocks-1.dat").exists());
+        assert!(db.contains(&hash(&b)));
+    }
+}
@@ -0,0 +1,17 @@
+// The global subscriber stays installed until the process ends, so this runs
+// in its own test binary rather than among the unit tests.
+
+use cardano_rust_node::config::{enable_tracing, LoggingConfig};
+
+#[test]
+fn enabling_twice_fails() {
+    let off = LoggingConfig {
+        level: "off".to_owned(),
+        ..LoggingConfig::default()
+    };
+    assert!(enable_tracing(&off).is_ok());
+    assert_eq!(
+        enable_tracing(&LoggingConfig::default()).err().unwrap(),
+        "Tracing is already enabled"
+    );
+}